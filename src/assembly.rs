@@ -0,0 +1,818 @@
+//! A textual assembly format for `FunctionProto` trees.
+//!
+//! Assembly is line based, and `;` starts a comment that runs to the end of the line.  A prototype
+//! is a `.function` block closed by `.end`, and `.function` blocks nested inside it become its
+//! nested prototypes in order.  Inside a block, directives describe the prototype:
+//!
+//! ```text
+//! .chunk "main.lua"         ; the name of the chunk the function was compiled from
+//! .defined 3                ; the function's definition starts on line 3
+//! .params 2                 ; number of fixed parameters
+//! .varargs                  ; the function takes varargs
+//! .stack 4                  ; stack size
+//! .register count r1        ; names register 1 `count` for the following instructions
+//! .constant "print"         ; appends to the constant pool, referenced as k0, k1, ...
+//! .upvalue environment      ; appends an upvalue descriptor, referenced as u0, u1, ...
+//! .upvalue local count     ; a register of the parent function, by number or name
+//! .upvalue outer u0 "x"     ; upvalues may be named, but then all of them must be
+//! .line 12                  ; following instructions were compiled from line 12
+//! .local "count" r1 2 9     ; local variable `count` is in r1 from instruction 2 up to 9
+//! ```
+//!
+//! A NaN constant is written as `NaN:0x` followed by its 16 digit bit pattern, so that its sign and
+//! payload survive a round trip.
+//!
+//! Every other line is an instruction, optionally preceded by a `label:`.  An instruction is the
+//! `OpCode` variant name followed by every one of its fields as `field=value`, in any order.
+//! Registers are `r<n>` or a register name, constants `k<n>`, upvalues `u<n>` and prototypes
+//! `p<n>`.  Counts are a number or `var`, optional registers a number or `none`, and jump offsets
+//! are either a label or a literal offset.
+//!
+//! ```text
+//! .function
+//!     .stack 1
+//!     .constant 10
+//!     LoadConstant dest=r0 constant=k0
+//! loop:
+//!     SubRC dest=r0 left=r0 right=k0
+//!     Jump offset=loop close_upvalues=none
+//! .end
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error as StdError;
+use std::fmt::{self, Write};
+
+use gc_arena::{Collect, Gc, MutationContext};
+
+use crate::{
+    Constant, ConstantIndex16, ConstantIndex8, FunctionProto, InternedStringSet, LineNumber,
+    LocalVariable, OpCode, Opt254, PrototypeIndex, RegisterIndex, UpValueDescriptor, UpValueIndex,
+    VarCount,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub enum AssemblerErrorKind {
+    UnknownDirective(String),
+    UnknownOpCode(String),
+    BadArgument(String),
+    MissingOperand(&'static str),
+    UnexpectedOperand(String),
+    BadOperand(String),
+    BadConstant(String),
+    UnknownLabel(String),
+    DuplicateLabel(String),
+    JumpRange,
+    OutsideFunction,
+    UnclosedFunction,
+    MissingFunction,
+}
+
+/// An error describing why assembly text was rejected by `assemble`.
+#[derive(Debug, Clone, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub struct AssemblerError {
+    /// The 1-indexed line of the assembly text containing the error.
+    pub line: usize,
+    pub kind: AssemblerErrorKind,
+}
+
+impl StdError for AssemblerError {}
+
+impl fmt::Display for AssemblerErrorKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssemblerErrorKind::UnknownDirective(d) => write!(fmt, "unknown directive '{}'", d),
+            AssemblerErrorKind::UnknownOpCode(op) => write!(fmt, "unknown opcode '{}'", op),
+            AssemblerErrorKind::BadArgument(arg) => write!(fmt, "bad directive argument '{}'", arg),
+            AssemblerErrorKind::MissingOperand(field) => write!(fmt, "missing operand '{}'", field),
+            AssemblerErrorKind::UnexpectedOperand(field) => {
+                write!(fmt, "unexpected operand '{}'", field)
+            }
+            AssemblerErrorKind::BadOperand(operand) => write!(fmt, "bad operand '{}'", operand),
+            AssemblerErrorKind::BadConstant(c) => write!(fmt, "bad constant '{}'", c),
+            AssemblerErrorKind::UnknownLabel(label) => write!(fmt, "unknown label '{}'", label),
+            AssemblerErrorKind::DuplicateLabel(label) => {
+                write!(fmt, "label '{}' is already defined", label)
+            }
+            AssemblerErrorKind::JumpRange => write!(fmt, "jump offset out of range"),
+            AssemblerErrorKind::OutsideFunction => write!(fmt, "expected a single .function block"),
+            AssemblerErrorKind::UnclosedFunction => write!(fmt, ".function block is not closed"),
+            AssemblerErrorKind::MissingFunction => write!(fmt, "no .function block"),
+        }
+    }
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "line {}: {}", self.line, self.kind)
+    }
+}
+
+/// Assembles the text of a single `.function` block into a `FunctionProto`.
+///
+/// The result is not verified, so this can be used to produce prototypes that `verify` would
+/// reject.
+pub fn assemble<'gc>(
+    mc: MutationContext<'gc, '_>,
+    interned_strings: InternedStringSet<'gc>,
+    source: &str,
+) -> Result<FunctionProto<'gc>, AssemblerError> {
+    let mut assembler = Assembler {
+        mc,
+        interned_strings,
+        functions: Vec::new(),
+        main: None,
+    };
+
+    let mut line = 0;
+    for (i, text) in source.lines().enumerate() {
+        line = i + 1;
+        assembler
+            .line(text)
+            .map_err(|kind| AssemblerError { line, kind })?;
+    }
+
+    if !assembler.functions.is_empty() {
+        return Err(AssemblerError {
+            line,
+            kind: AssemblerErrorKind::UnclosedFunction,
+        });
+    }
+    assembler.main.ok_or(AssemblerError {
+        line,
+        kind: AssemblerErrorKind::MissingFunction,
+    })
+}
+
+/// Produces assembly text for a `FunctionProto` tree, which `assemble` turns back into an identical
+/// tree.
+///
+/// Jump targets are given labels of the form `L<n>`, where `n` is the index of the target opcode.
+pub fn disassemble<'gc>(proto: &FunctionProto<'gc>) -> String {
+    let mut out = String::new();
+    disassemble_function(&mut out, proto, 0);
+    out
+}
+
+struct Assembler<'gc, 'a> {
+    mc: MutationContext<'gc, 'a>,
+    interned_strings: InternedStringSet<'gc>,
+    // The stack of currently open `.function` blocks
+    functions: Vec<FunctionBuilder<'gc>>,
+    main: Option<FunctionProto<'gc>>,
+}
+
+#[derive(Default)]
+struct FunctionBuilder<'gc> {
+    proto: FunctionProto<'gc>,
+    registers: HashMap<String, u8>,
+    labels: HashMap<String, usize>,
+    // Jumps to labels that have not been resolved yet, as the opcode index and label
+    label_jumps: Vec<(usize, String)>,
+    // The label of the jump operand currently being parsed, if any
+    pending_label: Option<String>,
+}
+
+impl<'gc, 'a> Assembler<'gc, 'a> {
+    fn line(&mut self, text: &str) -> Result<(), AssemblerErrorKind> {
+        let mut tokens = tokenize(text)?;
+        if tokens.is_empty() {
+            return Ok(());
+        }
+
+        if tokens[0] == ".function" {
+            if self.functions.is_empty() && self.main.is_some() {
+                return Err(AssemblerErrorKind::OutsideFunction);
+            }
+            expect_arguments(&tokens, 0)?;
+            self.functions.push(FunctionBuilder::default());
+            return Ok(());
+        }
+
+        let (function, parents) = self
+            .functions
+            .split_last_mut()
+            .ok_or(AssemblerErrorKind::OutsideFunction)?;
+
+        if let Some(label) = tokens[0].strip_suffix(':') {
+            if !is_identifier(label) {
+                return Err(AssemblerErrorKind::BadArgument(tokens[0].clone()));
+            }
+            let pc = function.proto.opcodes.len();
+            if function.labels.insert(label.to_owned(), pc).is_some() {
+                return Err(AssemblerErrorKind::DuplicateLabel(label.to_owned()));
+            }
+            tokens.remove(0);
+            if tokens.is_empty() {
+                return Ok(());
+            }
+        }
+
+        match tokens[0].as_str() {
+            ".end" => {
+                expect_arguments(&tokens, 0)?;
+                let proto = self.functions.pop().unwrap().finish()?;
+                match self.functions.last_mut() {
+                    Some(parent) => parent.proto.prototypes.push(Gc::allocate(self.mc, proto)),
+                    None => self.main = Some(proto),
+                }
+            }
+            ".chunk" => {
+                expect_arguments(&tokens, 1)?;
+                function.proto.chunk_name =
+                    match parse_constant(self.mc, self.interned_strings, &tokens[1])? {
+                        Constant::String(name) => Some(name),
+                        _ => return Err(AssemblerErrorKind::BadArgument(tokens[1].clone())),
+                    };
+            }
+            ".defined" => {
+                expect_arguments(&tokens, 1)?;
+                let line: u64 = parse_argument(&tokens[1])?;
+                if line == 0 {
+                    return Err(AssemblerErrorKind::BadArgument(tokens[1].clone()));
+                }
+                function.proto.line_defined = Some(LineNumber(line - 1));
+            }
+            ".params" => {
+                expect_arguments(&tokens, 1)?;
+                function.proto.fixed_params = parse_argument(&tokens[1])?;
+            }
+            ".varargs" => {
+                expect_arguments(&tokens, 0)?;
+                function.proto.has_varargs = true;
+            }
+            ".stack" => {
+                expect_arguments(&tokens, 1)?;
+                function.proto.stack_size = parse_argument(&tokens[1])?;
+            }
+            ".register" => {
+                expect_arguments(&tokens, 2)?;
+                let name = &tokens[1];
+                if !is_identifier(name) || parse_index::<u64>(name, 'r').is_some() {
+                    return Err(AssemblerErrorKind::BadArgument(name.clone()));
+                }
+                let register = parse_index(&tokens[2], 'r')
+                    .ok_or_else(|| AssemblerErrorKind::BadArgument(tokens[2].clone()))?;
+                function.registers.insert(name.clone(), register);
+            }
+            ".constant" => {
+                expect_arguments(&tokens, 1)?;
+                let constant = parse_constant(self.mc, self.interned_strings, &tokens[1])?;
+                function.proto.constants.push(constant);
+            }
+            ".upvalue" => {
+                // An optional trailing string names the upvalue.
+                let name = match tokens.last() {
+                    Some(name) if name.starts_with('"') => {
+                        let name = tokens.pop().unwrap();
+                        match parse_constant(self.mc, self.interned_strings, &name)? {
+                            Constant::String(name) => Some(name),
+                            _ => return Err(AssemblerErrorKind::BadArgument(name)),
+                        }
+                    }
+                    _ => None,
+                };
+                let upvalue = match (tokens.get(1).map(String::as_str), tokens.len()) {
+                    (Some("environment"), 2) => UpValueDescriptor::Environment,
+                    // Register names for parent locals are those of the parent function
+                    (Some("local"), 3) => UpValueDescriptor::ParentLocal(
+                        match parents.last_mut() {
+                            Some(parent) => RegisterIndex::parse(&tokens[2], parent),
+                            None => parse_index(&tokens[2], 'r').map(RegisterIndex),
+                        }
+                        .ok_or_else(|| AssemblerErrorKind::BadArgument(tokens[2].clone()))?,
+                    ),
+                    (Some("outer"), 3) => UpValueDescriptor::Outer(
+                        UpValueIndex::parse(&tokens[2], function)
+                            .ok_or_else(|| AssemblerErrorKind::BadArgument(tokens[2].clone()))?,
+                    ),
+                    _ => return Err(AssemblerErrorKind::BadArgument(tokens[1..].join(" "))),
+                };
+                let proto = &mut function.proto;
+                let consistent = match name {
+                    Some(_) => proto.upvalue_names.len() == proto.upvalues.len(),
+                    None => proto.upvalue_names.is_empty(),
+                };
+                if !consistent {
+                    return Err(AssemblerErrorKind::BadArgument(tokens[1..].join(" ")));
+                }
+                proto.upvalues.push(upvalue);
+                proto.upvalue_names.extend(name);
+            }
+            ".line" => {
+                expect_arguments(&tokens, 1)?;
+                let line: u64 = parse_argument(&tokens[1])?;
+                if line == 0 {
+                    return Err(AssemblerErrorKind::BadArgument(tokens[1].clone()));
+                }
+                let pc = function.proto.opcodes.len();
+                let opcode_lines = &mut function.proto.opcode_lines;
+                if opcode_lines.last().is_some_and(|&(last, _)| last == pc) {
+                    opcode_lines.pop();
+                }
+                opcode_lines.push((pc, LineNumber(line - 1)));
+            }
+            ".local" => {
+                expect_arguments(&tokens, 4)?;
+                let name = match parse_constant(self.mc, self.interned_strings, &tokens[1])? {
+                    Constant::String(name) => name,
+                    _ => return Err(AssemblerErrorKind::BadArgument(tokens[1].clone())),
+                };
+                let register = RegisterIndex::parse(&tokens[2], function)
+                    .ok_or_else(|| AssemblerErrorKind::BadArgument(tokens[2].clone()))?;
+                let start_pc: usize = parse_argument(&tokens[3])?;
+                let end_pc: usize = parse_argument(&tokens[4])?;
+                if end_pc < start_pc {
+                    return Err(AssemblerErrorKind::BadArgument(tokens[4].clone()));
+                }
+                function.proto.locals.push(LocalVariable {
+                    name,
+                    register,
+                    start_pc,
+                    end_pc,
+                });
+            }
+            directive if directive.starts_with('.') => {
+                return Err(AssemblerErrorKind::UnknownDirective(directive.to_owned()));
+            }
+            name => {
+                let mut operands = Vec::new();
+                for token in &tokens[1..] {
+                    match token.find('=') {
+                        Some(i) => operands.push((&token[..i], &token[i + 1..])),
+                        None => return Err(AssemblerErrorKind::BadOperand(token.clone())),
+                    }
+                }
+
+                let opcode = parse_opcode(name, &mut operands, function)?;
+                if let Some(&(field, _)) = operands.first() {
+                    return Err(AssemblerErrorKind::UnexpectedOperand(field.to_owned()));
+                }
+
+                let pc = function.proto.opcodes.len();
+                if let Some(label) = function.pending_label.take() {
+                    function.label_jumps.push((pc, label));
+                }
+                function.proto.opcodes.push(opcode);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'gc> FunctionBuilder<'gc> {
+    fn finish(mut self) -> Result<FunctionProto<'gc>, AssemblerErrorKind> {
+        for (pc, label) in self.label_jumps {
+            let target = *self
+                .labels
+                .get(&label)
+                .ok_or(AssemblerErrorKind::UnknownLabel(label))?;
+            let offset = target as isize - (pc as isize + 1);
+            let offset = if offset >= i16::MIN as isize && offset <= i16::MAX as isize {
+                offset as i16
+            } else {
+                return Err(AssemblerErrorKind::JumpRange);
+            };
+            *jump_offset(&mut self.proto.opcodes[pc]).unwrap() = offset;
+        }
+        Ok(self.proto)
+    }
+}
+
+fn disassemble_function<'gc>(out: &mut String, proto: &FunctionProto<'gc>, depth: usize) {
+    let outer = "    ".repeat(depth);
+    let indent = "    ".repeat(depth + 1);
+
+    writeln!(out, "{}.function", outer).unwrap();
+    if let Some(name) = proto.chunk_name {
+        writeln!(
+            out,
+            "{}.chunk {}",
+            indent,
+            format_constant(Constant::String(name))
+        )
+        .unwrap();
+    }
+    if let Some(line) = proto.line_defined {
+        writeln!(out, "{}.defined {}", indent, line).unwrap();
+    }
+    if proto.fixed_params != 0 {
+        writeln!(out, "{}.params {}", indent, proto.fixed_params).unwrap();
+    }
+    if proto.has_varargs {
+        writeln!(out, "{}.varargs", indent).unwrap();
+    }
+    writeln!(out, "{}.stack {}", indent, proto.stack_size).unwrap();
+    for &constant in &proto.constants {
+        writeln!(out, "{}.constant {}", indent, format_constant(constant)).unwrap();
+    }
+    for (i, &upvalue) in proto.upvalues.iter().enumerate() {
+        write!(out, "{}.upvalue ", indent).unwrap();
+        match upvalue {
+            UpValueDescriptor::Environment => write!(out, "environment"),
+            UpValueDescriptor::ParentLocal(r) => write!(out, "local r{}", r.0),
+            UpValueDescriptor::Outer(u) => write!(out, "outer u{}", u.0),
+        }
+        .unwrap();
+        if let Some(&name) = proto.upvalue_names.get(i) {
+            write!(out, " {}", format_constant(Constant::String(name))).unwrap();
+        }
+        out.push('\n');
+    }
+    for local in &proto.locals {
+        writeln!(
+            out,
+            "{}.local {} r{} {} {}",
+            indent,
+            format_constant(Constant::String(local.name)),
+            local.register.0,
+            local.start_pc,
+            local.end_pc
+        )
+        .unwrap();
+    }
+
+    let mut labels = HashSet::new();
+    for (pc, &opcode) in proto.opcodes.iter().enumerate() {
+        if let Some(target) = jump_target(pc, opcode) {
+            if target >= 0 && (target as usize) < proto.opcodes.len() {
+                labels.insert(target as usize);
+            }
+        }
+    }
+
+    let mut opcode_lines = proto.opcode_lines.iter().peekable();
+    for (pc, &opcode) in proto.opcodes.iter().enumerate() {
+        if labels.contains(&pc) {
+            writeln!(out, "{}L{}:", outer, pc).unwrap();
+        }
+        while let Some(&&(line_pc, line)) = opcode_lines.peek() {
+            if line_pc > pc {
+                break;
+            }
+            if line_pc == pc {
+                writeln!(out, "{}.line {}", indent, line).unwrap();
+            }
+            opcode_lines.next();
+        }
+
+        out.push_str(&indent);
+        format_opcode(
+            out,
+            opcode,
+            &FormatContext {
+                pc,
+                labels: &labels,
+            },
+        );
+        out.push('\n');
+    }
+
+    for p in &proto.prototypes {
+        disassemble_function(out, p, depth + 1);
+    }
+    writeln!(out, "{}.end", outer).unwrap();
+}
+
+// Returns the jump offset operand of the given opcode, if it has one.
+fn jump_offset(opcode: &mut OpCode) -> Option<&mut i16> {
+    match opcode {
+        OpCode::Jump { offset, .. } => Some(offset),
+        OpCode::NumericForPrep { jump, .. }
+        | OpCode::NumericForLoop { jump, .. }
+        | OpCode::GenericForLoop { jump, .. } => Some(jump),
+        _ => None,
+    }
+}
+
+fn jump_target(pc: usize, mut opcode: OpCode) -> Option<isize> {
+    jump_offset(&mut opcode).map(|&mut offset| pc as isize + 1 + offset as isize)
+}
+
+// Splits a line into whitespace separated tokens, stopping at a comment.  Quoted strings are kept
+// together as a single token, including their quotes.
+fn tokenize(line: &str) -> Result<Vec<String>, AssemblerErrorKind> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => break,
+            '"' => {
+                token.push(c);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => {
+                            token.push('\\');
+                            token.extend(chars.next());
+                        }
+                        Some(c) => token.push(c),
+                        None => return Err(AssemblerErrorKind::BadConstant(token)),
+                    }
+                }
+                token.push('"');
+            }
+            c if c.is_whitespace() => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn expect_arguments(tokens: &[String], count: usize) -> Result<(), AssemblerErrorKind> {
+    if tokens.len() == count + 1 {
+        Ok(())
+    } else {
+        Err(AssemblerErrorKind::BadArgument(tokens[1..].join(" ")))
+    }
+}
+
+fn parse_argument<T: std::str::FromStr>(arg: &str) -> Result<T, AssemblerErrorKind> {
+    arg.parse()
+        .map_err(|_| AssemblerErrorKind::BadArgument(arg.to_owned()))
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Parses an index with the given prefix, like `r3` or `k10`.
+fn parse_index<T: std::str::FromStr>(s: &str, prefix: char) -> Option<T> {
+    let digits = s.strip_prefix(prefix)?;
+    if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        digits.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn parse_constant<'gc>(
+    mc: MutationContext<'gc, '_>,
+    interned_strings: InternedStringSet<'gc>,
+    s: &str,
+) -> Result<Constant<'gc>, AssemblerErrorKind> {
+    let bad_constant = || AssemblerErrorKind::BadConstant(s.to_owned());
+    Ok(match s {
+        "nil" => Constant::Nil,
+        "true" => Constant::Boolean(true),
+        "false" => Constant::Boolean(false),
+        s if s.starts_with('"') => {
+            let s = &s[1..s.len() - 1];
+            let mut bytes = Vec::new();
+            let mut chars = s.chars();
+            while let Some(c) = chars.next() {
+                if c != '\\' {
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                    continue;
+                }
+                bytes.push(match chars.next() {
+                    Some('n') => b'\n',
+                    Some('r') => b'\r',
+                    Some('t') => b'\t',
+                    Some('\\') => b'\\',
+                    Some('"') => b'"',
+                    Some('x') => {
+                        let hex: String = chars.by_ref().take(2).collect();
+                        if hex.len() != 2 {
+                            return Err(bad_constant());
+                        }
+                        u8::from_str_radix(&hex, 16).map_err(|_| bad_constant())?
+                    }
+                    _ => return Err(bad_constant()),
+                });
+            }
+            Constant::String(interned_strings.new_string(mc, &bytes))
+        }
+        s if s.starts_with("NaN:0x") => {
+            let n = u64::from_str_radix(&s[6..], 16)
+                .map(f64::from_bits)
+                .map_err(|_| bad_constant())?;
+            if !n.is_nan() {
+                return Err(bad_constant());
+            }
+            Constant::Number(n)
+        }
+        s => match s.parse::<i64>() {
+            Ok(i) => Constant::Integer(i),
+            Err(_) => Constant::Number(s.parse::<f64>().map_err(|_| bad_constant())?),
+        },
+    })
+}
+
+fn format_constant<'gc>(constant: Constant<'gc>) -> String {
+    match constant {
+        Constant::Nil => "nil".to_owned(),
+        Constant::Boolean(b) => b.to_string(),
+        Constant::Integer(i) => i.to_string(),
+        // The debug representation of NaN does not keep its bit pattern, so it is written out.
+        Constant::Number(n) if n.is_nan() => format!("NaN:0x{:016x}", n.to_bits()),
+        // The debug representation always has a decimal point or exponent, or is `inf`, so it is
+        // never mistaken for an integer.
+        Constant::Number(n) => format!("{:?}", n),
+        Constant::String(s) => {
+            let mut out = String::from("\"");
+            for &b in s.as_bytes() {
+                match b {
+                    b'\n' => out.push_str("\\n"),
+                    b'\r' => out.push_str("\\r"),
+                    b'\t' => out.push_str("\\t"),
+                    b'\\' => out.push_str("\\\\"),
+                    b'"' => out.push_str("\\\""),
+                    b' '..=b'~' => out.push(b as char),
+                    b => write!(out, "\\x{:02x}", b).unwrap(),
+                }
+            }
+            out.push('"');
+            out
+        }
+    }
+}
+
+struct FormatContext<'a> {
+    pc: usize,
+    labels: &'a HashSet<usize>,
+}
+
+// An opcode operand with a textual representation.
+trait Operand: Sized {
+    fn parse<'gc>(s: &str, function: &mut FunctionBuilder<'gc>) -> Option<Self>;
+    fn format(self, context: &FormatContext) -> String;
+}
+
+impl Operand for u8 {
+    fn parse<'gc>(s: &str, _: &mut FunctionBuilder<'gc>) -> Option<u8> {
+        s.parse().ok()
+    }
+
+    fn format(self, _: &FormatContext) -> String {
+        self.to_string()
+    }
+}
+
+impl Operand for bool {
+    fn parse<'gc>(s: &str, _: &mut FunctionBuilder<'gc>) -> Option<bool> {
+        s.parse().ok()
+    }
+
+    fn format(self, _: &FormatContext) -> String {
+        self.to_string()
+    }
+}
+
+impl Operand for i16 {
+    fn parse<'gc>(s: &str, function: &mut FunctionBuilder<'gc>) -> Option<i16> {
+        if is_identifier(s) {
+            function.pending_label = Some(s.to_owned());
+            Some(0)
+        } else {
+            s.parse().ok()
+        }
+    }
+
+    fn format(self, context: &FormatContext) -> String {
+        let target = context.pc as isize + 1 + self as isize;
+        if target >= 0 && context.labels.contains(&(target as usize)) {
+            format!("L{}", target)
+        } else {
+            self.to_string()
+        }
+    }
+}
+
+impl Operand for VarCount {
+    fn parse<'gc>(s: &str, _: &mut FunctionBuilder<'gc>) -> Option<VarCount> {
+        if s == "var" {
+            Some(VarCount::variable())
+        } else {
+            VarCount::try_constant(s.parse().ok()?)
+        }
+    }
+
+    fn format(self, _: &FormatContext) -> String {
+        match self.to_constant() {
+            Some(count) => count.to_string(),
+            None => "var".to_owned(),
+        }
+    }
+}
+
+impl Operand for Opt254 {
+    fn parse<'gc>(s: &str, _: &mut FunctionBuilder<'gc>) -> Option<Opt254> {
+        if s == "none" {
+            Some(Opt254::none())
+        } else {
+            Opt254::try_some(s.parse().ok()?)
+        }
+    }
+
+    fn format(self, _: &FormatContext) -> String {
+        match self.to_u8() {
+            Some(v) => v.to_string(),
+            None => "none".to_owned(),
+        }
+    }
+}
+
+impl Operand for RegisterIndex {
+    fn parse<'gc>(s: &str, function: &mut FunctionBuilder<'gc>) -> Option<RegisterIndex> {
+        match function.registers.get(s) {
+            Some(&r) => Some(RegisterIndex(r)),
+            None => parse_index(s, 'r').map(RegisterIndex),
+        }
+    }
+
+    fn format(self, _: &FormatContext) -> String {
+        format!("r{}", self.0)
+    }
+}
+
+macro_rules! index_operand {
+    ($($index:ident => $prefix:literal),*) => {
+        $(
+            impl Operand for $index {
+                fn parse<'gc>(s: &str, _: &mut FunctionBuilder<'gc>) -> Option<$index> {
+                    parse_index(s, $prefix).map($index)
+                }
+
+                fn format(self, _: &FormatContext) -> String {
+                    format!("{}{}", $prefix, self.0)
+                }
+            }
+        )*
+    };
+}
+
+index_operand!(
+    ConstantIndex8 => 'k',
+    ConstantIndex16 => 'k',
+    UpValueIndex => 'u',
+    PrototypeIndex => 'p'
+);
+
+// Removes the operand with the given field name from the list of operands and parses it.
+fn take_operand<'gc, T: Operand>(
+    operands: &mut Vec<(&str, &str)>,
+    field: &'static str,
+    function: &mut FunctionBuilder<'gc>,
+) -> Result<T, AssemblerErrorKind> {
+    let i = operands
+        .iter()
+        .position(|&(f, _)| f == field)
+        .ok_or(AssemblerErrorKind::MissingOperand(field))?;
+    let (_, value) = operands.remove(i);
+    T::parse(value, function)
+        .ok_or_else(|| AssemblerErrorKind::BadOperand(format!("{}={}", field, value)))
+}
+
+// Generates `format_opcode` and `parse_opcode` from `opcode_table`, writing each opcode as its
+// name followed by `field=value` for each of its operands.
+macro_rules! opcode_text {
+    ($($tag:literal => $name:ident { $($field:ident),* },)*) => {
+        fn format_opcode(out: &mut String, opcode: OpCode, context: &FormatContext) {
+            match opcode {
+                $(
+                    OpCode::$name { $($field),* } => {
+                        out.push_str(stringify!($name));
+                        $(
+                            write!(out, " {}={}", stringify!($field), $field.format(context))
+                                .unwrap();
+                        )*
+                    }
+                )*
+            }
+        }
+
+        fn parse_opcode<'gc>(
+            name: &str,
+            operands: &mut Vec<(&str, &str)>,
+            function: &mut FunctionBuilder<'gc>,
+        ) -> Result<OpCode, AssemblerErrorKind> {
+            match name {
+                $(
+                    stringify!($name) => Ok(OpCode::$name {
+                        $($field: take_operand(operands, stringify!($field), function)?),*
+                    }),
+                )*
+                _ => Err(AssemblerErrorKind::UnknownOpCode(name.to_owned())),
+            }
+        }
+    };
+}
+
+opcode_table!(opcode_text);
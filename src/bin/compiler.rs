@@ -2,78 +2,284 @@ extern crate gc_arena;
 extern crate luster;
 
 use std::error::Error as StdError;
-use std::fs::File;
+use std::fs::{self, File};
+use std::process;
 
-use clap::{crate_authors, crate_description, crate_name, crate_version, App, Arg};
+use clap::{crate_description, crate_name, crate_version, App, Arg};
 
-use luster::{compile, io, parser, FunctionProto, Lua, StaticError};
+use luster::{
+    compile, io, is_binary_chunk, parser, read_chunk, write_chunk, Constant, Error, FunctionProto,
+    Lua, OpCode, Root, StaticError,
+};
 
-fn print_function_proto<'gc>(function: &FunctionProto<'gc>) {
-    println!("=============");
-    println!("FunctionProto({:p})", function);
-    println!("=============");
-    println!(
-        "fixed_params: {}, has_varargs: {}, stack_size: {}",
-        function.fixed_params, function.has_varargs, function.stack_size
-    );
-    if function.constants.len() > 0 {
-        println!("constants:");
-        for (i, c) in function.constants.iter().enumerate() {
-            println!("{}: {:?}", i, c);
+fn load_proto<'gc>(
+    mc: gc_arena::MutationContext<'gc, '_>,
+    root: Root<'gc>,
+    source: &[u8],
+) -> Result<FunctionProto<'gc>, StaticError> {
+    if is_binary_chunk(source) {
+        read_chunk(mc, root.interned_strings, source).map_err(|e| Error::from(e).to_static())
+    } else {
+        let source = io::buffered_read(source).map_err(StaticError::IoError)?;
+        compile(mc, root.interned_strings, source).map_err(|e| e.to_static())
+    }
+}
+
+fn constant_string<'gc>(constant: Constant<'gc>) -> String {
+    match constant {
+        Constant::String(s) => format!("{:?}", String::from_utf8_lossy(s.as_bytes())),
+        constant => {
+            let mut buf = Vec::new();
+            constant.to_value().display(&mut buf).unwrap();
+            String::from_utf8_lossy(&buf).into_owned()
         }
     }
-    if function.opcodes.len() > 0 {
-        println!("opcodes:");
-        for (i, c) in function.opcodes.iter().enumerate() {
-            println!("{}: {:?}", i, c);
+}
+
+// Returns the indexes of any constants referenced by the given opcode.
+fn constant_operands(opcode: OpCode) -> Vec<usize> {
+    match opcode {
+        OpCode::LoadConstant { constant, .. } => vec![constant.0 as usize],
+        OpCode::GetTableC { key, .. }
+        | OpCode::GetUpTableC { key, .. }
+        | OpCode::SetTableCR { key, .. }
+        | OpCode::SetUpTableCR { key, .. }
+        | OpCode::SelfC { key, .. } => vec![key.0 as usize],
+        OpCode::SetTableRC { value, .. } | OpCode::SetUpTableRC { value, .. } => {
+            vec![value.0 as usize]
+        }
+        OpCode::SetTableCC { key, value, .. } | OpCode::SetUpTableCC { key, value, .. } => {
+            vec![key.0 as usize, value.0 as usize]
         }
+        OpCode::EqRC { right, .. }
+        | OpCode::LessRC { right, .. }
+        | OpCode::LessEqRC { right, .. }
+        | OpCode::AddRC { right, .. }
+        | OpCode::SubRC { right, .. }
+        | OpCode::MulRC { right, .. }
+        | OpCode::DivRC { right, .. }
+        | OpCode::IDivRC { right, .. }
+        | OpCode::ModRC { right, .. }
+        | OpCode::PowRC { right, .. }
+        | OpCode::BitAndRC { right, .. }
+        | OpCode::BitOrRC { right, .. }
+        | OpCode::BitXorRC { right, .. }
+        | OpCode::ShiftLeftRC { right, .. }
+        | OpCode::ShiftRightRC { right, .. } => vec![right.0 as usize],
+        OpCode::EqCR { left, .. }
+        | OpCode::LessCR { left, .. }
+        | OpCode::LessEqCR { left, .. }
+        | OpCode::AddCR { left, .. }
+        | OpCode::SubCR { left, .. }
+        | OpCode::MulCR { left, .. }
+        | OpCode::DivCR { left, .. }
+        | OpCode::IDivCR { left, .. }
+        | OpCode::ModCR { left, .. }
+        | OpCode::PowCR { left, .. }
+        | OpCode::BitAndCR { left, .. }
+        | OpCode::BitOrCR { left, .. }
+        | OpCode::BitXorCR { left, .. }
+        | OpCode::ShiftLeftCR { left, .. }
+        | OpCode::ShiftRightCR { left, .. } => vec![left.0 as usize],
+        OpCode::EqCC { left, right, .. }
+        | OpCode::LessCC { left, right, .. }
+        | OpCode::LessEqCC { left, right, .. }
+        | OpCode::AddCC { left, right, .. }
+        | OpCode::SubCC { left, right, .. }
+        | OpCode::MulCC { left, right, .. }
+        | OpCode::DivCC { left, right, .. }
+        | OpCode::IDivCC { left, right, .. }
+        | OpCode::ModCC { left, right, .. }
+        | OpCode::PowCC { left, right, .. }
+        | OpCode::BitAndCC { left, right, .. }
+        | OpCode::BitOrCC { left, right, .. }
+        | OpCode::BitXorCC { left, right, .. }
+        | OpCode::ShiftLeftCC { left, right, .. }
+        | OpCode::ShiftRightCC { left, right, .. } => vec![left.0 as usize, right.0 as usize],
+        _ => Vec::new(),
+    }
+}
+
+// Returns the target of the given opcode at the given index, if it is a jump.
+fn jump_target(pc: usize, opcode: OpCode) -> Option<isize> {
+    match opcode {
+        OpCode::Jump { offset: jump, .. }
+        | OpCode::NumericForPrep { jump, .. }
+        | OpCode::NumericForLoop { jump, .. }
+        | OpCode::GenericForLoop { jump, .. } => Some(pc as isize + 1 + jump as isize),
+        _ => None,
     }
-    if function.upvalues.len() > 0 {
-        println!("upvalues:");
-        for (i, u) in function.upvalues.iter().enumerate() {
-            println!("{}: {:?}", i, u);
+}
+
+fn print_listing<'gc>(name: &str, function: &FunctionProto<'gc>, main: bool) {
+    let line = match function.line_defined {
+        Some(line) => line.to_string(),
+        None => "?".to_owned(),
+    };
+    if main {
+        print!("\nmain <{}>", name);
+    } else {
+        print!("\nfunction <{}:{}>", name, line);
+    }
+    println!(
+        " ({} instructions, {}{} params, {} slots, {} upvalues, {} constants, {} functions)",
+        function.opcodes.len(),
+        function.fixed_params,
+        if function.has_varargs { "+" } else { "" },
+        function.stack_size,
+        function.upvalues.len(),
+        function.constants.len(),
+        function.prototypes.len(),
+    );
+
+    for (pc, &opcode) in function.opcodes.iter().enumerate() {
+        let line = match function.line_number(pc) {
+            Some(line) => line.to_string(),
+            None => "-".to_owned(),
+        };
+
+        let mut notes = Vec::new();
+        for c in constant_operands(opcode) {
+            match function.constants.get(c) {
+                Some(&constant) => notes.push(format!("K{} = {}", c, constant_string(constant))),
+                None => notes.push(format!("K{} = ?", c)),
+            }
+        }
+        if let Some(target) = jump_target(pc, opcode) {
+            notes.push(format!("to {}", target));
+        }
+
+        if notes.is_empty() {
+            println!("\t{}\t[{}]\t{:?}", pc, line, opcode);
+        } else {
+            println!("\t{}\t[{}]\t{:?}\t; {}", pc, line, opcode, notes.join(", "));
         }
     }
-    if function.prototypes.len() > 0 {
-        println!("prototypes:");
-        for p in &function.prototypes {
-            print_function_proto(p);
+
+    println!("constants ({}):", function.constants.len());
+    for (i, &c) in function.constants.iter().enumerate() {
+        println!("\t{}\t{}", i, constant_string(c));
+    }
+    println!("locals ({}):", function.locals.len());
+    for (i, local) in function.locals.iter().enumerate() {
+        println!(
+            "\t{}\t{}\tR{}\t{}\t{}",
+            i,
+            String::from_utf8_lossy(local.name.as_bytes()),
+            local.register.0,
+            local.start_pc,
+            local.end_pc
+        );
+    }
+    println!("upvalues ({}):", function.upvalues.len());
+    for (i, u) in function.upvalues.iter().enumerate() {
+        match function.upvalue_names.get(i) {
+            Some(name) => println!(
+                "\t{}\t{}\t{:?}",
+                i,
+                String::from_utf8_lossy(name.as_bytes()),
+                u
+            ),
+            None => println!("\t{}\t-\t{:?}", i, u),
         }
     }
+
+    for p in &function.prototypes {
+        print_listing(name, p, false);
+    }
 }
 
 fn main() -> Result<(), Box<dyn StdError>> {
     let matches = App::new(crate_name!())
         .version(crate_version!())
         .about(crate_description!())
-        .author(crate_authors!(", "))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .arg(
+            Arg::with_name("ast")
+                .long("ast")
+                .help("Parse files only and output the AST"),
+        )
         .arg(
             Arg::with_name("parse")
                 .short("p")
                 .long("parse")
-                .help("Parse file only and output AST"),
+                .help("Check that files compile without producing any output"),
+        )
+        .arg(
+            Arg::with_name("list")
+                .short("l")
+                .long("list")
+                .help("Print a listing of the compiled bytecode, the default without --output"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Write the compiled binary chunk to the given file"),
+        )
+        .arg(
+            Arg::with_name("strip")
+                .short("s")
+                .long("strip")
+                .help("Strip debug info from the written binary chunk"),
         )
         .arg(
             Arg::with_name("file")
                 .required(true)
-                .help("File to compile")
+                .multiple(true)
+                .help("Files to compile, either source text or binary chunks")
                 .index(1),
         )
         .get_matches();
 
-    let file = io::buffered_read(File::open(matches.value_of("file").unwrap())?)?;
+    let files: Vec<&str> = matches.values_of("file").unwrap().collect();
 
-    if matches.is_present("parse") {
-        let chunk = parser::parse_chunk(file, |s| s.as_ref().to_vec().into_boxed_slice())?;
-        println!("{:#?}", chunk);
-    } else {
-        let mut lua = Lua::new();
-        lua.mutate(|mc, root| -> Result<(), StaticError> {
-            let function = compile(mc, root.interned_strings, file).map_err(|e| e.to_static())?;
-            print_function_proto(&function);
+    if matches.is_present("ast") {
+        for &name in &files {
+            let file = io::buffered_read(File::open(name)?)?;
+            let chunk = parser::parse_chunk(file, |s| s.as_ref().to_vec().into_boxed_slice())?;
+            println!("{:#?}", chunk);
+        }
+        return Ok(());
+    }
+
+    let output = matches.value_of("output");
+    if output.is_some() && files.len() != 1 {
+        return Err("only a single file can be compiled to an output file".into());
+    }
+
+    let check_only = matches.is_present("parse");
+    let list = !check_only && (matches.is_present("list") || output.is_none());
+    let strip = matches.is_present("strip");
+
+    let mut failed = false;
+    let mut lua = Lua::new();
+    for &name in &files {
+        let res = lua.mutate(|mc, root| -> Result<(), Box<dyn StdError>> {
+            let source = fs::read(name)?;
+            let function = load_proto(mc, root, &source)?;
+            if list {
+                print_listing(name, &function, true);
+            }
+            if let Some(output) = output {
+                write_chunk(File::create(output)?, &function, strip)?;
+            }
             Ok(())
-        })?;
+        });
+
+        if let Err(err) = res {
+            if !check_only {
+                return Err(err);
+            }
+            eprintln!("{}: {}", name, err);
+            failed = true;
+        }
     }
 
+    if failed {
+        process::exit(1);
+    }
     Ok(())
 }
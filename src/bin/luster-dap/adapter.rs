@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde_json::{json, Value as Json};
+
+use luster::{
+    io::OutputBuffer, DebugEvent, Debugger, HostError, InterruptHandle, Lua, PauseHandle,
+    PauseReason, Resume, StackFrame, StaticError, Variable,
+};
+
+use crate::message::write_message;
+
+// Lua code only ever runs on a single thread as far as the client is concerned, whichever
+// coroutine is actually running.
+const THREAD_ID: i64 = 1;
+
+// What a `variablesReference` given to the client refers to.  References are only valid while the
+// script stays paused.
+#[derive(Debug, Clone, Copy)]
+enum Reference {
+    Locals(usize),
+    Upvalues(usize),
+    Globals,
+    Table(usize),
+}
+
+pub struct Adapter<W> {
+    output: W,
+    seq: i64,
+    lua: Lua,
+    debugger: Debugger,
+    stdout: OutputBuffer,
+    stderr: OutputBuffer,
+    // The script to run, once launched
+    program: Option<String>,
+    stop_on_entry: bool,
+    configured: bool,
+    started: bool,
+    finished: bool,
+    // The ids of the breakpoints set in each source, by path
+    breakpoints: HashMap<String, Vec<usize>>,
+    references: Vec<Reference>,
+}
+
+impl<W: Write> Adapter<W> {
+    pub fn new(output: W) -> Adapter<W> {
+        let mut lua = Lua::new();
+        let debugger = Debugger::new(&mut lua);
+
+        // Scripts must not read or write the adapter's own stdin and stdout, which carry protocol
+        // messages.  Their output is sent to the client as events instead.
+        let stdout = OutputBuffer::new();
+        let stderr = OutputBuffer::new();
+        let streams = lua.std_streams();
+        streams.set_stdin(io::empty());
+        streams.set_stdout(stdout.clone());
+        streams.set_stderr(stderr.clone());
+
+        Adapter {
+            output,
+            seq: 0,
+            lua,
+            debugger,
+            stdout,
+            stderr,
+            program: None,
+            stop_on_entry: false,
+            configured: false,
+            started: false,
+            finished: false,
+            breakpoints: HashMap::new(),
+            references: Vec::new(),
+        }
+    }
+
+    /// Returns a handle which pauses the running script from another thread.
+    pub fn pause_handle(&self) -> PauseHandle {
+        self.debugger.pause_handle()
+    }
+
+    /// Returns a handle which ends the running script from another thread.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.lua.interrupt_handle()
+    }
+
+    /// Handles a single message from the client.  Returns false once the client has disconnected.
+    pub fn handle(&mut self, message: &Json) -> io::Result<bool> {
+        if message["type"] != "request" {
+            return Ok(true);
+        }
+        let command = message["command"].as_str().unwrap_or_default();
+        let arguments = &message["arguments"];
+
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsEvaluateForHovers": true,
+                "supportsTerminateRequest": true,
+                "exceptionBreakpointFilters": [{
+                    "filter": "error",
+                    "label": "Errors",
+                    "description": "Pause whenever an error is raised, even if it will be caught",
+                    "default": false,
+                }],
+            })),
+            // Scripts only ever run inside the adapter, so there is no other process to attach to,
+            // and attaching runs the given program just as launching does.
+            "launch" | "attach" => self.launch(arguments),
+            "setBreakpoints" => Ok(self.set_breakpoints(arguments)),
+            "setExceptionBreakpoints" => {
+                let filters = arguments["filters"].as_array();
+                self.debugger.set_pause_on_error(
+                    filters.is_some_and(|filters| filters.iter().any(|f| f == "error")),
+                );
+                Ok(Json::Null)
+            }
+            "configurationDone" => {
+                self.configured = true;
+                Ok(Json::Null)
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            "stackTrace" => Ok(self.stack_trace(arguments)),
+            "scopes" => self.scopes(arguments),
+            "variables" => self.variables(arguments),
+            "evaluate" => self.evaluate(arguments),
+            "continue" | "next" | "stepIn" | "stepOut" => {
+                if self.debugger.paused().is_some() {
+                    Ok(json!({ "allThreadsContinued": true }))
+                } else {
+                    Err("the script is not paused".to_owned())
+                }
+            }
+            "pause" => {
+                // The pause was requested as soon as this message was read, so by now the script
+                // has either paused or is not running at all.
+                self.debugger.pause_handle().cancel();
+                if self.debugger.paused().is_some() {
+                    Ok(Json::Null)
+                } else {
+                    Err("the script is not running".to_owned())
+                }
+            }
+            "disconnect" | "terminate" => Ok(Json::Null),
+            _ => Err(format!("unsupported request '{}'", command)),
+        };
+        let success = result.is_ok();
+
+        let mut response = json!({
+            "type": "response",
+            "request_seq": message["seq"],
+            "command": command,
+            "success": success,
+        });
+        match result {
+            Ok(Json::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(err) => response["message"] = Json::String(err),
+        }
+        self.send(response)?;
+
+        // Events which follow from a request are sent after its response.
+        match command {
+            "initialize" => self.send_event("initialized", Json::Null)?,
+            "launch" | "attach" | "configurationDone" if success => self.start()?,
+            "continue" if success => self.run(Resume::Continue)?,
+            "next" if success => self.run(Resume::StepOver)?,
+            "stepIn" if success => self.run(Resume::StepInto)?,
+            "stepOut" if success => self.run(Resume::StepOut)?,
+            // Resuming a paused script after it has been interrupted ends it.
+            "terminate" if self.started && !self.finished => self.run(Resume::Continue)?,
+            "terminate" if !self.started => self.send_event("terminated", Json::Null)?,
+            "disconnect" => return Ok(false),
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    // Loads the program to debug.
+    fn launch(&mut self, arguments: &Json) -> Result<Json, String> {
+        if self.program.is_some() {
+            return Err("a script has already been launched".to_owned());
+        }
+        let program = arguments["program"]
+            .as_str()
+            .ok_or_else(|| "missing 'program' argument".to_owned())?;
+        let source =
+            fs::read(program).map_err(|err| format!("cannot read {}: {}", program, err))?;
+        self.debugger
+            .load(&mut self.lua, program, &source)
+            .map_err(|err| err.to_string())?;
+        self.program = Some(program.to_owned());
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        Ok(Json::Null)
+    }
+
+    // Starts the script once it has been launched and the client has finished configuring
+    // breakpoints.
+    fn start(&mut self) -> io::Result<()> {
+        if self.program.is_none() || !self.configured || self.started {
+            return Ok(());
+        }
+        self.started = true;
+        if self.stop_on_entry {
+            self.run(Resume::StepInto)
+        } else {
+            self.run(Resume::Continue)
+        }
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Json) -> Json {
+        let path = arguments["source"]["path"]
+            .as_str()
+            .or_else(|| arguments["source"]["name"].as_str())
+            .unwrap_or_default()
+            .to_owned();
+        for id in self.breakpoints.remove(&path).unwrap_or_default() {
+            self.debugger.remove_breakpoint(id);
+        }
+
+        let mut ids = Vec::new();
+        let mut breakpoints = Vec::new();
+        for line in arguments["breakpoints"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|breakpoint| breakpoint["line"].as_u64())
+        {
+            let id = self.debugger.add_breakpoint(Some(&path), line as usize);
+            ids.push(id);
+            breakpoints.push(json!({ "id": id, "verified": true, "line": line }));
+        }
+        self.breakpoints.insert(path, ids);
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn stack_trace(&mut self, arguments: &Json) -> Json {
+        let frames = self.debugger.frames(&mut self.lua);
+        let start = arguments["startFrame"].as_u64().unwrap_or(0) as usize;
+        let levels = match arguments["levels"].as_u64() {
+            Some(levels) if levels > 0 => levels as usize,
+            _ => frames.len(),
+        };
+        let stack_frames = frames
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(levels)
+            .map(|(i, frame)| stack_frame(i, frame))
+            .collect::<Vec<_>>();
+        json!({ "stackFrames": stack_frames, "totalFrames": frames.len() })
+    }
+
+    fn scopes(&mut self, arguments: &Json) -> Result<Json, String> {
+        let frame = frame_arg(arguments).ok_or_else(|| "missing 'frameId' argument".to_owned())?;
+        let mut scopes = Vec::new();
+        if self.debugger.locals(&mut self.lua, frame).is_some() {
+            scopes.push(json!({
+                "name": "Locals",
+                "presentationHint": "locals",
+                "variablesReference": self.reference(Reference::Locals(frame)),
+                "expensive": false,
+            }));
+            scopes.push(json!({
+                "name": "Upvalues",
+                "variablesReference": self.reference(Reference::Upvalues(frame)),
+                "expensive": false,
+            }));
+        }
+        scopes.push(json!({
+            "name": "Globals",
+            "variablesReference": self.reference(Reference::Globals),
+            "expensive": true,
+        }));
+        Ok(json!({ "scopes": scopes }))
+    }
+
+    fn variables(&mut self, arguments: &Json) -> Result<Json, String> {
+        let reference = arguments["variablesReference"]
+            .as_u64()
+            .and_then(|reference| self.references.get((reference as usize).checked_sub(1)?))
+            .copied()
+            .ok_or_else(|| "invalid 'variablesReference' argument".to_owned())?;
+        let variables = match reference {
+            Reference::Locals(frame) => self.debugger.locals(&mut self.lua, frame),
+            Reference::Upvalues(frame) => self.debugger.upvalues(&mut self.lua, frame),
+            Reference::Globals => Some(self.debugger.globals(&mut self.lua)),
+            Reference::Table(table) => self.debugger.fields(&mut self.lua, table),
+        }
+        .ok_or_else(|| "the variables are no longer available".to_owned())?;
+
+        let variables = variables
+            .iter()
+            .map(|variable| {
+                json!({
+                    "name": variable.name,
+                    "value": variable.value,
+                    "type": variable.type_name,
+                    "variablesReference": self.table_reference(variable),
+                })
+            })
+            .collect::<Vec<_>>();
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn evaluate(&mut self, arguments: &Json) -> Result<Json, String> {
+        let expression = arguments["expression"]
+            .as_str()
+            .ok_or_else(|| "missing 'expression' argument".to_owned())?;
+        let frame = frame_arg(arguments).unwrap_or(0);
+        let results = self.debugger.evaluate(&mut self.lua, frame, expression)?;
+
+        let result = results
+            .iter()
+            .map(|result| result.value.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let (type_name, reference) = match results.as_slice() {
+            [result] => (result.type_name, self.table_reference(result)),
+            _ => ("", 0),
+        };
+        Ok(json!({
+            "result": result,
+            "type": type_name,
+            "variablesReference": reference,
+        }))
+    }
+
+    // Resumes the script, then reports where it paused, or that it finished.
+    fn run(&mut self, resume: Resume) -> io::Result<()> {
+        self.references.clear();
+        let event = self.debugger.resume(&mut self.lua, resume);
+        self.send_output()?;
+        match event {
+            DebugEvent::Paused(reason) => {
+                let mut body = json!({ "threadId": THREAD_ID, "allThreadsStopped": true });
+                match reason {
+                    PauseReason::Breakpoint(id) => {
+                        body["reason"] = json!("breakpoint");
+                        body["hitBreakpointIds"] = json!([id]);
+                    }
+                    PauseReason::Step if resume == Resume::StepInto && self.stop_on_entry => {
+                        self.stop_on_entry = false;
+                        body["reason"] = json!("entry");
+                    }
+                    PauseReason::Step => body["reason"] = json!("step"),
+                    PauseReason::Requested => body["reason"] = json!("pause"),
+                    PauseReason::Error(message) => {
+                        body["reason"] = json!("exception");
+                        body["description"] = json!("Paused on error");
+                        body["text"] = json!(message);
+                    }
+                }
+                self.send_event("stopped", body)
+            }
+            DebugEvent::Finished(result) => {
+                self.finished = true;
+                let exit_code = match result {
+                    Ok(()) => 0,
+                    Err(StaticError::HostError(HostError::Exit(code))) => code,
+                    // The client asked for the script to be terminated.
+                    Err(StaticError::HostError(HostError::Interrupted)) => 1,
+                    Err(err) => {
+                        self.send_event(
+                            "output",
+                            json!({ "category": "stderr", "output": format!("{}\n", err) }),
+                        )?;
+                        1
+                    }
+                };
+                self.send_event("exited", json!({ "exitCode": exit_code }))?;
+                self.send_event("terminated", Json::Null)
+            }
+        }
+    }
+
+    // Sends anything the script has written as output events.
+    fn send_output(&mut self) -> io::Result<()> {
+        for (category, buffer) in &[
+            ("stdout", self.stdout.clone()),
+            ("stderr", self.stderr.clone()),
+        ] {
+            let output = buffer.take();
+            if !output.is_empty() {
+                let output = String::from_utf8_lossy(&output);
+                self.send_event("output", json!({ "category": category, "output": output }))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn reference(&mut self, reference: Reference) -> usize {
+        self.references.push(reference);
+        self.references.len()
+    }
+
+    fn table_reference(&mut self, variable: &Variable) -> usize {
+        match variable.table {
+            Some(table) => self.reference(Reference::Table(table)),
+            None => 0,
+        }
+    }
+
+    fn send_event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.send(message)
+    }
+
+    fn send(&mut self, mut message: Json) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.output, &message)
+    }
+}
+
+// Frames are given to the client with ids one greater than their number, as some clients treat a
+// frame id of 0 as missing.
+fn frame_arg(arguments: &Json) -> Option<usize> {
+    (arguments["frameId"].as_u64()? as usize).checked_sub(1)
+}
+
+fn stack_frame(i: usize, frame: &StackFrame) -> Json {
+    let name = match &frame.name {
+        Some(name) => name.clone(),
+        None if frame.callback => "[callback]".to_owned(),
+        None => match frame.defined_line {
+            Some(line) => format!("function <line {}>", line),
+            None => "main chunk".to_owned(),
+        },
+    };
+    let mut stack_frame = json!({
+        "id": i + 1,
+        "name": name,
+        "line": frame.line.unwrap_or(0),
+        "column": if frame.line.is_some() { 1 } else { 0 },
+    });
+    match &frame.chunk_name {
+        Some(chunk_name) => {
+            let file_name = Path::new(chunk_name).file_name().map_or_else(
+                || chunk_name.clone(),
+                |name| name.to_string_lossy().into_owned(),
+            );
+            stack_frame["source"] = json!({ "name": file_name, "path": chunk_name });
+        }
+        None => stack_frame["presentationHint"] = json!("subtle"),
+    }
+    stack_frame
+}
@@ -0,0 +1,57 @@
+//! A Debug Adapter Protocol server for luster.
+//!
+//! Editors start `luster-dap` and exchange messages with it over stdin and stdout, each made of a
+//! `Content-Length` header, a blank line and a JSON body.  The adapter runs scripts in its own
+//! interpreter using `luster::Debugger`, and only a single script may be debugged per session.
+//! There is no way to attach to a script running in another process, so an `attach` request takes
+//! the same arguments as `launch` and starts the program itself.
+//!
+//! Messages are read on a separate thread, so that `pause`, `terminate` and `disconnect` requests
+//! take effect even while a script is running.
+
+mod adapter;
+#[path = "../common/message.rs"]
+mod message;
+
+use std::error::Error as StdError;
+use std::io;
+use std::sync::mpsc;
+use std::thread;
+
+use adapter::Adapter;
+use message::read_message;
+
+fn main() -> Result<(), Box<dyn StdError>> {
+    let mut adapter = Adapter::new(io::stdout());
+    let pause = adapter.pause_handle();
+    let interrupt = adapter.interrupt_handle();
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut input = stdin.lock();
+        loop {
+            let message = read_message(&mut input).transpose();
+            if let Some(Ok(message)) = &message {
+                // The adapter does not see these requests until the script stops, so act on them
+                // straight away.
+                match message["command"].as_str() {
+                    Some("pause") => pause.pause(),
+                    Some("terminate") | Some("disconnect") => interrupt.interrupt(),
+                    _ => {}
+                }
+            }
+            let done = !matches!(message, Some(Ok(_)));
+            if sender.send(message).is_err() || done {
+                break;
+            }
+        }
+    });
+
+    while let Some(message) = receiver.recv()? {
+        if !adapter.handle(&message?)? {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,502 @@
+use std::fmt;
+
+use luster::parser::{
+    AssignmentTarget, Block, CallSuffix, ConstructorField, Expression, FieldSuffix, ForStatement,
+    FunctionDefinition, HeadExpression, PrimaryExpression, RecordKey, SimpleExpression, Statement,
+    SuffixPart, SuffixedExpression,
+};
+use luster::{compile, parse_chunk_with_line, Error, Lexer, Lua, ParserError, Token};
+
+/// A range of bytes on a single line of a document.  Lines are 0-indexed and counted the way the
+/// lexer counts them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    // Whether the given position is inside the span or just after its end, where an editor's
+    // cursor sits after typing a name.
+    fn contains(&self, line: usize, column: usize) -> bool {
+        self.line == line && self.start <= column && column <= self.end
+    }
+}
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub line: usize,
+    /// The byte offset into the line the problem starts at, or `None` if only the line is known.
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DefinitionKind {
+    Local,
+    LocalFunction,
+    Parameter,
+    LoopVariable,
+}
+
+impl fmt::Display for DefinitionKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DefinitionKind::Local => write!(fmt, "local"),
+            DefinitionKind::LocalFunction => write!(fmt, "local function"),
+            DefinitionKind::Parameter => write!(fmt, "parameter"),
+            DefinitionKind::LoopVariable => write!(fmt, "loop variable"),
+        }
+    }
+}
+
+/// A local variable declaration.
+#[derive(Debug)]
+pub struct Definition {
+    pub name: String,
+    pub kind: DefinitionKind,
+    pub span: Span,
+    // The number of functions enclosing the declaration, the main chunk being the first
+    depth: usize,
+}
+
+/// A use of a local variable by name.
+#[derive(Debug)]
+pub struct Reference {
+    pub span: Span,
+    pub definition: usize,
+    /// Whether the variable is declared in an enclosing function, and so accessed as an upvalue.
+    pub upvalue: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Method,
+    Variable,
+}
+
+#[derive(Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub span: Span,
+    /// The name of the innermost named function the symbol is declared in.
+    pub container: Option<String>,
+}
+
+/// Everything known about a single document.
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic>,
+    pub symbols: Vec<Symbol>,
+    pub definitions: Vec<Definition>,
+    pub references: Vec<Reference>,
+}
+
+impl Analysis {
+    /// Lexes, parses and compiles the given source.  Declarations are only known if it parses.
+    pub fn new(lua: &mut Lua, source: &str) -> Analysis {
+        let (spans, lexer_error) = lex(source);
+        let mut analysis = Analysis::default();
+
+        let mut next_index = 0;
+        let parsed = parse_chunk_with_line(source.as_bytes(), |bytes| {
+            next_index += 1;
+            Name {
+                bytes: bytes.into(),
+                index: next_index - 1,
+            }
+        });
+        match parsed {
+            Ok(chunk) => {
+                let mut resolver = Resolver {
+                    spans: &spans,
+                    analysis: &mut analysis,
+                    scope: Vec::new(),
+                    functions: Vec::new(),
+                };
+                resolver.function_body(&[], &chunk.block, None);
+            }
+            Err((error, line_number)) => {
+                let (line, column) = match (&error, lexer_error) {
+                    (ParserError::LexerError(_), Some((line, column))) => (line, Some(column)),
+                    _ => (line_number.0 as usize, None),
+                };
+                analysis.diagnostics.push(Diagnostic {
+                    line,
+                    column,
+                    message: error.to_string(),
+                });
+                return analysis;
+            }
+        }
+
+        // The compiler does not report where its errors are, so they are placed on the first line.
+        let compiled =
+            lua.mutate(
+                |mc, root| match compile(mc, root.interned_strings, source.as_bytes()) {
+                    Ok(_) => None,
+                    Err(Error::CompilerError(error)) => Some(error.to_string()),
+                    Err(error) => Some(error.to_string()),
+                },
+            );
+        if let Some(message) = compiled {
+            analysis.diagnostics.push(Diagnostic {
+                line: 0,
+                column: None,
+                message,
+            });
+        }
+
+        analysis
+    }
+
+    /// Finds the local variable whose declaration or use is at the given position, along with
+    /// whether it is used there as an upvalue.
+    pub fn definition_at(&self, line: usize, column: usize) -> Option<(&Definition, bool)> {
+        if let Some(definition) = self
+            .definitions
+            .iter()
+            .find(|definition| definition.span.contains(line, column))
+        {
+            return Some((definition, false));
+        }
+        self.references
+            .iter()
+            .find(|reference| reference.span.contains(line, column))
+            .map(|reference| (&self.definitions[reference.definition], reference.upvalue))
+    }
+}
+
+/// Splits the source into lines, treating any of "\n", "\r", "\r\n" or "\n\r" as a single line
+/// ending just as the lexer does.
+pub fn split_lines(source: &str) -> Vec<&str> {
+    let bytes = source.as_bytes();
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c == b'\n' || c == b'\r' {
+            lines.push(&source[start..i]);
+            i += 1;
+            if i < bytes.len() && (bytes[i] == b'\n' || bytes[i] == b'\r') && bytes[i] != c {
+                i += 1;
+            }
+            start = i;
+        } else {
+            i += 1;
+        }
+    }
+    lines.push(&source[start..]);
+    lines
+}
+
+// Strings created by the parser, numbered in the order the lexer read them so that they can be
+// matched with the spans found by `lex`.
+#[derive(Debug, Clone)]
+struct Name {
+    bytes: Box<[u8]>,
+    index: usize,
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Name) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl Name {
+    fn to_string_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.bytes).into_owned()
+    }
+}
+
+// Returns the span of every name and string token in the source, and where the first lexer error
+// occurred, if any.
+fn lex(source: &str) -> (Vec<Span>, Option<(usize, usize)>) {
+    let mut lexer = Lexer::new(source.as_bytes(), |_| ());
+    let mut spans = Vec::new();
+    loop {
+        let position =
+            |lexer: &Lexer<_, _>| (lexer.line_number() as usize, lexer.column_number() as usize);
+        if lexer.skip_whitespace().is_err() {
+            return (spans, Some(position(&lexer)));
+        }
+        let (line, start) = position(&lexer);
+        match lexer.read_token() {
+            Ok(Some(Token::Name(())) | Some(Token::String(()))) => {
+                // Long strings may span several lines, in which case only the line they start on
+                // is covered.
+                let end = match position(&lexer) {
+                    (end_line, end) if end_line == line => end,
+                    _ => start,
+                };
+                spans.push(Span { line, start, end });
+            }
+            Ok(Some(_)) => {}
+            Ok(None) => return (spans, None),
+            Err(_) => return (spans, Some((line, start))),
+        }
+    }
+}
+
+// Walks a parsed chunk, resolving each name to the local it refers to using Lua's scoping rules.
+struct Resolver<'a> {
+    spans: &'a [Span],
+    analysis: &'a mut Analysis,
+    // The locals currently in scope, innermost last, as indexes into `analysis.definitions`
+    scope: Vec<usize>,
+    // The name of each function being walked, if it has one
+    functions: Vec<Option<String>>,
+}
+
+impl<'a> Resolver<'a> {
+    fn block(&mut self, block: &Block<Name>) {
+        let scope_len = self.scope.len();
+        self.statements(block);
+        self.scope.truncate(scope_len);
+    }
+
+    fn statements(&mut self, block: &Block<Name>) {
+        for (_, statement) in &block.statements {
+            self.statement(statement);
+        }
+        if let Some((_, return_statement)) = &block.return_statement {
+            self.expressions(&return_statement.returns);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement<Name>) {
+        match statement {
+            Statement::If(if_statement) => {
+                let (condition, block) = &if_statement.if_part;
+                self.expression(condition);
+                self.block(block);
+                for (condition, block) in &if_statement.else_if_parts {
+                    self.expression(condition);
+                    self.block(block);
+                }
+                if let Some(block) = &if_statement.else_part {
+                    self.block(block);
+                }
+            }
+            Statement::While(while_statement) => {
+                self.expression(&while_statement.condition);
+                self.block(&while_statement.block);
+            }
+            Statement::Do(block) => self.block(block),
+            Statement::For(ForStatement::Numeric {
+                name,
+                initial,
+                limit,
+                step,
+                body,
+            }) => {
+                self.expression(initial);
+                self.expression(limit);
+                if let Some(step) = step {
+                    self.expression(step);
+                }
+                let scope_len = self.scope.len();
+                self.define(name, DefinitionKind::LoopVariable);
+                self.block(body);
+                self.scope.truncate(scope_len);
+            }
+            Statement::For(ForStatement::Generic {
+                names,
+                arguments,
+                body,
+            }) => {
+                self.expressions(arguments);
+                let scope_len = self.scope.len();
+                for name in names {
+                    self.define(name, DefinitionKind::LoopVariable);
+                }
+                self.block(body);
+                self.scope.truncate(scope_len);
+            }
+            Statement::Repeat(repeat_statement) => {
+                // The condition can see the locals declared in the body.
+                let scope_len = self.scope.len();
+                self.statements(&repeat_statement.body);
+                self.expression(&repeat_statement.until);
+                self.scope.truncate(scope_len);
+            }
+            Statement::Function(function_statement) => {
+                self.reference(&function_statement.name);
+                let mut name = function_statement.name.to_string_lossy();
+                for field in &function_statement.fields {
+                    name.push('.');
+                    name.push_str(&field.to_string_lossy());
+                }
+                let (kind, last) = match &function_statement.method {
+                    Some(method) => {
+                        name.push(':');
+                        name.push_str(&method.to_string_lossy());
+                        (SymbolKind::Method, method)
+                    }
+                    None => (
+                        SymbolKind::Function,
+                        function_statement
+                            .fields
+                            .last()
+                            .unwrap_or(&function_statement.name),
+                    ),
+                };
+                self.symbol(name.clone(), kind, last);
+                let definition = &function_statement.definition;
+                self.function_body(&definition.parameters, &definition.body, Some(name));
+            }
+            Statement::LocalFunction(local_function) => {
+                let name = local_function.name.to_string_lossy();
+                // The function can refer to itself.
+                self.define(&local_function.name, DefinitionKind::LocalFunction);
+                self.symbol(name.clone(), SymbolKind::Function, &local_function.name);
+                let definition = &local_function.definition;
+                self.function_body(&definition.parameters, &definition.body, Some(name));
+            }
+            Statement::LocalStatement(local_statement) => {
+                self.expressions(&local_statement.values);
+                for name in &local_statement.names {
+                    self.define(name, DefinitionKind::Local);
+                    self.symbol(name.to_string_lossy(), SymbolKind::Variable, name);
+                }
+            }
+            Statement::Label(_) | Statement::Break | Statement::Goto(_) => {}
+            Statement::FunctionCall(function_call) => {
+                self.suffixed_expression(&function_call.head);
+                self.call_suffix(&function_call.call);
+            }
+            Statement::Assignment(assignment) => {
+                for target in &assignment.targets {
+                    match target {
+                        AssignmentTarget::Name(name) => self.reference(name),
+                        AssignmentTarget::Field(head, field) => {
+                            self.suffixed_expression(head);
+                            self.field_suffix(field);
+                        }
+                    }
+                }
+                self.expressions(&assignment.values);
+            }
+        }
+    }
+
+    fn function_body(&mut self, parameters: &[Name], body: &Block<Name>, name: Option<String>) {
+        self.functions.push(name);
+        let scope_len = self.scope.len();
+        for parameter in parameters {
+            self.define(parameter, DefinitionKind::Parameter);
+        }
+        self.statements(body);
+        self.scope.truncate(scope_len);
+        self.functions.pop();
+    }
+
+    fn expressions(&mut self, expressions: &[Expression<Name>]) {
+        for expression in expressions {
+            self.expression(expression);
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression<Name>) {
+        match &*expression.head {
+            HeadExpression::Simple(simple) => self.simple_expression(simple),
+            HeadExpression::UnaryOperator(_, operand) => self.expression(operand),
+        }
+        for (_, operand) in &expression.tail {
+            self.expression(operand);
+        }
+    }
+
+    fn simple_expression(&mut self, simple: &SimpleExpression<Name>) {
+        match simple {
+            SimpleExpression::TableConstructor(table) => {
+                for field in &table.fields {
+                    match field {
+                        ConstructorField::Array(value) => self.expression(value),
+                        ConstructorField::Record(key, value) => {
+                            if let RecordKey::Indexed(key) = key {
+                                self.expression(key);
+                            }
+                            self.expression(value);
+                        }
+                    }
+                }
+            }
+            SimpleExpression::Function(FunctionDefinition {
+                parameters, body, ..
+            }) => self.function_body(parameters, body, None),
+            SimpleExpression::Suffixed(suffixed) => self.suffixed_expression(suffixed),
+            _ => {}
+        }
+    }
+
+    fn suffixed_expression(&mut self, suffixed: &SuffixedExpression<Name>) {
+        match &suffixed.primary {
+            PrimaryExpression::Name(name) => self.reference(name),
+            PrimaryExpression::GroupedExpression(expression) => self.expression(expression),
+        }
+        for suffix in &suffixed.suffixes {
+            match suffix {
+                SuffixPart::Field(field) => self.field_suffix(field),
+                SuffixPart::Call(call) => self.call_suffix(call),
+            }
+        }
+    }
+
+    fn field_suffix(&mut self, field: &FieldSuffix<Name>) {
+        if let FieldSuffix::Indexed(key) = field {
+            self.expression(key);
+        }
+    }
+
+    fn call_suffix(&mut self, call: &CallSuffix<Name>) {
+        match call {
+            CallSuffix::Method(_, arguments) | CallSuffix::Function(arguments) => {
+                self.expressions(arguments)
+            }
+        }
+    }
+
+    fn define(&mut self, name: &Name, kind: DefinitionKind) {
+        self.analysis.definitions.push(Definition {
+            name: name.to_string_lossy(),
+            kind,
+            span: self.spans[name.index],
+            depth: self.functions.len(),
+        });
+        self.scope.push(self.analysis.definitions.len() - 1);
+    }
+
+    // Records a use of the given name if it refers to a local, otherwise it is a global.
+    fn reference(&mut self, name: &Name) {
+        let definitions = &self.analysis.definitions;
+        if let Some(&definition) = self
+            .scope
+            .iter()
+            .rev()
+            .find(|&&definition| definitions[definition].name.as_bytes() == &*name.bytes)
+        {
+            let upvalue = definitions[definition].depth < self.functions.len();
+            self.analysis.references.push(Reference {
+                span: self.spans[name.index],
+                definition,
+                upvalue,
+            });
+        }
+    }
+
+    fn symbol(&mut self, name: String, kind: SymbolKind, at: &Name) {
+        let container = self.functions.iter().rev().flatten().next().cloned();
+        self.analysis.symbols.push(Symbol {
+            name,
+            kind,
+            span: self.spans[at.index],
+            container,
+        });
+    }
+}
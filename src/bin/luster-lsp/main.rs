@@ -0,0 +1,29 @@
+//! A Language Server Protocol server for luster.
+//!
+//! Editors start `luster-lsp` and exchange JSON-RPC messages with it over stdin and stdout, each
+//! made of a `Content-Length` header, a blank line and a JSON body.  Documents are analyzed with
+//! luster's own lexer, parser and compiler, and are always synchronized in full.
+
+mod analysis;
+#[path = "../common/message.rs"]
+mod message;
+mod server;
+
+use std::error::Error as StdError;
+use std::io;
+use std::process;
+
+use message::read_message;
+use server::Server;
+
+fn main() -> Result<(), Box<dyn StdError>> {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut server = Server::new(io::stdout());
+    while let Some(message) = read_message(&mut input)? {
+        if !server.handle(&message)? {
+            break;
+        }
+    }
+    process::exit(server.exit_code());
+}
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use serde_json::{json, Value as Json};
+
+use luster::Lua;
+
+use crate::analysis::{split_lines, Analysis, Span, SymbolKind};
+use crate::message::write_message;
+
+// JSON-RPC error codes
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+// LSP symbol kinds
+const SYMBOL_METHOD: i64 = 6;
+const SYMBOL_FUNCTION: i64 = 12;
+const SYMBOL_VARIABLE: i64 = 13;
+
+struct Document {
+    text: String,
+    analysis: Analysis,
+}
+
+impl Document {
+    fn line(&self, line: usize) -> &str {
+        split_lines(&self.text).get(line).copied().unwrap_or("")
+    }
+
+    // Converts a byte offset into a line to an LSP position, which counts UTF-16 code units.
+    fn position(&self, line: usize, column: usize) -> Json {
+        let text = self.line(line);
+        let character = match text.get(..column) {
+            Some(prefix) => prefix.encode_utf16().count(),
+            None => text.encode_utf16().count(),
+        };
+        json!({ "line": line, "character": character })
+    }
+
+    // Converts an LSP position to a line and byte offset into it.
+    fn offset(&self, position: &Json) -> Option<(usize, usize)> {
+        let line = position["line"].as_u64()? as usize;
+        let character = position["character"].as_u64()? as usize;
+        let text = self.line(line);
+        let mut units = 0;
+        for (offset, c) in text.char_indices() {
+            if units >= character {
+                return Some((line, offset));
+            }
+            units += c.len_utf16();
+        }
+        Some((line, text.len()))
+    }
+
+    fn range(&self, span: Span) -> Json {
+        json!({
+            "start": self.position(span.line, span.start),
+            "end": self.position(span.line, span.end),
+        })
+    }
+}
+
+pub struct Server<W> {
+    output: W,
+    // Parsing strings and compiling requires an arena, so one is kept for the whole session
+    lua: Lua,
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+impl<W: Write> Server<W> {
+    pub fn new(output: W) -> Server<W> {
+        Server {
+            output,
+            lua: Lua::new(),
+            documents: HashMap::new(),
+            shutdown: false,
+        }
+    }
+
+    /// Handles a single message from the client.  Returns false once the client has asked the
+    /// server to exit.
+    pub fn handle(&mut self, message: &Json) -> io::Result<bool> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+
+        let id = match message.get("id") {
+            Some(id) => id,
+            None => {
+                match method {
+                    "exit" => return Ok(false),
+                    "textDocument/didOpen" => {
+                        let document = &params["textDocument"];
+                        if let (Some(uri), Some(text)) =
+                            (document["uri"].as_str(), document["text"].as_str())
+                        {
+                            self.update(uri, text.to_owned())?;
+                        }
+                    }
+                    "textDocument/didChange" => {
+                        // Documents are synchronized in full, so the last change holds all the text.
+                        let text = params["contentChanges"]
+                            .as_array()
+                            .and_then(|changes| changes.last())
+                            .and_then(|change| change["text"].as_str());
+                        if let (Some(uri), Some(text)) =
+                            (params["textDocument"]["uri"].as_str(), text)
+                        {
+                            self.update(uri, text.to_owned())?;
+                        }
+                    }
+                    "textDocument/didClose" => {
+                        if let Some(uri) = params["textDocument"]["uri"].as_str() {
+                            self.documents.remove(uri);
+                            self.publish_diagnostics(uri)?;
+                        }
+                    }
+                    _ => {}
+                }
+                return Ok(true);
+            }
+        };
+
+        let result = if self.shutdown {
+            Err((INVALID_REQUEST, "the server has been shut down".to_owned()))
+        } else {
+            match method {
+                "initialize" => Ok(json!({
+                    "capabilities": {
+                        "textDocumentSync": { "openClose": true, "change": 1 },
+                        "documentSymbolProvider": true,
+                        "definitionProvider": true,
+                        "hoverProvider": true,
+                    },
+                    "serverInfo": {
+                        "name": "luster-lsp",
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                })),
+                "shutdown" => {
+                    self.shutdown = true;
+                    Ok(Json::Null)
+                }
+                "textDocument/documentSymbol" => self.document_symbols(params),
+                "textDocument/definition" => self.definition(params),
+                "textDocument/hover" => self.hover(params),
+                _ => Err((METHOD_NOT_FOUND, format!("unsupported method '{}'", method))),
+            }
+        };
+
+        let mut response = json!({ "jsonrpc": "2.0", "id": id });
+        match result {
+            Ok(result) => response["result"] = result,
+            Err((code, message)) => response["error"] = json!({ "code": code, "message": message }),
+        }
+        write_message(&mut self.output, &response)?;
+        Ok(true)
+    }
+
+    /// The exit code the server should finish with: a client must ask the server to shut down
+    /// before telling it to exit.
+    pub fn exit_code(&self) -> i32 {
+        if self.shutdown {
+            0
+        } else {
+            1
+        }
+    }
+
+    fn update(&mut self, uri: &str, text: String) -> io::Result<()> {
+        let analysis = Analysis::new(&mut self.lua, &text);
+        self.documents
+            .insert(uri.to_owned(), Document { text, analysis });
+        self.publish_diagnostics(uri)
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) -> io::Result<()> {
+        let diagnostics = match self.documents.get(uri) {
+            Some(document) => document
+                .analysis
+                .diagnostics
+                .iter()
+                .map(|diagnostic| {
+                    // Without a column, the whole line is marked apart from its indentation.
+                    let text = document.line(diagnostic.line);
+                    let start = diagnostic
+                        .column
+                        .unwrap_or_else(|| text.len() - text.trim_start().len());
+                    json!({
+                        "range": {
+                            "start": document.position(diagnostic.line, start),
+                            "end": document.position(diagnostic.line, text.len()),
+                        },
+                        "severity": 1,
+                        "source": "luster",
+                        "message": diagnostic.message,
+                    })
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        write_message(
+            &mut self.output,
+            &json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/publishDiagnostics",
+                "params": { "uri": uri, "diagnostics": diagnostics },
+            }),
+        )
+    }
+
+    fn document<'a>(&'a self, params: &'a Json) -> Result<(&'a str, &'a Document), (i64, String)> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .ok_or_else(|| (INVALID_PARAMS, "missing document uri".to_owned()))?;
+        let document = self
+            .documents
+            .get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("unknown document '{}'", uri)))?;
+        Ok((uri, document))
+    }
+
+    fn document_symbols(&self, params: &Json) -> Result<Json, (i64, String)> {
+        let (uri, document) = self.document(params)?;
+        let symbols = document
+            .analysis
+            .symbols
+            .iter()
+            .map(|symbol| {
+                let kind = match symbol.kind {
+                    SymbolKind::Function => SYMBOL_FUNCTION,
+                    SymbolKind::Method => SYMBOL_METHOD,
+                    SymbolKind::Variable => SYMBOL_VARIABLE,
+                };
+                let mut information = json!({
+                    "name": symbol.name,
+                    "kind": kind,
+                    "location": { "uri": uri, "range": document.range(symbol.span) },
+                });
+                if let Some(container) = &symbol.container {
+                    information["containerName"] = json!(container);
+                }
+                information
+            })
+            .collect();
+        Ok(Json::Array(symbols))
+    }
+
+    fn definition(&self, params: &Json) -> Result<Json, (i64, String)> {
+        let (uri, document) = self.document(params)?;
+        Ok(document
+            .offset(&params["position"])
+            .and_then(|(line, column)| document.analysis.definition_at(line, column))
+            .map_or(
+                Json::Null,
+                |(definition, _)| json!({ "uri": uri, "range": document.range(definition.span) }),
+            ))
+    }
+
+    fn hover(&self, params: &Json) -> Result<Json, (i64, String)> {
+        let (_, document) = self.document(params)?;
+        let (line, column) = match document.offset(&params["position"]) {
+            Some(offset) => offset,
+            None => return Ok(Json::Null),
+        };
+        let (definition, upvalue) = match document.analysis.definition_at(line, column) {
+            Some(found) => found,
+            None => return Ok(Json::Null),
+        };
+
+        let mut value = format!(
+            "```lua\n{}\n```\n{} `{}` declared on line {}",
+            document.line(definition.span.line).trim(),
+            definition.kind,
+            definition.name,
+            definition.span.line + 1,
+        );
+        if upvalue {
+            value.push_str(", used here as an upvalue");
+        }
+        let range = document
+            .analysis
+            .references
+            .iter()
+            .map(|reference| reference.span)
+            .chain(Some(definition.span))
+            .find(|span| span.line == line && span.start <= column && column <= span.end)
+            .map_or(Json::Null, |span| document.range(span));
+        Ok(json!({
+            "contents": { "kind": "markdown", "value": value },
+            "range": range,
+        }))
+    }
+}
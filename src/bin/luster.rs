@@ -1,15 +1,26 @@
 use std::error::Error as StdError;
-use std::fs::File;
+use std::fs::{self, File};
+use std::io::BufRead;
+use std::process;
 use std::vec::Vec;
 
-use clap::{crate_authors, crate_description, crate_name, crate_version, App, Arg};
+use clap::{crate_description, crate_name, crate_version, App, Arg};
 use rustyline::Editor;
 
 use gc_sequence::{self as sequence, SequenceExt, SequenceResultExt};
 use luster::{
-    compile, io, Closure, Error, Function, Lua, ParserError, StaticError, ThreadSequence,
+    compile, compile_named, io, is_binary_chunk, read_chunk, Closure, DebugEvent, Debugger, Error,
+    Function, HostError, Lua, ParserError, PauseReason, Resume, StaticError, ThreadSequence,
+    Variable,
 };
 
+// Exits the process if the given error was raised by `os.exit`.
+fn check_exit(error: &StaticError) {
+    if let StaticError::HostError(HostError::Exit(code)) = *error {
+        process::exit(code);
+    }
+}
+
 fn run_repl(lua: &mut Lua) {
     let mut editor = Editor::<()>::new();
 
@@ -82,6 +93,7 @@ fn run_repl(lua: &mut Lua) {
                     break;
                 }
                 Err(e) => {
+                    check_exit(&e);
                     editor.add_history_entry(line);
                     eprintln!("error: {}", e);
                     break;
@@ -91,18 +103,268 @@ fn run_repl(lua: &mut Lua) {
     }
 }
 
+const DEBUGGER_HELP: &str = "\
+break [FILE:]LINE     set a breakpoint, also b
+delete [N]            delete breakpoint N, or all breakpoints
+info breakpoints      list breakpoints
+run                   start the program, also r
+continue              continue running, also c
+step                  step into the next line, also s
+next                  step over to the next line in this function, also n
+finish                run until the current function returns
+backtrace             show the call stack, also bt
+frame [N]             select frame N, or show the selected frame, also f
+up, down              select the caller or callee of the selected frame
+locals                show the local variables of the selected frame
+upvalues              show the upvalues of the selected frame
+globals               show the global variables
+print EXPR            evaluate an expression in the selected frame, also p
+errors on|off         pause whenever an error is raised
+quit                  exit, also q
+An empty line repeats the previous command.";
+
+fn print_variables(variables: &[Variable]) {
+    if variables.is_empty() {
+        println!("No variables.");
+    }
+    for variable in variables {
+        println!("{} = {}", variable.name, variable.value);
+    }
+}
+
+// Prints the location of the given frame, and the source line it is on if it is in the debugged
+// file.
+fn print_frame(debugger: &Debugger, lua: &mut Lua, path: &str, lines: &[String], frame: usize) {
+    let frames = debugger.frames(lua);
+    let stack_frame = match frames.get(frame) {
+        Some(stack_frame) => stack_frame,
+        None => return println!("No frame #{}.", frame),
+    };
+    println!("#{}  {}", frame, stack_frame);
+    if let (Some(chunk_name), Some(line)) = (&stack_frame.chunk_name, stack_frame.line) {
+        if chunk_name == path {
+            if let Some(text) = lines.get(line - 1) {
+                println!("{}\t{}", line, text);
+            }
+        }
+    }
+}
+
+// Runs the given file under a gdb-like command prompt.
+fn run_debugger(lua: &mut Lua, path: &str) -> Result<(), Box<dyn StdError>> {
+    let source = fs::read(path)?;
+    let lines = if is_binary_chunk(&source) {
+        Vec::new()
+    } else {
+        String::from_utf8_lossy(&source)
+            .lines()
+            .map(String::from)
+            .collect()
+    };
+
+    let debugger = Debugger::new(lua);
+    let mut editor = Editor::<()>::new();
+    let mut frame = 0;
+    let mut previous = String::new();
+    println!("Debugging {}.  Type 'help' for a list of commands.", path);
+
+    loop {
+        let input = match editor.readline("(luster) ") {
+            Ok(input) => input,
+            Err(_) => return Ok(()),
+        };
+        let input = if input.trim().is_empty() {
+            previous.clone()
+        } else {
+            editor.add_history_entry(input.as_str());
+            input
+        };
+        previous = input.clone();
+        let input = input.trim();
+        let (command, argument) = match input.find(char::is_whitespace) {
+            Some(i) => (&input[..i], input[i..].trim()),
+            None => (input, ""),
+        };
+
+        let paused = debugger.paused().is_some();
+        let resume = match command {
+            "" => continue,
+            "run" | "r" if paused => {
+                println!("The program is already running.  Use 'continue' to resume it.");
+                continue;
+            }
+            "run" | "r" => {
+                if let Err(err) = debugger.load(lua, path, &source) {
+                    println!("{}", err);
+                    continue;
+                }
+                Resume::Continue
+            }
+            "continue" | "c" | "step" | "s" | "next" | "n" | "finish" if !paused => {
+                println!("The program is not being run.");
+                continue;
+            }
+            "continue" | "c" => Resume::Continue,
+            "step" | "s" => Resume::StepInto,
+            "next" | "n" => Resume::StepOver,
+            "finish" => Resume::StepOut,
+            "break" | "b" => {
+                let (source, line) = match argument.rfind(':') {
+                    Some(i) => (Some(&argument[..i]), &argument[i + 1..]),
+                    None => (None, argument),
+                };
+                match line.parse::<usize>() {
+                    Ok(line) => {
+                        let source = source.unwrap_or(path);
+                        let id = debugger.add_breakpoint(Some(source), line);
+                        println!("Breakpoint {} at {}:{}", id, source, line);
+                    }
+                    Err(_) => println!("Usage: break [FILE:]LINE"),
+                }
+                continue;
+            }
+            "delete" | "d" => {
+                if argument.is_empty() {
+                    for breakpoint in debugger.breakpoints() {
+                        debugger.remove_breakpoint(breakpoint.id);
+                    }
+                } else {
+                    match argument.parse() {
+                        Ok(id) if debugger.remove_breakpoint(id) => {}
+                        _ => println!("No breakpoint number {}.", argument),
+                    }
+                }
+                continue;
+            }
+            "info" | "i" if "breakpoints".starts_with(argument) && !argument.is_empty() => {
+                let breakpoints = debugger.breakpoints();
+                if breakpoints.is_empty() {
+                    println!("No breakpoints.");
+                }
+                for breakpoint in breakpoints {
+                    let source = breakpoint.source.as_deref().unwrap_or("*");
+                    println!("{}\t{}:{}", breakpoint.id, source, breakpoint.line);
+                }
+                continue;
+            }
+            "errors" => {
+                match argument {
+                    "on" => debugger.set_pause_on_error(true),
+                    "off" => debugger.set_pause_on_error(false),
+                    _ => println!("Usage: errors on|off"),
+                }
+                continue;
+            }
+            "help" | "h" => {
+                println!("{}", DEBUGGER_HELP);
+                continue;
+            }
+            "quit" | "q" => return Ok(()),
+            "backtrace" | "bt" | "where" | "frame" | "f" | "up" | "down" | "locals"
+            | "upvalues" | "print" | "p"
+                if !paused =>
+            {
+                println!("The program is not being run.");
+                continue;
+            }
+            "backtrace" | "bt" | "where" => {
+                for (i, stack_frame) in debugger.frames(lua).iter().enumerate() {
+                    let marker = if i == frame { "*" } else { " " };
+                    println!("{}#{}  {}", marker, i, stack_frame);
+                }
+                continue;
+            }
+            "frame" | "f" | "up" | "down" => {
+                let frames = debugger.frames(lua).len();
+                let selected = match command {
+                    "up" => frame.checked_add(1).filter(|&f| f < frames),
+                    "down" => frame.checked_sub(1),
+                    _ if argument.is_empty() => Some(frame),
+                    _ => argument.parse().ok().filter(|&f| f < frames),
+                };
+                match selected {
+                    Some(selected) => {
+                        frame = selected;
+                        print_frame(&debugger, lua, path, &lines, frame);
+                    }
+                    None => println!("No such frame."),
+                }
+                continue;
+            }
+            "locals" | "upvalues" => {
+                let variables = if command == "locals" {
+                    debugger.locals(lua, frame)
+                } else {
+                    debugger.upvalues(lua, frame)
+                };
+                match variables {
+                    Some(variables) => print_variables(&variables),
+                    None => println!("Frame #{} is not a Lua function.", frame),
+                }
+                continue;
+            }
+            "globals" => {
+                print_variables(&debugger.globals(lua));
+                continue;
+            }
+            "print" | "p" => {
+                match debugger.evaluate(lua, frame, argument) {
+                    Ok(values) => {
+                        let values = values.into_iter().map(|v| v.value).collect::<Vec<_>>();
+                        println!("{}", values.join("\t"));
+                    }
+                    Err(err) => println!("error: {}", err),
+                }
+                continue;
+            }
+            _ => {
+                println!("Undefined command: \"{}\".  Try \"help\".", command);
+                continue;
+            }
+        };
+
+        frame = 0;
+        match debugger.resume(lua, resume) {
+            DebugEvent::Paused(reason) => {
+                match reason {
+                    PauseReason::Breakpoint(id) => println!("Breakpoint {}", id),
+                    PauseReason::Step | PauseReason::Requested => {}
+                    PauseReason::Error(message) => println!("Error raised: {}", message),
+                }
+                print_frame(&debugger, lua, path, &lines, frame);
+            }
+            DebugEvent::Finished(Ok(())) => println!("The program finished."),
+            DebugEvent::Finished(Err(err)) => {
+                check_exit(&err);
+                println!("The program failed: {}", err);
+            }
+        }
+    }
+}
+
 fn main() -> Result<(), Box<dyn StdError>> {
     let matches = App::new(crate_name!())
         .version(crate_version!())
         .about(crate_description!())
-        .author(crate_authors!(", "))
+        .author(env!("CARGO_PKG_AUTHORS"))
         .arg(
             Arg::with_name("repl")
                 .short("r")
                 .long("repl")
                 .help("Load into REPL after loading file, if any"),
         )
-        .arg(Arg::with_name("file").help("File to interpret").index(1))
+        .arg(
+            Arg::with_name("debug")
+                .short("d")
+                .long("debug")
+                .requires("file")
+                .help("Run the file under an interactive debugger"),
+        )
+        .arg(
+            Arg::with_name("file")
+                .help("File to interpret, either source text or a binary chunk")
+                .index(1),
+        )
         .get_matches();
 
     let mut lua = Lua::new();
@@ -112,15 +374,23 @@ fn main() -> Result<(), Box<dyn StdError>> {
         return Ok(());
     }
 
-    let file = io::buffered_read(File::open(matches.value_of("file").unwrap())?)?;
+    let path = matches.value_of("file").unwrap();
+    if matches.is_present("debug") {
+        return run_debugger(&mut lua, path);
+    }
+
+    let mut file = io::buffered_read(File::open(path)?)?;
+    let binary = is_binary_chunk(file.fill_buf()?);
 
-    lua.sequence(|root| {
-        sequence::from_fn_with(root, |mc, root| {
-            Ok(Closure::new(
-                mc,
-                compile(mc, root.interned_strings, file)?,
-                Some(root.globals),
-            )?)
+    let chunk_name = path.to_owned();
+    let res = lua.sequence(|root| {
+        sequence::from_fn_with(root, move |mc, root| {
+            let proto = if binary {
+                read_chunk(mc, root.interned_strings, file)?
+            } else {
+                compile_named(mc, root.interned_strings, &chunk_name, file)?
+            };
+            Ok(Closure::new(mc, proto, Some(root.globals))?)
         })
         .and_chain_with(root, |mc, root, closure| {
             Ok(ThreadSequence::call_function(
@@ -133,7 +403,11 @@ fn main() -> Result<(), Box<dyn StdError>> {
         .map_ok(|_| ())
         .map_err(|e| e.to_static())
         .boxed()
-    })?;
+    });
+    if let Err(err) = &res {
+        check_exit(err);
+    }
+    res?;
 
     if matches.is_present("repl") {
         run_repl(&mut lua);
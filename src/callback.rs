@@ -1,19 +1,62 @@
+use std::cell::Cell;
 use std::fmt::{self, Debug};
 use std::hash::{Hash, Hasher};
+use std::ops::{Deref, DerefMut};
 
 use gc_arena::{Collect, Gc, MutationContext, StaticCollect};
 use gc_sequence::{Sequence, SequenceExt};
 
-use crate::{Error, Function, Value};
+use crate::{Error, Function, InternedStringSet, Table, Thread, Value};
+
+/// A buffer of values passed to callbacks and continuations.
+///
+/// On entry, the buffer holds the arguments to the callback (or the results passed to the
+/// continuation).  Return values are written into the same buffer, which is then handed back to
+/// the VM.  Only the VM can construct a `ValueBuffer`, and it re-uses the storage of buffers that
+/// are returned to it, so calling a callback does not normally allocate.
+#[derive(Debug, Collect)]
+#[collect(no_drop)]
+pub struct ValueBuffer<'gc>(Vec<Value<'gc>>);
+
+impl<'gc> ValueBuffer<'gc> {
+    pub(crate) fn new(values: Vec<Value<'gc>>) -> ValueBuffer<'gc> {
+        ValueBuffer(values)
+    }
+
+    pub(crate) fn into_vec(self) -> Vec<Value<'gc>> {
+        self.0
+    }
+
+    /// Replaces the contents of this buffer with the given values, and returns the buffer.
+    pub fn returning<I: IntoIterator<Item = Value<'gc>>>(mut self, values: I) -> ValueBuffer<'gc> {
+        self.0.clear();
+        self.0.extend(values);
+        self
+    }
+}
+
+impl<'gc> Deref for ValueBuffer<'gc> {
+    type Target = Vec<Value<'gc>>;
+
+    fn deref(&self) -> &Vec<Value<'gc>> {
+        &self.0
+    }
+}
+
+impl<'gc> DerefMut for ValueBuffer<'gc> {
+    fn deref_mut(&mut self) -> &mut Vec<Value<'gc>> {
+        &mut self.0
+    }
+}
 
 #[derive(Collect)]
 #[collect(no_drop)]
 pub enum CallbackResult<'gc> {
-    Return(Vec<Value<'gc>>),
-    Yield(Vec<Value<'gc>>),
+    Return(ValueBuffer<'gc>),
+    Yield(ValueBuffer<'gc>),
     TailCall {
         function: Function<'gc>,
-        args: Vec<Value<'gc>>,
+        args: ValueBuffer<'gc>,
         continuation: Continuation<'gc>,
     },
 }
@@ -24,9 +67,16 @@ pub enum CallbackReturn<'gc> {
 }
 
 pub trait ContinuationFn<'gc>: Collect {
-    fn call(self: Box<Self>, res: Result<Vec<Value<'gc>>, Error<'gc>>) -> CallbackReturn<'gc>;
+    fn call(
+        self: Box<Self>,
+        res: Result<ValueBuffer<'gc>, (Error<'gc>, ValueBuffer<'gc>)>,
+    ) -> CallbackReturn<'gc>;
 }
 
+/// A function to call with the results of a `CallbackResult::TailCall`.
+///
+/// Continuations receive the buffer holding the results of the called function, or the error it
+/// raised along with an empty buffer to write return values into.
 #[derive(Collect)]
 #[collect(no_drop)]
 pub struct Continuation<'gc>(Box<dyn ContinuationFn<'gc> + 'gc>);
@@ -34,7 +84,8 @@ pub struct Continuation<'gc>(Box<dyn ContinuationFn<'gc> + 'gc>);
 impl<'gc> Continuation<'gc> {
     pub fn new<F>(cont: F) -> Continuation<'gc>
     where
-        F: 'static + FnOnce(Result<Vec<Value<'gc>>, Error<'gc>>) -> CallbackReturn<'gc>,
+        F: 'static
+            + FnOnce(Result<ValueBuffer<'gc>, (Error<'gc>, ValueBuffer<'gc>)>) -> CallbackReturn<'gc>,
     {
         #[derive(Collect)]
         #[collect(require_static)]
@@ -42,11 +93,14 @@ impl<'gc> Continuation<'gc> {
 
         impl<'gc, F> ContinuationFn<'gc> for StaticContinuationFn<F>
         where
-            F: 'static + FnOnce(Result<Vec<Value<'gc>>, Error<'gc>>) -> CallbackReturn<'gc>,
+            F: 'static
+                + FnOnce(
+                    Result<ValueBuffer<'gc>, (Error<'gc>, ValueBuffer<'gc>)>,
+                ) -> CallbackReturn<'gc>,
         {
             fn call(
                 self: Box<Self>,
-                res: Result<Vec<Value<'gc>>, Error<'gc>>,
+                res: Result<ValueBuffer<'gc>, (Error<'gc>, ValueBuffer<'gc>)>,
             ) -> CallbackReturn<'gc> {
                 self.0(res)
             }
@@ -58,7 +112,11 @@ impl<'gc> Continuation<'gc> {
     pub fn new_with<C, F>(context: C, continuation: F) -> Continuation<'gc>
     where
         C: 'gc + Collect,
-        F: 'static + FnOnce(C, Result<Vec<Value<'gc>>, Error<'gc>>) -> CallbackReturn<'gc>,
+        F: 'static
+            + FnOnce(
+                C,
+                Result<ValueBuffer<'gc>, (Error<'gc>, ValueBuffer<'gc>)>,
+            ) -> CallbackReturn<'gc>,
     {
         #[derive(Collect)]
         #[collect(no_drop)]
@@ -67,11 +125,15 @@ impl<'gc> Continuation<'gc> {
         impl<'gc, C, F> ContinuationFn<'gc> for ContextContinuationFn<C, F>
         where
             C: 'gc + Collect,
-            F: 'static + FnOnce(C, Result<Vec<Value<'gc>>, Error<'gc>>) -> CallbackReturn<'gc>,
+            F: 'static
+                + FnOnce(
+                    C,
+                    Result<ValueBuffer<'gc>, (Error<'gc>, ValueBuffer<'gc>)>,
+                ) -> CallbackReturn<'gc>,
         {
             fn call(
                 self: Box<Self>,
-                res: Result<Vec<Value<'gc>>, Error<'gc>>,
+                res: Result<ValueBuffer<'gc>, (Error<'gc>, ValueBuffer<'gc>)>,
             ) -> CallbackReturn<'gc> {
                 (self.1).0(self.0, res)
             }
@@ -86,7 +148,9 @@ impl<'gc> Continuation<'gc> {
     pub fn new_immediate<F>(cont: F) -> Continuation<'gc>
     where
         F: 'static
-            + FnOnce(Result<Vec<Value<'gc>>, Error<'gc>>) -> Result<CallbackResult<'gc>, Error<'gc>>,
+            + FnOnce(
+                Result<ValueBuffer<'gc>, (Error<'gc>, ValueBuffer<'gc>)>,
+            ) -> Result<CallbackResult<'gc>, Error<'gc>>,
     {
         Continuation::new(move |res| CallbackReturn::Immediate(cont(res)))
     }
@@ -97,7 +161,7 @@ impl<'gc> Continuation<'gc> {
         F: 'static
             + FnOnce(
                 C,
-                Result<Vec<Value<'gc>>, Error<'gc>>,
+                Result<ValueBuffer<'gc>, (Error<'gc>, ValueBuffer<'gc>)>,
             ) -> Result<CallbackResult<'gc>, Error<'gc>>,
     {
         Continuation::new_with(context, move |context, res| {
@@ -108,7 +172,10 @@ impl<'gc> Continuation<'gc> {
     pub fn new_sequence<S, F>(cont: F) -> Continuation<'gc>
     where
         S: 'gc + Sequence<'gc, Output = Result<CallbackResult<'gc>, Error<'gc>>>,
-        F: 'static + FnOnce(Result<Vec<Value<'gc>>, Error<'gc>>) -> Result<S, Error<'gc>>,
+        F: 'static
+            + FnOnce(
+                Result<ValueBuffer<'gc>, (Error<'gc>, ValueBuffer<'gc>)>,
+            ) -> Result<S, Error<'gc>>,
     {
         Continuation::new(move |res| match cont(res) {
             Ok(seq) => CallbackReturn::Sequence(seq.boxed()),
@@ -120,7 +187,11 @@ impl<'gc> Continuation<'gc> {
     where
         C: 'gc + Collect,
         S: 'gc + Sequence<'gc, Output = Result<CallbackResult<'gc>, Error<'gc>>>,
-        F: 'static + FnOnce(C, Result<Vec<Value<'gc>>, Error<'gc>>) -> Result<S, Error<'gc>>,
+        F: 'static
+            + FnOnce(
+                C,
+                Result<ValueBuffer<'gc>, (Error<'gc>, ValueBuffer<'gc>)>,
+            ) -> Result<S, Error<'gc>>,
     {
         Continuation::new_with(context, move |context, res| {
             match continuation(context, res) {
@@ -130,23 +201,50 @@ impl<'gc> Continuation<'gc> {
         })
     }
 
-    pub fn call(self, res: Result<Vec<Value<'gc>>, Error<'gc>>) -> CallbackReturn<'gc> {
+    pub fn call(
+        self,
+        res: Result<ValueBuffer<'gc>, (Error<'gc>, ValueBuffer<'gc>)>,
+    ) -> CallbackReturn<'gc> {
         self.0.call(res)
     }
 }
 
+/// Information about a particular call to a callback, which is provided to the callback along
+/// with its arguments.
+#[derive(Clone, Copy, Collect)]
+#[collect(no_drop)]
+pub struct CallContext<'gc> {
+    /// The thread that is calling the callback.
+    ///
+    /// This thread is always `Running` during the call.  Its state is locked while the callback
+    /// itself runs, so it must not be resumed or stepped from inside the callback.
+    pub thread: Thread<'gc>,
+    /// The globals table of the calling thread.
+    pub globals: Table<'gc>,
+    pub interned_strings: InternedStringSet<'gc>,
+    /// The name of the callback being called, if it has one.
+    pub name: Option<&'static str>,
+}
+
 pub trait CallbackFn<'gc>: Collect {
-    fn call(&self, res: Vec<Value<'gc>>) -> CallbackReturn<'gc>;
+    fn call(&self, ctx: CallContext<'gc>, args: ValueBuffer<'gc>) -> CallbackReturn<'gc>;
 }
 
 #[derive(Clone, Copy, Collect)]
 #[collect(no_drop)]
-pub struct Callback<'gc>(pub Gc<'gc, Box<dyn CallbackFn<'gc> + 'gc>>);
+pub struct Callback<'gc>(pub Gc<'gc, CallbackState<'gc>>);
+
+#[derive(Collect)]
+#[collect(no_drop)]
+pub struct CallbackState<'gc> {
+    name: Cell<Option<&'static str>>,
+    function: Box<dyn CallbackFn<'gc> + 'gc>,
+}
 
 impl<'gc> Callback<'gc> {
     pub fn new<F>(mc: MutationContext<'gc, '_>, f: F) -> Callback<'gc>
     where
-        F: 'static + Fn(Vec<Value<'gc>>) -> CallbackReturn<'gc>,
+        F: 'static + Fn(CallContext<'gc>, ValueBuffer<'gc>) -> CallbackReturn<'gc>,
     {
         #[derive(Collect)]
         #[collect(require_static)]
@@ -154,20 +252,20 @@ impl<'gc> Callback<'gc> {
 
         impl<'gc, F> CallbackFn<'gc> for StaticCallbackFn<F>
         where
-            F: 'static + Fn(Vec<Value<'gc>>) -> CallbackReturn<'gc>,
+            F: 'static + Fn(CallContext<'gc>, ValueBuffer<'gc>) -> CallbackReturn<'gc>,
         {
-            fn call(&self, res: Vec<Value<'gc>>) -> CallbackReturn<'gc> {
-                self.0(res)
+            fn call(&self, ctx: CallContext<'gc>, args: ValueBuffer<'gc>) -> CallbackReturn<'gc> {
+                self.0(ctx, args)
             }
         }
 
-        Callback(Gc::allocate(mc, Box::new(StaticCallbackFn(f))))
+        Callback::from_fn(mc, Box::new(StaticCallbackFn(f)))
     }
 
     pub fn new_with<C, F>(mc: MutationContext<'gc, '_>, c: C, f: F) -> Callback<'gc>
     where
         C: 'gc + Collect,
-        F: 'static + Fn(&C, Vec<Value<'gc>>) -> CallbackReturn<'gc>,
+        F: 'static + Fn(&C, CallContext<'gc>, ValueBuffer<'gc>) -> CallbackReturn<'gc>,
     {
         #[derive(Collect)]
         #[collect(no_drop)]
@@ -176,40 +274,41 @@ impl<'gc> Callback<'gc> {
         impl<'gc, C, F> CallbackFn<'gc> for ContextCallbackFn<C, F>
         where
             C: 'gc + Collect,
-            F: 'static + Fn(&C, Vec<Value<'gc>>) -> CallbackReturn<'gc>,
+            F: 'static + Fn(&C, CallContext<'gc>, ValueBuffer<'gc>) -> CallbackReturn<'gc>,
         {
-            fn call(&self, args: Vec<Value<'gc>>) -> CallbackReturn<'gc> {
-                (self.1).0(&self.0, args)
+            fn call(&self, ctx: CallContext<'gc>, args: ValueBuffer<'gc>) -> CallbackReturn<'gc> {
+                (self.1).0(&self.0, ctx, args)
             }
         }
 
-        Callback(Gc::allocate(
-            mc,
-            Box::new(ContextCallbackFn(c, StaticCollect(f))),
-        ))
+        Callback::from_fn(mc, Box::new(ContextCallbackFn(c, StaticCollect(f))))
     }
 
     pub fn new_immediate<F>(mc: MutationContext<'gc, '_>, f: F) -> Callback<'gc>
     where
-        F: 'static + Fn(Vec<Value<'gc>>) -> Result<CallbackResult<'gc>, Error<'gc>>,
+        F: 'static
+            + Fn(CallContext<'gc>, ValueBuffer<'gc>) -> Result<CallbackResult<'gc>, Error<'gc>>,
     {
-        Callback::new(mc, move |res| CallbackReturn::Immediate(f(res)))
+        Callback::new(mc, move |ctx, args| CallbackReturn::Immediate(f(ctx, args)))
     }
 
     pub fn new_immediate_with<C, F>(mc: MutationContext<'gc, '_>, c: C, f: F) -> Callback<'gc>
     where
         C: 'gc + Collect,
-        F: 'static + Fn(&C, Vec<Value<'gc>>) -> Result<CallbackResult<'gc>, Error<'gc>>,
+        F: 'static
+            + Fn(&C, CallContext<'gc>, ValueBuffer<'gc>) -> Result<CallbackResult<'gc>, Error<'gc>>,
     {
-        Callback::new_with(mc, c, move |c, res| CallbackReturn::Immediate(f(c, res)))
+        Callback::new_with(mc, c, move |c, ctx, args| {
+            CallbackReturn::Immediate(f(c, ctx, args))
+        })
     }
 
     pub fn new_sequence<S, F>(mc: MutationContext<'gc, '_>, f: F) -> Callback<'gc>
     where
         S: 'gc + Sequence<'gc, Output = Result<CallbackResult<'gc>, Error<'gc>>>,
-        F: 'static + Fn(Vec<Value<'gc>>) -> Result<S, Error<'gc>>,
+        F: 'static + Fn(CallContext<'gc>, ValueBuffer<'gc>) -> Result<S, Error<'gc>>,
     {
-        Callback::new(mc, move |res| match f(res) {
+        Callback::new(mc, move |ctx, args| match f(ctx, args) {
             Ok(seq) => CallbackReturn::Sequence(seq.boxed()),
             Err(err) => CallbackReturn::Immediate(Err(err)),
         })
@@ -219,16 +318,40 @@ impl<'gc> Callback<'gc> {
     where
         C: 'gc + Collect,
         S: 'gc + Sequence<'gc, Output = Result<CallbackResult<'gc>, Error<'gc>>>,
-        F: 'static + Fn(&C, Vec<Value<'gc>>) -> Result<S, Error<'gc>>,
+        F: 'static + Fn(&C, CallContext<'gc>, ValueBuffer<'gc>) -> Result<S, Error<'gc>>,
     {
-        Callback::new_with(mc, c, move |c, res| match f(c, res) {
+        Callback::new_with(mc, c, move |c, ctx, args| match f(c, ctx, args) {
             Ok(seq) => CallbackReturn::Sequence(seq.boxed()),
             Err(err) => CallbackReturn::Immediate(Err(err)),
         })
     }
 
-    pub fn call(&self, args: Vec<Value<'gc>>) -> CallbackReturn<'gc> {
-        self.0.call(args)
+    fn from_fn(
+        mc: MutationContext<'gc, '_>,
+        function: Box<dyn CallbackFn<'gc> + 'gc>,
+    ) -> Callback<'gc> {
+        Callback(Gc::allocate(
+            mc,
+            CallbackState {
+                name: Cell::new(None),
+                function,
+            },
+        ))
+    }
+
+    /// Sets the name that is reported to this callback in its `CallContext`, and returns the
+    /// callback.
+    pub fn named(self, name: &'static str) -> Callback<'gc> {
+        self.0.name.set(Some(name));
+        self
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        self.0.name.get()
+    }
+
+    pub fn call(&self, ctx: CallContext<'gc>, args: ValueBuffer<'gc>) -> CallbackReturn<'gc> {
+        self.0.function.call(ctx, args)
     }
 }
 
@@ -0,0 +1,534 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use gc_arena::{Collect, Gc, MutationContext};
+
+use crate::{
+    verify, Constant, ConstantIndex16, ConstantIndex8, FunctionProto, InternedStringSet,
+    LineNumber, LocalVariable, OpCode, Opt254, PrototypeIndex, RegisterIndex, String,
+    UpValueDescriptor, UpValueIndex, VarCount, VerifierError,
+};
+
+/// Every binary chunk starts with this signature.  The leading escape byte can never start a valid
+/// text chunk, so it is enough to tell the two apart.
+pub const CHUNK_SIGNATURE: &[u8] = b"\x1bLuster";
+
+/// The version of the binary chunk format, chunks with any other version are rejected.
+pub const CHUNK_VERSION: u8 = 4;
+
+// Set in the header flags if each prototype is followed by a debug info section.
+const FLAG_DEBUG_INFO: u8 = 1;
+
+// Chunks nested deeper than this are rejected rather than risk overflowing the stack while reading
+// or verifying them.
+const MAX_NESTING: usize = 200;
+
+#[derive(Debug, Collect)]
+#[collect(require_static)]
+pub enum ChunkError {
+    IoError(io::Error),
+    Signature,
+    Version(u8),
+    Truncated,
+    Malformed(&'static str),
+    Invalid(VerifierError),
+}
+
+impl StdError for ChunkError {}
+
+impl fmt::Display for ChunkError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChunkError::IoError(error) => write!(fmt, "i/o error: {}", error),
+            ChunkError::Signature => write!(fmt, "not a binary chunk"),
+            ChunkError::Version(version) => write!(
+                fmt,
+                "binary chunk version mismatch, found {} but expected {}",
+                version, CHUNK_VERSION
+            ),
+            ChunkError::Truncated => write!(fmt, "truncated binary chunk"),
+            ChunkError::Malformed(what) => write!(fmt, "malformed binary chunk: {}", what),
+            ChunkError::Invalid(error) => write!(fmt, "invalid binary chunk: {}", error),
+        }
+    }
+}
+
+impl From<io::Error> for ChunkError {
+    fn from(error: io::Error) -> ChunkError {
+        if error.kind() == io::ErrorKind::UnexpectedEof {
+            ChunkError::Truncated
+        } else {
+            ChunkError::IoError(error)
+        }
+    }
+}
+
+/// Returns true if the given data looks like a binary chunk rather than source text.
+pub fn is_binary_chunk(data: &[u8]) -> bool {
+    data.first() == CHUNK_SIGNATURE.first()
+}
+
+/// Serializes a `FunctionProto` tree as a binary chunk.
+///
+/// All values are stored little-endian.  The chunk is the signature, a version byte and a flags
+/// byte, followed by the prototype tree.  Each prototype is its parameter info and stack size, then
+/// its constants, opcodes, upvalue descriptors and nested prototypes, each as a 32 bit count
+/// followed by that many entries.  Unless `strip` is set, every prototype ends with a length
+/// prefixed debug info section holding its chunk name, the line it was defined on, its line number
+/// table, and its local variable and upvalue names.
+pub fn write_chunk<'gc, W: Write>(
+    mut writer: W,
+    proto: &FunctionProto<'gc>,
+    strip: bool,
+) -> Result<(), io::Error> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(CHUNK_SIGNATURE);
+    buf.push(CHUNK_VERSION);
+    buf.push(if strip { 0 } else { FLAG_DEBUG_INFO });
+    write_proto(&mut buf, proto, strip);
+    writer.write_all(&buf)
+}
+
+/// Deserializes a binary chunk written by `write_chunk`.
+///
+/// The whole of the reader must be a single chunk.  The resulting prototype tree is checked with
+/// `verify`, so corrupted or hand-crafted chunks are rejected here rather than misbehaving when run.
+pub fn read_chunk<'gc, R: Read>(
+    mc: MutationContext<'gc, '_>,
+    interned_strings: InternedStringSet<'gc>,
+    reader: R,
+) -> Result<FunctionProto<'gc>, ChunkError> {
+    let mut reader = ChunkReader {
+        mc,
+        interned_strings,
+        reader,
+    };
+
+    let mut signature = [0; CHUNK_SIGNATURE.len()];
+    match reader.reader.read_exact(&mut signature) {
+        Ok(()) if signature == CHUNK_SIGNATURE => {}
+        Ok(()) => return Err(ChunkError::Signature),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+            return Err(ChunkError::Signature)
+        }
+        Err(err) => return Err(err.into()),
+    }
+
+    let version = reader.u8()?;
+    if version != CHUNK_VERSION {
+        return Err(ChunkError::Version(version));
+    }
+
+    let flags = reader.u8()?;
+    if flags & !FLAG_DEBUG_INFO != 0 {
+        return Err(ChunkError::Malformed("unknown header flags"));
+    }
+
+    let proto = reader.proto(flags & FLAG_DEBUG_INFO != 0, 0)?;
+    if reader.reader.read(&mut [0])? != 0 {
+        return Err(ChunkError::Malformed("trailing data after chunk"));
+    }
+
+    verify(&proto).map_err(ChunkError::Invalid)?;
+    Ok(proto)
+}
+
+fn write_proto<'gc>(buf: &mut Vec<u8>, proto: &FunctionProto<'gc>, strip: bool) {
+    buf.push(proto.fixed_params);
+    proto.has_varargs.write(buf);
+    buf.extend_from_slice(&proto.stack_size.to_le_bytes());
+
+    write_count(buf, proto.constants.len());
+    for &constant in &proto.constants {
+        match constant {
+            Constant::Nil => buf.push(0),
+            Constant::Boolean(b) => {
+                buf.push(1);
+                b.write(buf);
+            }
+            Constant::Integer(i) => {
+                buf.push(2);
+                buf.extend_from_slice(&i.to_le_bytes());
+            }
+            Constant::Number(n) => {
+                buf.push(3);
+                buf.extend_from_slice(&n.to_bits().to_le_bytes());
+            }
+            Constant::String(s) => {
+                buf.push(4);
+                write_string(buf, s.as_bytes());
+            }
+        }
+    }
+
+    write_count(buf, proto.opcodes.len());
+    for &opcode in &proto.opcodes {
+        write_opcode(buf, opcode);
+    }
+
+    write_count(buf, proto.upvalues.len());
+    for &upvalue in &proto.upvalues {
+        match upvalue {
+            UpValueDescriptor::Environment => buf.push(0),
+            UpValueDescriptor::ParentLocal(r) => {
+                buf.push(1);
+                r.write(buf);
+            }
+            UpValueDescriptor::Outer(u) => {
+                buf.push(2);
+                u.write(buf);
+            }
+        }
+    }
+
+    write_count(buf, proto.prototypes.len());
+    for p in &proto.prototypes {
+        write_proto(buf, p, strip);
+    }
+
+    if !strip {
+        let mut debug_info = Vec::new();
+        write_string(
+            &mut debug_info,
+            proto
+                .chunk_name
+                .as_ref()
+                .map_or(&b""[..], |name| name.as_bytes()),
+        );
+        proto.line_defined.is_some().write(&mut debug_info);
+        if let Some(line_defined) = proto.line_defined {
+            debug_info.extend_from_slice(&line_defined.0.to_le_bytes());
+        }
+        write_count(&mut debug_info, proto.opcode_lines.len());
+        for &(pc, line_number) in &proto.opcode_lines {
+            write_count(&mut debug_info, pc);
+            debug_info.extend_from_slice(&line_number.0.to_le_bytes());
+        }
+        write_count(&mut debug_info, proto.locals.len());
+        for local in &proto.locals {
+            write_string(&mut debug_info, local.name.as_bytes());
+            local.register.write(&mut debug_info);
+            write_count(&mut debug_info, local.start_pc);
+            write_count(&mut debug_info, local.end_pc);
+        }
+        write_count(&mut debug_info, proto.upvalue_names.len());
+        for name in &proto.upvalue_names {
+            write_string(&mut debug_info, name.as_bytes());
+        }
+        write_count(buf, debug_info.len());
+        buf.extend_from_slice(&debug_info);
+    }
+}
+
+fn write_string(buf: &mut Vec<u8>, s: &[u8]) {
+    write_count(buf, s.len());
+    buf.extend_from_slice(s);
+}
+
+fn write_count(buf: &mut Vec<u8>, count: usize) {
+    assert!(count <= u32::MAX as usize, "binary chunk count overflow");
+    buf.extend_from_slice(&(count as u32).to_le_bytes());
+}
+
+struct ChunkReader<'gc, 'a, R> {
+    mc: MutationContext<'gc, 'a>,
+    interned_strings: InternedStringSet<'gc>,
+    reader: R,
+}
+
+impl<'gc, 'a, R: Read> ChunkReader<'gc, 'a, R> {
+    fn proto(&mut self, debug_info: bool, depth: usize) -> Result<FunctionProto<'gc>, ChunkError> {
+        if depth > MAX_NESTING {
+            return Err(ChunkError::Malformed("prototypes nested too deeply"));
+        }
+
+        let fixed_params = self.u8()?;
+        let has_varargs = bool::read(self)?;
+        let stack_size = u16::from_le_bytes(self.bytes()?);
+
+        // Counts come from untrusted data, so vectors are grown as entries are actually read rather
+        // than allocated up front.
+        let mut constants = Vec::new();
+        for _ in 0..self.count()? {
+            constants.push(match self.u8()? {
+                0 => Constant::Nil,
+                1 => Constant::Boolean(bool::read(self)?),
+                2 => Constant::Integer(i64::from_le_bytes(self.bytes()?)),
+                3 => Constant::Number(f64::from_bits(u64::from_le_bytes(self.bytes()?))),
+                4 => Constant::String(self.string()?),
+                _ => return Err(ChunkError::Malformed("invalid constant tag")),
+            });
+        }
+
+        let mut opcodes = Vec::new();
+        for _ in 0..self.count()? {
+            opcodes.push(read_opcode(self)?);
+        }
+
+        let mut upvalues = Vec::new();
+        for _ in 0..self.count()? {
+            upvalues.push(match self.u8()? {
+                0 => UpValueDescriptor::Environment,
+                1 => UpValueDescriptor::ParentLocal(RegisterIndex::read(self)?),
+                2 => UpValueDescriptor::Outer(UpValueIndex::read(self)?),
+                _ => return Err(ChunkError::Malformed("invalid upvalue descriptor")),
+            });
+        }
+
+        let mut prototypes = Vec::new();
+        for _ in 0..self.count()? {
+            let proto = self.proto(debug_info, depth + 1)?;
+            prototypes.push(Gc::allocate(self.mc, proto));
+        }
+
+        let mut proto = FunctionProto {
+            chunk_name: None,
+            line_defined: None,
+            fixed_params,
+            has_varargs,
+            stack_size,
+            constants,
+            opcodes,
+            opcode_lines: Vec::new(),
+            locals: Vec::new(),
+            upvalues,
+            upvalue_names: Vec::new(),
+            prototypes,
+        };
+        if debug_info {
+            self.debug_info(&mut proto)?;
+        }
+        Ok(proto)
+    }
+
+    // Reads a length prefixed debug info section into the given prototype.  The section must be
+    // entirely consumed.
+    fn debug_info(&mut self, proto: &mut FunctionProto<'gc>) -> Result<(), ChunkError> {
+        let opcode_count = proto.opcodes.len();
+        let len = self.count()?;
+        let mut section = Vec::new();
+        (&mut self.reader)
+            .take(len as u64)
+            .read_to_end(&mut section)?;
+        if section.len() != len {
+            return Err(ChunkError::Truncated);
+        }
+
+        let mut section = ChunkReader {
+            mc: self.mc,
+            interned_strings: self.interned_strings,
+            reader: &section[..],
+        };
+        let malformed = |err| match err {
+            ChunkError::Truncated => ChunkError::Malformed("invalid debug info"),
+            err => err,
+        };
+
+        // An empty chunk name means that the name was not known.
+        let chunk_name = section.string().map_err(malformed)?;
+        if !chunk_name.as_bytes().is_empty() {
+            proto.chunk_name = Some(chunk_name);
+        }
+        if bool::read(&mut section).map_err(malformed)? {
+            proto.line_defined = Some(LineNumber(u64::from_le_bytes(
+                section.bytes().map_err(malformed)?,
+            )));
+        }
+
+        let opcode_lines = &mut proto.opcode_lines;
+        for _ in 0..section.count().map_err(malformed)? {
+            let pc = section.count().map_err(malformed)?;
+            let line_number = LineNumber(u64::from_le_bytes(section.bytes().map_err(malformed)?));
+            if pc >= opcode_count || opcode_lines.last().is_some_and(|&(last, _)| pc <= last) {
+                return Err(ChunkError::Malformed("invalid debug info"));
+            }
+            opcode_lines.push((pc, line_number));
+        }
+
+        for _ in 0..section.count().map_err(malformed)? {
+            let name = section.string().map_err(malformed)?;
+            let register = RegisterIndex::read(&mut section).map_err(malformed)?;
+            let start_pc = section.count().map_err(malformed)?;
+            let end_pc = section.count().map_err(malformed)?;
+            if start_pc > end_pc || end_pc > opcode_count {
+                return Err(ChunkError::Malformed("invalid debug info"));
+            }
+            proto.locals.push(LocalVariable {
+                name,
+                register,
+                start_pc,
+                end_pc,
+            });
+        }
+
+        // Upvalue names are either all present or all missing.
+        let upvalue_name_count = section.count().map_err(malformed)?;
+        if upvalue_name_count != 0 && upvalue_name_count != proto.upvalues.len() {
+            return Err(ChunkError::Malformed("invalid debug info"));
+        }
+        for _ in 0..upvalue_name_count {
+            proto
+                .upvalue_names
+                .push(section.string().map_err(malformed)?);
+        }
+
+        if !section.reader.is_empty() {
+            return Err(ChunkError::Malformed("invalid debug info"));
+        }
+        Ok(())
+    }
+
+    // Reads a length prefixed string.
+    fn string(&mut self) -> Result<String<'gc>, ChunkError> {
+        let len = self.count()?;
+        let mut s = Vec::new();
+        (&mut self.reader).take(len as u64).read_to_end(&mut s)?;
+        if s.len() != len {
+            return Err(ChunkError::Truncated);
+        }
+        Ok(self.interned_strings.new_string(self.mc, &s))
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], ChunkError> {
+        let mut bytes = [0; N];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, ChunkError> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn count(&mut self) -> Result<usize, ChunkError> {
+        Ok(u32::from_le_bytes(self.bytes()?) as usize)
+    }
+}
+
+// An opcode operand, which is written as its in-memory representation.
+trait Operand: Sized {
+    fn write(self, buf: &mut Vec<u8>);
+    fn read<'gc, 'a, R: Read>(reader: &mut ChunkReader<'gc, 'a, R>) -> Result<Self, ChunkError>;
+}
+
+impl Operand for u8 {
+    fn write(self, buf: &mut Vec<u8>) {
+        buf.push(self);
+    }
+
+    fn read<'gc, 'a, R: Read>(reader: &mut ChunkReader<'gc, 'a, R>) -> Result<u8, ChunkError> {
+        reader.u8()
+    }
+}
+
+impl Operand for i16 {
+    fn write(self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn read<'gc, 'a, R: Read>(reader: &mut ChunkReader<'gc, 'a, R>) -> Result<i16, ChunkError> {
+        Ok(i16::from_le_bytes(reader.bytes()?))
+    }
+}
+
+impl Operand for bool {
+    fn write(self, buf: &mut Vec<u8>) {
+        buf.push(self as u8);
+    }
+
+    fn read<'gc, 'a, R: Read>(reader: &mut ChunkReader<'gc, 'a, R>) -> Result<bool, ChunkError> {
+        match reader.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(ChunkError::Malformed("invalid boolean")),
+        }
+    }
+}
+
+impl Operand for Opt254 {
+    fn write(self, buf: &mut Vec<u8>) {
+        buf.push(self.to_u8().unwrap_or(255));
+    }
+
+    fn read<'gc, 'a, R: Read>(reader: &mut ChunkReader<'gc, 'a, R>) -> Result<Opt254, ChunkError> {
+        Ok(match reader.u8()? {
+            255 => Opt254::none(),
+            v => Opt254::some(v),
+        })
+    }
+}
+
+impl Operand for VarCount {
+    fn write(self, buf: &mut Vec<u8>) {
+        buf.push(self.to_constant().unwrap_or(255));
+    }
+
+    fn read<'gc, 'a, R: Read>(
+        reader: &mut ChunkReader<'gc, 'a, R>,
+    ) -> Result<VarCount, ChunkError> {
+        Ok(match reader.u8()? {
+            255 => VarCount::variable(),
+            v => VarCount::constant(v),
+        })
+    }
+}
+
+macro_rules! index_operand {
+    ($($index:ident),*) => {
+        $(
+            impl Operand for $index {
+                fn write(self, buf: &mut Vec<u8>) {
+                    self.0.write(buf);
+                }
+
+                fn read<'gc, 'a, R: Read>(
+                    reader: &mut ChunkReader<'gc, 'a, R>,
+                ) -> Result<$index, ChunkError> {
+                    Ok($index(Operand::read(reader)?))
+                }
+            }
+        )*
+    };
+}
+
+index_operand!(RegisterIndex, ConstantIndex8, UpValueIndex, PrototypeIndex);
+
+impl Operand for ConstantIndex16 {
+    fn write(self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.0.to_le_bytes());
+    }
+
+    fn read<'gc, 'a, R: Read>(
+        reader: &mut ChunkReader<'gc, 'a, R>,
+    ) -> Result<ConstantIndex16, ChunkError> {
+        Ok(ConstantIndex16(u16::from_le_bytes(reader.bytes()?)))
+    }
+}
+
+// Generates `write_opcode` and `read_opcode` from `opcode_table`, writing each opcode as its tag
+// followed by its operands.
+macro_rules! opcode_tags {
+    ($($tag:literal => $name:ident { $($field:ident),* },)*) => {
+        fn write_opcode(buf: &mut Vec<u8>, opcode: OpCode) {
+            match opcode {
+                $(
+                    OpCode::$name { $($field),* } => {
+                        buf.push($tag);
+                        $($field.write(buf);)*
+                    }
+                )*
+            }
+        }
+
+        fn read_opcode<'gc, 'a, R: Read>(
+            reader: &mut ChunkReader<'gc, 'a, R>,
+        ) -> Result<OpCode, ChunkError> {
+            match reader.u8()? {
+                $($tag => Ok(OpCode::$name { $($field: Operand::read(reader)?),* }),)*
+                _ => Err(ChunkError::Malformed("invalid opcode")),
+            }
+        }
+    };
+}
+
+opcode_table!(opcode_tags);
@@ -4,7 +4,10 @@ use std::hash::{Hash, Hasher};
 
 use gc_arena::{Collect, Gc, GcCell, MutationContext};
 
-use crate::{Constant, OpCode, RegisterIndex, Table, Thread, UpValueIndex, Value};
+use crate::{
+    verify, Constant, ConstantIndex16, LineNumber, OpCode, RegisterIndex, String, Table, Thread,
+    UpValueIndex, Value, VerifierError,
+};
 
 #[derive(Debug, Collect, Clone, Copy, PartialEq, Eq)]
 #[collect(require_static)]
@@ -14,18 +17,226 @@ pub enum UpValueDescriptor {
     Outer(UpValueIndex),
 }
 
-#[derive(Debug, Collect)]
+/// The name of a local variable, and the range of instructions over which it is in scope.
+#[derive(Debug, Clone, Copy, Collect)]
+#[collect(no_drop)]
+pub struct LocalVariable<'gc> {
+    pub name: String<'gc>,
+    pub register: RegisterIndex,
+    /// The index of the first instruction where the variable is in scope.
+    pub start_pc: usize,
+    /// The index of the instruction after the last one where the variable is in scope.
+    pub end_pc: usize,
+}
+
+#[derive(Debug, Default, Collect)]
 #[collect(no_drop)]
 pub struct FunctionProto<'gc> {
+    /// The name of the chunk this function was compiled from, such as a file name, if known.
+    pub chunk_name: Option<String<'gc>>,
+    /// The line the function's definition starts on, or `None` for a main chunk or if it is not
+    /// known.
+    pub line_defined: Option<LineNumber>,
     pub fixed_params: u8,
     pub has_varargs: bool,
     pub stack_size: u16,
     pub constants: Vec<Constant<'gc>>,
     pub opcodes: Vec<OpCode>,
+    /// Line number information, may be empty if it is not available.  Each entry gives the line
+    /// that the opcodes from its index up to the index of the next entry were compiled from.
+    pub opcode_lines: Vec<(usize, LineNumber)>,
+    /// Local variable names, may be empty if they are not available.  Entries are in the order the
+    /// variables were declared.
+    pub locals: Vec<LocalVariable<'gc>>,
     pub upvalues: Vec<UpValueDescriptor>,
+    /// The names of the upvalues, may be empty if they are not available.
+    pub upvalue_names: Vec<String<'gc>>,
     pub prototypes: Vec<Gc<'gc, FunctionProto<'gc>>>,
 }
 
+impl<'gc> FunctionProto<'gc> {
+    /// Returns the line that the opcode at the given index was compiled from, if known.
+    pub fn line_number(&self, pc: usize) -> Option<LineNumber> {
+        match self.opcode_lines.binary_search_by_key(&pc, |&(i, _)| i) {
+            Ok(i) => Some(self.opcode_lines[i].1),
+            Err(0) => None,
+            Err(i) => Some(self.opcode_lines[i - 1].1),
+        }
+    }
+
+    /// Returns the local variables in scope at the given instruction, in the order they were
+    /// declared.
+    pub fn active_locals(&self, pc: usize) -> impl Iterator<Item = &LocalVariable<'gc>> {
+        self.locals
+            .iter()
+            .filter(move |local| local.start_pc <= pc && pc < local.end_pc)
+    }
+
+    /// Describes the value held in the given register just before the instruction at `pc`
+    /// executes.  Returns the kind of variable the value came from, such as "local", "global",
+    /// "field" or "method", along with its name.
+    pub fn register_name(
+        &self,
+        pc: usize,
+        register: RegisterIndex,
+    ) -> Option<(&'static str, String<'gc>)> {
+        if let Some(local) = self
+            .active_locals(pc)
+            .filter(|local| local.register == register)
+            .last()
+        {
+            return Some(("local", local.name));
+        }
+
+        let set_pc = self.find_set_register(pc, register)?;
+        match self.opcodes[set_pc] {
+            OpCode::Move { dest, source } if source.0 < dest.0 => {
+                self.register_name(set_pc, source)
+            }
+            OpCode::GetTableC { table, key, .. } => {
+                let kind = match self.register_name(set_pc, table) {
+                    Some(("local", name)) if name == b"_ENV" => "global",
+                    _ => "field",
+                };
+                Some((kind, self.string_constant(key.0 as usize)?))
+            }
+            OpCode::GetUpTableC { table, key, .. } => {
+                let kind = match self.upvalue_name(table) {
+                    Some(name) if name == b"_ENV" => "global",
+                    _ => "field",
+                };
+                Some((kind, self.string_constant(key.0 as usize)?))
+            }
+            OpCode::GetUpValue { source, .. } => Some(("upvalue", self.upvalue_name(source)?)),
+            OpCode::SelfC { key, .. } => Some(("method", self.string_constant(key.0 as usize)?)),
+            OpCode::LoadConstant {
+                constant: ConstantIndex16(constant),
+                ..
+            } => Some(("constant", self.string_constant(constant as usize)?)),
+            _ => None,
+        }
+    }
+
+    // Finds the last instruction before `last_pc` which sets the given register, like PUC-Rio Lua's
+    // `findsetreg`.  Instructions skipped over by a forward jump may not have executed, so if one of
+    // them is the last to set the register, the instruction is not known.
+    fn find_set_register(&self, last_pc: usize, register: RegisterIndex) -> Option<usize> {
+        let mut set_pc = None;
+        let mut jump_target = 0;
+        for (pc, &op) in self.opcodes[..last_pc.min(self.opcodes.len())]
+            .iter()
+            .enumerate()
+        {
+            if sets_register(op, register) {
+                set_pc = if pc < jump_target { None } else { Some(pc) };
+            }
+            if let OpCode::Jump { offset, .. } = op {
+                let target = pc as isize + 1 + offset as isize;
+                if (pc as isize) < target && target <= last_pc as isize {
+                    jump_target = jump_target.max(target as usize);
+                }
+            }
+        }
+        set_pc
+    }
+
+    /// Returns the name of the given upvalue, if known.
+    pub fn upvalue_name(&self, upvalue: UpValueIndex) -> Option<String<'gc>> {
+        self.upvalue_names.get(upvalue.0 as usize).copied()
+    }
+
+    fn string_constant(&self, index: usize) -> Option<String<'gc>> {
+        match self.constants.get(index) {
+            Some(&Constant::String(s)) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+// Whether the given instruction may write to the given register.
+fn sets_register(op: OpCode, register: RegisterIndex) -> bool {
+    let r = register.0;
+    match op {
+        OpCode::LoadNil { dest, count } => {
+            dest.0 <= r && (r as usize) < dest.0 as usize + count as usize
+        }
+        OpCode::Call { func, .. } | OpCode::TailCall { func, .. } => r >= func.0,
+        OpCode::VarArgs { dest, .. } => r >= dest.0,
+        OpCode::NumericForPrep { base, .. }
+        | OpCode::NumericForLoop { base, .. }
+        | OpCode::GenericForLoop { base, .. } => base.0 <= r && r <= base.0.saturating_add(3),
+        OpCode::GenericForCall { base, .. } => r >= base.0,
+        OpCode::SelfR { base, .. } | OpCode::SelfC { base, .. } => {
+            r == base.0 || r == base.0.wrapping_add(1)
+        }
+        OpCode::Move { dest, .. }
+        | OpCode::LoadConstant { dest, .. }
+        | OpCode::LoadBool { dest, .. }
+        | OpCode::NewTable { dest, .. }
+        | OpCode::GetTableR { dest, .. }
+        | OpCode::GetTableC { dest, .. }
+        | OpCode::GetUpTableR { dest, .. }
+        | OpCode::GetUpTableC { dest, .. }
+        | OpCode::TestSet { dest, .. }
+        | OpCode::Closure { dest, .. }
+        | OpCode::Concat { dest, .. }
+        | OpCode::GetUpValue { dest, .. }
+        | OpCode::Length { dest, .. }
+        | OpCode::Not { dest, .. }
+        | OpCode::Minus { dest, .. }
+        | OpCode::AddRR { dest, .. }
+        | OpCode::AddRC { dest, .. }
+        | OpCode::AddCR { dest, .. }
+        | OpCode::AddCC { dest, .. }
+        | OpCode::SubRR { dest, .. }
+        | OpCode::SubRC { dest, .. }
+        | OpCode::SubCR { dest, .. }
+        | OpCode::SubCC { dest, .. }
+        | OpCode::MulRR { dest, .. }
+        | OpCode::MulRC { dest, .. }
+        | OpCode::MulCR { dest, .. }
+        | OpCode::MulCC { dest, .. }
+        | OpCode::DivRR { dest, .. }
+        | OpCode::DivRC { dest, .. }
+        | OpCode::DivCR { dest, .. }
+        | OpCode::DivCC { dest, .. }
+        | OpCode::IDivRR { dest, .. }
+        | OpCode::IDivRC { dest, .. }
+        | OpCode::IDivCR { dest, .. }
+        | OpCode::IDivCC { dest, .. }
+        | OpCode::ModRR { dest, .. }
+        | OpCode::ModRC { dest, .. }
+        | OpCode::ModCR { dest, .. }
+        | OpCode::ModCC { dest, .. }
+        | OpCode::PowRR { dest, .. }
+        | OpCode::PowRC { dest, .. }
+        | OpCode::PowCR { dest, .. }
+        | OpCode::PowCC { dest, .. }
+        | OpCode::BitAndRR { dest, .. }
+        | OpCode::BitAndRC { dest, .. }
+        | OpCode::BitAndCR { dest, .. }
+        | OpCode::BitAndCC { dest, .. }
+        | OpCode::BitOrRR { dest, .. }
+        | OpCode::BitOrRC { dest, .. }
+        | OpCode::BitOrCR { dest, .. }
+        | OpCode::BitOrCC { dest, .. }
+        | OpCode::BitXorRR { dest, .. }
+        | OpCode::BitXorRC { dest, .. }
+        | OpCode::BitXorCR { dest, .. }
+        | OpCode::BitXorCC { dest, .. }
+        | OpCode::ShiftLeftRR { dest, .. }
+        | OpCode::ShiftLeftRC { dest, .. }
+        | OpCode::ShiftLeftCR { dest, .. }
+        | OpCode::ShiftLeftCC { dest, .. }
+        | OpCode::ShiftRightRR { dest, .. }
+        | OpCode::ShiftRightRC { dest, .. }
+        | OpCode::ShiftRightCR { dest, .. }
+        | OpCode::ShiftRightCC { dest, .. }
+        | OpCode::BitNot { dest, .. } => dest == register,
+        _ => false,
+    }
+}
+
 #[derive(Debug, Collect, Copy, Clone)]
 #[collect(no_drop)]
 pub enum UpValueState<'gc> {
@@ -37,11 +248,48 @@ pub enum UpValueState<'gc> {
 #[collect(no_drop)]
 pub struct UpValue<'gc>(pub GcCell<'gc, UpValueState<'gc>>);
 
+impl<'gc> PartialEq for UpValue<'gc> {
+    fn eq(&self, other: &UpValue<'gc>) -> bool {
+        GcCell::ptr_eq(self.0, other.0)
+    }
+}
+
+impl<'gc> Eq for UpValue<'gc> {}
+
+impl<'gc> UpValue<'gc> {
+    /// Returns the current value of this upvalue.
+    ///
+    /// Panics if the upvalue is open and the state of the thread it belongs to is borrowed, as it
+    /// is while that thread runs Lua code.
+    pub fn get(self) -> Value<'gc> {
+        match *self.0.read() {
+            UpValueState::Open(thread, ind) => thread.stack_value(ind),
+            UpValueState::Closed(value) => value,
+        }
+    }
+
+    /// Sets the value of this upvalue, with the same restriction as `UpValue::get`.
+    pub fn set(self, mc: MutationContext<'gc, '_>, value: Value<'gc>) {
+        let mut state = self.0.write(mc);
+        match &mut *state {
+            UpValueState::Open(thread, ind) => thread.set_stack_value(mc, *ind, value),
+            UpValueState::Closed(v) => *v = value,
+        }
+    }
+
+    /// An identifier for this upvalue, which is shared by every closure that captures the same
+    /// variable.
+    pub fn id(self) -> usize {
+        self.0.as_ptr() as usize
+    }
+}
+
 #[derive(Debug, Collect)]
 #[collect(no_drop)]
 pub struct ClosureState<'gc> {
     pub proto: Gc<'gc, FunctionProto<'gc>>,
-    pub upvalues: Vec<UpValue<'gc>>,
+    /// The upvalues of a closure may be replaced after it is created, by `debug.upvaluejoin`.
+    pub upvalues: GcCell<'gc, Vec<UpValue<'gc>>>,
 }
 
 #[derive(Debug, Copy, Clone, Collect)]
@@ -67,6 +315,7 @@ impl<'gc> Hash for Closure<'gc> {
 pub enum ClosureError {
     HasUpValues,
     RequiresEnv,
+    Invalid(VerifierError),
 }
 
 impl StdError for ClosureError {}
@@ -82,17 +331,20 @@ impl fmt::Display for ClosureError {
                 fmt,
                 "closure requires _ENV upvalue but no environment was provided"
             ),
+            ClosureError::Invalid(error) => write!(fmt, "invalid function prototype: {}", error),
         }
     }
 }
 
 impl<'gc> Closure<'gc> {
-    /// Create a top-level closure, prototype must not have any upvalues besides _ENV.
+    /// Create a top-level closure, prototype must not have any upvalues besides _ENV.  The prototype
+    /// and all of its nested prototypes are checked with `verify` before use.
     pub fn new(
         mc: MutationContext<'gc, '_>,
         proto: FunctionProto<'gc>,
         environment: Option<Table<'gc>>,
     ) -> Result<Closure<'gc>, ClosureError> {
+        verify(&proto).map_err(ClosureError::Invalid)?;
         let proto = Gc::allocate(mc, proto);
         let mut upvalues = Vec::new();
 
@@ -109,6 +361,38 @@ impl<'gc> Closure<'gc> {
             }
         }
 
-        Ok(Closure(Gc::allocate(mc, ClosureState { proto, upvalues })))
+        Ok(Closure(Gc::allocate(
+            mc,
+            ClosureState {
+                proto,
+                upvalues: GcCell::allocate(mc, upvalues),
+            },
+        )))
+    }
+
+    /// Create a top-level closure the way `load` does.  Prototypes from binary chunks may have any
+    /// upvalues, so whatever their descriptors, the first upvalue is set to the given environment
+    /// and the rest are set to nil.
+    pub fn load(
+        mc: MutationContext<'gc, '_>,
+        proto: FunctionProto<'gc>,
+        environment: Value<'gc>,
+    ) -> Result<Closure<'gc>, ClosureError> {
+        verify(&proto).map_err(ClosureError::Invalid)?;
+        let proto = Gc::allocate(mc, proto);
+        let upvalues = (0..proto.upvalues.len())
+            .map(|i| {
+                let value = if i == 0 { environment } else { Value::Nil };
+                UpValue(GcCell::allocate(mc, UpValueState::Closed(value)))
+            })
+            .collect();
+
+        Ok(Closure(Gc::allocate(
+            mc,
+            ClosureState {
+                proto,
+                upvalues: GcCell::allocate(mc, upvalues),
+            },
+        )))
     }
 }
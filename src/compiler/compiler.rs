@@ -1,5 +1,6 @@
 use std::collections::{HashMap, VecDeque};
 use std::error::Error as StdError;
+use std::rc::Rc;
 use std::{fmt, iter, mem};
 
 use num_traits::cast;
@@ -15,8 +16,8 @@ use crate::parser::{
     WhileStatement,
 };
 use crate::{
-    Constant, ConstantIndex16, ConstantIndex8, FunctionProto, OpCode, Opt254, PrototypeIndex,
-    RegisterIndex, String, UpValueDescriptor, UpValueIndex, VarCount,
+    Constant, ConstantIndex16, ConstantIndex8, FunctionProto, LineNumber, LocalVariable, OpCode,
+    Opt254, PrototypeIndex, RegisterIndex, String, UpValueDescriptor, UpValueIndex, VarCount,
 };
 
 use super::operators::{
@@ -39,6 +40,7 @@ pub enum CompilerError {
     GotoInvalid,
     JumpLocal,
     JumpOverflow,
+    RecursionLimit,
 }
 
 impl StdError for CompilerError {}
@@ -56,27 +58,37 @@ impl fmt::Display for CompilerError {
             CompilerError::GotoInvalid => write!(fmt, "goto target label not found"),
             CompilerError::JumpLocal => write!(fmt, "jump into scope of new local variable"),
             CompilerError::JumpOverflow => write!(fmt, "jump offset overflow"),
+            CompilerError::RecursionLimit => write!(fmt, "expression too complex"),
         }
     }
 }
 
+/// Compiles a parsed chunk, recording the given chunk name in every prototype.
 pub fn compile_chunk<'gc>(
     mc: MutationContext<'gc, '_>,
+    chunk_name: Option<String<'gc>>,
     chunk: &Chunk<String<'gc>>,
 ) -> Result<FunctionProto<'gc>, CompilerError> {
     let mut compiler = Compiler {
         mutation_context: mc,
+        chunk_name,
         current_function: CompilerFunction::start(&[], true)?,
         upper_functions: Vec::new(),
+        recursion_guard: Rc::new(()),
     };
     compiler.block(&chunk.block)?;
-    compiler.current_function.finish(mc)
+    compiler.current_function.finish(mc, chunk_name)
 }
 
+// The maximum depth of nested expressions, which are compiled recursively.
+const MAX_RECURSION: usize = 200;
+
 struct Compiler<'gc, 'a> {
     mutation_context: MutationContext<'gc, 'a>,
+    chunk_name: Option<String<'gc>>,
     current_function: CompilerFunction<'gc>,
     upper_functions: Vec<CompilerFunction<'gc>>,
+    recursion_guard: Rc<()>,
 }
 
 #[derive(Default)]
@@ -91,14 +103,18 @@ struct CompilerFunction<'gc> {
 
     has_varargs: bool,
     fixed_params: u8,
-    locals: Vec<(String<'gc>, RegisterIndex)>,
+    // The local variables currently in scope, with the index of their entry in `local_variables`
+    locals: Vec<(String<'gc>, RegisterIndex, usize)>,
+    local_variables: Vec<LocalVariable<'gc>>,
 
     blocks: Vec<BlockDescriptor>,
     unique_jump_id: u64,
     jump_targets: Vec<JumpTarget<'gc>>,
     pending_jumps: Vec<PendingJump<'gc>>,
 
+    line_defined: Option<LineNumber>,
     opcodes: Vec<OpCode>,
+    opcode_lines: Vec<(usize, LineNumber)>,
 }
 
 #[derive(Debug)]
@@ -222,10 +238,10 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
     fn exit_block(&mut self) -> Result<(), CompilerError> {
         let last_block = self.current_function.blocks.pop().unwrap();
 
-        while let Some((_, last)) = self.current_function.locals.last() {
+        while let Some(&(_, last, _)) = self.current_function.locals.last() {
             if last.0 as u16 >= last_block.stack_bottom {
-                self.current_function.register_allocator.free(*last);
-                self.current_function.locals.pop();
+                self.current_function.pop_local();
+                self.current_function.register_allocator.free(last);
             } else {
                 break;
             }
@@ -276,7 +292,7 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
         } else {
             let mut last = block.statements.len();
             for i in (0..block.statements.len()).rev() {
-                match &block.statements[i] {
+                match &block.statements[i].1 {
                     Statement::Label(_) => {}
                     _ => break,
                 }
@@ -291,13 +307,17 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
             self.exit_block()?;
 
             for label_statement in trailing_labels {
-                self.statement(&label_statement)?;
+                self.statement(label_statement)?;
             }
         }
         Ok(())
     }
 
-    fn statement(&mut self, statement: &Statement<String<'gc>>) -> Result<(), CompilerError> {
+    fn statement(
+        &mut self,
+        (line_number, statement): &(LineNumber, Statement<String<'gc>>),
+    ) -> Result<(), CompilerError> {
+        self.current_function.set_line_number(*line_number);
         match statement {
             Statement::If(if_statement) => self.if_statement(if_statement),
             Statement::While(while_statement) => self.while_statement(while_statement),
@@ -321,8 +341,9 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
 
     fn return_statement(
         &mut self,
-        return_statement: &ReturnStatement<String<'gc>>,
+        (line_number, return_statement): &(LineNumber, ReturnStatement<String<'gc>>),
     ) -> Result<(), CompilerError> {
+        self.current_function.set_line_number(*line_number);
         let mut returns = return_statement
             .returns
             .iter()
@@ -434,7 +455,7 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
                     .register_allocator
                     .push(1)
                     .ok_or(CompilerError::Registers)?;
-                self.current_function.locals.push((*name, loop_var));
+                self.current_function.declare_local(*name, loop_var);
 
                 self.block_statements(body)?;
                 self.exit_block()?;
@@ -511,8 +532,7 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
                     .ok_or(CompilerError::Registers)?;
                 for i in 0..name_count {
                     self.current_function
-                        .locals
-                        .push((names[i as usize], RegisterIndex(names_reg.0 + i)));
+                        .declare_local(names[i as usize], RegisterIndex(names_reg.0 + i));
                 }
 
                 self.jump(loop_label)?;
@@ -631,16 +651,11 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
             let mut parameters = vec![String::new_static(b"self")];
             parameters.extend(&function_statement.definition.parameters);
 
-            self.new_prototype(
-                &parameters,
-                function_statement.definition.has_varargs,
-                &function_statement.definition.body,
-            )?
+            self.new_prototype(&function_statement.definition, &parameters)?
         } else {
             self.new_prototype(
+                &function_statement.definition,
                 &function_statement.definition.parameters,
-                function_statement.definition.has_varargs,
-                &function_statement.definition.body,
             )?
         };
 
@@ -672,8 +687,7 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
                 .push(OpCode::LoadNil { dest, count });
             for i in 0..name_len {
                 self.current_function
-                    .locals
-                    .push((local_statement.names[i], RegisterIndex(dest.0 + i as u8)));
+                    .declare_local(local_statement.names[i], RegisterIndex(dest.0 + i as u8));
             }
         } else {
             for i in 0..val_len {
@@ -688,16 +702,15 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
                     let dest = self.expr_push_count(expr, names_left)?;
 
                     for j in 0..names_left {
-                        self.current_function.locals.push((
+                        self.current_function.declare_local(
                             local_statement.names[val_len - 1 + j as usize],
                             RegisterIndex(dest.0 + j),
-                        ));
+                        );
                     }
                 } else {
                     let reg = self.expr_discharge(expr, ExprDestination::PushNew)?;
                     self.current_function
-                        .locals
-                        .push((local_statement.names[i], reg));
+                        .declare_local(local_statement.names[i], reg);
                 }
             }
         }
@@ -816,9 +829,8 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
         local_function: &LocalFunctionStatement<String<'gc>>,
     ) -> Result<(), CompilerError> {
         let proto = self.new_prototype(
+            &local_function.definition,
             &local_function.definition.parameters,
-            local_function.definition.has_varargs,
-            &local_function.definition.body,
         )?;
 
         let dest = self
@@ -830,8 +842,7 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
             .opcodes
             .push(OpCode::Closure { proto, dest });
         self.current_function
-            .locals
-            .push((local_function.name, dest));
+            .declare_local(local_function.name, dest);
 
         Ok(())
     }
@@ -840,14 +851,31 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
         &mut self,
         expression: &Expression<String<'gc>>,
     ) -> Result<ExprDescriptor<'gc>, CompilerError> {
+        let _recursion_guard = self.recursion_guard()?;
+        // Every operator that is not constant folded nests the resulting expression one level
+        // deeper, and discharging it later recurses just as deeply.
+        let mut operator_guards = Vec::new();
         let mut expr = self.head_expression(&expression.head)?;
         for (binop, right) in &expression.tail {
             let right = self.expression(&right)?;
             expr = self.binary_operator_expression(expr, *binop, right)?;
+            if !matches!(expr, ExprDescriptor::Constant(_)) {
+                operator_guards.push(self.recursion_guard()?);
+            }
         }
         Ok(expr)
     }
 
+    // Error if we have more than MAX_RECURSION guards live, otherwise return a new recursion guard
+    // (a recursion guard is just an Rc used solely for its live count).
+    fn recursion_guard(&self) -> Result<Rc<()>, CompilerError> {
+        if Rc::strong_count(&self.recursion_guard) < MAX_RECURSION {
+            Ok(self.recursion_guard.clone())
+        } else {
+            Err(CompilerError::RecursionLimit)
+        }
+    }
+
     fn head_expression(
         &mut self,
         head_expression: &HeadExpression<String<'gc>>,
@@ -912,8 +940,7 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
         &mut self,
         function: &FunctionDefinition<String<'gc>>,
     ) -> Result<ExprDescriptor<'gc>, CompilerError> {
-        let proto =
-            self.new_prototype(&function.parameters, function.has_varargs, &function.body)?;
+        let proto = self.new_prototype(function, &function.parameters)?;
         Ok(ExprDescriptor::Closure(proto))
     }
 
@@ -1059,23 +1086,28 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
         }
     }
 
+    // Compiles the given function definition with the given parameters, which for a method include
+    // `self`.
     fn new_prototype(
         &mut self,
+        definition: &FunctionDefinition<String<'gc>>,
         parameters: &[String<'gc>],
-        has_varargs: bool,
-        body: &Block<String<'gc>>,
     ) -> Result<PrototypeIndex, CompilerError> {
         let old_current = mem::replace(
             &mut self.current_function,
-            CompilerFunction::start(parameters, has_varargs)?,
+            CompilerFunction::start(parameters, definition.has_varargs)?,
         );
+        // Until its first statement, a function is attributed to the line of its definition
+        self.current_function.line_defined = Some(definition.line_number);
+        self.current_function
+            .set_line_number(definition.line_number);
         self.upper_functions.push(old_current);
-        self.block(body)?;
+        self.block(&definition.body)?;
         let proto = mem::replace(
             &mut self.current_function,
             self.upper_functions.pop().unwrap(),
         )
-        .finish(self.mutation_context)?;
+        .finish(self.mutation_context, self.chunk_name)?;
         self.current_function.prototypes.push(proto);
         Ok(PrototypeIndex(
             cast(self.current_function.prototypes.len() - 1).ok_or(CompilerError::Functions)?,
@@ -1102,7 +1134,7 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
 
         for i in (0..=current_function).rev() {
             for j in (0..get_function(self, i).locals.len()).rev() {
-                let (local_name, register) = get_function(self, i).locals[j];
+                let (local_name, register, _) = get_function(self, i).locals[j];
                 if name == local_name {
                     if i == current_function {
                         return Ok(VariableDescriptor::Local(register));
@@ -1494,6 +1526,14 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
                     self.call_function(*func, args, VarCount::variable())?;
                     VarCount::variable()
                 }
+                ExprDescriptor::MethodCall {
+                    table,
+                    method,
+                    args,
+                } => {
+                    self.call_method(*table, *method, args, VarCount::variable())?;
+                    VarCount::variable()
+                }
                 ExprDescriptor::VarArgs => {
                     self.current_function.opcodes.push(OpCode::VarArgs {
                         dest: RegisterIndex(
@@ -1913,6 +1953,23 @@ impl<'gc, 'a> Compiler<'gc, 'a> {
                     .ok_or(CompilerError::Registers)?;
                 dest
             }
+            ExprDescriptor::MethodCall {
+                table,
+                method,
+                args,
+            } => {
+                let dest = self.call_method(
+                    *table,
+                    *method,
+                    args,
+                    VarCount::try_constant(count).ok_or(CompilerError::Registers)?,
+                )?;
+                self.current_function
+                    .register_allocator
+                    .push(count)
+                    .ok_or(CompilerError::Registers)?;
+                dest
+            }
             ExprDescriptor::VarArgs => {
                 let dest = self
                     .current_function
@@ -2038,20 +2095,56 @@ impl<'gc> CompilerFunction<'gc> {
         function.has_varargs = has_varargs;
         function.fixed_params = fixed_params;
         for i in 0..fixed_params {
-            function
-                .locals
-                .push((parameters[i as usize], RegisterIndex(i)));
+            function.declare_local(parameters[i as usize], RegisterIndex(i));
         }
         Ok(function)
     }
 
-    fn finish(mut self, mc: MutationContext<'gc, '_>) -> Result<FunctionProto<'gc>, CompilerError> {
+    // Brings a local variable into scope in the given register, starting with the next opcode.
+    fn declare_local(&mut self, name: String<'gc>, register: RegisterIndex) {
+        let pc = self.opcodes.len();
+        self.locals
+            .push((name, register, self.local_variables.len()));
+        self.local_variables.push(LocalVariable {
+            name,
+            register,
+            start_pc: pc,
+            end_pc: pc,
+        });
+    }
+
+    // Takes the innermost local variable out of scope after the last opcode emitted, returning its
+    // register.
+    fn pop_local(&mut self) -> Option<RegisterIndex> {
+        let (_, register, index) = self.locals.pop()?;
+        self.local_variables[index].end_pc = self.opcodes.len();
+        Some(register)
+    }
+
+    // Attributes all opcodes emitted from now on to the given line.
+    fn set_line_number(&mut self, line_number: LineNumber) {
+        let pc = self.opcodes.len();
+        if let Some(&(last_pc, _)) = self.opcode_lines.last() {
+            if last_pc == pc {
+                self.opcode_lines.pop();
+            }
+        }
+        if self.opcode_lines.last().map(|&(_, l)| l) != Some(line_number) {
+            self.opcode_lines.push((pc, line_number));
+        }
+    }
+
+    fn finish(
+        mut self,
+        mc: MutationContext<'gc, '_>,
+        chunk_name: Option<String<'gc>>,
+    ) -> Result<FunctionProto<'gc>, CompilerError> {
         self.opcodes.push(OpCode::Return {
             start: RegisterIndex(0),
             count: VarCount::constant(0),
         });
         assert!(self.locals.len() == self.fixed_params as usize);
-        for (_, r) in self.locals.drain(..) {
+        while let Some(r) = self.pop_local() {
             self.register_allocator.free(r);
         }
         assert_eq!(
@@ -2065,12 +2158,17 @@ impl<'gc> CompilerFunction<'gc> {
         }
 
         Ok(FunctionProto {
+            chunk_name,
+            line_defined: self.line_defined,
             fixed_params: self.fixed_params,
             has_varargs: self.has_varargs,
             stack_size: self.register_allocator.stack_size(),
             constants: self.constants,
             opcodes: self.opcodes,
+            opcode_lines: self.opcode_lines,
+            locals: self.local_variables,
             upvalues: self.upvalues.iter().map(|(_, d)| *d).collect(),
+            upvalue_names: self.upvalues.iter().map(|(n, _)| *n).collect(),
             prototypes: self
                 .prototypes
                 .into_iter()
@@ -17,6 +17,22 @@ pub fn compile<'gc, R: Read>(
 ) -> Result<FunctionProto<'gc>, Error<'gc>> {
     Ok(compile_chunk(
         mc,
+        None,
+        &parse_chunk(source, |s| interned_strings.new_string(mc, s))?,
+    )?)
+}
+
+/// Like `compile`, but records the given chunk name, usually a file name, in every prototype so that
+/// debuggers can tell where functions came from.
+pub fn compile_named<'gc, R: Read>(
+    mc: MutationContext<'gc, '_>,
+    interned_strings: InternedStringSet<'gc>,
+    chunk_name: &str,
+    source: R,
+) -> Result<FunctionProto<'gc>, Error<'gc>> {
+    Ok(compile_chunk(
+        mc,
+        Some(interned_strings.new_string(mc, chunk_name.as_bytes())),
         &parse_chunk(source, |s| interned_strings.new_string(mc, s))?,
     )?)
 }
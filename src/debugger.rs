@@ -0,0 +1,862 @@
+//! A debugger for scripts running on the main thread of a `Lua`.
+//!
+//! The `Debugger` installs a hook on the main thread, which coroutines inherit.  When the hook
+//! decides to pause, at a breakpoint, after a step or on an error, it returns a sequence which does
+//! not finish until the debugger is resumed, leaving the paused thread `Running` with every frame
+//! intact.  While paused, the host inspects the thread by stepping the main thread, which steps the
+//! paused hook sequence and lets it answer a single request.
+//!
+//! Frames are numbered from 0 for the innermost frame of the paused thread, not counting the hook
+//! itself.  Only the frames of the paused thread are visible, not those of the threads which
+//! resumed it.
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt;
+use std::path::Path;
+use std::rc::Rc;
+use std::string::String as StdString;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::Arc;
+
+use gc_arena::{Collect, GcCell, MutationContext, StaticCollect};
+use gc_sequence::Sequence;
+
+use crate::{
+    compile_named, is_binary_chunk, read_chunk, CallContext, Callback, CallbackResult,
+    CallbackReturn, Closure, Error, Function, Hook, HookMask, Lua, StaticError, String, Table,
+    Thread, ThreadMode, UpValueIndex, Value, ValueBuffer,
+};
+
+/// How to continue running a paused script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Resume {
+    /// Run until the next breakpoint or error.
+    #[default]
+    Continue,
+    /// Pause on the next line executed, in any function.
+    StepInto,
+    /// Pause on the next line executed in the current function or one of its callers.
+    StepOver,
+    /// Pause on the next line executed in a caller of the current function.
+    StepOut,
+}
+
+/// Why a script paused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PauseReason {
+    /// A breakpoint with the given id was reached.
+    Breakpoint(usize),
+    /// A step requested with `Debugger::resume` completed.
+    Step,
+    /// An error with the given message was raised, and has not yet unwound any frames.
+    Error(StdString),
+    /// A pause was requested with a `PauseHandle`.
+    Requested,
+}
+
+/// What happened after a script was resumed.
+#[derive(Debug)]
+pub enum DebugEvent {
+    Paused(PauseReason),
+    /// The script returned or failed, and is no longer running.
+    Finished(Result<(), StaticError>),
+}
+
+/// A line on which to pause.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub id: usize,
+    /// The chunk the line is in, or `None` to pause on the line in any chunk.  A chunk matches if
+    /// either its name or this source is a path ending with the other, so `main.lua` matches a
+    /// chunk named `scripts/main.lua`.
+    pub source: Option<StdString>,
+    /// The 1-indexed line number.
+    pub line: usize,
+}
+
+/// Describes a frame of the paused thread.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackFrame {
+    /// How the caller named the function, such as "function 'print'" or "local 'f'", if known.
+    pub name: Option<StdString>,
+    /// The name of the chunk the function was compiled from, `None` for callbacks.
+    pub chunk_name: Option<StdString>,
+    /// The 1-indexed line being executed, if known.
+    pub line: Option<usize>,
+    /// The 1-indexed line the function was defined on, if known.
+    pub defined_line: Option<usize>,
+    /// Whether the frame is running a callback rather than a Lua function.
+    pub callback: bool,
+}
+
+impl fmt::Display for StackFrame {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if self.callback {
+            write!(fmt, "[callback]")?;
+        } else {
+            write!(fmt, "{}:", self.chunk_name.as_deref().unwrap_or("?"))?;
+            match self.line {
+                Some(line) => write!(fmt, "{}", line)?,
+                None => write!(fmt, "?")?,
+            }
+        }
+        match &self.name {
+            Some(name) => write!(fmt, " in {}", name),
+            None if self.callback => Ok(()),
+            None => match self.defined_line {
+                Some(line) => write!(
+                    fmt,
+                    " in function <{}:{}>",
+                    self.chunk_name.as_deref().unwrap_or("?"),
+                    line
+                ),
+                None => write!(fmt, " in main chunk"),
+            },
+        }
+    }
+}
+
+/// A handle for pausing a script running under a `Debugger` from any OS thread.
+///
+/// The script pauses on the next line it executes, even in the middle of a line it is looping on.
+#[derive(Debug, Clone, Default)]
+pub struct PauseHandle(Arc<AtomicBool>);
+
+impl PauseHandle {
+    pub fn pause(&self) {
+        self.0.store(true, AtomicOrdering::Relaxed);
+    }
+
+    /// Withdraws a requested pause which has not yet taken effect.
+    pub fn cancel(&self) {
+        self.0.store(false, AtomicOrdering::Relaxed);
+    }
+
+    pub fn is_pause_requested(&self) -> bool {
+        self.0.load(AtomicOrdering::Relaxed)
+    }
+
+    // Clears a requested pause, returning whether there was one.
+    fn take(&self) -> bool {
+        self.0.swap(false, AtomicOrdering::Relaxed)
+    }
+}
+
+/// A named value, displayed as text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variable {
+    pub name: StdString,
+    /// The Lua type of the value, as returned by `type`.
+    pub type_name: &'static str,
+    /// The value as `tostring` would display it, except that strings are quoted and escaped.
+    pub value: StdString,
+    /// For tables, a handle that may be passed to `Debugger::fields` until the script is resumed.
+    pub table: Option<usize>,
+}
+
+/// A debugger for the main thread of a `Lua`, and the coroutines it creates.
+///
+/// A script is started with `Debugger::load` and run with `Debugger::resume`, which returns once
+/// the script pauses or finishes.  Until it finishes, the main thread must only be run through the
+/// debugger.
+#[derive(Clone)]
+pub struct Debugger {
+    shared: Rc<RefCell<Shared>>,
+}
+
+impl Debugger {
+    /// Creates a debugger and installs its hook on the main thread of the given `Lua`, replacing
+    /// any existing hook.
+    pub fn new(lua: &mut Lua) -> Debugger {
+        let shared = Rc::new(RefCell::new(Shared::default()));
+        lua.mutate({
+            let shared = shared.clone();
+            move |mc, root| {
+                let context = HookContext {
+                    shared: StaticCollect(shared),
+                    origin: GcCell::allocate(mc, None),
+                };
+                root.main_thread.set_hook(
+                    mc,
+                    Some(Hook {
+                        function: Function::Callback(Callback::new_with(mc, context, call_hook)),
+                        mask: HookMask {
+                            line: true,
+                            error: true,
+                            ..HookMask::default()
+                        },
+                    }),
+                );
+            }
+        });
+        Debugger { shared }
+    }
+
+    /// Compiles the given source text or binary chunk and starts it on the main thread, which must
+    /// be stopped.  The script does not run until it is resumed.
+    pub fn load(&self, lua: &mut Lua, chunk_name: &str, source: &[u8]) -> Result<(), StaticError> {
+        lua.mutate(|mc, root| {
+            let start = || -> Result<(), Error> {
+                let proto = if is_binary_chunk(source) {
+                    read_chunk(mc, root.interned_strings, source)?
+                } else {
+                    compile_named(mc, root.interned_strings, chunk_name, source)?
+                };
+                let closure = Closure::new(mc, proto, Some(root.globals))?;
+                root.main_thread
+                    .start(mc, Function::Closure(closure), &[])?;
+                Ok(())
+            };
+            start().map_err(Error::to_static)
+        })
+    }
+
+    /// Runs the loaded script until it pauses or finishes.
+    pub fn resume(&self, lua: &mut Lua, resume: Resume) -> DebugEvent {
+        {
+            let mut shared = self.shared.borrow_mut();
+            shared.paused = None;
+            shared.resume = resume;
+        }
+        loop {
+            let finished = lua.mutate(|mc, root| {
+                let thread = root.main_thread;
+                match thread.mode() {
+                    ThreadMode::Running => {
+                        thread.step(mc).unwrap();
+                        None
+                    }
+                    ThreadMode::Results => Some(
+                        thread
+                            .take_results(mc)
+                            .unwrap()
+                            .map(|_| ())
+                            .map_err(Error::to_static),
+                    ),
+                    _ => Some(Err(StaticError::BadThreadMode(
+                        thread.step(mc).unwrap_err(),
+                    ))),
+                }
+            });
+            if let Some(result) = finished {
+                return DebugEvent::Finished(result);
+            }
+            if let Some(reason) = self.shared.borrow().paused.clone() {
+                return DebugEvent::Paused(reason);
+            }
+        }
+    }
+
+    /// Returns a handle which may be used to pause the script while it is running.
+    pub fn pause_handle(&self) -> PauseHandle {
+        self.shared.borrow().pause.clone()
+    }
+
+    /// Why the script is paused, or `None` if it is not.
+    pub fn paused(&self) -> Option<PauseReason> {
+        self.shared.borrow().paused.clone()
+    }
+
+    /// Adds a breakpoint, and returns its id.
+    pub fn add_breakpoint(&self, source: Option<&str>, line: usize) -> usize {
+        let mut shared = self.shared.borrow_mut();
+        shared.next_breakpoint += 1;
+        let id = shared.next_breakpoint;
+        shared.breakpoints.push(Breakpoint {
+            id,
+            source: source.map(StdString::from),
+            line,
+        });
+        id
+    }
+
+    /// Removes the breakpoint with the given id, returning false if there was none.
+    pub fn remove_breakpoint(&self, id: usize) -> bool {
+        let mut shared = self.shared.borrow_mut();
+        let len = shared.breakpoints.len();
+        shared.breakpoints.retain(|breakpoint| breakpoint.id != id);
+        shared.breakpoints.len() != len
+    }
+
+    pub fn breakpoints(&self) -> Vec<Breakpoint> {
+        self.shared.borrow().breakpoints.clone()
+    }
+
+    /// Sets whether to pause whenever an error is raised, even one that will be caught by `pcall`.
+    pub fn set_pause_on_error(&self, pause_on_error: bool) {
+        self.shared.borrow_mut().pause_on_error = pause_on_error;
+    }
+
+    pub fn pause_on_error(&self) -> bool {
+        self.shared.borrow().pause_on_error
+    }
+
+    /// Describes the frames of the paused thread, innermost first.  Empty if the script is not
+    /// paused.
+    pub fn frames(&self, lua: &mut Lua) -> Vec<StackFrame> {
+        match self.request(lua, Request::Frames) {
+            Some(Response::Frames(frames)) => frames,
+            _ => Vec::new(),
+        }
+    }
+
+    /// Lists the local variables in scope in the given frame, in the order they were declared.
+    /// Returns `None` if there is no such frame, or it is not a Lua frame.
+    pub fn locals(&self, lua: &mut Lua, frame: usize) -> Option<Vec<Variable>> {
+        match self.request(lua, Request::Locals(frame))? {
+            Response::Variables(variables) => variables,
+            _ => None,
+        }
+    }
+
+    /// Lists the upvalues of the function running in the given frame.  Returns `None` if there is
+    /// no such frame, or it is not a Lua frame.
+    pub fn upvalues(&self, lua: &mut Lua, frame: usize) -> Option<Vec<Variable>> {
+        match self.request(lua, Request::Upvalues(frame))? {
+            Response::Variables(variables) => variables,
+            _ => None,
+        }
+    }
+
+    /// Lists the global variables, named as by `Debugger::fields`.  Tables have no handles unless
+    /// the script is paused.
+    pub fn globals(&self, lua: &mut Lua) -> Vec<Variable> {
+        match self.request(lua, Request::Globals) {
+            Some(Response::Variables(Some(variables))) => variables,
+            _ => lua.mutate(|_, root| {
+                table_fields(root.globals)
+                    .into_iter()
+                    .map(|(name, value)| variable(name, value, None))
+                    .collect()
+            }),
+        }
+    }
+
+    /// Lists the fields of the table with the given handle, taken from a `Variable`.  Fields with
+    /// identifier keys are named by the key, and others by the key in brackets, such as `[1]` or
+    /// `["a key"]`.  Integer keys come first in order, followed by the rest sorted by name.
+    /// Returns `None` if the handle is no longer valid.
+    pub fn fields(&self, lua: &mut Lua, table: usize) -> Option<Vec<Variable>> {
+        match self.request(lua, Request::Fields(table))? {
+            Response::Variables(variables) => variables,
+            _ => None,
+        }
+    }
+
+    /// Evaluates an expression, or several separated by commas, as though it appeared in the given
+    /// frame.  The frame's local variables and upvalues are visible, but assigning to them has no
+    /// effect on the frame.  Returns the results, named by their position, or an error message.
+    pub fn evaluate(
+        &self,
+        lua: &mut Lua,
+        frame: usize,
+        expression: &str,
+    ) -> Result<Vec<Variable>, StdString> {
+        match self.request(lua, Request::Evaluate(frame, expression.to_owned())) {
+            Some(Response::Evaluate(result)) => result,
+            _ => Err("not paused".to_owned()),
+        }
+    }
+
+    // Steps the main thread until the paused hook answers the given request, or returns `None` if
+    // the script is not paused.
+    fn request(&self, lua: &mut Lua, request: Request) -> Option<Response> {
+        {
+            let mut shared = self.shared.borrow_mut();
+            shared.paused.as_ref()?;
+            shared.request = Some(request);
+        }
+        loop {
+            let running = lua.mutate(|mc, root| {
+                let thread = root.main_thread;
+                thread.mode() == ThreadMode::Running && thread.step(mc).is_ok()
+            });
+            let mut shared = self.shared.borrow_mut();
+            if let Some(response) = shared.response.take() {
+                return Some(response);
+            }
+            if !running {
+                shared.request = None;
+                return None;
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    breakpoints: Vec<Breakpoint>,
+    next_breakpoint: usize,
+    pause_on_error: bool,
+    pause: PauseHandle,
+    resume: Resume,
+    paused: Option<PauseReason>,
+    request: Option<Request>,
+    response: Option<Response>,
+}
+
+enum Request {
+    Frames,
+    Locals(usize),
+    Upvalues(usize),
+    Globals,
+    Fields(usize),
+    Evaluate(usize, StdString),
+}
+
+enum Response {
+    Frames(Vec<StackFrame>),
+    Variables(Option<Vec<Variable>>),
+    Evaluate(Result<Vec<Variable>, StdString>),
+}
+
+#[derive(Collect)]
+#[collect(no_drop)]
+struct HookContext<'gc> {
+    shared: StaticCollect<Rc<RefCell<Shared>>>,
+    // The thread and stack depth a step over or out started from
+    origin: GcCell<'gc, Option<(Thread<'gc>, usize)>>,
+}
+
+#[derive(Debug, Clone, Collect)]
+#[collect(require_static)]
+enum Event {
+    Line(usize),
+    Error(StdString),
+}
+
+fn call_hook<'gc>(
+    context: &HookContext<'gc>,
+    ctx: CallContext<'gc>,
+    args: ValueBuffer<'gc>,
+) -> CallbackReturn<'gc> {
+    let event = match (args.first(), args.get(1)) {
+        (Some(Value::String(event)), Some(&Value::Integer(line))) if *event == b"line" => {
+            Event::Line(line as usize)
+        }
+        (Some(Value::String(event)), Some(&error)) if *event == b"error" => {
+            Event::Error(display(error))
+        }
+        _ => return CallbackReturn::Immediate(Ok(CallbackResult::Return(args.returning(None)))),
+    };
+
+    // Most lines are not interesting, so avoid creating a sequence for them.
+    let shared = context.shared.0.borrow();
+    let interesting = match &event {
+        Event::Line(line) => {
+            shared.resume != Resume::Continue
+                || shared.pause.is_pause_requested()
+                || shared
+                    .breakpoints
+                    .iter()
+                    .any(|breakpoint| breakpoint.line == *line)
+        }
+        Event::Error(_) => shared.pause_on_error,
+    };
+    if !interesting {
+        return CallbackReturn::Immediate(Ok(CallbackResult::Return(args.returning(None))));
+    }
+
+    CallbackReturn::Sequence(Box::new(HookSequence {
+        shared: StaticCollect(context.shared.0.clone()),
+        origin: context.origin,
+        thread: ctx.thread,
+        globals: ctx.globals,
+        event,
+        paused: false,
+        evaluating: None,
+        tables: Vec::new(),
+        args: Some(args),
+    }))
+}
+
+#[derive(Collect)]
+#[collect(no_drop)]
+struct HookSequence<'gc> {
+    shared: StaticCollect<Rc<RefCell<Shared>>>,
+    origin: GcCell<'gc, Option<(Thread<'gc>, usize)>>,
+    thread: Thread<'gc>,
+    globals: Table<'gc>,
+    event: Event,
+    paused: bool,
+    // A thread evaluating an expression for a request
+    evaluating: Option<Thread<'gc>>,
+    // The tables whose handles have been given out while paused
+    tables: Vec<Table<'gc>>,
+    args: Option<ValueBuffer<'gc>>,
+}
+
+impl<'gc> Sequence<'gc> for HookSequence<'gc> {
+    type Output = Result<CallbackResult<'gc>, Error<'gc>>;
+
+    fn step(&mut self, mc: MutationContext<'gc, '_>) -> Option<Self::Output> {
+        if !self.paused {
+            match self.pause_reason() {
+                Some(reason) => {
+                    let mut shared = self.shared.0.borrow_mut();
+                    shared.paused = Some(reason);
+                    shared.resume = Resume::Continue;
+                    self.paused = true;
+                    return None;
+                }
+                None => return Some(Ok(self.finish())),
+            }
+        }
+
+        if let Some(thread) = self.evaluating {
+            match thread.mode() {
+                ThreadMode::Running => thread.step(mc).unwrap(),
+                _ => {
+                    let result = match thread.take_results(mc) {
+                        Some(Ok(values)) => Ok(values
+                            .into_iter()
+                            .enumerate()
+                            .map(|(i, value)| self.variable((i + 1).to_string(), value))
+                            .collect()),
+                        Some(Err(err)) => Err(display_error(err)),
+                        None => Err("expression did not return".to_owned()),
+                    };
+                    self.evaluating = None;
+                    self.shared.0.borrow_mut().response = Some(Response::Evaluate(result));
+                }
+            }
+            return None;
+        }
+
+        let shared = self.shared.0.clone();
+        let mut shared = shared.borrow_mut();
+        if let Some(request) = shared.request.take() {
+            let response = match request {
+                Request::Frames => Response::Frames(self.frames()),
+                Request::Locals(frame) => Response::Variables(self.locals(frame)),
+                Request::Upvalues(frame) => Response::Variables(self.upvalues(frame)),
+                Request::Globals => Response::Variables(Some(self.fields(self.globals))),
+                Request::Fields(table) => Response::Variables(
+                    self.tables
+                        .get(table)
+                        .copied()
+                        .map(|table| self.fields(table)),
+                ),
+                Request::Evaluate(frame, expression) => {
+                    match self.start_evaluating(mc, frame, &expression) {
+                        Ok(thread) => {
+                            self.evaluating = Some(thread);
+                            return None;
+                        }
+                        Err(err) => Response::Evaluate(Err(err)),
+                    }
+                }
+            };
+            shared.response = Some(response);
+            None
+        } else if shared.paused.is_none() {
+            *self.origin.write(mc) = match shared.resume {
+                Resume::StepOver | Resume::StepOut => {
+                    self.thread.stack_depth().map(|depth| (self.thread, depth))
+                }
+                _ => None,
+            };
+            drop(shared);
+            Some(Ok(self.finish()))
+        } else {
+            None
+        }
+    }
+}
+
+impl<'gc> HookSequence<'gc> {
+    fn finish(&mut self) -> CallbackResult<'gc> {
+        CallbackResult::Return(self.args.take().unwrap().returning(None))
+    }
+
+    fn pause_reason(&self) -> Option<PauseReason> {
+        let shared = self.shared.0.borrow();
+        let line = match &self.event {
+            Event::Error(message) => return Some(PauseReason::Error(message.clone())),
+            Event::Line(line) => *line,
+        };
+        let requested = shared.pause.take();
+
+        let chunk_name = match self.thread.frame_info(1)?.function? {
+            Function::Closure(closure) => closure.0.proto.chunk_name.map(lossy),
+            Function::Callback(_) => None,
+        };
+        for breakpoint in &shared.breakpoints {
+            if breakpoint.line == line
+                && source_matches(breakpoint.source.as_deref(), chunk_name.as_deref())
+            {
+                return Some(PauseReason::Breakpoint(breakpoint.id));
+            }
+        }
+
+        let step = match (shared.resume, *self.origin.read()) {
+            (Resume::Continue, _) => false,
+            (Resume::StepInto, _) | (Resume::StepOver, None) => true,
+            (Resume::StepOut, None) => false,
+            (resume, Some((thread, depth))) => {
+                if thread == self.thread {
+                    let current = self.thread.stack_depth().unwrap_or(0);
+                    if resume == Resume::StepOver {
+                        current <= depth
+                    } else {
+                        current < depth
+                    }
+                } else {
+                    // The thread the step started on has yielded or finished, so this thread
+                    // resumed it.
+                    thread.mode() != ThreadMode::Running
+                }
+            }
+        };
+        if step {
+            Some(PauseReason::Step)
+        } else if requested {
+            Some(PauseReason::Requested)
+        } else {
+            None
+        }
+    }
+
+    fn frames(&self) -> Vec<StackFrame> {
+        let mut frames = Vec::new();
+        while let Some(info) = self.thread.frame_info(frames.len() + 1) {
+            let name = info.name.map(|(kind, name)| {
+                let kind = if kind == "global" { "function" } else { kind };
+                format!("{} '{}'", kind, StdString::from_utf8_lossy(name.as_bytes()))
+            });
+            frames.push(match info.function {
+                Some(Function::Closure(closure)) => StackFrame {
+                    name,
+                    chunk_name: closure.0.proto.chunk_name.map(lossy),
+                    line: info.current_line.map(|line| line.0 as usize + 1),
+                    defined_line: closure.0.proto.line_defined.map(|line| line.0 as usize + 1),
+                    callback: false,
+                },
+                _ => StackFrame {
+                    name,
+                    chunk_name: None,
+                    line: None,
+                    defined_line: None,
+                    callback: true,
+                },
+            });
+        }
+        frames
+    }
+
+    // Returns the closure of the Lua frame with the given number, and its current instruction.
+    fn frame_closure(&self, frame: usize) -> Option<(Closure<'gc>, usize)> {
+        let info = self.thread.frame_info(frame + 1)?;
+        match (info.function?, info.pc?) {
+            (Function::Closure(closure), pc) => Some((closure, pc)),
+            _ => None,
+        }
+    }
+
+    fn locals(&mut self, frame: usize) -> Option<Vec<Variable>> {
+        let (closure, pc) = self.frame_closure(frame)?;
+        let locals = closure
+            .0
+            .proto
+            .active_locals(pc)
+            .map(|local| {
+                let value = self
+                    .thread
+                    .frame_register(frame + 1, local.register)
+                    .unwrap_or(Value::Nil);
+                (lossy(local.name), value)
+            })
+            .collect::<Vec<_>>();
+        Some(self.variables(locals))
+    }
+
+    fn upvalues(&mut self, frame: usize) -> Option<Vec<Variable>> {
+        let (closure, _) = self.frame_closure(frame)?;
+        let upvalues = closure
+            .0
+            .upvalues
+            .read()
+            .iter()
+            .enumerate()
+            .map(|(i, upvalue)| {
+                let name = closure
+                    .0
+                    .proto
+                    .upvalue_name(UpValueIndex(i as u8))
+                    .map(lossy)
+                    .unwrap_or_else(|| "?".to_owned());
+                (name, upvalue.get())
+            })
+            .collect::<Vec<_>>();
+        Some(self.variables(upvalues))
+    }
+
+    fn fields(&mut self, table: Table<'gc>) -> Vec<Variable> {
+        let fields = table_fields(table);
+        self.variables(fields)
+    }
+
+    fn variables(&mut self, values: Vec<(StdString, Value<'gc>)>) -> Vec<Variable> {
+        values
+            .into_iter()
+            .map(|(name, value)| self.variable(name, value))
+            .collect()
+    }
+
+    // Describes a value, giving out a handle if it is a table.
+    fn variable(&mut self, name: StdString, value: Value<'gc>) -> Variable {
+        let table = match value {
+            Value::Table(table) => Some(
+                match self.tables.iter().position(|&handle| handle == table) {
+                    Some(handle) => handle,
+                    None => {
+                        self.tables.push(table);
+                        self.tables.len() - 1
+                    }
+                },
+            ),
+            _ => None,
+        };
+        variable(name, value, table)
+    }
+
+    // Compiles the expression as a chunk whose parameters are the frame's upvalues and locals, and
+    // starts it on a new thread.
+    fn start_evaluating(
+        &self,
+        mc: MutationContext<'gc, '_>,
+        frame: usize,
+        expression: &str,
+    ) -> Result<Thread<'gc>, StdString> {
+        let (closure, pc) = self
+            .frame_closure(frame)
+            .ok_or_else(|| format!("no Lua frame #{}", frame))?;
+        let proto = &closure.0.proto;
+        let mut names = Vec::new();
+        let mut values = Vec::new();
+        for (i, upvalue) in closure.0.upvalues.read().iter().enumerate() {
+            if let Some(name) = proto.upvalue_name(UpValueIndex(i as u8)) {
+                names.push(name);
+                values.push(upvalue.get());
+            }
+        }
+        for local in proto.active_locals(pc) {
+            names.push(local.name);
+            values.push(
+                self.thread
+                    .frame_register(frame + 1, local.register)
+                    .unwrap_or(Value::Nil),
+            );
+        }
+
+        // Later declarations shadow earlier ones, as locals shadow upvalues.  Names which are not
+        // identifiers, such as those of hidden for loop variables, are skipped.
+        let mut source = StdString::new();
+        let mut args = Vec::new();
+        for (name, value) in names.into_iter().zip(values) {
+            let name = lossy(name);
+            if is_identifier(&name) && name != "_ENV" {
+                source.push_str(if args.is_empty() { "local " } else { ", " });
+                source.push_str(&name);
+                args.push(value);
+            }
+        }
+        if !args.is_empty() {
+            source.push_str(" = ...\n");
+        }
+        source.push_str("return ");
+        source.push_str(expression);
+
+        let interned_strings = self.thread.interned_strings();
+        let proto = compile_named(mc, interned_strings, "(eval)", source.as_bytes())
+            .map_err(display_error)?;
+        let closure = Closure::new(mc, proto, Some(self.globals)).map_err(|err| err.to_string())?;
+        let thread = Thread::new(mc, self.globals, interned_strings, false);
+        thread.inherit_limits(self.thread);
+        thread
+            .start(mc, Function::Closure(closure), &args)
+            .map_err(|err| err.to_string())?;
+        Ok(thread)
+    }
+}
+
+// Whether the chunk with the given name matches the source of a breakpoint.
+fn source_matches(source: Option<&str>, chunk_name: Option<&str>) -> bool {
+    match (source, chunk_name) {
+        (None, _) => true,
+        (Some(source), Some(chunk_name)) => {
+            Path::new(chunk_name).ends_with(source) || Path::new(source).ends_with(chunk_name)
+        }
+        (Some(_), None) => false,
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    const KEYWORDS: &[&str] = &[
+        "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if",
+        "in", "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+    ];
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_') && !KEYWORDS.contains(&name)
+}
+
+// Lists the fields of a table, named and ordered as described for `Debugger::fields`.
+fn table_fields(table: Table) -> Vec<(StdString, Value)> {
+    let mut fields = table.0.read().iter().collect::<Vec<_>>();
+    fields.sort_by(|(a, _), (b, _)| match (a, b) {
+        (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+        (Value::Integer(_), _) => Ordering::Less,
+        (_, Value::Integer(_)) => Ordering::Greater,
+        (a, b) => field_name(*a).cmp(&field_name(*b)),
+    });
+    fields
+        .into_iter()
+        .map(|(key, value)| (field_name(key), value))
+        .collect()
+}
+
+fn field_name(key: Value) -> StdString {
+    match key {
+        Value::String(name) if is_identifier(&lossy(name)) => lossy(name),
+        key => format!("[{}]", describe(key)),
+    }
+}
+
+fn variable(name: StdString, value: Value, table: Option<usize>) -> Variable {
+    Variable {
+        name,
+        type_name: value.type_name(),
+        value: describe(value),
+        table,
+    }
+}
+
+// Displays a value, quoting strings.
+fn describe(value: Value) -> StdString {
+    match value {
+        Value::String(s) => format!("{:?}", StdString::from_utf8_lossy(s.as_bytes())),
+        value => display(value),
+    }
+}
+
+fn display(value: Value) -> StdString {
+    let mut buf = Vec::new();
+    value.display(&mut buf).unwrap();
+    StdString::from_utf8_lossy(&buf).into_owned()
+}
+
+fn lossy(s: String) -> StdString {
+    StdString::from_utf8_lossy(s.as_bytes()).into_owned()
+}
+
+fn display_error(error: Error) -> StdString {
+    match error {
+        Error::RuntimeError(error) => display(error.0),
+        error => error.to_string(),
+    }
+}
@@ -5,8 +5,9 @@ use std::{fmt, io};
 use gc_arena::{Collect, MutationContext, StaticCollect};
 
 use crate::{
-    BadThreadMode, BinaryOperatorError, ClosureError, CompilerError, InternedStringSet,
-    InvalidTableKey, ParserError, StringError, ThreadError, Value,
+    BadThreadMode, BinaryOperatorError, ChunkError, ClosureError, CompilerError, InternedStringSet,
+    InvalidTableKey, OperationError, ParserError, StackOverflow, StringError, TableError,
+    ThreadError, Value,
 };
 
 #[derive(Debug, Clone, Copy, Collect)]
@@ -28,6 +29,33 @@ impl fmt::Display for TypeError {
     }
 }
 
+/// Errors imposed on running scripts by the host.
+///
+/// These cannot be caught by `pcall` or `coroutine.resume`, and always unwind every frame of every
+/// thread involved back up to the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub enum HostError {
+    OutOfFuel,
+    OutOfMemory,
+    Interrupted,
+    /// Raised by `os.exit`, the host decides how to handle the requested exit code.
+    Exit(i32),
+}
+
+impl StdError for HostError {}
+
+impl fmt::Display for HostError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HostError::OutOfFuel => write!(fmt, "out of fuel"),
+            HostError::OutOfMemory => write!(fmt, "not enough memory"),
+            HostError::Interrupted => write!(fmt, "interrupted"),
+            HostError::Exit(code) => write!(fmt, "exit with code {}", code),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Collect)]
 #[collect(no_drop)]
 pub struct RuntimeError<'gc>(pub Value<'gc>);
@@ -49,14 +77,18 @@ pub enum Error<'gc> {
     IoError(StaticCollect<io::Error>),
     ParserError(ParserError),
     CompilerError(CompilerError),
+    ChunkError(ChunkError),
     ClosureError(ClosureError),
     InvalidTableKey(InvalidTableKey),
     StringError(StringError),
     ThreadError(ThreadError),
+    StackOverflow(StackOverflow),
     BadThreadMode(BadThreadMode),
     TypeError(TypeError),
     BinaryOperatorError(BinaryOperatorError),
+    OperationError(OperationError),
     RuntimeError(RuntimeError<'gc>),
+    HostError(HostError),
 }
 
 impl<'gc> StdError for Error<'gc> {}
@@ -67,14 +99,18 @@ impl<'gc> fmt::Display for Error<'gc> {
             Error::IoError(error) => write!(fmt, "i/o error: {}", error.0),
             Error::ParserError(error) => write!(fmt, "parser error: {}", error),
             Error::CompilerError(error) => write!(fmt, "compiler error: {}", error),
+            Error::ChunkError(error) => write!(fmt, "chunk error: {}", error),
             Error::ClosureError(error) => write!(fmt, "closure error: {}", error),
             Error::InvalidTableKey(error) => write!(fmt, "invalid table key: {}", error),
             Error::StringError(error) => write!(fmt, "string error: {}", error),
             Error::ThreadError(error) => write!(fmt, "thread error: {}", error),
+            Error::StackOverflow(error) => write!(fmt, "{}", error),
             Error::BadThreadMode(error) => write!(fmt, "bad thread mode: {}", error),
             Error::TypeError(error) => write!(fmt, "type error: {}", error),
             Error::BinaryOperatorError(error) => write!(fmt, "operator error: {}", error),
+            Error::OperationError(error) => write!(fmt, "{}", error),
             Error::RuntimeError(error) => write!(fmt, "runtime error: {}", error),
+            Error::HostError(error) => write!(fmt, "host error: {}", error),
         }
     }
 }
@@ -97,6 +133,12 @@ impl<'gc> From<CompilerError> for Error<'gc> {
     }
 }
 
+impl<'gc> From<ChunkError> for Error<'gc> {
+    fn from(error: ChunkError) -> Error<'gc> {
+        Error::ChunkError(error)
+    }
+}
+
 impl<'gc> From<ClosureError> for Error<'gc> {
     fn from(error: ClosureError) -> Error<'gc> {
         Error::ClosureError(error)
@@ -109,6 +151,15 @@ impl<'gc> From<InvalidTableKey> for Error<'gc> {
     }
 }
 
+impl<'gc> From<TableError> for Error<'gc> {
+    fn from(error: TableError) -> Error<'gc> {
+        match error {
+            TableError::InvalidKey(error) => Error::InvalidTableKey(error),
+            TableError::HostError(error) => Error::HostError(error),
+        }
+    }
+}
+
 impl<'gc> From<StringError> for Error<'gc> {
     fn from(error: StringError) -> Error<'gc> {
         Error::StringError(error)
@@ -121,6 +172,12 @@ impl<'gc> From<ThreadError> for Error<'gc> {
     }
 }
 
+impl<'gc> From<StackOverflow> for Error<'gc> {
+    fn from(error: StackOverflow) -> Error<'gc> {
+        Error::StackOverflow(error)
+    }
+}
+
 impl<'gc> From<BadThreadMode> for Error<'gc> {
     fn from(error: BadThreadMode) -> Error<'gc> {
         Error::BadThreadMode(error)
@@ -139,30 +196,52 @@ impl<'gc> From<BinaryOperatorError> for Error<'gc> {
     }
 }
 
+impl<'gc> From<OperationError> for Error<'gc> {
+    fn from(error: OperationError) -> Error<'gc> {
+        Error::OperationError(error)
+    }
+}
+
 impl<'gc> From<RuntimeError<'gc>> for Error<'gc> {
     fn from(error: RuntimeError<'gc>) -> Error<'gc> {
         Error::RuntimeError(error)
     }
 }
 
+impl<'gc> From<HostError> for Error<'gc> {
+    fn from(error: HostError) -> Error<'gc> {
+        Error::HostError(error)
+    }
+}
+
 impl<'gc> Error<'gc> {
+    /// Whether this error may be caught by protected calls, which is true of every error except a
+    /// `HostError`.
+    pub fn is_catchable(&self) -> bool {
+        !matches!(self, Error::HostError(_))
+    }
+
     pub fn to_static(self) -> StaticError {
         match self {
             Error::IoError(error) => StaticError::IoError(error.0),
             Error::ParserError(error) => StaticError::ParserError(error),
             Error::CompilerError(error) => StaticError::CompilerError(error),
+            Error::ChunkError(error) => StaticError::ChunkError(error),
             Error::ClosureError(error) => StaticError::ClosureError(error),
             Error::InvalidTableKey(error) => StaticError::InvalidTableKey(error),
             Error::StringError(error) => StaticError::StringError(error),
             Error::ThreadError(error) => StaticError::ThreadError(error),
+            Error::StackOverflow(error) => StaticError::StackOverflow(error),
             Error::BadThreadMode(error) => StaticError::BadThreadMode(error),
             Error::TypeError(error) => StaticError::TypeError(error),
             Error::BinaryOperatorError(error) => StaticError::BinaryOperatorError(error),
+            Error::OperationError(error) => StaticError::OperationError(error),
             Error::RuntimeError(error) => {
                 let mut buf = Vec::new();
                 error.0.display(&mut buf).unwrap();
                 StaticError::RuntimeError(StdString::from_utf8_lossy(&buf).to_owned().to_string())
             }
+            Error::HostError(error) => StaticError::HostError(error),
         }
     }
 
@@ -187,14 +266,18 @@ pub enum StaticError {
     IoError(io::Error),
     ParserError(ParserError),
     CompilerError(CompilerError),
+    ChunkError(ChunkError),
     ClosureError(ClosureError),
     InvalidTableKey(InvalidTableKey),
     StringError(StringError),
     ThreadError(ThreadError),
+    StackOverflow(StackOverflow),
     BadThreadMode(BadThreadMode),
     TypeError(TypeError),
     BinaryOperatorError(BinaryOperatorError),
+    OperationError(OperationError),
     RuntimeError(String),
+    HostError(HostError),
 }
 
 impl StdError for StaticError {}
@@ -205,14 +288,18 @@ impl fmt::Display for StaticError {
             StaticError::IoError(error) => write!(fmt, "i/o error: {}", error),
             StaticError::ParserError(error) => write!(fmt, "parser error: {}", error),
             StaticError::CompilerError(error) => write!(fmt, "compiler error: {}", error),
+            StaticError::ChunkError(error) => write!(fmt, "chunk error: {}", error),
             StaticError::ClosureError(error) => write!(fmt, "closure error: {}", error),
             StaticError::InvalidTableKey(error) => write!(fmt, "invalid table key: {}", error),
             StaticError::StringError(error) => write!(fmt, "string error: {}", error),
             StaticError::ThreadError(error) => write!(fmt, "thread error: {}", error),
+            StaticError::StackOverflow(error) => write!(fmt, "{}", error),
             StaticError::BadThreadMode(error) => write!(fmt, "bad thread mode: {}", error),
             StaticError::TypeError(error) => write!(fmt, "type error: {}", error),
             StaticError::BinaryOperatorError(error) => write!(fmt, "operator error: {}", error),
+            StaticError::OperationError(error) => write!(fmt, "{}", error),
             StaticError::RuntimeError(error) => write!(fmt, "runtime error: {}", error),
+            StaticError::HostError(error) => write!(fmt, "host error: {}", error),
         }
     }
 }
@@ -1,4 +1,8 @@
-use std::io::{self, BufRead, BufReader, Read};
+use std::cell::{RefCell, RefMut};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::rc::Rc;
+
+use gc_arena::Collect;
 
 /// Takes an `R: BufRead` and:
 ///
@@ -51,3 +55,126 @@ pub fn buffered_read<R: Read>(r: R) -> Result<BufReader<R>, io::Error> {
     skip_prefix(&mut r)?;
     Ok(r)
 }
+
+/// The standard input, output, and error streams of a Lua state, used by `print` and the `io`
+/// library.
+///
+/// By default these are the process's standard streams, but the host may redirect any of them to
+/// arbitrary Rust `BufRead` / `Write` objects.  Cloning a `StdStreams` produces another handle to
+/// the same set of streams, so a redirection is visible to every clone.
+#[derive(Clone, Collect)]
+#[collect(require_static)]
+pub struct StdStreams(Rc<StdStreamsState>);
+
+struct StdStreamsState {
+    stdin: RefCell<Box<dyn BufRead>>,
+    stdout: RefCell<Box<dyn Write>>,
+    stderr: RefCell<Box<dyn Write>>,
+}
+
+impl StdStreams {
+    /// Creates a set of streams connected to the process's standard input, output, and error.
+    pub fn new() -> StdStreams {
+        StdStreams::with(BufReader::new(io::stdin()), io::stdout(), io::stderr())
+    }
+
+    pub fn with<I, O, E>(stdin: I, stdout: O, stderr: E) -> StdStreams
+    where
+        I: BufRead + 'static,
+        O: Write + 'static,
+        E: Write + 'static,
+    {
+        StdStreams(Rc::new(StdStreamsState {
+            stdin: RefCell::new(Box::new(stdin)),
+            stdout: RefCell::new(Box::new(stdout)),
+            stderr: RefCell::new(Box::new(stderr)),
+        }))
+    }
+
+    pub fn set_stdin<R: BufRead + 'static>(&self, stdin: R) {
+        *self.0.stdin.borrow_mut() = Box::new(stdin);
+    }
+
+    pub fn set_stdout<W: Write + 'static>(&self, stdout: W) {
+        let _ = self.stdout().flush();
+        *self.0.stdout.borrow_mut() = Box::new(stdout);
+    }
+
+    pub fn set_stderr<W: Write + 'static>(&self, stderr: W) {
+        let _ = self.stderr().flush();
+        *self.0.stderr.borrow_mut() = Box::new(stderr);
+    }
+
+    pub fn stdin(&self) -> RefMut<'_, dyn BufRead> {
+        RefMut::map(self.0.stdin.borrow_mut(), |r| &mut **r)
+    }
+
+    pub fn stdout(&self) -> RefMut<'_, dyn Write> {
+        RefMut::map(self.0.stdout.borrow_mut(), |w| &mut **w)
+    }
+
+    pub fn stderr(&self) -> RefMut<'_, dyn Write> {
+        RefMut::map(self.0.stderr.borrow_mut(), |w| &mut **w)
+    }
+}
+
+impl Default for StdStreams {
+    fn default() -> StdStreams {
+        StdStreams::new()
+    }
+}
+
+/// A `Write` sink which appends everything written to a shared in-memory buffer, useful for
+/// capturing script output.  Clones share the same buffer.
+#[derive(Clone, Default)]
+pub struct OutputBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl OutputBuffer {
+    pub fn new() -> OutputBuffer {
+        OutputBuffer::default()
+    }
+
+    /// Returns a copy of everything written so far.
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+
+    /// Returns everything written so far and clears the buffer.
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.borrow_mut())
+    }
+}
+
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
+
+/// A `Write` sink which passes everything written to a callback.
+///
+/// `print` performs a single write per call, so a `CallbackWriter` used as standard output sees
+/// one complete line per `print`.
+pub struct CallbackWriter<F>(F);
+
+impl<F: FnMut(&[u8])> CallbackWriter<F> {
+    pub fn new(f: F) -> CallbackWriter<F> {
+        CallbackWriter(f)
+    }
+}
+
+impl<F: FnMut(&[u8])> Write for CallbackWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        (self.0)(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
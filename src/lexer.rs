@@ -116,12 +116,25 @@ impl fmt::Display for LexerError {
     }
 }
 
+/// A line number in a source file, stored 0-indexed but displayed 1-indexed as in Lua error
+/// messages.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Collect)]
+#[collect(require_static)]
+pub struct LineNumber(pub u64);
+
+impl fmt::Display for LineNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0 + 1)
+    }
+}
+
 pub struct Lexer<R, CS> {
     source: Option<R>,
     create_string: CS,
     peek_buffer: Vec<u8>,
     string_buffer: Vec<u8>,
     line_number: u64,
+    column_number: u64,
 }
 
 impl<R, S, CS> Lexer<R, CS>
@@ -136,6 +149,7 @@ where
             peek_buffer: Vec::new(),
             string_buffer: Vec::new(),
             line_number: 0,
+            column_number: 0,
         }
     }
 
@@ -144,6 +158,11 @@ where
         self.line_number
     }
 
+    /// Current byte offset into the current line of the source file, 0-indexed
+    pub fn column_number(&self) -> u64 {
+        self.column_number
+    }
+
     pub fn skip_whitespace(&mut self) -> Result<(), LexerError> {
         let mut do_skip_whitespace = || {
             while let Some(c) = self.peek(0)? {
@@ -390,6 +409,7 @@ where
         }
 
         self.line_number += 1;
+        self.column_number = 0;
         Ok(())
     }
 
@@ -739,6 +759,7 @@ where
             "cannot advance over un-peeked characters"
         );
         self.peek_buffer.drain(0..n);
+        self.column_number += n as u64;
     }
 
     fn take_string(&mut self) -> S {
@@ -1,40 +1,62 @@
 #[macro_use]
+mod opcode;
+
+mod assembly;
+#[macro_use]
 mod callback;
+mod chunk;
 mod closure;
 mod compiler;
 mod constant;
+mod debugger;
 mod error;
 pub mod io;
 mod lexer;
 #[macro_use]
 mod lua;
-mod opcode;
 pub mod parser;
 mod string;
 mod table;
 mod thread;
 mod types;
+mod userdata;
 mod value;
+mod verifier;
 
 mod stdlib;
 
-pub use callback::{Callback, CallbackResult, CallbackReturn, Continuation};
+pub use assembly::{assemble, disassemble, AssemblerError, AssemblerErrorKind};
+pub use callback::{
+    CallContext, Callback, CallbackResult, CallbackReturn, Continuation, ValueBuffer,
+};
+pub use chunk::{
+    is_binary_chunk, read_chunk, write_chunk, ChunkError, CHUNK_SIGNATURE, CHUNK_VERSION,
+};
 pub use closure::{
-    Closure, ClosureError, ClosureState, FunctionProto, UpValue, UpValueDescriptor, UpValueState,
+    Closure, ClosureError, ClosureState, FunctionProto, LocalVariable, UpValue, UpValueDescriptor,
+    UpValueState,
 };
-pub use compiler::{compile, compile_chunk, CompilerError};
+pub use compiler::{compile, compile_chunk, compile_named, CompilerError};
 pub use constant::Constant;
-pub use error::{Error, RuntimeError, StaticError, TypeError};
-pub use lexer::{Lexer, LexerError, Token};
+pub use debugger::{
+    Breakpoint, DebugEvent, Debugger, PauseHandle, PauseReason, Resume, StackFrame, Variable,
+};
+pub use error::{Error, HostError, RuntimeError, StaticError, TypeError};
+pub use lexer::{Lexer, LexerError, LineNumber, Token};
 pub use lua::{Lua, Root};
 pub use opcode::OpCode;
-pub use parser::{parse_chunk, ParserError};
-pub use string::{InternedStringSet, String, StringError};
-pub use table::{InvalidTableKey, Table, TableState};
+pub use parser::{parse_chunk, parse_chunk_with_line, ParserError};
+pub use stdlib::{load_os_with, Clock, OsOptions, SystemClock};
+pub use string::{InternedStringSet, LongString, String, StringError};
+pub use table::{InvalidTableKey, Table, TableError, TableState};
 pub use thread::{
-    BadThreadMode, BinaryOperatorError, Thread, ThreadError, ThreadMode, ThreadSequence,
+    BadThreadMode, BinaryOperatorError, FrameInfo, Fuel, Hook, HookEvent, HookMask,
+    InterruptHandle, Operation, OperationError, StackOverflow, Thread, ThreadError, ThreadMode,
+    ThreadSequence, VariableInfo,
 };
 pub use types::{
     ConstantIndex16, ConstantIndex8, Opt254, PrototypeIndex, RegisterIndex, UpValueIndex, VarCount,
 };
+pub use userdata::{UserData, UserDataState};
 pub use value::{Function, Value};
+pub use verifier::{verify, VerifierError, VerifierErrorKind, MAX_STACK_SIZE};
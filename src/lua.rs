@@ -1,9 +1,14 @@
-use gc_arena::{ArenaParameters, Collect, MutationContext};
+use gc_arena::{ArenaParameters, Collect, Gc, MutationContext};
 use gc_sequence::{make_sequencable_arena, Sequence};
 
 use crate::{
-    stdlib::{load_base, load_coroutine, load_math, load_string},
-    InternedStringSet, Table, Thread,
+    io::StdStreams,
+    stdlib::{
+        load_base, load_coroutine, load_debug, load_io, load_math, load_os, load_string,
+        load_table, load_utf8,
+    },
+    thread::MemoryTracker,
+    Fuel, InternedStringSet, InterruptHandle, Table, Thread, ThreadMode,
 };
 
 #[derive(Collect, Clone, Copy)]
@@ -12,20 +17,29 @@ pub struct Root<'gc> {
     pub main_thread: Thread<'gc>,
     pub globals: Table<'gc>,
     pub interned_strings: InternedStringSet<'gc>,
+    pub std_streams: Gc<'gc, StdStreams>,
 }
 
 impl<'gc> Root<'gc> {
     pub fn new(mc: MutationContext<'gc, '_>) -> Root<'gc> {
+        let globals = Table::new(mc);
+        let interned_strings = InternedStringSet::new(mc);
         let root = Root {
-            main_thread: Thread::new(mc, false),
-            globals: Table::new(mc),
-            interned_strings: InternedStringSet::new(mc),
+            main_thread: Thread::new(mc, globals, interned_strings, false),
+            globals,
+            interned_strings,
+            std_streams: Gc::allocate(mc, StdStreams::new()),
         };
 
         load_base(mc, root, root.globals);
         load_coroutine(mc, root, root.globals);
+        load_debug(mc, root, root.globals);
+        load_io(mc, root, root.globals);
         load_math(mc, root, root.globals);
+        load_os(mc, root, root.globals);
         load_string(mc, root, root.globals);
+        load_table(mc, root, root.globals);
+        load_utf8(mc, root, root.globals);
 
         root
     }
@@ -37,15 +51,96 @@ pub use lua_arena::Arena;
 pub use lua_arena::Sequencer;
 
 /// Simpler wrapper for `Arena` that automatically garbage collects at reasonable intervals.
-pub struct Lua(Option<lua_arena::Arena>);
+pub struct Lua {
+    arena: Option<lua_arena::Arena>,
+    memory: MemoryTracker,
+    interrupt: InterruptHandle,
+}
 
 const COLLECTOR_GRANULARITY: f64 = 1024.0;
 
 impl Lua {
     pub fn new() -> Lua {
-        Lua(Some(Arena::new(ArenaParameters::default(), |mc| {
-            Root::new(mc)
-        })))
+        let memory = MemoryTracker::new();
+        let interrupt = InterruptHandle::new();
+        let active = memory.enter();
+        let arena = Arena::new(ArenaParameters::default(), {
+            let memory = memory.clone();
+            let interrupt = interrupt.clone();
+            move |mc| {
+                let root = Root::new(mc);
+                root.main_thread.set_memory_tracker(Some(memory));
+                root.main_thread.set_interrupt_handle(Some(interrupt));
+                root
+            }
+        });
+        drop(active);
+        memory.record(arena.total_allocated());
+        Lua {
+            arena: Some(arena),
+            memory,
+            interrupt,
+        }
+    }
+
+    /// Returns a handle to the standard input, output, and error streams used by `print` and the
+    /// `io` library.  These may be redirected at any time, for example to an `io::OutputBuffer` to
+    /// capture script output.
+    pub fn std_streams(&mut self) -> StdStreams {
+        self.mutate(|_, root| (*root.std_streams).clone())
+    }
+
+    /// Limits the work done by scripts running on the main thread, and any coroutines they create,
+    /// to the given fuel.  Passing `None` removes any limit.
+    pub fn set_fuel(&mut self, fuel: Option<Fuel>) {
+        self.mutate(move |_, root| root.main_thread.set_fuel(fuel))
+    }
+
+    /// Limits the memory used by the Lua arena to the given number of bytes, or removes any limit.
+    ///
+    /// Memory usage is checked in-between mutations and sequence steps.  Once it exceeds the limit,
+    /// a full garbage collection is performed, and if usage is still over the limit then scripts
+    /// running on the main thread, and any coroutines they create, fail with
+    /// `HostError::OutOfMemory`, which cannot be caught by `pcall`.
+    ///
+    /// Growing a table or concatenating strings also fails if it would take usage over the limit,
+    /// even once garbage has been collected.
+    ///
+    /// Memory allocated by the garbage collector is counted along with the storage behind tables
+    /// and the contents of long strings.  Other internal buffers, such as the stacks of threads, are
+    /// not counted.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory.set_limit(limit);
+    }
+
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.memory.limit()
+    }
+
+    /// Returns the current memory usage of the Lua arena in bytes.
+    pub fn memory_usage(&self) -> usize {
+        self.arena
+            .as_ref()
+            .unwrap()
+            .total_allocated()
+            .saturating_add(self.memory.external())
+    }
+
+    /// Returns the highest memory usage observed in-between mutations and sequence steps since the
+    /// `Lua` was created or the peak was last reset.
+    pub fn peak_memory_usage(&self) -> usize {
+        self.memory.peak().max(self.memory_usage())
+    }
+
+    pub fn reset_peak_memory_usage(&mut self) {
+        self.memory
+            .record(self.arena.as_ref().unwrap().total_allocated());
+        self.memory.reset_peak();
+    }
+
+    /// Returns a handle which may be used to cancel running scripts from another OS thread.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
     }
 
     /// Runs a single action inside the Lua arena, during which no garbage collection may take place.
@@ -54,11 +149,20 @@ impl Lua {
         R: 'static,
         F: for<'gc> FnOnce(MutationContext<'gc, '_>, Root<'gc>) -> R,
     {
-        let arena = self.0.as_mut().unwrap();
+        let arena = self.arena.as_mut().unwrap();
+        let active = self.memory.enter();
         let r = arena.mutate(move |mc, root| f(mc, *root));
+        drop(active);
         if arena.allocation_debt() > COLLECTOR_GRANULARITY {
             arena.collect_debt();
         }
+        if self.memory.record(arena.total_allocated()) | self.memory.take_collection_request() {
+            // A collection cycle may already be in progress, so finish it and then run a whole new
+            // cycle to free everything that is unreachable.
+            arena.collect_all();
+            arena.collect_all();
+            self.memory.record(arena.total_allocated());
+        }
         r
     }
 
@@ -69,11 +173,25 @@ impl Lua {
         R: 'static,
         F: for<'gc> FnOnce(Root<'gc>) -> Box<dyn Sequence<'gc, Output = R> + 'gc>,
     {
-        let mut sequencer = self.0.take().unwrap().sequence(move |root| f(*root));
+        let active = self.memory.enter();
+        let mut sequencer = self.arena.take().unwrap().sequence(move |root| f(*root));
+        drop(active);
         loop {
-            match sequencer.step() {
+            let active = self.memory.enter();
+            let step = sequencer.step();
+            drop(active);
+            match step {
                 Ok((arena, output)) => {
-                    self.0 = Some(arena);
+                    self.arena = Some(arena);
+                    if self.interrupt.clear() {
+                        // Discard the error of an interrupted main thread if the sequence did not
+                        // take it, so that the main thread may be used again.
+                        self.mutate(|mc, root| {
+                            if root.main_thread.mode() == ThreadMode::Results {
+                                root.main_thread.take_results(mc);
+                            }
+                        });
+                    }
                     return output;
                 }
                 Err(s) => {
@@ -81,6 +199,13 @@ impl Lua {
                     if sequencer.allocation_debt() > COLLECTOR_GRANULARITY {
                         sequencer.collect_debt();
                     }
+                    if self.memory.record(sequencer.total_allocated())
+                        | self.memory.take_collection_request()
+                    {
+                        sequencer.collect_all();
+                        sequencer.collect_all();
+                        self.memory.record(sequencer.total_allocated());
+                    }
                 }
             }
         }
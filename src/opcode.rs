@@ -515,3 +515,111 @@ pub enum OpCode {
         source: RegisterIndex,
     },
 }
+
+// Invokes the given macro with every `OpCode` variant and its field names, in declaration order and
+// each paired with a numeric tag.  Tags are part of the binary chunk format, so new opcodes must be
+// given new tags rather than reusing or renumbering existing ones.
+macro_rules! opcode_table {
+    ($m:ident) => {
+        $m! {
+            0 => Move { dest, source },
+            1 => LoadConstant { dest, constant },
+            2 => LoadBool { dest, value, skip_next },
+            3 => LoadNil { dest, count },
+            4 => NewTable { dest },
+            5 => GetTableR { dest, table, key },
+            6 => GetTableC { dest, table, key },
+            7 => SetTableRR { table, key, value },
+            8 => SetTableRC { table, key, value },
+            9 => SetTableCR { table, key, value },
+            10 => SetTableCC { table, key, value },
+            11 => GetUpTableR { dest, table, key },
+            12 => GetUpTableC { dest, table, key },
+            13 => SetUpTableRR { table, key, value },
+            14 => SetUpTableRC { table, key, value },
+            15 => SetUpTableCR { table, key, value },
+            16 => SetUpTableCC { table, key, value },
+            17 => Call { func, args, returns },
+            18 => TailCall { func, args },
+            19 => Return { start, count },
+            20 => VarArgs { dest, count },
+            21 => Jump { offset, close_upvalues },
+            22 => Test { value, is_true },
+            23 => TestSet { dest, value, is_true },
+            24 => Closure { dest, proto },
+            25 => NumericForPrep { base, jump },
+            26 => NumericForLoop { base, jump },
+            27 => GenericForCall { base, var_count },
+            28 => GenericForLoop { base, jump },
+            29 => SelfR { base, table, key },
+            30 => SelfC { base, table, key },
+            31 => Concat { dest, source, count },
+            32 => GetUpValue { dest, source },
+            33 => SetUpValue { dest, source },
+            34 => Length { dest, source },
+            35 => EqRR { skip_if, left, right },
+            36 => EqRC { skip_if, left, right },
+            37 => EqCR { skip_if, left, right },
+            38 => EqCC { skip_if, left, right },
+            39 => LessRR { skip_if, left, right },
+            40 => LessRC { skip_if, left, right },
+            41 => LessCR { skip_if, left, right },
+            42 => LessCC { skip_if, left, right },
+            43 => LessEqRR { skip_if, left, right },
+            44 => LessEqRC { skip_if, left, right },
+            45 => LessEqCR { skip_if, left, right },
+            46 => LessEqCC { skip_if, left, right },
+            47 => Not { dest, source },
+            48 => Minus { dest, source },
+            49 => AddRR { dest, left, right },
+            50 => AddRC { dest, left, right },
+            51 => AddCR { dest, left, right },
+            52 => AddCC { dest, left, right },
+            53 => SubRR { dest, left, right },
+            54 => SubRC { dest, left, right },
+            55 => SubCR { dest, left, right },
+            56 => SubCC { dest, left, right },
+            57 => MulRR { dest, left, right },
+            58 => MulRC { dest, left, right },
+            59 => MulCR { dest, left, right },
+            60 => MulCC { dest, left, right },
+            61 => DivRR { dest, left, right },
+            62 => DivRC { dest, left, right },
+            63 => DivCR { dest, left, right },
+            64 => DivCC { dest, left, right },
+            65 => IDivRR { dest, left, right },
+            66 => IDivRC { dest, left, right },
+            67 => IDivCR { dest, left, right },
+            68 => IDivCC { dest, left, right },
+            69 => ModRR { dest, left, right },
+            70 => ModRC { dest, left, right },
+            71 => ModCR { dest, left, right },
+            72 => ModCC { dest, left, right },
+            73 => PowRR { dest, left, right },
+            74 => PowRC { dest, left, right },
+            75 => PowCR { dest, left, right },
+            76 => PowCC { dest, left, right },
+            77 => BitAndRR { dest, left, right },
+            78 => BitAndRC { dest, left, right },
+            79 => BitAndCR { dest, left, right },
+            80 => BitAndCC { dest, left, right },
+            81 => BitOrRR { dest, left, right },
+            82 => BitOrRC { dest, left, right },
+            83 => BitOrCR { dest, left, right },
+            84 => BitOrCC { dest, left, right },
+            85 => BitXorRR { dest, left, right },
+            86 => BitXorRC { dest, left, right },
+            87 => BitXorCR { dest, left, right },
+            88 => BitXorCC { dest, left, right },
+            89 => ShiftLeftRR { dest, left, right },
+            90 => ShiftLeftRC { dest, left, right },
+            91 => ShiftLeftCR { dest, left, right },
+            92 => ShiftLeftCC { dest, left, right },
+            93 => ShiftRightRR { dest, left, right },
+            94 => ShiftRightRC { dest, left, right },
+            95 => ShiftRightCR { dest, left, right },
+            96 => ShiftRightCC { dest, left, right },
+            97 => BitNot { dest, source },
+        }
+    };
+}
@@ -5,7 +5,7 @@ use std::rc::Rc;
 
 use gc_arena::Collect;
 
-use crate::{Lexer, LexerError, Token};
+use crate::{Lexer, LexerError, LineNumber, Token};
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Chunk<S> {
@@ -14,8 +14,9 @@ pub struct Chunk<S> {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Block<S> {
-    pub statements: Vec<Statement<S>>,
-    pub return_statement: Option<ReturnStatement<S>>,
+    /// Each statement, along with the line that it starts on
+    pub statements: Vec<(LineNumber, Statement<S>)>,
+    pub return_statement: Option<(LineNumber, ReturnStatement<S>)>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -196,6 +197,7 @@ pub struct SuffixedExpression<S> {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct FunctionDefinition<S> {
+    pub line_number: LineNumber,
     pub parameters: Vec<S>,
     pub has_varargs: bool,
     pub body: Block<S>,
@@ -292,17 +294,43 @@ where
     S: fmt::Debug + PartialEq,
     CS: FnMut(&[u8]) -> S,
 {
-    Parser {
+    parse_chunk_with_line(source, create_string).map_err(|(error, _)| error)
+}
+
+/// Like `parse_chunk`, but on failure also returns the line the error was found on.
+///
+/// This is the line of the token the parser rejected, or for lexer errors the line the lexer had
+/// reached.
+pub fn parse_chunk_with_line<R, S, CS>(
+    source: R,
+    create_string: CS,
+) -> Result<Chunk<S>, (ParserError, LineNumber)>
+where
+    R: Read,
+    S: fmt::Debug + PartialEq,
+    CS: FnMut(&[u8]) -> S,
+{
+    let mut parser = Parser {
         lexer: Lexer::new(source, create_string),
         read_buffer: Vec::new(),
+        last_line: LineNumber(0),
         recursion_guard: Rc::new(()),
-    }
-    .parse_chunk()
+    };
+    parser.parse_chunk().map_err(|error| {
+        let line_number = match error {
+            ParserError::LexerError(_) => LineNumber(parser.lexer.line_number()),
+            _ => parser.last_line,
+        };
+        (error, line_number)
+    })
 }
 
 struct Parser<R, S, CS> {
     lexer: Lexer<R, CS>,
-    read_buffer: Vec<Token<S>>,
+    // Tokens that have been read ahead, along with the line each one starts on
+    read_buffer: Vec<(Token<S>, LineNumber)>,
+    // The line of the token most recently taken from the read buffer
+    last_line: LineNumber,
     recursion_guard: Rc<()>,
 }
 
@@ -315,6 +343,7 @@ where
     fn parse_chunk(&mut self) -> Result<Chunk<S>, ParserError> {
         let block = self.parse_block()?;
         if self.look_ahead(0)? != None {
+            self.last_line = self.line_number()?;
             Err(ParserError::EndOfStream { expected: None })
         } else {
             Ok(Chunk { block })
@@ -333,12 +362,14 @@ where
                     self.take_next()?;
                 }
                 Some(&Token::Return) => {
-                    return_statement = Some(self.parse_return_statement()?);
+                    let line_number = self.line_number()?;
+                    return_statement = Some((line_number, self.parse_return_statement()?));
                     break;
                 }
                 None => break,
                 _ => {
-                    statements.push(self.parse_statement()?);
+                    let line_number = self.line_number()?;
+                    statements.push((line_number, self.parse_statement()?));
                 }
             }
         }
@@ -507,6 +538,7 @@ where
     }
 
     fn parse_function_statement(&mut self) -> Result<FunctionStatement<S>, ParserError> {
+        let line_number = self.line_number()?;
         self.expect_next(Token::Function)?;
 
         let name = self.expect_name()?;
@@ -527,7 +559,7 @@ where
             }
         }
 
-        let definition = self.parse_function_definition()?;
+        let definition = self.parse_function_definition(line_number)?;
 
         Ok(FunctionStatement {
             name,
@@ -538,10 +570,11 @@ where
     }
 
     fn parse_local_function_statement(&mut self) -> Result<LocalFunctionStatement<S>, ParserError> {
+        let line_number = self.line_number()?;
         self.expect_next(Token::Function)?;
 
         let name = self.expect_name()?;
-        let definition = self.parse_function_definition()?;
+        let definition = self.parse_function_definition(line_number)?;
 
         Ok(LocalFunctionStatement { name, definition })
     }
@@ -701,8 +734,9 @@ where
             }
             Token::LeftBrace => SimpleExpression::TableConstructor(self.parse_table_constructor()?),
             Token::Function => {
+                let line_number = self.line_number()?;
                 self.take_next()?;
-                SimpleExpression::Function(self.parse_function_definition()?)
+                SimpleExpression::Function(self.parse_function_definition(line_number)?)
             }
             _ => SimpleExpression::Suffixed(self.parse_suffixed_expression()?),
         })
@@ -822,7 +856,11 @@ where
         Ok(SuffixedExpression { primary, suffixes })
     }
 
-    fn parse_function_definition(&mut self) -> Result<FunctionDefinition<S>, ParserError> {
+    // Parses the parameters and body of a function whose `function` keyword was on the given line.
+    fn parse_function_definition(
+        &mut self,
+        line_number: LineNumber,
+    ) -> Result<FunctionDefinition<S>, ParserError> {
         self.expect_next(Token::LeftParen)?;
 
         let mut parameters = Vec::new();
@@ -855,6 +893,7 @@ where
         self.expect_next(Token::End)?;
 
         Ok(FunctionDefinition {
+            line_number,
             parameters,
             has_varargs,
             body,
@@ -917,7 +956,7 @@ where
     // Return a reference to the next token in the stream, erroring if we are at the end.
     fn get_next(&mut self) -> Result<&Token<S>, ParserError> {
         self.read_ahead(1)?;
-        if let Some(token) = self.read_buffer.get(0) {
+        if let Some((token, _)) = self.read_buffer.first() {
             Ok(token)
         } else {
             Err(ParserError::EndOfStream { expected: None })
@@ -932,7 +971,7 @@ where
                 expected: Some(format!("{:?}", token)),
             })
         } else {
-            let next_token = self.read_buffer.remove(0);
+            let next_token = self.pop_token();
             if next_token == token {
                 Ok(())
             } else {
@@ -952,7 +991,7 @@ where
                 expected: Some("name".to_owned()),
             })
         } else {
-            match self.read_buffer.remove(0) {
+            match self.pop_token() {
                 Token::Name(name) => Ok(name),
                 token => Err(ParserError::Unexpected {
                     unexpected: format!("{:?}", token),
@@ -970,7 +1009,7 @@ where
                 expected: Some("string".to_owned()),
             })
         } else {
-            match self.read_buffer.remove(0) {
+            match self.pop_token() {
                 Token::String(string) => Ok(string),
                 token => Err(ParserError::Unexpected {
                     unexpected: format!("{:?}", token),
@@ -986,21 +1025,39 @@ where
         if self.read_buffer.is_empty() {
             Err(ParserError::EndOfStream { expected: None })
         } else {
-            Ok(self.read_buffer.remove(0))
+            Ok(self.pop_token())
         }
     }
 
+    // Remove the next token from the read buffer, which must not be empty, remembering the line
+    // that it was on.
+    fn pop_token(&mut self) -> Token<S> {
+        let (token, line_number) = self.read_buffer.remove(0);
+        self.last_line = line_number;
+        token
+    }
+
     // Return the nth token ahead in the stream, if it is not past the end.
     fn look_ahead(&mut self, n: usize) -> Result<Option<&Token<S>>, ParserError> {
         self.read_ahead(n + 1)?;
-        Ok(self.read_buffer.get(n))
+        Ok(self.read_buffer.get(n).map(|(token, _)| token))
+    }
+
+    // Return the line that the next token starts on, or the current line if there are no more
+    // tokens.
+    fn line_number(&mut self) -> Result<LineNumber, ParserError> {
+        self.read_ahead(1)?;
+        Ok(match self.read_buffer.first() {
+            Some(&(_, line_number)) => line_number,
+            None => LineNumber(self.lexer.line_number()),
+        })
     }
 
     // Return true if the nth token ahead in the stream matches the given token.  If this would read
     // past the end of the stream, this will simply return false.
     fn check_ahead(&mut self, n: usize, token: Token<S>) -> Result<bool, ParserError> {
         self.read_ahead(n)?;
-        Ok(if let Some(t) = self.read_buffer.get(n) {
+        Ok(if let Some((t, _)) = self.read_buffer.get(n) {
             *t == token
         } else {
             false
@@ -1011,8 +1068,12 @@ where
     // possible).
     fn read_ahead(&mut self, n: usize) -> Result<(), ParserError> {
         while self.read_buffer.len() <= n {
+            self.lexer
+                .skip_whitespace()
+                .map_err(ParserError::LexerError)?;
+            let line_number = LineNumber(self.lexer.line_number());
             if let Some(token) = self.lexer.read_token().map_err(ParserError::LexerError)? {
-                self.read_buffer.push(token);
+                self.read_buffer.push((token, line_number));
             } else {
                 break;
             }
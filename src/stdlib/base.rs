@@ -1,45 +1,50 @@
-use std::io::{self, Write};
-
 use gc_arena::MutationContext;
 use gc_sequence as sequence;
 
 use crate::{
-    Callback, CallbackResult, Continuation, Root, RuntimeError, String, Table, TypeError, Value,
+    compile, is_binary_chunk, read_chunk, Callback, CallbackResult, Closure, Continuation, Error,
+    Root, RuntimeError, String, Table, TypeError, Value,
 };
 
 pub fn load_base<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: Table<'gc>) {
+    let std_streams = (*root.std_streams).clone();
     env.set(
         mc,
         String::new_static(b"print"),
-        Callback::new_immediate(mc, |args| {
-            let mut stdout = io::stdout();
+        Callback::new_immediate(mc, move |_, args| {
+            // The whole line is written at once, so that output sinks see complete lines
+            let mut line = Vec::new();
             for i in 0..args.len() {
-                args[i].display(&mut stdout)?;
+                args[i].display(&mut line)?;
                 if i != args.len() - 1 {
-                    stdout.write_all(&b"\t"[..])?;
+                    line.push(b'\t');
                 }
             }
-            stdout.write_all(&b"\n"[..])?;
+            line.push(b'\n');
+            let mut stdout = std_streams.stdout();
+            stdout.write_all(&line)?;
             stdout.flush()?;
-            Ok(CallbackResult::Return(vec![]))
-        }),
+            Ok(CallbackResult::Return(args.returning([])))
+        })
+        .named("print"),
     )
     .unwrap();
 
     env.set(
         mc,
         String::new_static(b"error"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             let err = args.get(0).cloned().unwrap_or(Value::Nil);
             Err(RuntimeError(err).into())
-        }),
+        })
+        .named("error"),
     )
     .unwrap();
 
     env.set(
         mc,
         String::new_static(b"assert"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             let v = args.get(0).cloned().unwrap_or(Value::Nil);
             let message = args
                 .get(1)
@@ -51,88 +56,171 @@ pub fn load_base<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: Table<
             } else {
                 Err(RuntimeError(message).into())
             }
-        }),
+        })
+        .named("assert"),
     )
     .unwrap();
 
     env.set(
         mc,
         String::new_static(b"pcall"),
-        Callback::new_immediate_with(mc, root.interned_strings, |interned_strings, mut args| {
-            let function = match args.get(0).cloned().unwrap_or(Value::Nil) {
-                Value::Function(function) => function,
-                value => {
-                    return Err(TypeError {
-                        expected: "function",
-                        found: value.type_name(),
+        Callback::new_immediate_with(
+            mc,
+            root.interned_strings,
+            |interned_strings, _, mut args| {
+                let function = match args.get(0).cloned().unwrap_or(Value::Nil) {
+                    Value::Function(function) => function,
+                    value => {
+                        return Err(TypeError {
+                            expected: "function",
+                            found: value.type_name(),
+                        }
+                        .into());
                     }
-                    .into());
-                }
-            };
+                };
 
-            args.remove(0);
-            Ok(CallbackResult::TailCall {
-                function,
-                args,
-                continuation: Continuation::new_sequence_with(
-                    *interned_strings,
-                    move |interned_strings, res| {
-                        Ok(sequence::from_fn_with(
-                            (res, interned_strings),
-                            |mc, (res, interned_strings)| {
-                                Ok(CallbackResult::Return(match res {
-                                    Ok(mut res) => {
-                                        res.insert(0, Value::Boolean(true));
-                                        res
-                                    }
-                                    Err(err) => vec![
-                                        Value::Boolean(false),
-                                        err.to_value(mc, interned_strings),
-                                    ],
-                                }))
-                            },
-                        ))
-                    },
-                ),
-            })
-        }),
+                args.remove(0);
+                Ok(CallbackResult::TailCall {
+                    function,
+                    args,
+                    continuation: Continuation::new_sequence_with(
+                        *interned_strings,
+                        move |interned_strings, res| {
+                            Ok(sequence::from_fn_with(
+                                (res, interned_strings),
+                                |mc, (res, interned_strings)| {
+                                    Ok(CallbackResult::Return(match res {
+                                        Ok(mut res) => {
+                                            res.insert(0, Value::Boolean(true));
+                                            res
+                                        }
+                                        Err((err, res)) => res.returning([
+                                            Value::Boolean(false),
+                                            err.to_value(mc, interned_strings),
+                                        ]),
+                                    }))
+                                },
+                            ))
+                        },
+                    ),
+                })
+            },
+        )
+        .named("pcall"),
     )
     .unwrap();
 
     env.set(
         mc,
         String::new_static(b"type"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             if args.len() == 0 {
                 return Err(RuntimeError(Value::String(String::new_static(
                     b"Missing argument to type",
                 )))
                 .into());
             }
-            Ok(CallbackResult::Return(vec![Value::String(
-                String::new_static(args.get(0).cloned().unwrap().type_name().as_bytes()),
-            )]))
-        }),
+            let type_name = args.get(0).cloned().unwrap().type_name();
+            Ok(CallbackResult::Return(args.returning([Value::String(
+                String::new_static(type_name.as_bytes()),
+            )])))
+        })
+        .named("type"),
     )
     .unwrap();
 
     env.set(
         mc,
         String::new_static(b"select"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, mut args| {
             match args.get(0).cloned().unwrap_or(Value::Nil).to_integer() {
-                Some(n) if n >= 1 && (n as usize) <= args.len() => Ok(CallbackResult::Return(
-                    args[n as usize..args.len()].to_vec(),
-                )),
+                Some(n) if n >= 1 && (n as usize) <= args.len() => {
+                    args.drain(0..n as usize);
+                    Ok(CallbackResult::Return(args))
+                }
                 // This is required because Rust will panic if the starting slice index is out of
                 // range by more than one
-                Some(n) if n as usize > args.len() => Ok(CallbackResult::Return(vec![])),
+                Some(n) if n as usize > args.len() => {
+                    Ok(CallbackResult::Return(args.returning([])))
+                }
                 _ => Err(RuntimeError(Value::String(String::new_static(
                     b"Bad argument to select",
                 )))
                 .into()),
             }
-        }),
+        })
+        .named("select"),
+    )
+    .unwrap();
+
+    env.set(
+        mc,
+        String::new_static(b"load"),
+        Callback::new_sequence_with(
+            mc,
+            (root.interned_strings, env),
+            |&(interned_strings, globals), _, args| {
+                Ok(sequence::from_fn_with(
+                    (interned_strings, globals, args),
+                    |mc, (interned_strings, globals, args)| {
+                        let chunk = match args.first().cloned().unwrap_or(Value::Nil) {
+                            Value::String(chunk) => chunk,
+                            value => {
+                                return Err(TypeError {
+                                    expected: "string",
+                                    found: value.type_name(),
+                                }
+                                .into());
+                            }
+                        };
+                        let mode = match args.get(2).cloned().unwrap_or(Value::Nil) {
+                            Value::Nil => None,
+                            Value::String(mode) => Some(mode),
+                            value => {
+                                return Err(TypeError {
+                                    expected: "string",
+                                    found: value.type_name(),
+                                }
+                                .into());
+                            }
+                        };
+                        let mode = mode.as_ref().map_or(&b"bt"[..], |m| m.as_bytes());
+                        // An explicitly passed environment is used even if it is nil
+                        let environment = match args.get(3) {
+                            Some(&environment) => environment,
+                            None => Value::Table(globals),
+                        };
+
+                        let binary = is_binary_chunk(chunk.as_bytes());
+                        let loaded = if !mode.contains(if binary { &b'b' } else { &b't' }) {
+                            let message = format!(
+                                "attempt to load a {} chunk (mode is '{}')",
+                                if binary { "binary" } else { "text" },
+                                std::string::String::from_utf8_lossy(mode)
+                            );
+                            Err(RuntimeError(Value::String(
+                                interned_strings.new_string(mc, message.as_bytes()),
+                            ))
+                            .into())
+                        } else if binary {
+                            read_chunk(mc, interned_strings, chunk.as_bytes()).map_err(Error::from)
+                        } else {
+                            compile(mc, interned_strings, chunk.as_bytes())
+                        };
+
+                        let res =
+                            loaded.and_then(|proto| Ok(Closure::load(mc, proto, environment)?));
+                        Ok(CallbackResult::Return(match res {
+                            Ok(closure) => args.returning([closure.into()]),
+                            Err(err) => {
+                                args.returning([Value::Nil, err.to_value(mc, interned_strings)])
+                            }
+                        }))
+                    },
+                ))
+            },
+        )
+        .named("load"),
     )
     .unwrap();
 }
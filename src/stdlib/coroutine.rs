@@ -1,9 +1,9 @@
 use gc_arena::MutationContext;
-use gc_sequence::{self as sequence, SequenceExt, SequenceResultExt};
+use gc_sequence::{self as sequence, Sequence, SequenceExt, SequenceResultExt};
 
 use crate::{
-    Callback, CallbackResult, Root, RuntimeError, String, Table, Thread, ThreadMode,
-    ThreadSequence, TypeError, Value,
+    CallContext, Callback, CallbackResult, Error, Function, Root, RuntimeError, String, Table,
+    Thread, ThreadMode, ThreadSequence, TypeError, Value, ValueBuffer,
 };
 
 pub fn load_coroutine<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: Table<'gc>) {
@@ -13,24 +13,18 @@ pub fn load_coroutine<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: T
         .set(
             mc,
             String::new_static(b"create"),
-            Callback::new_sequence(mc, |args| {
-                let function = match args.get(0).cloned().unwrap_or(Value::Nil) {
-                    Value::Function(function) => function,
-                    value => {
-                        return Err(TypeError {
-                            expected: "function",
-                            found: value.type_name(),
-                        }
-                        .into());
-                    }
-                };
-
-                Ok(sequence::from_fn_with(function, |mc, function| {
-                    let thread = Thread::new(mc, true);
-                    thread.start_suspended(mc, function).unwrap();
-                    Ok(CallbackResult::Return(vec![Value::Thread(thread)]))
-                }))
-            }),
+            Callback::new_sequence(mc, |ctx, args| {
+                let function = function_arg(&args)?;
+                Ok(sequence::from_fn_with(
+                    (ctx, function, args),
+                    |mc, (ctx, function, args)| {
+                        Ok(CallbackResult::Return(args.returning([Value::Thread(
+                            new_coroutine(mc, ctx, function),
+                        )])))
+                    },
+                ))
+            })
+            .named("create"),
         )
         .unwrap();
 
@@ -38,76 +32,138 @@ pub fn load_coroutine<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: T
         .set(
             mc,
             String::new_static(b"resume"),
-            Callback::new_sequence_with(mc, root.interned_strings, |interned_strings, mut args| {
-                let thread = match args.get(0).cloned().unwrap_or(Value::Nil) {
-                    Value::Thread(closure) => closure,
-                    value => {
-                        return Err(TypeError {
-                            expected: "thread",
-                            found: value.type_name(),
-                        }
-                        .into());
-                    }
-                };
-
+            Callback::new_sequence(mc, |ctx, mut args| {
+                let thread = thread_arg(&args)?;
                 args.remove(0);
-                Ok(
-                    sequence::from_fn_with((thread, args), |mc, (thread, args)| {
-                        if let Ok(()) = thread.resume(mc, &args) {
-                            Ok(ThreadSequence(thread))
-                        } else {
-                            Err(RuntimeError(Value::String(String::new_static(
-                                b"cannot resume thread",
-                            )))
-                            .into())
+                Ok(resume_coroutine(ctx, thread, args).map(|res| {
+                    Ok(CallbackResult::Return(match res? {
+                        Ok(mut res) => {
+                            res.insert(0, Value::Boolean(true));
+                            res
                         }
-                    })
-                    .flatten_ok()
-                    .then_with(
-                        *interned_strings,
-                        |mc, interned_strings, res| {
-                            Ok(CallbackResult::Return(match res {
-                                Ok(mut res) => {
-                                    res.insert(0, Value::Boolean(true));
-                                    res
-                                }
-                                Err(err) => {
-                                    vec![Value::Boolean(false), err.to_value(mc, interned_strings)]
-                                }
-                            }))
-                        },
-                    ),
-                )
-            }),
+                        Err(mut res) => {
+                            res.insert(0, Value::Boolean(false));
+                            res
+                        }
+                    }))
+                }))
+            })
+            .named("resume"),
         )
         .unwrap();
 
     coroutine
         .set(
             mc,
-            String::new_static(b"status"),
-            Callback::new_immediate(mc, |args| {
-                let thread = match args.get(0).cloned().unwrap_or(Value::Nil) {
-                    Value::Thread(closure) => closure,
-                    value => {
-                        return Err(TypeError {
-                            expected: "thread",
-                            found: value.type_name(),
-                        }
-                        .into());
-                    }
-                };
+            String::new_static(b"wrap"),
+            Callback::new_sequence(mc, |ctx, args| {
+                let function = function_arg(&args)?;
+                Ok(sequence::from_fn_with(
+                    (ctx, function, args),
+                    |mc, (ctx, function, args)| {
+                        let thread = new_coroutine(mc, ctx, function);
+                        let wrapped =
+                            Callback::new_sequence_with(mc, thread, |thread, ctx, args| {
+                                Ok(resume_coroutine(ctx, *thread, args).then_with(
+                                    *thread,
+                                    |mc, thread, res| match res? {
+                                        Ok(res) => Ok(CallbackResult::Return(res)),
+                                        Err(res) => {
+                                            Err(RuntimeError(wrap_error(mc, thread, res[0])).into())
+                                        }
+                                    },
+                                ))
+                            });
+                        Ok(CallbackResult::Return(args.returning([wrapped.into()])))
+                    },
+                ))
+            })
+            .named("wrap"),
+        )
+        .unwrap();
 
-                Ok(CallbackResult::Return(vec![Value::String(
-                    // TODO: When the current thread is available again for callbacks, whether or
-                    // not the active thread matches will determine 'normal' from 'running'.
+    coroutine
+        .set(
+            mc,
+            String::new_static(b"status"),
+            Callback::new_immediate(mc, |ctx, args| {
+                let thread = thread_arg(&args)?;
+                Ok(CallbackResult::Return(args.returning([Value::String(
                     String::new_static(match thread.mode() {
                         ThreadMode::Stopped | ThreadMode::Results => b"dead",
-                        ThreadMode::Running => b"running",
+                        ThreadMode::Running => {
+                            if thread == ctx.thread {
+                                b"running"
+                            } else {
+                                b"normal"
+                            }
+                        }
                         ThreadMode::Suspended => b"suspended",
                     }),
-                )]))
-            }),
+                )])))
+            })
+            .named("status"),
+        )
+        .unwrap();
+
+    coroutine
+        .set(
+            mc,
+            String::new_static(b"running"),
+            Callback::new_immediate_with(mc, root.main_thread, |main_thread, ctx, args| {
+                Ok(CallbackResult::Return(args.returning([
+                    Value::Thread(ctx.thread),
+                    Value::Boolean(ctx.thread == *main_thread),
+                ])))
+            })
+            .named("running"),
+        )
+        .unwrap();
+
+    coroutine
+        .set(
+            mc,
+            String::new_static(b"isyieldable"),
+            Callback::new_immediate(mc, |ctx, args| {
+                Ok(CallbackResult::Return(
+                    args.returning([Value::Boolean(ctx.thread.allow_yield())]),
+                ))
+            })
+            .named("isyieldable"),
+        )
+        .unwrap();
+
+    coroutine
+        .set(
+            mc,
+            String::new_static(b"close"),
+            Callback::new_sequence(mc, |ctx, args| {
+                let thread = thread_arg(&args)?;
+                Ok(sequence::from_fn_with(
+                    (ctx.thread, thread, args),
+                    |mc, (current, thread, args)| match thread.mode() {
+                        ThreadMode::Running => Err(RuntimeError(Value::String(
+                            String::new_static(if thread == current {
+                                b"cannot close a running coroutine"
+                            } else {
+                                b"cannot close a normal coroutine"
+                            }),
+                        ))
+                        .into()),
+                        mode => {
+                            if mode == ThreadMode::Results {
+                                thread.take_results(mc);
+                            }
+                            thread.reset(mc)?;
+                            Ok(CallbackResult::Return(match thread.take_dead_error(mc) {
+                                Some(err) => args.returning([Value::Boolean(false), err]),
+                                None => args.returning([Value::Boolean(true)]),
+                            }))
+                        }
+                    },
+                ))
+            })
+            .named("close"),
         )
         .unwrap();
 
@@ -115,10 +171,108 @@ pub fn load_coroutine<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: T
         .set(
             mc,
             String::new_static(b"yield"),
-            Callback::new_immediate(mc, |args| Ok(CallbackResult::Yield(args))),
+            Callback::new_immediate(mc, |_, args| Ok(CallbackResult::Yield(args))).named("yield"),
         )
         .unwrap();
 
     env.set(mc, String::new_static(b"coroutine"), coroutine)
         .unwrap();
 }
+
+// Creates a new suspended coroutine which shares the globals, limits and hook of the calling
+// thread.
+fn new_coroutine<'gc>(
+    mc: MutationContext<'gc, '_>,
+    ctx: CallContext<'gc>,
+    function: Function<'gc>,
+) -> Thread<'gc> {
+    let thread = Thread::new(mc, ctx.globals, ctx.interned_strings, true);
+    thread.inherit_limits(ctx.thread);
+    thread.set_hook(mc, ctx.thread.hook());
+    thread.start_suspended(mc, function).unwrap();
+    thread
+}
+
+// Coroutines resuming coroutines are stepped recursively, so like the C stack limit of PUC-Rio Lua,
+// only this many may be waiting on each other at once.
+const MAX_COROUTINE_NESTING: usize = 200;
+
+// Resumes the given coroutine with the arguments in the given buffer, and returns a sequence which
+// results in the same buffer holding either the values the coroutine yields or returns, or a single
+// error value.  The error is either the value the coroutine raised, or the reason it could not be
+// resumed.  Errors which cannot be caught are instead propagated as the error of the sequence.
+fn resume_coroutine<'gc>(
+    ctx: CallContext<'gc>,
+    thread: Thread<'gc>,
+    args: ValueBuffer<'gc>,
+) -> impl Sequence<'gc, Output = Result<Result<ValueBuffer<'gc>, ValueBuffer<'gc>>, Error<'gc>>> {
+    sequence::from_fn_with((ctx.thread, thread, args), |mc, (current, thread, args)| {
+        let err: &'static [u8] = match thread.mode() {
+            ThreadMode::Suspended if current.nesting() >= MAX_COROUTINE_NESTING => {
+                b"stack overflow"
+            }
+            ThreadMode::Suspended => {
+                thread.set_nesting(current.nesting() + 1);
+                thread.resume(mc, &args).unwrap();
+                return Ok((thread, args));
+            }
+            ThreadMode::Running => b"cannot resume non-suspended coroutine",
+            ThreadMode::Stopped | ThreadMode::Results => b"cannot resume dead coroutine",
+        };
+        Err(args.returning([Value::String(String::new_static(err))]))
+    })
+    .and_chain(|_, (thread, args)| {
+        Ok(ThreadSequence(thread).then_with(args, |_, args, res| Ok((res, args))))
+    })
+    .then_with(
+        (ctx.interned_strings, thread),
+        |mc, (interned_strings, thread), res| match res {
+            Ok((Ok(res), args)) => Ok(Ok(args.returning(res))),
+            Ok((Err(err), _)) if !err.is_catchable() => Err(err),
+            Ok((Err(err), args)) => {
+                let err = err.to_value(mc, interned_strings);
+                thread.set_dead_error(mc, Some(err));
+                Ok(Err(args.returning([err])))
+            }
+            Err(args) => Ok(Err(args)),
+        },
+    )
+}
+
+// Like PUC-Rio Lua, a string error raised by a coroutine is re-raised by the function `wrap`
+// returns with the location it was raised at prepended to it.
+fn wrap_error<'gc>(
+    mc: MutationContext<'gc, '_>,
+    thread: Thread<'gc>,
+    error: Value<'gc>,
+) -> Value<'gc> {
+    match (error, thread.take_error_location(mc)) {
+        (Value::String(message), Some(location)) => {
+            let mut bytes = location.into_bytes();
+            bytes.extend_from_slice(b": ");
+            bytes.extend_from_slice(message.as_bytes());
+            Value::String(String::new(mc, &bytes))
+        }
+        (error, _) => error,
+    }
+}
+
+fn function_arg<'gc>(args: &[Value<'gc>]) -> Result<Function<'gc>, TypeError> {
+    match args.get(0).cloned().unwrap_or(Value::Nil) {
+        Value::Function(function) => Ok(function),
+        value => Err(TypeError {
+            expected: "function",
+            found: value.type_name(),
+        }),
+    }
+}
+
+fn thread_arg<'gc>(args: &[Value<'gc>]) -> Result<Thread<'gc>, TypeError> {
+    match args.get(0).cloned().unwrap_or(Value::Nil) {
+        Value::Thread(thread) => Ok(thread),
+        value => Err(TypeError {
+            expected: "thread",
+            found: value.type_name(),
+        }),
+    }
+}
@@ -0,0 +1,623 @@
+use std::convert::TryFrom;
+
+use gc_arena::MutationContext;
+use gc_sequence as sequence;
+
+use crate::{
+    Callback, CallbackResult, Closure, Error, FrameInfo, Function, Hook, HookMask, LineNumber,
+    LocalVariable, Root, RuntimeError, String, Table, Thread, UpValueIndex, Value,
+};
+
+pub fn load_debug<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc>) {
+    let debug = Table::new(mc);
+
+    debug
+        .set(
+            mc,
+            String::new_static(b"sethook"),
+            Callback::new_sequence(mc, |ctx, args| {
+                Ok(sequence::from_fn_with(
+                    (ctx.thread, args),
+                    |mc, (current, args)| {
+                        let (thread, rest) = thread_arg(current, &args);
+                        let hook = match rest.first() {
+                            None | Some(Value::Nil) => None,
+                            Some(&Value::Function(function)) => Some(Hook {
+                                function,
+                                mask: hook_mask(rest.get(1), rest.get(2))?,
+                            }),
+                            Some(_) => {
+                                return Err(runtime_error(
+                                    b"bad argument to 'sethook' (function expected)",
+                                ))
+                            }
+                        };
+                        thread.set_hook(mc, hook);
+                        Ok(CallbackResult::Return(args.returning(None)))
+                    },
+                ))
+            })
+            .named("sethook"),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            mc,
+            String::new_static(b"gethook"),
+            Callback::new_sequence(mc, |ctx, args| {
+                Ok(sequence::from_fn_with(
+                    (ctx.thread, args),
+                    |mc, (current, args)| {
+                        let (thread, _) = thread_arg(current, &args);
+                        Ok(CallbackResult::Return(match thread.hook() {
+                            Some(hook) => {
+                                let mut mask = Vec::new();
+                                if hook.mask.call {
+                                    mask.push(b'c');
+                                }
+                                if hook.mask.ret {
+                                    mask.push(b'r');
+                                }
+                                if hook.mask.line {
+                                    mask.push(b'l');
+                                }
+                                args.returning([
+                                    Value::Function(hook.function),
+                                    Value::String(String::new(mc, &mask)),
+                                    Value::Integer(hook.mask.count as i64),
+                                ])
+                            }
+                            None => args.returning([Value::Nil]),
+                        }))
+                    },
+                ))
+            })
+            .named("gethook"),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            mc,
+            String::new_static(b"traceback"),
+            Callback::new_sequence(mc, |ctx, args| {
+                Ok(sequence::from_fn_with(
+                    (ctx.thread, args),
+                    |mc, (current, args)| {
+                        let (thread, rest) = thread_arg(current, &args);
+                        let message = match rest.first() {
+                            None | Some(Value::Nil) => None,
+                            Some(&Value::String(message)) => Some(message.as_bytes().to_vec()),
+                            Some(&message @ Value::Integer(_))
+                            | Some(&message @ Value::Number(_)) => {
+                                let mut buf = Vec::new();
+                                message.display(&mut buf)?;
+                                Some(buf)
+                            }
+                            // Other messages are returned untouched, as in PUC-Rio Lua.
+                            Some(&message) => {
+                                return Ok(CallbackResult::Return(args.returning([message])))
+                            }
+                        };
+                        let level = match rest.get(1) {
+                            None | Some(Value::Nil) => {
+                                if thread == current {
+                                    1
+                                } else {
+                                    0
+                                }
+                            }
+                            Some(level) => level.to_integer().ok_or_else(|| {
+                                arg_error(
+                                    mc,
+                                    arg_offset(&args, rest) + 2,
+                                    "traceback",
+                                    "number expected",
+                                )
+                            })?,
+                        };
+
+                        let mut traceback = Vec::new();
+                        if let Some(message) = message {
+                            traceback.extend_from_slice(&message);
+                            traceback.push(b'\n');
+                        }
+                        traceback.extend_from_slice(b"stack traceback:");
+                        if level >= 0 {
+                            for line in thread.traceback(level as usize).unwrap_or_default() {
+                                traceback.extend_from_slice(b"\n\t");
+                                traceback.extend_from_slice(line.as_bytes());
+                            }
+                        }
+                        Ok(CallbackResult::Return(
+                            args.returning([Value::String(String::new(mc, &traceback))]),
+                        ))
+                    },
+                ))
+            })
+            .named("traceback"),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            mc,
+            String::new_static(b"getinfo"),
+            Callback::new_sequence(mc, |ctx, args| {
+                Ok(sequence::from_fn_with(
+                    (ctx.thread, args),
+                    |mc, (current, args)| {
+                        let (thread, rest) = thread_arg(current, &args);
+                        let offset = arg_offset(&args, rest);
+                        let what = match rest.get(1) {
+                            None | Some(Value::Nil) => b"flnStu".to_vec(),
+                            Some(Value::String(what)) => what.as_bytes().to_vec(),
+                            Some(_) => {
+                                return Err(arg_error(mc, offset + 2, "getinfo", "string expected"))
+                            }
+                        };
+                        let info = match rest.first() {
+                            Some(&Value::Function(function)) => FrameInfo {
+                                function: Some(function),
+                                pc: None,
+                                current_line: None,
+                                tail_call: false,
+                                name: None,
+                            },
+                            level => match level.and_then(|level| level.to_integer()) {
+                                Some(level) => match usize::try_from(level)
+                                    .ok()
+                                    .and_then(|level| thread.frame_info(level))
+                                {
+                                    Some(info) => info,
+                                    None => {
+                                        return Ok(CallbackResult::Return(
+                                            args.returning([Value::Nil]),
+                                        ))
+                                    }
+                                },
+                                None => {
+                                    return Err(arg_error(
+                                        mc,
+                                        offset + 1,
+                                        "getinfo",
+                                        "function or level expected",
+                                    ))
+                                }
+                            },
+                        };
+                        let info = info_table(mc, &info, &what, offset)?;
+                        Ok(CallbackResult::Return(args.returning([Value::Table(info)])))
+                    },
+                ))
+            })
+            .named("getinfo"),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            mc,
+            String::new_static(b"getlocal"),
+            Callback::new_sequence(mc, |ctx, args| {
+                Ok(sequence::from_fn_with(
+                    (ctx.thread, args),
+                    |mc, (current, args)| {
+                        let (thread, rest) = thread_arg(current, &args);
+                        let offset = arg_offset(&args, rest);
+                        let n = integer_arg(mc, rest, 1, offset, "getlocal")?;
+                        let res = match rest.first() {
+                            // Without a running frame, only parameter names are known.
+                            Some(&Value::Function(Function::Closure(closure))) => {
+                                let proto = &closure.0.proto;
+                                let param = usize::try_from(n - 1).ok().and_then(|i| {
+                                    proto
+                                        .active_locals(0)
+                                        .take(proto.fixed_params as usize)
+                                        .nth(i)
+                                });
+                                match param {
+                                    Some(local) => vec![Value::String(local.name)],
+                                    None => vec![Value::Nil],
+                                }
+                            }
+                            Some(&Value::Function(Function::Callback(_))) => vec![Value::Nil],
+                            _ => {
+                                let level = level_arg(mc, thread, rest, offset, "getlocal")?;
+                                match frame_local(thread, level, n) {
+                                    Some(local) => vec![
+                                        Value::String(local.name),
+                                        thread
+                                            .frame_register(level, local.register)
+                                            .unwrap_or(Value::Nil),
+                                    ],
+                                    None => vec![Value::Nil],
+                                }
+                            }
+                        };
+                        Ok(CallbackResult::Return(args.returning(res)))
+                    },
+                ))
+            })
+            .named("getlocal"),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            mc,
+            String::new_static(b"setlocal"),
+            Callback::new_sequence(mc, |ctx, args| {
+                Ok(sequence::from_fn_with(
+                    (ctx.thread, args),
+                    |mc, (current, args)| {
+                        let (thread, rest) = thread_arg(current, &args);
+                        let offset = arg_offset(&args, rest);
+                        let level = level_arg(mc, thread, rest, offset, "setlocal")?;
+                        let n = integer_arg(mc, rest, 1, offset, "setlocal")?;
+                        let value = rest.get(2).copied().unwrap_or(Value::Nil);
+                        let res = match frame_local(thread, level, n) {
+                            Some(local) => {
+                                thread.set_frame_register(mc, level, local.register, value);
+                                Value::String(local.name)
+                            }
+                            None => Value::Nil,
+                        };
+                        Ok(CallbackResult::Return(args.returning([res])))
+                    },
+                ))
+            })
+            .named("setlocal"),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            mc,
+            String::new_static(b"getupvalue"),
+            Callback::new_sequence(mc, |_, args| {
+                Ok(sequence::from_fn_with(args, |mc, args| {
+                    let res = match upvalue_arg(mc, &args, 0, "getupvalue")? {
+                        Some((closure, index)) => {
+                            let upvalue = closure.0.upvalues.read()[index];
+                            vec![Value::String(upvalue_name(closure, index)), upvalue.get()]
+                        }
+                        None => vec![Value::Nil],
+                    };
+                    Ok(CallbackResult::Return(args.returning(res)))
+                }))
+            })
+            .named("getupvalue"),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            mc,
+            String::new_static(b"setupvalue"),
+            Callback::new_sequence(mc, |_, args| {
+                Ok(sequence::from_fn_with(args, |mc, args| {
+                    let value = args.get(2).copied().unwrap_or(Value::Nil);
+                    let res = match upvalue_arg(mc, &args, 0, "setupvalue")? {
+                        Some((closure, index)) => {
+                            let upvalue = closure.0.upvalues.read()[index];
+                            upvalue.set(mc, value);
+                            Value::String(upvalue_name(closure, index))
+                        }
+                        None => Value::Nil,
+                    };
+                    Ok(CallbackResult::Return(args.returning([res])))
+                }))
+            })
+            .named("setupvalue"),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            mc,
+            String::new_static(b"upvalueid"),
+            Callback::new_sequence(mc, |_, args| {
+                Ok(sequence::from_fn_with(args, |mc, args| {
+                    let (closure, index) = upvalue_arg(mc, &args, 0, "upvalueid")?
+                        .ok_or_else(|| arg_error(mc, 2, "upvalueid", "invalid upvalue index"))?;
+                    let id = closure.0.upvalues.read()[index].id();
+                    Ok(CallbackResult::Return(
+                        args.returning([Value::Integer(id as i64)]),
+                    ))
+                }))
+            })
+            .named("upvalueid"),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            mc,
+            String::new_static(b"upvaluejoin"),
+            Callback::new_sequence(mc, |_, args| {
+                Ok(sequence::from_fn_with(args, |mc, args| {
+                    let (closure1, index1) = upvalue_arg(mc, &args, 0, "upvaluejoin")?
+                        .ok_or_else(|| arg_error(mc, 2, "upvaluejoin", "invalid upvalue index"))?;
+                    let (closure2, index2) = upvalue_arg(mc, &args, 2, "upvaluejoin")?
+                        .ok_or_else(|| arg_error(mc, 4, "upvaluejoin", "invalid upvalue index"))?;
+                    let upvalue = closure2.0.upvalues.read()[index2];
+                    closure1.0.upvalues.write(mc)[index1] = upvalue;
+                    Ok(CallbackResult::Return(args.returning(None)))
+                }))
+            })
+            .named("upvaluejoin"),
+        )
+        .unwrap();
+
+    // There are no metatables yet, so every value has a nil metatable and it can't be changed.
+    debug
+        .set(
+            mc,
+            String::new_static(b"getmetatable"),
+            Callback::new_immediate(mc, |_, args| {
+                Ok(CallbackResult::Return(args.returning([Value::Nil])))
+            })
+            .named("getmetatable"),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            mc,
+            String::new_static(b"setmetatable"),
+            Callback::new_immediate(mc, |_, args| match args.get(1) {
+                None | Some(Value::Nil) => {
+                    let value = args.first().copied().unwrap_or(Value::Nil);
+                    Ok(CallbackResult::Return(args.returning([value])))
+                }
+                Some(Value::Table(_)) => Err(runtime_error(
+                    b"bad argument #2 to 'setmetatable' (metatables are not supported)",
+                )),
+                Some(_) => Err(runtime_error(
+                    b"bad argument #2 to 'setmetatable' (nil or table expected)",
+                )),
+            })
+            .named("setmetatable"),
+        )
+        .unwrap();
+
+    env.set(mc, String::new_static(b"debug"), debug).unwrap();
+}
+
+// Builds the table returned by `debug.getinfo`, with the fields selected by `what`.
+fn info_table<'gc>(
+    mc: MutationContext<'gc, '_>,
+    info: &FrameInfo<'gc>,
+    what: &[u8],
+    offset: usize,
+) -> Result<Table<'gc>, Error<'gc>> {
+    let line = |line: Option<LineNumber>| line.map(|line| line.0 as i64 + 1).unwrap_or(-1);
+    let closure = match info.function {
+        Some(Function::Closure(closure)) => Some(closure),
+        _ => None,
+    };
+
+    let table = Table::new(mc);
+    for &option in what {
+        match option {
+            b'S' => {
+                // Only chunks compiled with a name record where they were loaded from.  Functions do
+                // not record where their definition ends, so the line of their last instruction
+                // stands in for it, and a main chunk has no definition, which is line 0 like in
+                // PUC-Rio Lua.
+                let (source, short_src) = match closure.and_then(|c| c.0.proto.chunk_name) {
+                    Some(chunk_name) => {
+                        let mut source = b"@".to_vec();
+                        source.extend_from_slice(chunk_name.as_bytes());
+                        (String::new(mc, &source), chunk_name)
+                    }
+                    None => (String::new_static(b"=?"), String::new_static(b"?")),
+                };
+                table.set(mc, String::new_static(b"source"), source)?;
+                table.set(mc, String::new_static(b"short_src"), short_src)?;
+                let (what, line_defined, last_line_defined) = match closure {
+                    Some(closure) => {
+                        let proto = &closure.0.proto;
+                        (
+                            &b"Lua"[..],
+                            proto.line_defined.map_or(0, |line| line.0 as i64 + 1),
+                            line(proto.opcode_lines.iter().map(|&(_, line)| line).max()),
+                        )
+                    }
+                    None => (&b"C"[..], -1, -1),
+                };
+                table.set(mc, String::new_static(b"what"), String::new_static(what))?;
+                table.set(mc, String::new_static(b"linedefined"), line_defined)?;
+                table.set(
+                    mc,
+                    String::new_static(b"lastlinedefined"),
+                    last_line_defined,
+                )?;
+            }
+            b'l' => {
+                table.set(
+                    mc,
+                    String::new_static(b"currentline"),
+                    line(info.current_line),
+                )?;
+            }
+            b'u' => {
+                let (nups, nparams, isvararg) = match closure {
+                    Some(closure) => (
+                        closure.0.upvalues.read().len() as i64,
+                        closure.0.proto.fixed_params as i64,
+                        closure.0.proto.has_varargs,
+                    ),
+                    None => (0, 0, true),
+                };
+                table.set(mc, String::new_static(b"nups"), nups)?;
+                table.set(mc, String::new_static(b"nparams"), nparams)?;
+                table.set(mc, String::new_static(b"isvararg"), isvararg)?;
+            }
+            b'n' => match info.name {
+                Some((kind, name)) => {
+                    table.set(mc, String::new_static(b"name"), name)?;
+                    table.set(
+                        mc,
+                        String::new_static(b"namewhat"),
+                        String::new_static(kind.as_bytes()),
+                    )?;
+                }
+                None => {
+                    table.set(mc, String::new_static(b"namewhat"), String::new_static(b""))?;
+                }
+            },
+            b't' => {
+                table.set(mc, String::new_static(b"istailcall"), info.tail_call)?;
+            }
+            b'f' => {
+                if let Some(function) = info.function {
+                    table.set(mc, String::new_static(b"func"), function)?;
+                }
+            }
+            _ => return Err(arg_error(mc, offset + 2, "getinfo", "invalid option")),
+        }
+    }
+    Ok(table)
+}
+
+// Debug functions optionally take the thread to operate on as their first argument.
+fn thread_arg<'gc, 'a>(
+    current: Thread<'gc>,
+    args: &'a [Value<'gc>],
+) -> (Thread<'gc>, &'a [Value<'gc>]) {
+    match args.first() {
+        Some(&Value::Thread(thread)) => (thread, &args[1..]),
+        _ => (current, args),
+    }
+}
+
+// The number of arguments before those returned by `thread_arg`, used to number arguments in errors.
+fn arg_offset(args: &[Value], rest: &[Value]) -> usize {
+    args.len() - rest.len()
+}
+
+fn integer_arg<'gc>(
+    mc: MutationContext<'gc, '_>,
+    args: &[Value<'gc>],
+    i: usize,
+    offset: usize,
+    func: &str,
+) -> Result<i64, Error<'gc>> {
+    args.get(i)
+        .and_then(|arg| arg.to_integer())
+        .ok_or_else(|| arg_error(mc, offset + i + 1, func, "number expected"))
+}
+
+// Reads the stack level given as the first argument, which must name a frame of the thread.
+fn level_arg<'gc>(
+    mc: MutationContext<'gc, '_>,
+    thread: Thread<'gc>,
+    args: &[Value<'gc>],
+    offset: usize,
+    func: &str,
+) -> Result<usize, Error<'gc>> {
+    let level = integer_arg(mc, args, 0, offset, func)?;
+    usize::try_from(level)
+        .ok()
+        .filter(|&level| thread.frame_info(level).is_some())
+        .ok_or_else(|| arg_error(mc, offset + 1, func, "level out of range"))
+}
+
+// Finds the `n`th local variable in scope in the Lua frame at the given level, if any.
+fn frame_local<'gc>(thread: Thread<'gc>, level: usize, n: i64) -> Option<LocalVariable<'gc>> {
+    let info = thread.frame_info(level)?;
+    match (info.function?, info.pc?) {
+        (Function::Closure(closure), pc) if n >= 1 => closure
+            .0
+            .proto
+            .active_locals(pc)
+            .nth(n as usize - 1)
+            .copied(),
+        _ => None,
+    }
+}
+
+// Reads a function and a 1-based upvalue index starting at argument `i`.  Returns `None` if the
+// function is a callback or has no such upvalue.
+fn upvalue_arg<'gc>(
+    mc: MutationContext<'gc, '_>,
+    args: &[Value<'gc>],
+    i: usize,
+    func: &str,
+) -> Result<Option<(Closure<'gc>, usize)>, Error<'gc>> {
+    let function = match args.get(i) {
+        Some(&Value::Function(function)) => function,
+        _ => return Err(arg_error(mc, i + 1, func, "function expected")),
+    };
+    let n = integer_arg(mc, args, i + 1, 0, func)?;
+    Ok(match function {
+        Function::Closure(closure)
+            if n >= 1 && n as u64 <= closure.0.upvalues.read().len() as u64 =>
+        {
+            Some((closure, n as usize - 1))
+        }
+        _ => None,
+    })
+}
+
+// Returns the name of an upvalue of the given closure, or "?" if it is not known.
+fn upvalue_name<'gc>(closure: Closure<'gc>, index: usize) -> String<'gc> {
+    closure
+        .0
+        .proto
+        .upvalue_name(UpValueIndex(index as u8))
+        .unwrap_or_else(|| String::new_static(b"?"))
+}
+
+fn hook_mask<'gc>(
+    mask: Option<&Value<'gc>>,
+    count: Option<&Value<'gc>>,
+) -> Result<HookMask, Error<'gc>> {
+    let mut hook_mask = HookMask::default();
+    match mask {
+        Some(Value::String(mask)) => {
+            for &c in mask.as_bytes() {
+                match c {
+                    b'c' => hook_mask.call = true,
+                    b'r' => hook_mask.ret = true,
+                    b'l' => hook_mask.line = true,
+                    _ => {}
+                }
+            }
+        }
+        None | Some(Value::Nil) => {}
+        Some(_) => {
+            return Err(runtime_error(
+                b"bad argument to 'sethook' (string expected)",
+            ))
+        }
+    }
+    match count.copied().unwrap_or(Value::Nil) {
+        Value::Nil => {}
+        count => match count.to_integer() {
+            Some(count) if count > 0 => hook_mask.count = count.min(u32::MAX as i64) as u32,
+            Some(_) => {}
+            None => {
+                return Err(runtime_error(
+                    b"bad argument to 'sethook' (number expected)",
+                ))
+            }
+        },
+    }
+    Ok(hook_mask)
+}
+
+fn runtime_error<'gc>(msg: &'static [u8]) -> Error<'gc> {
+    RuntimeError(Value::String(String::new_static(msg))).into()
+}
+
+fn arg_error<'gc>(mc: MutationContext<'gc, '_>, arg: usize, func: &str, msg: &str) -> Error<'gc> {
+    RuntimeError(Value::String(String::new(
+        mc,
+        format!("bad argument #{} to '{}' ({})", arg, func, msg).as_bytes(),
+    )))
+    .into()
+}
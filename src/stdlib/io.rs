@@ -0,0 +1,987 @@
+use std::cell::RefMut;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+
+use gc_arena::{Collect, GcCell, MutationContext};
+use gc_sequence as sequence;
+
+use crate::{
+    io::StdStreams,
+    lexer::{read_float, read_hex_float, read_hex_integer, read_integer},
+    Callback, CallbackResult, Error, Root, RuntimeError, String, Table, TypeError, UserData, Value,
+};
+
+// The maximum length of a numeral read with the "n" format, mirrors PUC-Rio Lua's `L_MAXLENNUM`.
+const MAX_NUMERAL_LENGTH: usize = 200;
+
+const DEFAULT_BUFFER_SIZE: usize = 8192;
+
+/// Loads the `io` library.
+///
+/// File handles are closed when they are collected or closed explicitly.  They are not closed by
+/// `__close`, since userdata have no metatables and the parser has no `<close>` variables.
+pub fn load_io<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: Table<'gc>) {
+    let io = Table::new(mc);
+    let methods = Table::new(mc);
+
+    let std_file = |stream| UserData::new(mc, LuaFile(Some(stream)), Some(methods));
+    let stdin = std_file(Stream::Stdin((*root.std_streams).clone()));
+    let stdout = std_file(Stream::Stdout((*root.std_streams).clone()));
+    let stderr = std_file(Stream::Stderr((*root.std_streams).clone()));
+
+    let ctx = IoContext {
+        methods,
+        defaults: GcCell::allocate(
+            mc,
+            Defaults {
+                input: stdin,
+                output: stdout,
+            },
+        ),
+    };
+
+    io.set(mc, String::new_static(b"stdin"), stdin).unwrap();
+    io.set(mc, String::new_static(b"stdout"), stdout).unwrap();
+    io.set(mc, String::new_static(b"stderr"), stderr).unwrap();
+
+    io.set(
+        mc,
+        String::new_static(b"open"),
+        Callback::new_sequence_with(mc, ctx, |ctx, _, args| {
+            Ok(sequence::from_fn_with((*ctx, args), |mc, (ctx, args)| {
+                let name = string_arg(mc, &args, 0, "open")?;
+                let mode = match args.get(1).cloned().unwrap_or(Value::Nil) {
+                    Value::Nil => None,
+                    v => Some(
+                        v.to_string(mc)
+                            .ok_or_else(|| arg_error(mc, 2, "open", "string expected"))?,
+                    ),
+                };
+                let options = match open_options(mode.as_ref().map_or(b"r", |m| m.as_bytes())) {
+                    Some(options) => options,
+                    None => return Err(arg_error(mc, 2, "open", "invalid mode")),
+                };
+
+                let name = std::string::String::from_utf8_lossy(&name).into_owned();
+                Ok(CallbackResult::Return(args.returning(
+                    match options.open(&name) {
+                        Ok(file) => vec![Value::UserData(ctx.new_file(mc, file))],
+                        Err(err) => io_error(mc, err, Some(&name)),
+                    },
+                )))
+            }))
+        })
+        .named("open"),
+    )
+    .unwrap();
+
+    io.set(
+        mc,
+        String::new_static(b"close"),
+        Callback::new_sequence_with(mc, ctx, |ctx, _, args| {
+            Ok(sequence::from_fn_with((*ctx, args), |mc, (ctx, args)| {
+                let file = match args.get(0).cloned().unwrap_or(Value::Nil) {
+                    Value::Nil => ctx.defaults.read().output,
+                    _ => file_arg(&args, 0)?,
+                };
+                let res = file_close(mc, file)?;
+                Ok(CallbackResult::Return(args.returning(res)))
+            }))
+        })
+        .named("close"),
+    )
+    .unwrap();
+
+    io.set(
+        mc,
+        String::new_static(b"flush"),
+        Callback::new_sequence_with(mc, ctx, |ctx, _, args| {
+            Ok(sequence::from_fn_with((*ctx, args), |mc, (ctx, args)| {
+                let res = file_flush(mc, ctx.defaults.read().output)?;
+                Ok(CallbackResult::Return(args.returning(res)))
+            }))
+        })
+        .named("flush"),
+    )
+    .unwrap();
+
+    io.set(
+        mc,
+        String::new_static(b"input"),
+        Callback::new_sequence_with(mc, ctx, |ctx, _, args| {
+            Ok(sequence::from_fn_with((*ctx, args), |mc, (ctx, args)| {
+                if let Some(file) = ctx.default_file(mc, &args, "input", b"r")? {
+                    ctx.defaults.write(mc).input = file;
+                }
+                Ok(CallbackResult::Return(
+                    args.returning([Value::UserData(ctx.defaults.read().input)]),
+                ))
+            }))
+        })
+        .named("input"),
+    )
+    .unwrap();
+
+    io.set(
+        mc,
+        String::new_static(b"output"),
+        Callback::new_sequence_with(mc, ctx, |ctx, _, args| {
+            Ok(sequence::from_fn_with((*ctx, args), |mc, (ctx, args)| {
+                if let Some(file) = ctx.default_file(mc, &args, "output", b"w")? {
+                    ctx.defaults.write(mc).output = file;
+                }
+                Ok(CallbackResult::Return(
+                    args.returning([Value::UserData(ctx.defaults.read().output)]),
+                ))
+            }))
+        })
+        .named("output"),
+    )
+    .unwrap();
+
+    io.set(
+        mc,
+        String::new_static(b"read"),
+        Callback::new_sequence_with(mc, ctx, |ctx, _, args| {
+            Ok(sequence::from_fn_with((*ctx, args), |mc, (ctx, args)| {
+                let res = file_read(mc, ctx.defaults.read().input, &args, 1)?;
+                Ok(CallbackResult::Return(args.returning(res)))
+            }))
+        })
+        .named("read"),
+    )
+    .unwrap();
+
+    io.set(
+        mc,
+        String::new_static(b"write"),
+        Callback::new_sequence_with(mc, ctx, |ctx, _, args| {
+            Ok(sequence::from_fn_with((*ctx, args), |mc, (ctx, args)| {
+                let res = file_write(mc, ctx.defaults.read().output, &args, 1)?;
+                Ok(CallbackResult::Return(args.returning(res)))
+            }))
+        })
+        .named("write"),
+    )
+    .unwrap();
+
+    io.set(
+        mc,
+        String::new_static(b"lines"),
+        Callback::new_sequence_with(mc, ctx, |ctx, _, args| {
+            Ok(sequence::from_fn_with((*ctx, args), |mc, (ctx, args)| {
+                let formats = args.get(1..).unwrap_or(&[]).to_vec();
+                let iter = match args.get(0).cloned().unwrap_or(Value::Nil) {
+                    Value::Nil => {
+                        let input = ctx.defaults.read().input;
+                        check_open(input)?;
+                        lines_iterator(mc, input, formats, false)
+                    }
+                    _ => {
+                        let name = string_arg(mc, &args, 0, "lines")?;
+                        let name = std::string::String::from_utf8_lossy(&name).into_owned();
+                        match File::open(&name) {
+                            Ok(file) => lines_iterator(mc, ctx.new_file(mc, file), formats, true),
+                            Err(err) => {
+                                return Err(RuntimeError(Value::String(String::new(
+                                    mc,
+                                    format!("{}: {}", name, err).as_bytes(),
+                                )))
+                                .into());
+                            }
+                        }
+                    }
+                };
+                Ok(CallbackResult::Return(args.returning([Value::from(iter)])))
+            }))
+        })
+        .named("lines"),
+    )
+    .unwrap();
+
+    io.set(
+        mc,
+        String::new_static(b"type"),
+        Callback::new_immediate(mc, |_, args| {
+            let file_type = match args.get(0).cloned().unwrap_or(Value::Nil) {
+                Value::UserData(ud) => match ud.read::<LuaFile>() {
+                    Some(file) if file.0.is_some() => String::new_static(b"file").into(),
+                    Some(_) => String::new_static(b"closed file").into(),
+                    None => Value::Nil,
+                },
+                _ => Value::Nil,
+            };
+            Ok(CallbackResult::Return(args.returning([file_type])))
+        })
+        .named("type"),
+    )
+    .unwrap();
+
+    methods
+        .set(
+            mc,
+            String::new_static(b"close"),
+            Callback::new_sequence(mc, |_, args| {
+                Ok(sequence::from_fn_with(args, |mc, args| {
+                    let res = file_close(mc, file_arg(&args, 0)?)?;
+                    Ok(CallbackResult::Return(args.returning(res)))
+                }))
+            })
+            .named("close"),
+        )
+        .unwrap();
+
+    methods
+        .set(
+            mc,
+            String::new_static(b"flush"),
+            Callback::new_sequence(mc, |_, args| {
+                Ok(sequence::from_fn_with(args, |mc, args| {
+                    let res = file_flush(mc, file_arg(&args, 0)?)?;
+                    Ok(CallbackResult::Return(args.returning(res)))
+                }))
+            })
+            .named("flush"),
+        )
+        .unwrap();
+
+    methods
+        .set(
+            mc,
+            String::new_static(b"lines"),
+            Callback::new_sequence(mc, |_, args| {
+                Ok(sequence::from_fn_with(args, |mc, args| {
+                    let file = file_arg(&args, 0)?;
+                    check_open(file)?;
+                    let formats = args[1..].to_vec();
+                    Ok(CallbackResult::Return(args.returning([Value::from(
+                        lines_iterator(mc, file, formats, false),
+                    )])))
+                }))
+            })
+            .named("lines"),
+        )
+        .unwrap();
+
+    methods
+        .set(
+            mc,
+            String::new_static(b"read"),
+            Callback::new_sequence(mc, |_, args| {
+                Ok(sequence::from_fn_with(args, |mc, args| {
+                    let file = file_arg(&args, 0)?;
+                    let res = file_read(mc, file, &args[1..], 2)?;
+                    Ok(CallbackResult::Return(args.returning(res)))
+                }))
+            })
+            .named("read"),
+        )
+        .unwrap();
+
+    methods
+        .set(
+            mc,
+            String::new_static(b"seek"),
+            Callback::new_sequence(mc, |_, args| {
+                Ok(sequence::from_fn_with(args, |mc, args| {
+                    let file = file_arg(&args, 0)?;
+                    let whence = match args.get(1).cloned().unwrap_or(Value::Nil) {
+                        Value::Nil => None,
+                        v => Some(
+                            v.to_string(mc)
+                                .ok_or_else(|| arg_error(mc, 2, "seek", "string expected"))?,
+                        ),
+                    };
+                    let offset = match args.get(2).cloned().unwrap_or(Value::Nil) {
+                        Value::Nil => 0,
+                        v => v
+                            .to_integer()
+                            .ok_or_else(|| arg_error(mc, 3, "seek", "number expected"))?,
+                    };
+                    let pos = match whence.as_ref().map_or(&b"cur"[..], |w| w.as_bytes()) {
+                        b"set" if offset >= 0 => SeekFrom::Start(offset as u64),
+                        b"set" => {
+                            return Ok(CallbackResult::Return(args.returning(io_error(
+                                mc,
+                                io::Error::from(io::ErrorKind::InvalidInput),
+                                None,
+                            ))));
+                        }
+                        b"cur" => SeekFrom::Current(offset),
+                        b"end" => SeekFrom::End(offset),
+                        _ => return Err(arg_error(mc, 2, "seek", "invalid option")),
+                    };
+
+                    let res = open_stream(&file)?.seek(pos);
+                    Ok(CallbackResult::Return(args.returning(match res {
+                        Ok(pos) => vec![Value::Integer(pos as i64)],
+                        Err(err) => io_error(mc, err, None),
+                    })))
+                }))
+            })
+            .named("seek"),
+        )
+        .unwrap();
+
+    methods
+        .set(
+            mc,
+            String::new_static(b"setvbuf"),
+            Callback::new_sequence(mc, |_, args| {
+                Ok(sequence::from_fn_with(args, |mc, args| {
+                    let file = file_arg(&args, 0)?;
+                    let mode = string_arg(mc, &args, 1, "setvbuf")?;
+                    let buffering = match mode.as_bytes() {
+                        b"no" => Buffering::No,
+                        b"full" => Buffering::Full,
+                        b"line" => Buffering::Line,
+                        _ => return Err(arg_error(mc, 2, "setvbuf", "invalid option")),
+                    };
+                    let size = match args.get(2).cloned().unwrap_or(Value::Nil) {
+                        Value::Nil => DEFAULT_BUFFER_SIZE,
+                        v => match v.to_integer() {
+                            Some(size) if size >= 0 => size as usize,
+                            _ => return Err(arg_error(mc, 3, "setvbuf", "number expected")),
+                        },
+                    };
+
+                    let res = open_stream(&file)?.set_buffering(buffering, size);
+                    Ok(CallbackResult::Return(args.returning(match res {
+                        Ok(()) => vec![Value::Boolean(true)],
+                        Err(err) => io_error(mc, err, None),
+                    })))
+                }))
+            })
+            .named("setvbuf"),
+        )
+        .unwrap();
+
+    methods
+        .set(
+            mc,
+            String::new_static(b"write"),
+            Callback::new_sequence(mc, |_, args| {
+                Ok(sequence::from_fn_with(args, |mc, args| {
+                    let file = file_arg(&args, 0)?;
+                    let res = file_write(mc, file, &args[1..], 2)?;
+                    Ok(CallbackResult::Return(args.returning(res)))
+                }))
+            })
+            .named("write"),
+        )
+        .unwrap();
+
+    env.set(mc, String::new_static(b"io"), io).unwrap();
+}
+
+#[derive(Collect, Clone, Copy)]
+#[collect(no_drop)]
+struct IoContext<'gc> {
+    // The methods shared by every file handle
+    methods: Table<'gc>,
+    defaults: GcCell<'gc, Defaults<'gc>>,
+}
+
+#[derive(Collect)]
+#[collect(no_drop)]
+struct Defaults<'gc> {
+    input: UserData<'gc>,
+    output: UserData<'gc>,
+}
+
+impl<'gc> IoContext<'gc> {
+    fn new_file(&self, mc: MutationContext<'gc, '_>, file: File) -> UserData<'gc> {
+        UserData::new(
+            mc,
+            LuaFile(Some(Stream::File(FileStream::new(file)))),
+            Some(self.methods),
+        )
+    }
+
+    // Handles the argument to `io.input` and `io.output`, which may either be a file name to open
+    // with the given mode or an existing file handle.
+    fn default_file(
+        &self,
+        mc: MutationContext<'gc, '_>,
+        args: &[Value<'gc>],
+        func: &str,
+        mode: &[u8],
+    ) -> Result<Option<UserData<'gc>>, Error<'gc>> {
+        match args.get(0).cloned().unwrap_or(Value::Nil) {
+            Value::Nil => Ok(None),
+            Value::UserData(_) => {
+                let file = file_arg(args, 0)?;
+                check_open(file)?;
+                Ok(Some(file))
+            }
+            _ => {
+                let name = string_arg(mc, args, 0, func)?;
+                let name = std::string::String::from_utf8_lossy(&name).into_owned();
+                match open_options(mode).unwrap().open(&name) {
+                    Ok(file) => Ok(Some(self.new_file(mc, file))),
+                    Err(err) => Err(RuntimeError(Value::String(String::new(
+                        mc,
+                        format!("{}: {}", name, err).as_bytes(),
+                    )))
+                    .into()),
+                }
+            }
+        }
+    }
+}
+
+// The data held by a file handle userdata, the stream is `None` once the file has been closed.
+// Files that are never explicitly closed are closed when their userdata is collected.
+struct LuaFile(Option<Stream>);
+
+enum Stream {
+    Stdin(StdStreams),
+    Stdout(StdStreams),
+    Stderr(StdStreams),
+    File(FileStream),
+}
+
+impl Stream {
+    fn with_reader<R>(
+        &mut self,
+        f: impl FnOnce(&mut dyn BufRead) -> Result<R, io::Error>,
+    ) -> Result<R, io::Error> {
+        match self {
+            Stream::Stdin(streams) => f(&mut *streams.stdin()),
+            Stream::Stdout(_) | Stream::Stderr(_) => Err(bad_file_descriptor()),
+            Stream::File(file) => {
+                file.flush_writes()?;
+                f(&mut file.reader)
+            }
+        }
+    }
+
+    fn with_writer<R>(
+        &mut self,
+        f: impl FnOnce(&mut dyn Write) -> Result<R, io::Error>,
+    ) -> Result<R, io::Error> {
+        match self {
+            Stream::Stdin(_) => Err(bad_file_descriptor()),
+            Stream::Stdout(streams) => f(&mut *streams.stdout()),
+            Stream::Stderr(streams) => f(&mut *streams.stderr()),
+            Stream::File(file) => f(file),
+        }
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, io::Error> {
+        match self {
+            Stream::File(file) => {
+                file.flush_writes()?;
+                file.reader.seek(pos)
+            }
+            _ => Err(io::Error::other("Illegal seek")),
+        }
+    }
+
+    fn set_buffering(&mut self, buffering: Buffering, size: usize) -> Result<(), io::Error> {
+        match self {
+            Stream::File(file) => {
+                file.flush_writes()?;
+                file.buffering = buffering;
+                file.buffer_size = size;
+                Ok(())
+            }
+            // Buffering of the standard streams is up to the host
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Buffering {
+    No,
+    Full,
+    Line,
+}
+
+// A file that may be both read from and written to.  Reads go through a `BufReader`, while writes
+// are buffered separately according to the buffering mode set with `setvbuf`.  Switching from
+// reading to writing discards any read-ahead, and switching from writing to reading flushes any
+// pending writes, so that both always happen at the logical position of the file.
+struct FileStream {
+    reader: BufReader<File>,
+    write_buf: Vec<u8>,
+    buffering: Buffering,
+    buffer_size: usize,
+}
+
+impl FileStream {
+    fn new(file: File) -> FileStream {
+        // Not `io::buffered_read`, which skips a leading BOM and "#" line as Lua does for scripts,
+        // but which would silently drop data read from a file.
+        FileStream {
+            reader: BufReader::new(file),
+            write_buf: Vec::new(),
+            buffering: Buffering::Full,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+        }
+    }
+
+    fn flush_writes(&mut self) -> Result<(), io::Error> {
+        if !self.write_buf.is_empty() {
+            let res = self.reader.get_mut().write_all(&self.write_buf);
+            self.write_buf.clear();
+            res?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for FileStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        if !self.reader.buffer().is_empty() {
+            // Seeking the `BufReader` discards any read-ahead
+            let pos = self.reader.stream_position()?;
+            self.reader.seek(SeekFrom::Start(pos))?;
+        }
+        self.write_buf.extend_from_slice(buf);
+        let flush = match self.buffering {
+            Buffering::No => true,
+            Buffering::Full => self.write_buf.len() >= self.buffer_size,
+            Buffering::Line => buf.contains(&b'\n'),
+        };
+        if flush {
+            self.flush_writes()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.flush_writes()?;
+        self.reader.get_mut().flush()
+    }
+}
+
+impl Drop for FileStream {
+    fn drop(&mut self) {
+        let _ = self.flush_writes();
+    }
+}
+
+#[derive(Copy, Clone)]
+enum ReadFormat {
+    Number,
+    Line,
+    LineWithNewline,
+    All,
+    Count(u64),
+}
+
+impl ReadFormat {
+    fn parse<'gc>(
+        mc: MutationContext<'gc, '_>,
+        value: Value<'gc>,
+        arg: usize,
+    ) -> Result<ReadFormat, Error<'gc>> {
+        match value {
+            Value::Integer(_) | Value::Number(_) => match value.to_integer() {
+                Some(n) => Ok(ReadFormat::Count(n.max(0) as u64)),
+                None => Err(arg_error(
+                    mc,
+                    arg,
+                    "read",
+                    "number has no integer representation",
+                )),
+            },
+            Value::String(s) => {
+                // Lua 5.3 formats may be prefixed with a '*'
+                let s = match s.as_bytes() {
+                    [b'*', rest @ ..] => rest,
+                    s => s,
+                };
+                match s.first() {
+                    Some(b'n') => Ok(ReadFormat::Number),
+                    Some(b'l') => Ok(ReadFormat::Line),
+                    Some(b'L') => Ok(ReadFormat::LineWithNewline),
+                    Some(b'a') => Ok(ReadFormat::All),
+                    _ => Err(arg_error(mc, arg, "read", "invalid format")),
+                }
+            }
+            _ => Err(arg_error(mc, arg, "read", "invalid format")),
+        }
+    }
+}
+
+// Reads from the file with the given formats.  Errors number the first format as argument
+// `first_arg`, which is 2 where the file itself is argument 1.
+fn file_read<'gc>(
+    mc: MutationContext<'gc, '_>,
+    file: UserData<'gc>,
+    formats: &[Value<'gc>],
+    first_arg: usize,
+) -> Result<Vec<Value<'gc>>, Error<'gc>> {
+    let formats = if formats.is_empty() {
+        vec![ReadFormat::Line]
+    } else {
+        formats
+            .iter()
+            .enumerate()
+            .map(|(i, &f)| ReadFormat::parse(mc, f, first_arg + i))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    let res = open_stream(&file)?.with_reader(|r| {
+        let mut results = Vec::new();
+        for format in formats {
+            let res = match format {
+                ReadFormat::Number => read_number(r)?,
+                ReadFormat::Line => read_line(r, false)?.map(|l| String::new(mc, &l).into()),
+                ReadFormat::LineWithNewline => {
+                    read_line(r, true)?.map(|l| String::new(mc, &l).into())
+                }
+                ReadFormat::All => {
+                    let mut buf = Vec::new();
+                    r.read_to_end(&mut buf)?;
+                    Some(String::new(mc, &buf).into())
+                }
+                ReadFormat::Count(n) => read_count(r, n)?.map(|b| String::new(mc, &b).into()),
+            };
+            match res {
+                Some(v) => results.push(v),
+                None => {
+                    results.push(Value::Nil);
+                    break;
+                }
+            }
+        }
+        Ok(results)
+    });
+
+    Ok(match res {
+        Ok(results) => results,
+        Err(err) => io_error(mc, err, None),
+    })
+}
+
+// Writes the values to the file, numbering the first value as argument `first_arg` in errors.
+fn file_write<'gc>(
+    mc: MutationContext<'gc, '_>,
+    file: UserData<'gc>,
+    values: &[Value<'gc>],
+    first_arg: usize,
+) -> Result<Vec<Value<'gc>>, Error<'gc>> {
+    for (i, v) in values.iter().enumerate() {
+        match v {
+            Value::String(_) | Value::Integer(_) | Value::Number(_) => {}
+            v => {
+                return Err(arg_error(
+                    mc,
+                    first_arg + i,
+                    "write",
+                    &format!("string expected, got {}", v.type_name()),
+                ));
+            }
+        }
+    }
+
+    let res = open_stream(&file)?.with_writer(|w| {
+        for v in values {
+            v.display(&mut *w)?;
+        }
+        Ok(())
+    });
+
+    Ok(match res {
+        Ok(()) => vec![Value::UserData(file)],
+        Err(err) => io_error(mc, err, None),
+    })
+}
+
+fn file_flush<'gc>(
+    mc: MutationContext<'gc, '_>,
+    file: UserData<'gc>,
+) -> Result<Vec<Value<'gc>>, Error<'gc>> {
+    let res = open_stream(&file)?.with_writer(|w| w.flush());
+    Ok(match res {
+        Ok(()) => vec![Value::Boolean(true)],
+        Err(err) => io_error(mc, err, None),
+    })
+}
+
+fn file_close<'gc>(
+    mc: MutationContext<'gc, '_>,
+    file: UserData<'gc>,
+) -> Result<Vec<Value<'gc>>, Error<'gc>> {
+    let mut file = file.write::<LuaFile>().unwrap();
+    match file.0.take() {
+        Some(Stream::File(mut stream)) => Ok(match stream.flush() {
+            Ok(()) => vec![Value::Boolean(true)],
+            Err(err) => io_error(mc, err, None),
+        }),
+        Some(stream) => {
+            file.0 = Some(stream);
+            Ok(vec![
+                Value::Nil,
+                Value::String(String::new_static(b"cannot close standard file")),
+            ])
+        }
+        None => Err(closed_file_error()),
+    }
+}
+
+// Returns an iterator function that reads from the given file with the given formats on every
+// call, optionally closing the file once the first result is nil.
+fn lines_iterator<'gc>(
+    mc: MutationContext<'gc, '_>,
+    file: UserData<'gc>,
+    formats: Vec<Value<'gc>>,
+    close: bool,
+) -> Callback<'gc> {
+    Callback::new_sequence_with(mc, (file, formats), move |(file, formats), _, args| {
+        Ok(sequence::from_fn_with(
+            (*file, formats.clone(), args),
+            move |mc, (file, formats, args)| {
+                if file.read::<LuaFile>().unwrap().0.is_none() {
+                    return Err(RuntimeError(Value::String(String::new_static(
+                        b"file is already closed",
+                    )))
+                    .into());
+                }
+
+                let results = file_read(mc, file, &formats, 2)?;
+                if let Some(Value::Nil) = results.first() {
+                    if let Some(Value::String(err)) = results.get(1) {
+                        return Err(RuntimeError(Value::String(*err)).into());
+                    }
+                    if close {
+                        file_close(mc, file)?;
+                    }
+                }
+                Ok(CallbackResult::Return(args.returning(results)))
+            },
+        ))
+    })
+}
+
+fn read_line(r: &mut dyn BufRead, keep_newline: bool) -> Result<Option<Vec<u8>>, io::Error> {
+    let mut buf = Vec::new();
+    if r.read_until(b'\n', &mut buf)? == 0 {
+        return Ok(None);
+    }
+    if !keep_newline && buf.last() == Some(&b'\n') {
+        buf.pop();
+    }
+    Ok(Some(buf))
+}
+
+fn read_count(r: &mut dyn BufRead, count: u64) -> Result<Option<Vec<u8>>, io::Error> {
+    if count == 0 {
+        // Reading zero bytes is a test for the end of file
+        return Ok(if r.fill_buf()?.is_empty() {
+            None
+        } else {
+            Some(Vec::new())
+        });
+    }
+
+    let mut buf = Vec::new();
+    r.take(count).read_to_end(&mut buf)?;
+    Ok(if buf.is_empty() { None } else { Some(buf) })
+}
+
+// Reads the longest prefix of the input that may be a numeral, mirroring PUC-Rio Lua's `l_getn`.
+// Only the characters that are part of the potential numeral are consumed, and if they turn out
+// not to form a valid number, nil is returned.
+fn read_number<'gc>(r: &mut dyn BufRead) -> Result<Option<Value<'gc>>, io::Error> {
+    struct Numeral<'a> {
+        r: &'a mut dyn BufRead,
+        buf: Vec<u8>,
+        overflow: bool,
+    }
+
+    impl<'a> Numeral<'a> {
+        // Consumes the next character if it is one of the given set of characters.
+        fn test(&mut self, set: &[u8]) -> Result<bool, io::Error> {
+            match self.r.fill_buf()?.first() {
+                Some(&c) if set.contains(&c) => {
+                    if self.buf.len() >= MAX_NUMERAL_LENGTH {
+                        self.overflow = true;
+                        Ok(false)
+                    } else {
+                        self.buf.push(c);
+                        self.r.consume(1);
+                        Ok(true)
+                    }
+                }
+                _ => Ok(false),
+            }
+        }
+
+        fn digits(&mut self, hex: bool) -> Result<usize, io::Error> {
+            let set: &[u8] = if hex {
+                b"0123456789abcdefABCDEF"
+            } else {
+                b"0123456789"
+            };
+            let mut count = 0;
+            while self.test(set)? {
+                count += 1;
+            }
+            Ok(count)
+        }
+    }
+
+    loop {
+        let (skip, done) = {
+            let buf = r.fill_buf()?;
+            let skip = buf.iter().take_while(|c| c.is_ascii_whitespace()).count();
+            (skip, skip < buf.len() || buf.is_empty())
+        };
+        r.consume(skip);
+        if done {
+            break;
+        }
+    }
+
+    let mut numeral = Numeral {
+        r,
+        buf: Vec::new(),
+        overflow: false,
+    };
+
+    numeral.test(b"+-")?;
+    let mut count = 0;
+    let mut hex = false;
+    if numeral.test(b"0")? {
+        if numeral.test(b"xX")? {
+            hex = true;
+        } else {
+            count = 1;
+        }
+    }
+    count += numeral.digits(hex)?;
+    if numeral.test(b".")? {
+        count += numeral.digits(hex)?;
+    }
+    if count > 0 && numeral.test(if hex { b"pP" } else { b"eE" })? {
+        numeral.test(b"+-")?;
+        numeral.digits(false)?;
+    }
+
+    if numeral.overflow {
+        return Ok(None);
+    }
+    Ok(parse_numeral(&numeral.buf, hex))
+}
+
+fn parse_numeral<'gc>(s: &[u8], hex: bool) -> Option<Value<'gc>> {
+    let unsigned = match s.first() {
+        Some(b'+') | Some(b'-') => &s[1..],
+        _ => s,
+    };
+
+    if hex {
+        if unsigned.len() <= 2 {
+            None
+        } else if let Some(i) = read_hex_integer(s) {
+            Some(Value::Integer(i))
+        } else {
+            read_hex_float(s).map(Value::Number)
+        }
+    } else if unsigned.is_empty() {
+        None
+    } else if let Some(i) = read_integer(s) {
+        Some(Value::Integer(i))
+    } else {
+        read_float(s).map(Value::Number)
+    }
+}
+
+fn open_options(mode: &[u8]) -> Option<OpenOptions> {
+    let (kind, rest) = mode.split_first()?;
+    let (update, rest) = match rest.split_first() {
+        Some((b'+', rest)) => (true, rest),
+        _ => (false, rest),
+    };
+    if !rest.iter().all(|&c| c == b'b') {
+        return None;
+    }
+
+    let mut options = OpenOptions::new();
+    match kind {
+        b'r' => options.read(true).write(update),
+        b'w' => options.read(update).write(true).create(true).truncate(true),
+        b'a' => options.read(update).append(true).create(true),
+        _ => return None,
+    };
+    Some(options)
+}
+
+// Borrows the stream of an open file, erroring if the file has been closed.
+fn open_stream<'a, 'gc>(file: &'a UserData<'gc>) -> Result<RefMut<'a, Stream>, Error<'gc>> {
+    RefMut::filter_map(file.write::<LuaFile>().unwrap(), |f| f.0.as_mut())
+        .map_err(|_| closed_file_error())
+}
+
+fn check_open<'gc>(file: UserData<'gc>) -> Result<(), Error<'gc>> {
+    if file.read::<LuaFile>().unwrap().0.is_some() {
+        Ok(())
+    } else {
+        Err(closed_file_error())
+    }
+}
+
+fn file_arg<'gc>(args: &[Value<'gc>], i: usize) -> Result<UserData<'gc>, Error<'gc>> {
+    match args.get(i).cloned().unwrap_or(Value::Nil) {
+        Value::UserData(ud) if ud.is::<LuaFile>() => Ok(ud),
+        value => Err(TypeError {
+            expected: "FILE*",
+            found: value.type_name(),
+        }
+        .into()),
+    }
+}
+
+fn string_arg<'gc>(
+    mc: MutationContext<'gc, '_>,
+    args: &[Value<'gc>],
+    i: usize,
+    func: &str,
+) -> Result<String<'gc>, Error<'gc>> {
+    args.get(i)
+        .cloned()
+        .unwrap_or(Value::Nil)
+        .to_string(mc)
+        .ok_or_else(|| arg_error(mc, i + 1, func, "string expected"))
+}
+
+fn arg_error<'gc>(mc: MutationContext<'gc, '_>, arg: usize, func: &str, msg: &str) -> Error<'gc> {
+    RuntimeError(Value::String(String::new(
+        mc,
+        format!("bad argument #{} to '{}' ({})", arg, func, msg).as_bytes(),
+    )))
+    .into()
+}
+
+fn closed_file_error<'gc>() -> Error<'gc> {
+    RuntimeError(Value::String(String::new_static(
+        b"attempt to use a closed file",
+    )))
+    .into()
+}
+
+fn bad_file_descriptor() -> io::Error {
+    io::Error::other("Bad file descriptor")
+}
+
+// Returns the conventional `nil, message, errno` triple for a failed I/O operation.
+fn io_error<'gc>(
+    mc: MutationContext<'gc, '_>,
+    err: io::Error,
+    name: Option<&str>,
+) -> Vec<Value<'gc>> {
+    let msg = match name {
+        Some(name) => format!("{}: {}", name, err),
+        None => err.to_string(),
+    };
+    vec![
+        Value::Nil,
+        Value::String(String::new(mc, msg.as_bytes())),
+        Value::Integer(err.raw_os_error().unwrap_or(0) as i64),
+    ]
+}
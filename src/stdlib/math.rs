@@ -14,200 +14,228 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"abs"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match args.get(0).cloned().unwrap_or(Value::Nil) {
-                Value::Integer(a) => Ok(CallbackResult::Return(vec![Value::Integer(a.abs())])),
+                Value::Integer(a) => Ok(CallbackResult::Return(
+                    args.returning([Value::Integer(a.abs())]),
+                )),
                 a => match a.to_number() {
-                    Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.abs())])),
+                    Some(f) => Ok(CallbackResult::Return(
+                        args.returning([Value::Number(f.abs())]),
+                    )),
                     _ => Err(RuntimeError(Value::String(String::new_static(
                         b"Bad argument to abs",
                     )))
                     .into()),
                 },
             }
-        }),
+        })
+        .named("abs"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"acos"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
-                Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.acos())])),
+                Some(f) => Ok(CallbackResult::Return(
+                    args.returning([Value::Number(f.acos())]),
+                )),
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to acos"))).into(),
                 ),
             }
-        }),
+        })
+        .named("acos"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"asin"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
-                Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.asin())])),
+                Some(f) => Ok(CallbackResult::Return(
+                    args.returning([Value::Number(f.asin())]),
+                )),
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to asin"))).into(),
                 ),
             }
-        }),
+        })
+        .named("asin"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"atan"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
-                Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.atan())])),
+                Some(f) => Ok(CallbackResult::Return(
+                    args.returning([Value::Number(f.atan())]),
+                )),
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to atan"))).into(),
                 ),
             }
-        }),
+        })
+        .named("atan"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"atan2"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match (
                 args.get(0).cloned().unwrap_or(Value::Nil).to_number(),
                 args.get(1).cloned().unwrap_or(Value::Nil).to_number(),
             ) {
-                (Some(f), Some(g)) => Ok(CallbackResult::Return(vec![Value::Number(f.atan2(g))])),
+                (Some(f), Some(g)) => Ok(CallbackResult::Return(
+                    args.returning([Value::Number(f.atan2(g))]),
+                )),
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to atan2")))
                         .into(),
                 ),
             }
-        }),
+        })
+        .named("atan2"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"ceil"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
-                Some(f) => Ok(CallbackResult::Return(vec![
-                    Value::Integer(f.ceil() as i64),
-                ])),
+                Some(f) => Ok(CallbackResult::Return(
+                    args.returning([Value::Integer(f.ceil() as i64)]),
+                )),
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to ceil"))).into(),
                 ),
             }
-        }),
+        })
+        .named("ceil"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"cos"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
-                Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.cos())])),
+                Some(f) => Ok(CallbackResult::Return(
+                    args.returning([Value::Number(f.cos())]),
+                )),
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to cos"))).into(),
                 ),
             }
-        }),
+        })
+        .named("cos"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"cosh"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
-                Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.cosh())])),
+                Some(f) => Ok(CallbackResult::Return(
+                    args.returning([Value::Number(f.cosh())]),
+                )),
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to cosh"))).into(),
                 ),
             }
-        }),
+        })
+        .named("cosh"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"deg"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
-                Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.to_degrees())])),
+                Some(f) => Ok(CallbackResult::Return(
+                    args.returning([Value::Number(f.to_degrees())]),
+                )),
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to deg"))).into(),
                 ),
             }
-        }),
+        })
+        .named("deg"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"exp"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
-                Some(f) => Ok(CallbackResult::Return(vec![Value::Number(
-                    std::f64::consts::E.powf(f),
-                )])),
+                Some(f) => Ok(CallbackResult::Return(
+                    args.returning([Value::Number(std::f64::consts::E.powf(f))]),
+                )),
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to exp"))).into(),
                 ),
             }
-        }),
+        })
+        .named("exp"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"floor"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
-                Some(f) => Ok(CallbackResult::Return(vec![Value::Integer(
-                    f.floor() as i64
-                )])),
+                Some(f) => Ok(CallbackResult::Return(
+                    args.returning([Value::Integer(f.floor() as i64)]),
+                )),
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to floor")))
                         .into(),
                 ),
             }
-        }),
+        })
+        .named("floor"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"fmod"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match (
                 args.get(0).cloned().unwrap_or(Value::Nil).to_number(),
                 args.get(1).cloned().unwrap_or(Value::Nil).to_number(),
             ) {
                 (Some(f), Some(g)) => {
                     let result = (f % g).abs();
-                    Ok(CallbackResult::Return(vec![Value::Number(if f < 0.0 {
-                        -result
-                    } else {
-                        result
-                    })]))
+                    Ok(CallbackResult::Return(args.returning([Value::Number(
+                        if f < 0.0 { -result } else { result },
+                    )])))
                 }
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to fmod"))).into(),
                 ),
             }
-        }),
+        })
+        .named("fmod"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"frexp"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
                 Some(f) if f.is_finite() => {
                     let bits = f.to_bits();
@@ -218,21 +246,20 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
                     // put into range of result
                     let e = ((bits >> 52) & 0x7ff) as i64 - 1023 + 1;
 
-                    Ok(CallbackResult::Return(vec![
-                        Value::Number(m),
-                        Value::Integer(e),
-                    ]))
+                    Ok(CallbackResult::Return(
+                        args.returning([Value::Number(m), Value::Integer(e)]),
+                    ))
                 }
-                Some(f) => Ok(CallbackResult::Return(vec![
-                    Value::Number(f),
-                    Value::Integer(0),
-                ])),
+                Some(f) => Ok(CallbackResult::Return(
+                    args.returning([Value::Number(f), Value::Integer(0)]),
+                )),
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to frexp")))
                         .into(),
                 ),
             }
-        }),
+        })
+        .named("frexp"),
     )
     .unwrap();
 
@@ -246,56 +273,63 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"ldexp"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match (
                 args.get(0).cloned().unwrap_or(Value::Nil).to_number(),
                 args.get(1).cloned().unwrap_or(Value::Nil).to_number(),
             ) {
-                (Some(f), Some(g)) => Ok(CallbackResult::Return(vec![Value::Number(
-                    f * 2.0_f64.powf(g),
-                )])),
+                (Some(f), Some(g)) => Ok(CallbackResult::Return(
+                    args.returning([Value::Number(f * 2.0_f64.powf(g))]),
+                )),
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to ldexp")))
                         .into(),
                 ),
             }
-        }),
+        })
+        .named("ldexp"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"log"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
-                Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.ln())])),
+                Some(f) => Ok(CallbackResult::Return(
+                    args.returning([Value::Number(f.ln())]),
+                )),
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to log"))).into(),
                 ),
             }
-        }),
+        })
+        .named("log"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"log10"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
-                Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.log10())])),
+                Some(f) => Ok(CallbackResult::Return(
+                    args.returning([Value::Number(f.log10())]),
+                )),
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to log10")))
                         .into(),
                 ),
             }
-        }),
+        })
+        .named("log10"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"max"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             if args.len() == 0 {
                 return Err(RuntimeError(Value::String(String::new_static(
                     b"Bad argument to max",
@@ -312,8 +346,9 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
                         )
                         .and_then(|less| if less { Ok(entry) } else { Ok(max) })
                 })
-                .map(|a| CallbackResult::Return(vec![a]))
-        }),
+                .map(|a| CallbackResult::Return(args.returning([a])))
+        })
+        .named("max"),
     )
     .unwrap();
 
@@ -327,7 +362,7 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"min"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             if args.len() == 0 {
                 return Err(RuntimeError(Value::String(String::new_static(
                     b"Bad argument to min",
@@ -345,8 +380,9 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
                         )
                         .and_then(|less| if less { Ok(entry) } else { Ok(min) })
                 })
-                .map(|a| CallbackResult::Return(vec![a]))
-        }),
+                .map(|a| CallbackResult::Return(args.returning([a])))
+        })
+        .named("min"),
     )
     .unwrap();
 
@@ -360,17 +396,20 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"modf"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
-                Some(f) => Ok(CallbackResult::Return(vec![
-                    Value::Integer(f as i64 / 1),
-                    Value::Number(f % 1.0),
-                ])),
+                Some(f) => {
+                    Ok(CallbackResult::Return(args.returning([
+                        Value::Integer(f as i64 / 1),
+                        Value::Number(f % 1.0),
+                    ])))
+                }
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to modf"))).into(),
                 ),
             }
-        }),
+        })
+        .named("modf"),
     )
     .unwrap();
 
@@ -384,14 +423,17 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"rad"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
-                Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.to_radians())])),
+                Some(f) => Ok(CallbackResult::Return(
+                    args.returning([Value::Number(f.to_radians())]),
+                )),
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to rad"))).into(),
                 ),
             }
-        }),
+        })
+        .named("rad"),
     )
     .unwrap();
 
@@ -400,24 +442,24 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"random"),
-        Callback::new_immediate(mc, move |args| {
+        Callback::new_immediate(mc, move |_, args| {
             let rng = &random_rng;
             match (
                 args.get(0).cloned().unwrap_or(Value::Nil),
                 args.get(1).cloned().unwrap_or(Value::Nil),
             ) {
-                (Value::Nil, Value::Nil) => Ok(CallbackResult::Return(vec![Value::Number(
-                    rng.borrow_mut().gen::<f64>(),
-                )])),
+                (Value::Nil, Value::Nil) => Ok(CallbackResult::Return(
+                    args.returning([Value::Number(rng.borrow_mut().gen::<f64>())]),
+                )),
                 (a, b) => {
                     if let (Some(first), Value::Nil) = (a.to_integer(), b) {
-                        Ok(CallbackResult::Return(vec![Value::Integer(
+                        Ok(CallbackResult::Return(args.returning([Value::Integer(
                             rng.borrow_mut().gen_range(1, first + 1),
-                        )]))
+                        )])))
                     } else if let (Some(first), Some(second)) = (a.to_integer(), b.to_integer()) {
-                        Ok(CallbackResult::Return(vec![Value::Integer(
+                        Ok(CallbackResult::Return(args.returning([Value::Integer(
                             rng.borrow_mut().gen_range(first, second + 1),
-                        )]))
+                        )])))
                     } else {
                         Err(RuntimeError(Value::String(String::new_static(
                             b"Bad argument to random",
@@ -426,7 +468,8 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
                     }
                 }
             }
-        }),
+        })
+        .named("random"),
     )
     .unwrap();
 
@@ -434,109 +477,122 @@ pub fn load_math<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc
     math.set(
         mc,
         String::new_static(b"randomseed"),
-        Callback::new_immediate(mc, move |args| {
+        Callback::new_immediate(mc, move |_, args| {
             let rng = &randomseed_rng;
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
                 Some(f) => {
                     *(rng.borrow_mut().deref_mut()) = Xoshiro256StarStar::seed_from_u64(f as u64);
-                    Ok(CallbackResult::Return(vec![]))
+                    Ok(CallbackResult::Return(args.returning([])))
                 }
                 _ => Err(RuntimeError(Value::String(String::new_static(
                     b"Bad argument to randomseed",
                 )))
                 .into()),
             }
-        }),
+        })
+        .named("randomseed"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"sin"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
-                Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.sin())])),
+                Some(f) => Ok(CallbackResult::Return(
+                    args.returning([Value::Number(f.sin())]),
+                )),
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to sin"))).into(),
                 ),
             }
-        }),
+        })
+        .named("sin"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"sqrt"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
-                Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.sqrt())])),
+                Some(f) => Ok(CallbackResult::Return(
+                    args.returning([Value::Number(f.sqrt())]),
+                )),
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to sqrt"))).into(),
                 ),
             }
-        }),
+        })
+        .named("sqrt"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"tan"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match args.get(0).cloned().unwrap_or(Value::Nil).to_number() {
-                Some(f) => Ok(CallbackResult::Return(vec![Value::Number(f.tan())])),
+                Some(f) => Ok(CallbackResult::Return(
+                    args.returning([Value::Number(f.tan())]),
+                )),
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to tan"))).into(),
                 ),
             }
-        }),
+        })
+        .named("tan"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"tointeger"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match args.get(0).cloned().unwrap_or(Value::Nil).to_integer() {
-                Some(f) => Ok(CallbackResult::Return(vec![Value::Integer(f)])),
-                _ => Ok(CallbackResult::Return(vec![Value::Nil])),
+                Some(f) => Ok(CallbackResult::Return(args.returning([Value::Integer(f)]))),
+                _ => Ok(CallbackResult::Return(args.returning([Value::Nil]))),
             }
-        }),
+        })
+        .named("tointeger"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"type"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match args.get(0).cloned().unwrap_or(Value::Nil) {
-                Value::Integer(_) => Ok(CallbackResult::Return(vec![Value::String(
-                    String::new_static(b"integer"),
-                )])),
-                Value::Number(_) => Ok(CallbackResult::Return(vec![Value::String(
-                    String::new_static(b"float"),
-                )])),
-                _ => Ok(CallbackResult::Return(vec![Value::Nil])),
+                Value::Integer(_) => Ok(CallbackResult::Return(
+                    args.returning([Value::String(String::new_static(b"integer"))]),
+                )),
+                Value::Number(_) => Ok(CallbackResult::Return(
+                    args.returning([Value::String(String::new_static(b"float"))]),
+                )),
+                _ => Ok(CallbackResult::Return(args.returning([Value::Nil]))),
             }
-        }),
+        })
+        .named("type"),
     )
     .unwrap();
 
     math.set(
         mc,
         String::new_static(b"ult"),
-        Callback::new_immediate(mc, |args| {
+        Callback::new_immediate(mc, |_, args| {
             match (
                 args.get(0).cloned().unwrap_or(Value::Nil).to_integer(),
                 args.get(1).cloned().unwrap_or(Value::Nil).to_integer(),
             ) {
-                (Some(f), Some(g)) => Ok(CallbackResult::Return(vec![Value::Boolean(
-                    (f as u64) < (g as u64),
-                )])),
+                (Some(f), Some(g)) => Ok(CallbackResult::Return(
+                    args.returning([Value::Boolean((f as u64) < (g as u64))]),
+                )),
                 _ => Err(
                     RuntimeError(Value::String(String::new_static(b"Bad argument to ult"))).into(),
                 ),
             }
-        }),
+        })
+        .named("ult"),
     )
     .unwrap();
 
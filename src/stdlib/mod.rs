@@ -1,9 +1,19 @@
 mod base;
 mod coroutine;
+mod debug;
+mod io;
 mod math;
+mod os;
 mod string;
+mod table;
+mod utf8;
 
 pub use base::load_base;
 pub use coroutine::load_coroutine;
+pub use debug::load_debug;
+pub use io::load_io;
 pub use math::load_math;
+pub use os::{load_os, load_os_with, Clock, OsOptions, SystemClock};
 pub use string::load_string;
+pub use table::load_table;
+pub use utf8::load_utf8;
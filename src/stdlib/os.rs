@@ -0,0 +1,683 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use gc_arena::{Collect, MutationContext};
+use gc_sequence::{self as sequence, Sequence};
+use rand::Rng;
+
+use crate::{
+    Callback, CallbackResult, Error, HostError, Root, RuntimeError, String, Table, Value,
+    ValueBuffer,
+};
+
+/// Source of time for the `os` library.
+///
+/// The default `SystemClock` reads the system clock, hosts may provide their own implementation to
+/// make scripts deterministic (e.g. for tests).
+pub trait Clock {
+    /// The current time, in seconds since the unix epoch.
+    fn time(&self) -> i64;
+
+    /// An approximation of the processor time used by the program, in seconds.
+    fn clock(&self) -> f64;
+
+    /// The offset of local time from UTC, in seconds, at the given unix time.
+    fn utc_offset(&self, time: i64) -> i64;
+}
+
+/// `Clock` implementation backed by the system clock.
+///
+/// Local time is considered to be UTC, and `clock` measures the time elapsed since the
+/// `SystemClock` was created rather than true processor time.
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn time(&self) -> i64 {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        }
+    }
+
+    fn clock(&self) -> f64 {
+        self.start.elapsed().as_secs_f64()
+    }
+
+    fn utc_offset(&self, _: i64) -> i64 {
+        0
+    }
+}
+
+/// Configures which parts of the `os` library are loaded.
+#[derive(Clone)]
+pub struct OsOptions {
+    /// Load the functions which access the host process and environment: `exit` and `getenv`.
+    pub process: bool,
+    /// Load the functions which access the filesystem: `remove`, `rename`, and `tmpname`.
+    pub filesystem: bool,
+    /// The clock used by `time`, `clock`, and `date`.
+    pub clock: Rc<dyn Clock>,
+}
+
+impl OsOptions {
+    /// The full `os` library.
+    pub fn full() -> OsOptions {
+        OsOptions {
+            process: true,
+            filesystem: true,
+            clock: Rc::new(SystemClock::new()),
+        }
+    }
+
+    /// Only `clock`, `date`, `difftime`, and `time`, which cannot affect the host.
+    pub fn safe() -> OsOptions {
+        OsOptions {
+            process: false,
+            filesystem: false,
+            clock: Rc::new(SystemClock::new()),
+        }
+    }
+
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> OsOptions {
+        self.clock = Rc::new(clock);
+        self
+    }
+}
+
+impl Default for OsOptions {
+    fn default() -> OsOptions {
+        OsOptions::full()
+    }
+}
+
+pub fn load_os<'gc>(mc: MutationContext<'gc, '_>, root: Root<'gc>, env: Table<'gc>) {
+    load_os_with(mc, root, env, OsOptions::default());
+}
+
+/// Loads the `os` library into the given environment with the given options, replacing any `os`
+/// table which is already present.
+pub fn load_os_with<'gc>(
+    mc: MutationContext<'gc, '_>,
+    _: Root<'gc>,
+    env: Table<'gc>,
+    options: OsOptions,
+) {
+    let os = Table::new(mc);
+
+    let clock = options.clock.clone();
+    os.set(
+        mc,
+        String::new_static(b"clock"),
+        Callback::new_immediate(mc, move |_, args| {
+            Ok(CallbackResult::Return(
+                args.returning([Value::Number(clock.clock())]),
+            ))
+        })
+        .named("clock"),
+    )
+    .unwrap();
+
+    let clock = options.clock.clone();
+    os.set(
+        mc,
+        String::new_static(b"date"),
+        Callback::new_sequence(mc, move |_, args| {
+            let format = match args.get(0).cloned().unwrap_or(Value::Nil) {
+                Value::Nil => None,
+                Value::String(s) => Some(s.as_bytes().to_vec()),
+                v @ Value::Integer(_) | v @ Value::Number(_) => {
+                    let mut buf = Vec::new();
+                    v.display(&mut buf)?;
+                    Some(buf)
+                }
+                _ => {
+                    return Err(runtime_error(
+                        b"bad argument #1 to 'date' (string expected)",
+                    ))
+                }
+            };
+            let format = format.unwrap_or_else(|| b"%c".to_vec());
+
+            let time = match args.get(1).cloned().unwrap_or(Value::Nil) {
+                Value::Nil => clock.time(),
+                v => v.to_integer().ok_or_else(|| {
+                    runtime_error(
+                        b"bad argument #2 to 'date' (number has no integer representation)",
+                    )
+                })?,
+            };
+
+            let (utc, format) = match format.split_first() {
+                Some((b'!', rest)) => (true, rest.to_vec()),
+                _ => (false, format),
+            };
+            let offset = if utc { 0 } else { clock.utc_offset(time) };
+            let date = time
+                .checked_add(offset)
+                .map(DateTime::from_unix)
+                .filter(|date| i32::try_from(date.year - 1900).is_ok())
+                .ok_or_else(|| {
+                    runtime_error(b"date result cannot be represented in this installation")
+                })?;
+
+            let result = if format.starts_with(b"*t") {
+                DateResult::Table(date)
+            } else {
+                DateResult::String(strftime(&format, &date, utc, offset)?)
+            };
+
+            Ok(sequence::from_fn_with(
+                (result, args),
+                |mc, (result, args)| {
+                    let result = match result {
+                        DateResult::Table(date) => Value::Table(date_table(mc, &date)?),
+                        DateResult::String(s) => Value::String(String::new(mc, &s)),
+                    };
+                    Ok(CallbackResult::Return(args.returning([result])))
+                },
+            ))
+        })
+        .named("date"),
+    )
+    .unwrap();
+
+    os.set(
+        mc,
+        String::new_static(b"difftime"),
+        Callback::new_immediate(mc, |_, args| {
+            let t2 = args
+                .get(0)
+                .cloned()
+                .unwrap_or(Value::Nil)
+                .to_number()
+                .ok_or_else(|| runtime_error(b"bad argument #1 to 'difftime' (number expected)"))?;
+            let t1 = args
+                .get(1)
+                .cloned()
+                .unwrap_or(Value::Nil)
+                .to_number()
+                .ok_or_else(|| runtime_error(b"bad argument #2 to 'difftime' (number expected)"))?;
+            Ok(CallbackResult::Return(
+                args.returning([Value::Number(t2 - t1)]),
+            ))
+        })
+        .named("difftime"),
+    )
+    .unwrap();
+
+    let clock = options.clock.clone();
+    os.set(
+        mc,
+        String::new_static(b"time"),
+        Callback::new_immediate(mc, move |_, args| {
+            match args.get(0).cloned().unwrap_or(Value::Nil) {
+                Value::Nil => Ok(CallbackResult::Return(
+                    args.returning([Value::Integer(clock.time())]),
+                )),
+                Value::Table(t) => {
+                    let year = date_field(t, b"year", None)?;
+                    let month = date_field(t, b"month", None)?;
+                    let day = date_field(t, b"day", None)?;
+                    let hour = date_field(t, b"hour", Some(12))?;
+                    let min = date_field(t, b"min", Some(0))?;
+                    let sec = date_field(t, b"sec", Some(0))?;
+
+                    // The fields are bounded by `date_field`, so only the final result can
+                    // overflow.
+                    let time = days_from_civil(year, month, 1)
+                        .checked_add(day - 1)
+                        .and_then(|days| days.checked_mul(86400))
+                        .and_then(|secs| secs.checked_add(hour * 3600 + min * 60 + sec))
+                        .and_then(|local| local.checked_sub(clock.utc_offset(local)))
+                        .ok_or_else(|| {
+                            runtime_error(b"time result cannot be represented in this installation")
+                        })?;
+                    Ok(CallbackResult::Return(
+                        args.returning([Value::Integer(time)]),
+                    ))
+                }
+                _ => Err(runtime_error(b"bad argument #1 to 'time' (table expected)")),
+            }
+        })
+        .named("time"),
+    )
+    .unwrap();
+
+    if options.process {
+        os.set(
+            mc,
+            String::new_static(b"exit"),
+            Callback::new_immediate(mc, |_, args| {
+                let code = match args.first().cloned().unwrap_or(Value::Nil) {
+                    Value::Nil | Value::Boolean(true) => 0,
+                    Value::Boolean(false) => 1,
+                    v => v.to_integer().ok_or_else(|| {
+                        runtime_error(b"bad argument #1 to 'exit' (number expected)")
+                    })? as i32,
+                };
+                // Exiting is left to the host, which may not want a script to end the process
+                Err(HostError::Exit(code).into())
+            })
+            .named("exit"),
+        )
+        .unwrap();
+
+        os.set(
+            mc,
+            String::new_static(b"getenv"),
+            Callback::new_sequence(mc, |_, args| {
+                let name = match args.get(0).cloned().unwrap_or(Value::Nil) {
+                    Value::String(s) => s.as_bytes().to_vec(),
+                    _ => {
+                        return Err(runtime_error(
+                            b"bad argument #1 to 'getenv' (string expected)",
+                        ))
+                    }
+                };
+                let var = std::str::from_utf8(&name)
+                    .ok()
+                    .and_then(std::env::var_os)
+                    .map(|v| v.to_string_lossy().into_owned());
+
+                Ok(sequence::from_fn_with((var, args), |mc, (var, args)| {
+                    let var = match var {
+                        Some(var) => Value::String(String::new(mc, var.as_bytes())),
+                        None => Value::Nil,
+                    };
+                    Ok(CallbackResult::Return(args.returning([var])))
+                }))
+            })
+            .named("getenv"),
+        )
+        .unwrap();
+    }
+
+    if options.filesystem {
+        os.set(
+            mc,
+            String::new_static(b"remove"),
+            Callback::new_sequence(mc, |_, args| {
+                let name = path_arg(&args, 0, b"bad argument #1 to 'remove' (string expected)")?;
+                let res = match fs::metadata(&name) {
+                    Ok(ref m) if m.is_dir() => fs::remove_dir(&name),
+                    _ => fs::remove_file(&name),
+                };
+                Ok(file_result(res, name, args))
+            })
+            .named("remove"),
+        )
+        .unwrap();
+
+        os.set(
+            mc,
+            String::new_static(b"rename"),
+            Callback::new_sequence(mc, |_, args| {
+                let from = path_arg(&args, 0, b"bad argument #1 to 'rename' (string expected)")?;
+                let to = path_arg(&args, 1, b"bad argument #2 to 'rename' (string expected)")?;
+                Ok(file_result(fs::rename(&from, &to), from, args))
+            })
+            .named("rename"),
+        )
+        .unwrap();
+
+        os.set(
+            mc,
+            String::new_static(b"tmpname"),
+            Callback::new_sequence(mc, |_, args| {
+                let mut rng = rand::thread_rng();
+                let mut path = None;
+                for _ in 0..16 {
+                    let candidate = std::env::temp_dir().join(format!(
+                        "lua_{:08x}{:08x}",
+                        rng.gen::<u32>(),
+                        rng.gen::<u32>()
+                    ));
+                    if fs::OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(&candidate)
+                        .is_ok()
+                    {
+                        path = Some(candidate.to_string_lossy().into_owned());
+                        break;
+                    }
+                }
+                let path =
+                    path.ok_or_else(|| runtime_error(b"unable to generate a unique filename"))?;
+
+                Ok(sequence::from_fn_with((path, args), |mc, (path, args)| {
+                    Ok(CallbackResult::Return(args.returning([Value::String(
+                        String::new(mc, path.as_bytes()),
+                    )])))
+                }))
+            })
+            .named("tmpname"),
+        )
+        .unwrap();
+    }
+
+    env.set(mc, String::new_static(b"os"), os).unwrap();
+}
+
+#[derive(Collect)]
+#[collect(require_static)]
+enum DateResult {
+    Table(DateTime),
+    String(Vec<u8>),
+}
+
+/// A broken down date and time, equivalent to C's `struct tm` but with a real year and a 1-based
+/// month.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DateTime {
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    min: i64,
+    sec: i64,
+    // Day of the week, 0 is Sunday
+    wday: i64,
+    // Day of the year, 1 is January 1st
+    yday: i64,
+}
+
+impl DateTime {
+    fn from_unix(time: i64) -> DateTime {
+        let days = time.div_euclid(86400);
+        let secs = time.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: secs / 3600,
+            min: secs % 3600 / 60,
+            sec: secs % 60,
+            wday: (days + 4).rem_euclid(7),
+            yday: days - days_from_civil(year, 1, 1) + 1,
+        }
+    }
+}
+
+// Days since the unix epoch of the given proleptic Gregorian date.  Months outside of 1-12 are
+// normalized into the year.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = year + (month - 1).div_euclid(12);
+    let month = (month - 1).rem_euclid(12) + 1;
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// Inverse of `days_from_civil`, returns (year, month, day).
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn date_table<'gc>(
+    mc: MutationContext<'gc, '_>,
+    date: &DateTime,
+) -> Result<Table<'gc>, Error<'gc>> {
+    let t = Table::new(mc);
+    t.set(mc, String::new_static(b"year"), date.year)?;
+    t.set(mc, String::new_static(b"month"), date.month)?;
+    t.set(mc, String::new_static(b"day"), date.day)?;
+    t.set(mc, String::new_static(b"hour"), date.hour)?;
+    t.set(mc, String::new_static(b"min"), date.min)?;
+    t.set(mc, String::new_static(b"sec"), date.sec)?;
+    t.set(mc, String::new_static(b"wday"), date.wday + 1)?;
+    t.set(mc, String::new_static(b"yday"), date.yday)?;
+    t.set(mc, String::new_static(b"isdst"), false)?;
+    Ok(t)
+}
+
+fn date_field<'gc>(
+    table: Table<'gc>,
+    name: &'static [u8],
+    default: Option<i64>,
+) -> Result<i64, Error<'gc>> {
+    match table.get(String::new_static(name)) {
+        Value::Nil => default.ok_or_else(|| {
+            RuntimeError(Value::String(String::new_static(match name {
+                b"year" => b"field 'year' missing in date table",
+                b"month" => b"field 'month' missing in date table",
+                _ => b"field 'day' missing in date table",
+            })))
+            .into()
+        }),
+        v => {
+            let v = v
+                .to_integer()
+                .ok_or_else(|| runtime_error(b"date table field is not an integer"))?;
+            // As in PUC-Rio Lua, fields must fit in a C int once adjusted the way `struct tm`
+            // stores them.
+            let delta = match name {
+                b"year" => 1900,
+                b"month" => 1,
+                _ => 0,
+            };
+            if v.checked_sub(delta)
+                .and_then(|v| i32::try_from(v).ok())
+                .is_none()
+            {
+                return Err(runtime_error(match name {
+                    b"year" => b"field 'year' is out-of-bound",
+                    b"month" => b"field 'month' is out-of-bound",
+                    b"day" => b"field 'day' is out-of-bound",
+                    b"hour" => b"field 'hour' is out-of-bound",
+                    b"min" => b"field 'min' is out-of-bound",
+                    _ => b"field 'sec' is out-of-bound",
+                }));
+            }
+            Ok(v)
+        }
+    }
+}
+
+const DAY_NAMES: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+const MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+// Formats the given date like C's `strftime` in the "C" locale.
+fn strftime<'gc>(
+    format: &[u8],
+    date: &DateTime,
+    utc: bool,
+    offset: i64,
+) -> Result<Vec<u8>, Error<'gc>> {
+    let mut out = Vec::new();
+    let mut iter = format.iter();
+    while let Some(&c) = iter.next() {
+        if c != b'%' {
+            out.push(c);
+            continue;
+        }
+
+        let spec = match iter.next() {
+            Some(&spec) => spec,
+            None => return Err(runtime_error(b"invalid conversion specifier '%'")),
+        };
+        write_conversion(&mut out, spec, date, utc, offset)?;
+    }
+    Ok(out)
+}
+
+fn write_conversion<'gc>(
+    out: &mut Vec<u8>,
+    spec: u8,
+    date: &DateTime,
+    utc: bool,
+    offset: i64,
+) -> Result<(), Error<'gc>> {
+    let day_name = DAY_NAMES[date.wday as usize];
+    let month_name = MONTH_NAMES[date.month as usize - 1];
+    let hour12 = if date.hour % 12 == 0 {
+        12
+    } else {
+        date.hour % 12
+    };
+
+    match spec {
+        b'a' => write!(out, "{}", &day_name[..3]),
+        b'A' => write!(out, "{}", day_name),
+        b'b' | b'h' => write!(out, "{}", &month_name[..3]),
+        b'B' => write!(out, "{}", month_name),
+        b'c' => write!(
+            out,
+            "{} {} {:2} {:02}:{:02}:{:02} {}",
+            &day_name[..3],
+            &month_name[..3],
+            date.day,
+            date.hour,
+            date.min,
+            date.sec,
+            date.year
+        ),
+        b'C' => write!(out, "{:02}", date.year.div_euclid(100)),
+        b'd' => write!(out, "{:02}", date.day),
+        b'D' => write!(
+            out,
+            "{:02}/{:02}/{:02}",
+            date.month,
+            date.day,
+            date.year.rem_euclid(100)
+        ),
+        b'e' => write!(out, "{:2}", date.day),
+        b'F' => write!(out, "{}-{:02}-{:02}", date.year, date.month, date.day),
+        b'H' => write!(out, "{:02}", date.hour),
+        b'I' => write!(out, "{:02}", hour12),
+        b'j' => write!(out, "{:03}", date.yday),
+        b'm' => write!(out, "{:02}", date.month),
+        b'M' => write!(out, "{:02}", date.min),
+        b'n' => writeln!(out),
+        b'p' => write!(out, "{}", if date.hour < 12 { "AM" } else { "PM" }),
+        b'r' => write!(
+            out,
+            "{:02}:{:02}:{:02} {}",
+            hour12,
+            date.min,
+            date.sec,
+            if date.hour < 12 { "AM" } else { "PM" }
+        ),
+        b'R' => write!(out, "{:02}:{:02}", date.hour, date.min),
+        b'S' => write!(out, "{:02}", date.sec),
+        b't' => write!(out, "\t"),
+        b'T' | b'X' => write!(out, "{:02}:{:02}:{:02}", date.hour, date.min, date.sec),
+        b'u' => write!(out, "{}", if date.wday == 0 { 7 } else { date.wday }),
+        b'w' => write!(out, "{}", date.wday),
+        b'x' => write!(
+            out,
+            "{:02}/{:02}/{:02}",
+            date.month,
+            date.day,
+            date.year.rem_euclid(100)
+        ),
+        b'y' => write!(out, "{:02}", date.year.rem_euclid(100)),
+        b'Y' => write!(out, "{}", date.year),
+        b'z' => write!(
+            out,
+            "{}{:02}{:02}",
+            if offset < 0 { '-' } else { '+' },
+            offset.abs() / 3600,
+            offset.abs() % 3600 / 60
+        ),
+        b'Z' => write!(out, "{}", if utc { "GMT" } else { "" }),
+        b'%' => write!(out, "%"),
+        _ => {
+            return Err(RuntimeError(Value::String(String::new_static(
+                b"invalid conversion specifier",
+            )))
+            .into());
+        }
+    }?;
+    Ok(())
+}
+
+fn runtime_error<'gc>(msg: &'static [u8]) -> Error<'gc> {
+    RuntimeError(Value::String(String::new_static(msg))).into()
+}
+
+fn path_arg<'gc>(
+    args: &[Value<'gc>],
+    i: usize,
+    msg: &'static [u8],
+) -> Result<std::string::String, Error<'gc>> {
+    match args.get(i).cloned().unwrap_or(Value::Nil) {
+        Value::String(s) => Ok(std::string::String::from_utf8_lossy(s.as_bytes()).into_owned()),
+        _ => Err(runtime_error(msg)),
+    }
+}
+
+// Returns `true` on success, or `nil, "filename: error message", errno` on failure, like PUC-Rio
+// Lua's `luaL_fileresult`.
+fn file_result<'gc>(
+    res: Result<(), io::Error>,
+    name: std::string::String,
+    buffer: ValueBuffer<'gc>,
+) -> impl Sequence<'gc, Output = Result<CallbackResult<'gc>, Error<'gc>>> {
+    let res = res.map_err(|e| (format!("{}: {}", name, e), e.raw_os_error().unwrap_or(0)));
+    sequence::from_fn_with((res, buffer), |mc, (res, buffer)| {
+        Ok(CallbackResult::Return(match res {
+            Ok(()) => buffer.returning([Value::Boolean(true)]),
+            Err((msg, errno)) => buffer.returning([
+                Value::Nil,
+                Value::String(String::new(mc, msg.as_bytes())),
+                Value::Integer(errno as i64),
+            ]),
+        }))
+    })
+}
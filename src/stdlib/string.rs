@@ -1,7 +1,9 @@
 use gc_arena::MutationContext;
 use gc_sequence as sequence;
 
-use crate::{Callback, CallbackResult, Root, RuntimeError, String, Table, Value};
+use crate::{
+    write_chunk, Callback, CallbackResult, Function, Root, RuntimeError, String, Table, Value,
+};
 
 pub fn load_string<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc>) {
     let string = Table::new(mc);
@@ -10,17 +12,48 @@ pub fn load_string<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'
         .set(
             mc,
             String::new_static(b"len"),
-            Callback::new_sequence(mc, |args| {
+            Callback::new_sequence(mc, |_, args| {
                 Ok(sequence::from_fn_with(args, |mc, args| {
                     match args.get(0).cloned().unwrap_or(Value::Nil).to_string(mc) {
-                        Some(s) => Ok(CallbackResult::Return(vec![Value::Integer(s.len())])),
+                        Some(s) => Ok(CallbackResult::Return(
+                            args.returning([Value::Integer(s.len())]),
+                        )),
                         None => Err(RuntimeError(Value::String(String::new_static(
                             b"Bad argument to len",
                         )))
                         .into()),
                     }
                 }))
-            }),
+            })
+            .named("len"),
+        )
+        .unwrap();
+
+    string
+        .set(
+            mc,
+            String::new_static(b"dump"),
+            Callback::new_sequence(mc, |_, args| {
+                Ok(sequence::from_fn_with(args, |mc, args| {
+                    let closure = match args.first().cloned().unwrap_or(Value::Nil) {
+                        Value::Function(Function::Closure(closure)) => closure,
+                        _ => {
+                            return Err(RuntimeError(Value::String(String::new_static(
+                                b"unable to dump given function",
+                            )))
+                            .into());
+                        }
+                    };
+                    let strip = args.get(1).cloned().unwrap_or(Value::Nil).to_bool();
+
+                    let mut chunk = Vec::new();
+                    write_chunk(&mut chunk, &closure.0.proto, strip)?;
+                    Ok(CallbackResult::Return(
+                        args.returning([Value::String(String::new(mc, &chunk))]),
+                    ))
+                }))
+            })
+            .named("dump"),
         )
         .unwrap();
 
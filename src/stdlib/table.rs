@@ -0,0 +1,292 @@
+use gc_arena::{Collect, MutationContext};
+use gc_sequence::{self as sequence, SequenceExt};
+
+use crate::{
+    BinaryOperatorError, Callback, CallbackResult, CallbackReturn, Continuation, Error, Function,
+    Root, RuntimeError, String, Table, TypeError, Value, ValueBuffer,
+};
+
+pub fn load_table<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc>) {
+    let table = Table::new(mc);
+
+    table
+        .set(
+            mc,
+            String::new_static(b"sort"),
+            Callback::new(mc, |_, args| {
+                let table = match args.get(0).cloned().unwrap_or(Value::Nil) {
+                    Value::Table(table) => table,
+                    value => {
+                        return CallbackReturn::Immediate(Err(TypeError {
+                            expected: "table",
+                            found: value.type_name(),
+                        }
+                        .into()));
+                    }
+                };
+
+                let comparator = match args.get(1).cloned().unwrap_or(Value::Nil) {
+                    Value::Nil => None,
+                    Value::Function(function) => Some(function),
+                    value => {
+                        return CallbackReturn::Immediate(Err(TypeError {
+                            expected: "function",
+                            found: value.type_name(),
+                        }
+                        .into()));
+                    }
+                };
+
+                let len = table.length();
+                if len >= i32::MAX as i64 {
+                    return CallbackReturn::Immediate(Err(RuntimeError(Value::String(
+                        String::new_static(b"array too big"),
+                    ))
+                    .into()));
+                }
+
+                let values = (1..=len).map(|i| table.get(i)).collect();
+                sort_run(SortState::new(table, comparator, values), args, None)
+            })
+            .named("sort"),
+        )
+        .unwrap();
+
+    env.set(mc, String::new_static(b"table"), table).unwrap();
+}
+
+// The sort algorithm is a quicksort modeled after PUC-Rio Lua's `auxsort`, but because comparators
+// may be Lua functions (which cannot be called synchronously from a callback), it is written as an
+// explicit state machine.  Every time a comparison is required, the state machine stops and asks
+// for the result of `a < b`, and is resumed with the answer once the comparison has been performed.
+#[derive(Collect)]
+#[collect(no_drop)]
+struct SortState<'gc> {
+    table: Table<'gc>,
+    comparator: Option<Function<'gc>>,
+    values: Vec<Value<'gc>>,
+    // Pending (lo, up) ranges that still need to be sorted, inclusive on both ends
+    ranges: Vec<(usize, usize)>,
+    lo: usize,
+    up: usize,
+    p: usize,
+    i: usize,
+    j: usize,
+    pivot: Value<'gc>,
+    phase: SortPhase,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Collect)]
+#[collect(require_static)]
+enum SortPhase {
+    // Waiting to pop a new range to sort
+    Start,
+    // Waiting on `a[up] < a[lo]`
+    CompareUpLo,
+    // Waiting on `a[p] < a[lo]`
+    CompareMidLo,
+    // Waiting on `a[up] < a[p]`
+    CompareUpMid,
+    // Waiting on `a[i] < pivot`
+    ScanUp,
+    // Waiting on `pivot < a[j]`
+    ScanDown,
+}
+
+impl<'gc> SortState<'gc> {
+    fn new(
+        table: Table<'gc>,
+        comparator: Option<Function<'gc>>,
+        values: Vec<Value<'gc>>,
+    ) -> SortState<'gc> {
+        let mut ranges = Vec::new();
+        if values.len() > 1 {
+            ranges.push((0, values.len() - 1));
+        }
+
+        SortState {
+            table,
+            comparator,
+            values,
+            ranges,
+            lo: 0,
+            up: 0,
+            p: 0,
+            i: 0,
+            j: 0,
+            pivot: Value::Nil,
+            phase: SortPhase::Start,
+        }
+    }
+
+    // Advance the state machine given the result of the last requested comparison.  Returns the
+    // next pair of values to compare with `<`, or None if sorting is finished.
+    fn advance(
+        &mut self,
+        mut last: Option<bool>,
+    ) -> Result<Option<(Value<'gc>, Value<'gc>)>, Error<'gc>> {
+        loop {
+            match self.phase {
+                SortPhase::Start => match self.ranges.pop() {
+                    Some((lo, up)) => {
+                        self.lo = lo;
+                        self.up = up;
+                        self.phase = SortPhase::CompareUpLo;
+                        return Ok(Some((self.values[up], self.values[lo])));
+                    }
+                    None => return Ok(None),
+                },
+
+                SortPhase::CompareUpLo => {
+                    if take_result(&mut last) {
+                        self.values.swap(self.lo, self.up);
+                    }
+                    if self.up - self.lo == 1 {
+                        self.phase = SortPhase::Start;
+                    } else {
+                        self.p = self.lo + (self.up - self.lo) / 2;
+                        self.phase = SortPhase::CompareMidLo;
+                        return Ok(Some((self.values[self.p], self.values[self.lo])));
+                    }
+                }
+
+                SortPhase::CompareMidLo => {
+                    if take_result(&mut last) {
+                        self.values.swap(self.p, self.lo);
+                        if let Some(next) = self.start_partition() {
+                            return Ok(Some(next));
+                        }
+                    } else {
+                        self.phase = SortPhase::CompareUpMid;
+                        return Ok(Some((self.values[self.up], self.values[self.p])));
+                    }
+                }
+
+                SortPhase::CompareUpMid => {
+                    if take_result(&mut last) {
+                        self.values.swap(self.p, self.up);
+                    }
+                    if let Some(next) = self.start_partition() {
+                        return Ok(Some(next));
+                    }
+                }
+
+                SortPhase::ScanUp => {
+                    if take_result(&mut last) {
+                        if self.i == self.up - 1 {
+                            return Err(invalid_order_function());
+                        }
+                        self.i += 1;
+                        return Ok(Some((self.values[self.i], self.pivot)));
+                    } else {
+                        self.j -= 1;
+                        self.phase = SortPhase::ScanDown;
+                        return Ok(Some((self.pivot, self.values[self.j])));
+                    }
+                }
+
+                SortPhase::ScanDown => {
+                    if take_result(&mut last) {
+                        if self.j < self.i {
+                            return Err(invalid_order_function());
+                        }
+                        self.j -= 1;
+                        return Ok(Some((self.pivot, self.values[self.j])));
+                    } else if self.j < self.i {
+                        // The partition is finished, the pivot goes in its final position and the
+                        // two remaining sub-ranges are queued.
+                        self.values.swap(self.up - 1, self.i);
+                        if self.i - self.lo > 1 {
+                            self.ranges.push((self.lo, self.i - 1));
+                        }
+                        if self.up - self.i > 1 {
+                            self.ranges.push((self.i + 1, self.up));
+                        }
+                        self.phase = SortPhase::Start;
+                    } else {
+                        self.values.swap(self.i, self.j);
+                        self.i += 1;
+                        self.phase = SortPhase::ScanUp;
+                        return Ok(Some((self.values[self.i], self.pivot)));
+                    }
+                }
+            }
+        }
+    }
+
+    // Called once `a[lo] <= a[p] <= a[up]`, moves the pivot to `up - 1` and begins partitioning the
+    // range between `lo` and `up - 1`.  Returns the first comparison of the partition, if one is
+    // needed.
+    fn start_partition(&mut self) -> Option<(Value<'gc>, Value<'gc>)> {
+        if self.up - self.lo == 2 {
+            self.phase = SortPhase::Start;
+            None
+        } else {
+            self.pivot = self.values[self.p];
+            self.values.swap(self.p, self.up - 1);
+            self.i = self.lo + 1;
+            self.j = self.up - 1;
+            self.phase = SortPhase::ScanUp;
+            Some((self.values[self.i], self.pivot))
+        }
+    }
+}
+
+fn take_result(last: &mut Option<bool>) -> bool {
+    last.take()
+        .expect("sort state machine advanced without a comparison result")
+}
+
+fn invalid_order_function<'gc>() -> Error<'gc> {
+    RuntimeError(Value::String(String::new_static(
+        b"invalid order function for sorting",
+    )))
+    .into()
+}
+
+// Runs the sort state machine, using the given buffer to call the comparator and finally to return.
+fn sort_run<'gc>(
+    mut state: SortState<'gc>,
+    buffer: ValueBuffer<'gc>,
+    mut last: Option<bool>,
+) -> CallbackReturn<'gc> {
+    loop {
+        match state.advance(last) {
+            Ok(Some((a, b))) => match state.comparator {
+                None => match a.less_than(b) {
+                    Some(res) => last = Some(res),
+                    None => {
+                        return CallbackReturn::Immediate(
+                            Err(BinaryOperatorError::LessThan.into()),
+                        );
+                    }
+                },
+                Some(function) => {
+                    return CallbackReturn::Immediate(Ok(CallbackResult::TailCall {
+                        function,
+                        args: buffer.returning([a, b]),
+                        continuation: Continuation::new_with(state, |state, res| match res {
+                            Ok(res) => {
+                                let less = res.get(0).cloned().unwrap_or(Value::Nil).to_bool();
+                                sort_run(state, res, Some(less))
+                            }
+                            Err((err, _)) => CallbackReturn::Immediate(Err(err)),
+                        }),
+                    }));
+                }
+            },
+            Ok(None) => {
+                return CallbackReturn::Sequence(
+                    sequence::from_fn_with((state, buffer), |mc, (state, buffer)| {
+                        for (i, v) in state.values.into_iter().enumerate() {
+                            state.table.set(mc, i as i64 + 1, v)?;
+                        }
+                        Ok(CallbackResult::Return(buffer.returning([])))
+                    })
+                    .boxed(),
+                );
+            }
+            Err(err) => return CallbackReturn::Immediate(Err(err)),
+        }
+    }
+}
@@ -0,0 +1,362 @@
+use gc_arena::MutationContext;
+use gc_sequence as sequence;
+
+use crate::{Callback, CallbackResult, Error, Root, RuntimeError, String, Table, Value};
+
+const MAX_UNICODE: u32 = 0x10FFFF;
+
+pub fn load_utf8<'gc>(mc: MutationContext<'gc, '_>, _: Root<'gc>, env: Table<'gc>) {
+    let utf8 = Table::new(mc);
+
+    utf8.set(
+        mc,
+        String::new_static(b"charpattern"),
+        String::new_static(b"[\x00-\x7F\xC2-\xF4][\x80-\xBF]*"),
+    )
+    .unwrap();
+
+    utf8.set(
+        mc,
+        String::new_static(b"char"),
+        Callback::new_sequence(mc, |_, args| {
+            Ok(sequence::from_fn_with(args, |mc, args| {
+                let mut bytes = Vec::new();
+                for (i, arg) in args.iter().enumerate() {
+                    match arg.to_integer() {
+                        Some(c) if c >= 0 && c <= MAX_UNICODE as i64 => {
+                            encode_utf8(c as u32, &mut bytes);
+                        }
+                        Some(_) => return Err(arg_error(mc, i + 1, "char", "value out of range")),
+                        None => return Err(arg_error(mc, i + 1, "char", "number expected")),
+                    }
+                }
+                Ok(CallbackResult::Return(
+                    args.returning([Value::String(String::new(mc, &bytes))]),
+                ))
+            }))
+        })
+        .named("char"),
+    )
+    .unwrap();
+
+    utf8.set(
+        mc,
+        String::new_static(b"codepoint"),
+        Callback::new_sequence(mc, |_, args| {
+            Ok(sequence::from_fn_with(args, |mc, args| {
+                let s = string_arg(
+                    mc,
+                    &args,
+                    b"bad argument #1 to 'codepoint' (string expected)",
+                )?;
+                let len = s.len();
+                let posi = relative_position(integer_arg(mc, &args, 1, 1, "codepoint")?, len);
+                let pose = relative_position(integer_arg(mc, &args, 2, posi, "codepoint")?, len);
+
+                if posi < 1 {
+                    return Err(runtime_error(
+                        b"bad argument #2 to 'codepoint' (out of range)",
+                    ));
+                }
+                if pose > len {
+                    return Err(runtime_error(
+                        b"bad argument #3 to 'codepoint' (out of range)",
+                    ));
+                }
+
+                let mut ret = Vec::new();
+                let mut pos = posi as usize - 1;
+                while pos < pose as usize {
+                    match decode_utf8(&s[pos..]) {
+                        Some((c, size)) => {
+                            ret.push(Value::Integer(c as i64));
+                            pos += size;
+                        }
+                        None => return Err(runtime_error(b"invalid UTF-8 code")),
+                    }
+                }
+                Ok(CallbackResult::Return(args.returning(ret)))
+            }))
+        })
+        .named("codepoint"),
+    )
+    .unwrap();
+
+    utf8.set(
+        mc,
+        String::new_static(b"len"),
+        Callback::new_sequence(mc, |_, args| {
+            Ok(sequence::from_fn_with(args, |mc, args| {
+                let s = string_arg(mc, &args, b"bad argument #1 to 'len' (string expected)")?;
+                let len = s.len();
+                let posi = relative_position(integer_arg(mc, &args, 1, 1, "len")?, len);
+                let posj = relative_position(integer_arg(mc, &args, 2, -1, "len")?, len);
+
+                if posi < 1 || posi - 1 > len {
+                    return Err(runtime_error(
+                        b"bad argument #2 to 'len' (initial position out of string)",
+                    ));
+                }
+                if posj > len {
+                    return Err(runtime_error(
+                        b"bad argument #3 to 'len' (final position out of string)",
+                    ));
+                }
+
+                let mut count = 0;
+                let mut pos = posi - 1;
+                while pos < posj {
+                    match decode_utf8(&s[pos as usize..]) {
+                        Some((_, size)) => {
+                            pos += size as i64;
+                            count += 1;
+                        }
+                        None => {
+                            return Ok(CallbackResult::Return(
+                                args.returning([Value::Nil, Value::Integer(pos + 1)]),
+                            ));
+                        }
+                    }
+                }
+                Ok(CallbackResult::Return(
+                    args.returning([Value::Integer(count)]),
+                ))
+            }))
+        })
+        .named("len"),
+    )
+    .unwrap();
+
+    utf8.set(
+        mc,
+        String::new_static(b"offset"),
+        Callback::new_sequence(mc, |_, args| {
+            Ok(sequence::from_fn_with(args, |mc, args| {
+                let s = string_arg(mc, &args, b"bad argument #1 to 'offset' (string expected)")?;
+                let len = s.len();
+                let mut n = match args.get(1).cloned().unwrap_or(Value::Nil).to_integer() {
+                    Some(n) => n,
+                    None => {
+                        return Err(runtime_error(
+                            b"bad argument #2 to 'offset' (number expected)",
+                        ));
+                    }
+                };
+                let default_i = if n >= 0 { 1 } else { len + 1 };
+                let mut posi =
+                    relative_position(integer_arg(mc, &args, 2, default_i, "offset")?, len);
+                if posi < 1 || posi - 1 > len {
+                    return Err(runtime_error(
+                        b"bad argument #3 to 'offset' (position out of range)",
+                    ));
+                }
+                posi -= 1;
+
+                let continuation_at = |pos: i64| pos < len && is_continuation(s[pos as usize]);
+
+                if n == 0 {
+                    while posi > 0 && continuation_at(posi) {
+                        posi -= 1;
+                    }
+                } else {
+                    if continuation_at(posi) {
+                        return Err(runtime_error(b"initial position is a continuation byte"));
+                    }
+                    if n < 0 {
+                        while n < 0 && posi > 0 {
+                            posi -= 1;
+                            while posi > 0 && continuation_at(posi) {
+                                posi -= 1;
+                            }
+                            n += 1;
+                        }
+                    } else {
+                        n -= 1;
+                        while n > 0 && posi < len {
+                            posi += 1;
+                            while continuation_at(posi) {
+                                posi += 1;
+                            }
+                            n -= 1;
+                        }
+                    }
+                }
+
+                if n == 0 {
+                    Ok(CallbackResult::Return(
+                        args.returning([Value::Integer(posi + 1)]),
+                    ))
+                } else {
+                    Ok(CallbackResult::Return(args.returning([Value::Nil])))
+                }
+            }))
+        })
+        .named("offset"),
+    )
+    .unwrap();
+
+    let codes_iter = Callback::new_sequence(mc, |_, args| {
+        Ok(sequence::from_fn_with(args, |mc, args| {
+            let s = string_arg(mc, &args, b"bad argument #1 to 'codes' (string expected)")?;
+            let len = s.len();
+            let mut n = integer_arg(mc, &args, 1, 0, "codes")? - 1;
+
+            if n < 0 {
+                n = 0;
+            } else if n < len {
+                n += 1;
+                while n < len && is_continuation(s[n as usize]) {
+                    n += 1;
+                }
+            }
+
+            if n >= len {
+                Ok(CallbackResult::Return(args.returning([])))
+            } else {
+                match decode_utf8(&s[n as usize..]) {
+                    Some((c, size))
+                        if n + size as i64 >= len || !is_continuation(s[n as usize + size]) =>
+                    {
+                        Ok(CallbackResult::Return(args.returning([
+                            Value::Integer(n + 1),
+                            Value::Integer(c as i64),
+                        ])))
+                    }
+                    _ => Err(runtime_error(b"invalid UTF-8 code")),
+                }
+            }
+        }))
+    });
+
+    utf8.set(
+        mc,
+        String::new_static(b"codes"),
+        Callback::new_immediate_with(mc, codes_iter, |codes_iter, _, args| {
+            match args.get(0).cloned().unwrap_or(Value::Nil) {
+                s @ Value::String(_) | s @ Value::Integer(_) | s @ Value::Number(_) => {
+                    Ok(CallbackResult::Return(args.returning([
+                        Value::from(*codes_iter),
+                        s,
+                        Value::Integer(0),
+                    ])))
+                }
+                _ => Err(runtime_error(
+                    b"bad argument #1 to 'codes' (string expected)",
+                )),
+            }
+        })
+        .named("codes"),
+    )
+    .unwrap();
+
+    env.set(mc, String::new_static(b"utf8"), utf8).unwrap();
+}
+
+fn runtime_error<'gc>(msg: &'static [u8]) -> Error<'gc> {
+    RuntimeError(Value::String(String::new_static(msg))).into()
+}
+
+fn string_arg<'gc>(
+    mc: MutationContext<'gc, '_>,
+    args: &[Value<'gc>],
+    msg: &'static [u8],
+) -> Result<String<'gc>, Error<'gc>> {
+    args.get(0)
+        .cloned()
+        .unwrap_or(Value::Nil)
+        .to_string(mc)
+        .ok_or_else(|| runtime_error(msg))
+}
+
+fn arg_error<'gc>(mc: MutationContext<'gc, '_>, arg: usize, func: &str, msg: &str) -> Error<'gc> {
+    RuntimeError(Value::String(String::new(
+        mc,
+        format!("bad argument #{} to '{}' ({})", arg, func, msg).as_bytes(),
+    )))
+    .into()
+}
+
+// Returns the integer argument at the given index, or the default if the argument is absent or nil.
+fn integer_arg<'gc>(
+    mc: MutationContext<'gc, '_>,
+    args: &[Value<'gc>],
+    i: usize,
+    default: i64,
+    func: &str,
+) -> Result<i64, Error<'gc>> {
+    match args.get(i).cloned().unwrap_or(Value::Nil) {
+        Value::Nil => Ok(default),
+        v => v
+            .to_integer()
+            .ok_or_else(|| arg_error(mc, i + 1, func, "number expected")),
+    }
+}
+
+// Translates a relative string position (negative means back from the end) into an absolute one,
+// clamping to 0 when the position is before the start of the string.
+fn relative_position(pos: i64, len: i64) -> i64 {
+    if pos >= 0 {
+        pos
+    } else if pos.wrapping_neg() > len {
+        0
+    } else {
+        len + pos + 1
+    }
+}
+
+fn is_continuation(b: u8) -> bool {
+    b & 0xC0 == 0x80
+}
+
+fn encode_utf8(c: u32, bytes: &mut Vec<u8>) {
+    if c < 0x80 {
+        bytes.push(c as u8);
+    } else if c < 0x800 {
+        bytes.push(0xC0 | (c >> 6) as u8);
+        bytes.push(0x80 | (c & 0x3F) as u8);
+    } else if c < 0x10000 {
+        bytes.push(0xE0 | (c >> 12) as u8);
+        bytes.push(0x80 | ((c >> 6) & 0x3F) as u8);
+        bytes.push(0x80 | (c & 0x3F) as u8);
+    } else {
+        bytes.push(0xF0 | (c >> 18) as u8);
+        bytes.push(0x80 | ((c >> 12) & 0x3F) as u8);
+        bytes.push(0x80 | ((c >> 6) & 0x3F) as u8);
+        bytes.push(0x80 | (c & 0x3F) as u8);
+    }
+}
+
+// Decodes a single UTF-8 sequence from the start of the given bytes, returning the code point and
+// the length of the sequence.  Mirrors PUC-Rio Lua 5.3's `utf8_decode`, which rejects overlong
+// encodings and code points past U+10FFFF, but allows surrogates.
+fn decode_utf8(bytes: &[u8]) -> Option<(u32, usize)> {
+    const LIMITS: [u32; 4] = [!0, 0x80, 0x800, 0x10000];
+
+    let first = *bytes.first()? as u32;
+    if first < 0x80 {
+        return Some((first, 1));
+    }
+
+    let mut c = first;
+    let mut res = 0;
+    let mut count = 0;
+    while c & 0x40 != 0 {
+        count += 1;
+        if count > 3 {
+            return None;
+        }
+        let cc = *bytes.get(count)? as u32;
+        if cc & 0xC0 != 0x80 {
+            return None;
+        }
+        res = (res << 6) | (cc & 0x3F);
+        c <<= 1;
+    }
+    res |= (c & 0x7F) << (count * 5);
+
+    if res > MAX_UNICODE || res < LIMITS[count] {
+        None
+    } else {
+        Some((res, count + 1))
+    }
+}
@@ -10,7 +10,7 @@ use rustc_hash::FxHashSet;
 
 use gc_arena::{Collect, Gc, GcCell, MutationContext};
 
-use crate::Value;
+use crate::{thread::ExternalMemory, Value};
 
 #[derive(Debug, Clone, Copy, Collect)]
 #[collect(require_static)]
@@ -35,10 +35,20 @@ impl fmt::Display for StringError {
 pub enum String<'gc> {
     Short8(u8, Gc<'gc, [u8; 8]>),
     Short32(u8, Gc<'gc, [u8; 32]>),
-    Long(Gc<'gc, Box<[u8]>>),
+    Long(Gc<'gc, LongString>),
     Static(&'static [u8]),
 }
 
+/// The contents of a long string, which are allocated outside of the arena.
+#[derive(Collect)]
+#[collect(require_static)]
+pub struct LongString {
+    bytes: Box<[u8]>,
+    // Charges the bytes to the memory limit of the `Lua` the string was created in until the
+    // string is collected.
+    _memory: ExternalMemory,
+}
+
 impl<'gc> Debug for String<'gc> {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -70,7 +80,13 @@ impl<'gc> String<'gc> {
             b[..len].copy_from_slice(s);
             String::Short32(len as u8, Gc::allocate(mc, b))
         } else {
-            String::Long(Gc::allocate(mc, s.to_vec().into_boxed_slice()))
+            String::Long(Gc::allocate(
+                mc,
+                LongString {
+                    bytes: s.to_vec().into_boxed_slice(),
+                    _memory: ExternalMemory::new(len),
+                },
+            ))
         }
     }
 
@@ -99,6 +115,11 @@ impl<'gc> String<'gc> {
                 Value::Thread(_) => {
                     return Err(StringError::Concat { bad_type: "thread" });
                 }
+                Value::UserData(_) => {
+                    return Err(StringError::Concat {
+                        bad_type: "userdata",
+                    });
+                }
             }
         }
         Ok(String::new(mc, &bytes))
@@ -108,7 +129,7 @@ impl<'gc> String<'gc> {
         match self {
             String::Short8(l, b) => &b[0..*l as usize],
             String::Short32(l, b) => &b[0..*l as usize],
-            String::Long(b) => b,
+            String::Long(b) => &b.bytes,
             String::Static(b) => b,
         }
     }
@@ -124,7 +145,7 @@ impl<'gc> String<'gc> {
 
         match self {
             String::Short8(l, _) | String::Short32(l, _) => *l as i64,
-            String::Long(b) => as_i64(b.len()),
+            String::Long(b) => as_i64(b.bytes.len()),
             String::Static(b) => as_i64(b.len()),
         }
     }
@@ -7,7 +7,7 @@ use rustc_hash::FxHashMap;
 
 use gc_arena::{Collect, GcCell, MutationContext};
 
-use crate::Value;
+use crate::{thread::ExternalMemory, HostError, Value};
 
 #[derive(Debug, Copy, Clone, Collect)]
 #[collect(no_drop)]
@@ -31,6 +31,38 @@ impl fmt::Display for InvalidTableKey {
     }
 }
 
+/// An error setting a table entry, either because of an invalid key or because growing the table
+/// would exceed the memory limit of the `Lua` it belongs to.
+#[derive(Debug, Clone, Copy, Collect)]
+#[collect(require_static)]
+pub enum TableError {
+    InvalidKey(InvalidTableKey),
+    HostError(HostError),
+}
+
+impl StdError for TableError {}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TableError::InvalidKey(error) => write!(fmt, "{}", error),
+            TableError::HostError(error) => write!(fmt, "{}", error),
+        }
+    }
+}
+
+impl From<InvalidTableKey> for TableError {
+    fn from(error: InvalidTableKey) -> TableError {
+        TableError::InvalidKey(error)
+    }
+}
+
+impl From<HostError> for TableError {
+    fn from(error: HostError) -> TableError {
+        TableError::HostError(error)
+    }
+}
+
 impl<'gc> PartialEq for Table<'gc> {
     fn eq(&self, other: &Table<'gc>) -> bool {
         GcCell::ptr_eq(self.0, other.0)
@@ -59,7 +91,7 @@ impl<'gc> Table<'gc> {
         mc: MutationContext<'gc, '_>,
         key: K,
         value: V,
-    ) -> Result<Value<'gc>, InvalidTableKey> {
+    ) -> Result<Value<'gc>, TableError> {
         self.0.write(mc).set(key.into(), value.into())
     }
 
@@ -73,6 +105,8 @@ impl<'gc> Table<'gc> {
 pub struct TableState<'gc> {
     array: Vec<Value<'gc>>,
     map: FxHashMap<TableKey<'gc>, Value<'gc>>,
+    // The storage behind the array and map parts, which is not allocated by the arena.
+    memory: ExternalMemory,
 }
 
 impl<'gc> TableState<'gc> {
@@ -90,11 +124,7 @@ impl<'gc> TableState<'gc> {
         }
     }
 
-    pub fn set(
-        &mut self,
-        key: Value<'gc>,
-        value: Value<'gc>,
-    ) -> Result<Value<'gc>, InvalidTableKey> {
+    pub fn set(&mut self, key: Value<'gc>, value: Value<'gc>) -> Result<Value<'gc>, TableError> {
         let index_key = to_array_index(key);
         if let Some(index) = index_key {
             if index < self.array.len() {
@@ -162,6 +192,8 @@ impl<'gc> TableState<'gc> {
                 // If we're growing the array part, we need to grow the array and take any newly valid
                 // array keys from the map part.
 
+                self.memory
+                    .check_grow((optimal_size - old_array_size) * mem::size_of::<Value>())?;
                 self.array.reserve(optimal_size - old_array_size);
                 let capacity = self.array.capacity();
                 self.array.resize(capacity, Value::Nil);
@@ -183,19 +215,37 @@ impl<'gc> TableState<'gc> {
                 // without the advertised capacity growing, so to make sure that we don't try to
                 // grow repeatedly, we need to make sure the capacity actually increases.  We simply
                 // double the capacity here.
+                self.memory
+                    .check_grow(old_map_size.max(MIN_MAP_CAPACITY) * MAP_ENTRY_SIZE)?;
                 self.map.reserve(old_map_size);
             }
 
             // Now we can insert the new key value pair
-            if let Some(index) = index_key {
-                if index < self.array.len() {
-                    return Ok(mem::replace(&mut self.array[index], value));
+            let previous = match index_key {
+                Some(index) if index < self.array.len() => {
+                    mem::replace(&mut self.array[index], value)
                 }
-            }
-            Ok(self.map.insert(hash_key, value).unwrap_or(Value::Nil))
+                _ => self.map.insert(hash_key, value).unwrap_or(Value::Nil),
+            };
+            self.memory.resize(
+                self.array.capacity() * mem::size_of::<Value>()
+                    + self.map.capacity() * MAP_ENTRY_SIZE,
+            );
+            Ok(previous)
         }
     }
 
+    /// Iterates over every key and value in the table which is not nil, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (Value<'gc>, Value<'gc>)> + '_ {
+        let array = self
+            .array
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| (Value::Integer(i as i64 + 1), value));
+        let map = self.map.iter().map(|(key, &value)| (key.0, value));
+        array.chain(map).filter(|(_, value)| *value != Value::Nil)
+    }
+
     /// Returns a 'border' for this table.
     ///
     /// A 'border' for a table is any i >= 0 where:
@@ -254,6 +304,13 @@ impl<'gc> TableState<'gc> {
     }
 }
 
+// The approximate size of each entry in the map part of a table, including the control byte the
+// map uses to track it.
+const MAP_ENTRY_SIZE: usize = mem::size_of::<(TableKey<'static>, Value<'static>)>() + 1;
+
+// The capacity of the map part when it is first allocated.
+const MIN_MAP_CAPACITY: usize = 4;
+
 // Value which implements Hash and Eq, and cannot contain Nil or NaN values.
 #[derive(Debug, Collect, PartialEq)]
 #[collect(no_drop)]
@@ -293,6 +350,10 @@ impl<'gc> Hash for TableKey<'gc> {
                 Hash::hash(&7, state);
                 t.hash(state);
             }
+            Value::UserData(u) => {
+                Hash::hash(&8, state);
+                u.hash(state);
+            }
         }
     }
 }
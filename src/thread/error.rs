@@ -51,6 +51,110 @@ impl fmt::Display for BinaryOperatorError {
     }
 }
 
+/// Where a value involved in a failed operation came from.
+#[derive(Debug, Clone, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub struct VariableInfo {
+    /// One of "local", "global", "field", "method", "upvalue" or "constant".
+    pub kind: &'static str,
+    pub name: String,
+}
+
+impl fmt::Display for VariableInfo {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{} '{}'", self.kind, self.name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub enum Operation {
+    Index,
+    Call,
+    Arithmetic,
+    Bitwise,
+    Concatenate,
+    Length,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operation::Index => write!(fmt, "index"),
+            Operation::Call => write!(fmt, "call"),
+            Operation::Arithmetic => write!(fmt, "perform arithmetic on"),
+            Operation::Bitwise => write!(fmt, "perform bitwise operation on"),
+            Operation::Concatenate => write!(fmt, "concatenate"),
+            Operation::Length => write!(fmt, "get length of"),
+        }
+    }
+}
+
+/// An instruction failed because of the values it operated on.
+///
+/// Raised by the VM in place of the less specific `TypeError` and `BinaryOperatorError`, naming the
+/// variable that held the offending value wherever the function's debug info allows.
+#[derive(Debug, Clone, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub enum OperationError {
+    BadOperand {
+        operation: Operation,
+        found: &'static str,
+        variable: Option<VariableInfo>,
+    },
+    Compare {
+        left: &'static str,
+        right: &'static str,
+    },
+    /// A float operand of a bitwise operation has no exact integer value.
+    NoIntegerRepresentation {
+        variable: Option<VariableInfo>,
+    },
+    DivideByZero,
+    ModuloByZero,
+    /// The initial value, limit or step of a numeric for loop is not a number.
+    ForLoop {
+        what: &'static str,
+    },
+}
+
+impl StdError for OperationError {}
+
+impl fmt::Display for OperationError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OperationError::BadOperand {
+                operation,
+                found,
+                variable,
+            } => {
+                write!(fmt, "attempt to {} a {} value", operation, found)?;
+                if let Some(variable) = variable {
+                    write!(fmt, " ({})", variable)?;
+                }
+                Ok(())
+            }
+            OperationError::Compare { left, right } => {
+                if left == right {
+                    write!(fmt, "attempt to compare two {} values", left)
+                } else {
+                    write!(fmt, "attempt to compare {} with {}", left, right)
+                }
+            }
+            OperationError::NoIntegerRepresentation { variable } => {
+                write!(fmt, "number")?;
+                if let Some(variable) = variable {
+                    write!(fmt, " ({})", variable)?;
+                }
+                write!(fmt, " has no integer representation")
+            }
+            OperationError::DivideByZero => write!(fmt, "attempt to perform 'n//0'"),
+            OperationError::ModuloByZero => write!(fmt, "attempt to perform 'n%0'"),
+            OperationError::ForLoop { what } => write!(fmt, "'for' {} must be a number", what),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Collect)]
 #[collect(require_static)]
 pub struct BadThreadMode {
@@ -94,3 +198,24 @@ impl fmt::Display for ThreadError {
         }
     }
 }
+
+/// Raised when a call would take a thread past its maximum call depth or stack size.
+#[derive(Debug, Clone, Collect)]
+#[collect(require_static)]
+pub struct StackOverflow {
+    /// A description of each frame on the thread at the time of the overflow, innermost first.
+    /// Very deep stacks have their middle frames elided.
+    pub traceback: Vec<String>,
+}
+
+impl StdError for StackOverflow {}
+
+impl fmt::Display for StackOverflow {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "stack overflow\nstack traceback:")?;
+        for line in &self.traceback {
+            write!(fmt, "\n\t{}", line)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+use std::cell::Cell;
+use std::fmt;
+use std::rc::Rc;
+
+use gc_arena::Collect;
+
+/// A shared budget of work that threads may perform, for bounding the time spent running untrusted
+/// scripts.
+///
+/// Every VM instruction consumes one unit of fuel and every callback call consumes the configured
+/// callback cost.  Once a thread's fuel runs out it fails with `HostError::OutOfFuel`, which
+/// cannot be caught by `pcall`.  Clones of a `Fuel` share the same budget, and coroutines created
+/// by a thread draw from the fuel of that thread.
+#[derive(Clone, Collect)]
+#[collect(require_static)]
+pub struct Fuel(Rc<FuelState>);
+
+struct FuelState {
+    remaining: Cell<u64>,
+    callback_cost: Cell<u64>,
+}
+
+impl fmt::Debug for Fuel {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Fuel")
+            .field("remaining", &self.remaining())
+            .field("callback_cost", &self.callback_cost())
+            .finish()
+    }
+}
+
+impl Fuel {
+    /// The amount of fuel consumed by a callback call unless configured otherwise.
+    pub const DEFAULT_CALLBACK_COST: u64 = 1;
+
+    pub fn new(amount: u64) -> Fuel {
+        Fuel(Rc::new(FuelState {
+            remaining: Cell::new(amount),
+            callback_cost: Cell::new(Fuel::DEFAULT_CALLBACK_COST),
+        }))
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.0.remaining.get()
+    }
+
+    pub fn set_remaining(&self, amount: u64) {
+        self.0.remaining.set(amount);
+    }
+
+    /// Adds to the remaining fuel, allowing a thread that ran out to be started again.
+    pub fn refuel(&self, amount: u64) {
+        self.0
+            .remaining
+            .set(self.0.remaining.get().saturating_add(amount));
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.0.remaining.get() == 0
+    }
+
+    pub fn callback_cost(&self) -> u64 {
+        self.0.callback_cost.get()
+    }
+
+    pub fn set_callback_cost(&self, cost: u64) {
+        self.0.callback_cost.set(cost);
+    }
+
+    // Consumes up to the given amount of fuel, returning false if there was not enough.
+    pub(crate) fn consume(&self, amount: u64) -> bool {
+        let remaining = self.0.remaining.get();
+        self.0.remaining.set(remaining.saturating_sub(amount));
+        remaining >= amount
+    }
+}
@@ -0,0 +1,165 @@
+use std::cell::Cell;
+
+use gc_arena::Collect;
+
+use crate::{Function, FunctionProto, LineNumber, OpCode};
+
+/// The events which cause a hook to be called.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub struct HookMask {
+    /// Call the hook on entry to every Lua function, before its first instruction.
+    pub call: bool,
+    /// Call the hook before every Lua function returns.
+    pub ret: bool,
+    /// Call the hook before executing an instruction on a new line, or when jumping backwards.
+    pub line: bool,
+    /// If non-zero, call the hook after every `count` instructions.
+    pub count: u32,
+    /// Call the hook when an error is raised, before any frames are unwound.
+    pub error: bool,
+}
+
+impl HookMask {
+    pub fn is_empty(&self) -> bool {
+        !self.call && !self.ret && !self.line && self.count == 0 && !self.error
+    }
+}
+
+/// A function to be called on the given events while a thread runs Lua code.
+///
+/// The function is called with the name of the event, followed by the new line number for line
+/// events or the error value for error events.  Its results are discarded, and any error it raises
+/// propagates as though raised by the hooked function.
+///
+/// Once the hook returns from an error event, the original error continues to unwind the stack.
+/// The hook is not called for errors raised while a hook is running, but may be called more than
+/// once for the same error if a callback catches and re-raises it.
+#[derive(Debug, Clone, Copy, Collect)]
+#[collect(no_drop)]
+pub struct Hook<'gc> {
+    pub function: Function<'gc>,
+    pub mask: HookMask,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    Call,
+    TailCall,
+    Return,
+    Line(LineNumber),
+    Count,
+    Error,
+}
+
+impl HookEvent {
+    /// The name of this event, as passed to hook functions.
+    pub fn name(self) -> &'static str {
+        match self {
+            HookEvent::Call => "call",
+            HookEvent::TailCall => "tail call",
+            HookEvent::Return => "return",
+            HookEvent::Line(_) => "line",
+            HookEvent::Count => "count",
+            HookEvent::Error => "error",
+        }
+    }
+}
+
+const PENDING_CALL: u8 = 1 << 0;
+const PENDING_COUNT: u8 = 1 << 1;
+const PENDING_LINE: u8 = 1 << 2;
+const PENDING_RETURN: u8 = 1 << 3;
+
+// Tracks which hooks have been called for a single Lua frame.
+#[derive(Debug, Default, Clone, Copy, Collect)]
+#[collect(require_static)]
+pub(crate) struct FrameHookState {
+    // Whether the frame was entered through a tail call
+    pub tail_call: bool,
+    // Set to the event whose hook must be called before this frame may continue
+    pub firing: Option<HookEvent>,
+    called: bool,
+    last_pc: Option<usize>,
+    // The instruction whose hooks are being called, and the events still pending for it
+    hooking_pc: Option<usize>,
+    pending: u8,
+}
+
+impl FrameHookState {
+    // Whether hooks are being called before executing the instruction at `pc`
+    pub(crate) fn is_hooking(&self, pc: usize) -> bool {
+        self.hooking_pc == Some(pc)
+    }
+
+    // Determines the next event due before the instruction at `pc` executes, and if there is one,
+    // marks it as firing and returns true.  Once every event due for an instruction has fired, this
+    // returns false and the instruction may execute.
+    pub(crate) fn fire_next(
+        &mut self,
+        proto: &FunctionProto,
+        mask: HookMask,
+        count: &Cell<u32>,
+        pc: usize,
+    ) -> bool {
+        if self.hooking_pc != Some(pc) {
+            self.hooking_pc = Some(pc);
+            self.pending = 0;
+
+            // Frames that were already running when the hook was set have no call event.
+            if mask.call && !self.called && pc == 0 {
+                self.pending |= PENDING_CALL;
+            }
+            self.called = true;
+
+            if mask.count != 0 {
+                if count.get() <= 1 {
+                    count.set(mask.count);
+                    self.pending |= PENDING_COUNT;
+                } else {
+                    count.set(count.get() - 1);
+                }
+            }
+
+            if mask.line {
+                let new_line = match self.last_pc {
+                    None => true,
+                    Some(last_pc) => {
+                        pc <= last_pc || proto.line_number(pc) != proto.line_number(last_pc)
+                    }
+                };
+                if new_line && proto.line_number(pc).is_some() {
+                    self.pending |= PENDING_LINE;
+                }
+                self.last_pc = Some(pc);
+            }
+
+            if mask.ret && matches!(proto.opcodes[pc], OpCode::Return { .. }) {
+                self.pending |= PENDING_RETURN;
+            }
+        }
+
+        let event = if self.pending & PENDING_CALL != 0 {
+            self.pending &= !PENDING_CALL;
+            if self.tail_call {
+                HookEvent::TailCall
+            } else {
+                HookEvent::Call
+            }
+        } else if self.pending & PENDING_COUNT != 0 {
+            self.pending &= !PENDING_COUNT;
+            HookEvent::Count
+        } else if self.pending & PENDING_LINE != 0 {
+            self.pending &= !PENDING_LINE;
+            HookEvent::Line(proto.line_number(pc).unwrap())
+        } else if self.pending & PENDING_RETURN != 0 {
+            self.pending &= !PENDING_RETURN;
+            HookEvent::Return
+        } else {
+            self.hooking_pc = None;
+            return false;
+        };
+        self.firing = Some(event);
+        true
+    }
+}
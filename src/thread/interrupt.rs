@@ -0,0 +1,30 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A handle for cancelling the scripts running in a `Lua` from any OS thread.
+///
+/// Once interrupted, scripts running on the main thread, and any coroutines they create, fail with
+/// `HostError::Interrupted` the next time they are stepped, and this error cannot be caught by
+/// `pcall`.  The interrupt is cleared when the current `Lua::sequence` call finishes, so an
+/// interrupt requested while no sequence is running cancels the next one.
+#[derive(Debug, Clone)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    pub(crate) fn new() -> InterruptHandle {
+        InterruptHandle(Arc::new(AtomicBool::new(false)))
+    }
+
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    // Clears any pending interrupt, returning whether there was one.
+    pub(crate) fn clear(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,220 @@
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::rc::Rc;
+
+use gc_arena::Collect;
+
+use crate::HostError;
+
+// The memory usage of an arena, shared with every thread running in that arena so that they fail
+// once the memory limit is exceeded.
+//
+// Usage is made up of the memory allocated by the arena itself, as last observed in-between
+// mutations, and the memory held outside of the arena by tables and long strings, which is kept up
+// to date as that memory is allocated and freed.
+#[derive(Clone, Collect)]
+#[collect(require_static)]
+pub(crate) struct MemoryTracker(Rc<MemoryState>);
+
+struct MemoryState {
+    limit: Cell<Option<usize>>,
+    usage: Cell<usize>,
+    external: Cell<usize>,
+    peak: Cell<usize>,
+    collection: Cell<Collection>,
+}
+
+// Tracks full collections performed because external memory could not be allocated.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Collection {
+    None,
+    Requested,
+    Performed,
+}
+
+thread_local! {
+    // The tracker of the arena currently being mutated on this OS thread, which any external memory
+    // allocated during the mutation is charged to.
+    static ACTIVE: RefCell<Option<MemoryTracker>> = const { RefCell::new(None) };
+}
+
+impl MemoryTracker {
+    pub(crate) fn new() -> MemoryTracker {
+        MemoryTracker(Rc::new(MemoryState {
+            limit: Cell::new(None),
+            usage: Cell::new(0),
+            external: Cell::new(0),
+            peak: Cell::new(0),
+            collection: Cell::new(Collection::None),
+        }))
+    }
+
+    pub(crate) fn limit(&self) -> Option<usize> {
+        self.0.limit.get()
+    }
+
+    pub(crate) fn set_limit(&self, limit: Option<usize>) {
+        self.0.limit.set(limit);
+    }
+
+    pub(crate) fn peak(&self) -> usize {
+        self.0.peak.get()
+    }
+
+    pub(crate) fn reset_peak(&self) {
+        self.0.peak.set(self.usage());
+    }
+
+    // Returns the external memory currently held by objects in the arena.
+    pub(crate) fn external(&self) -> usize {
+        self.0.external.get()
+    }
+
+    // Returns the last recorded arena usage plus the external memory currently held.
+    pub(crate) fn usage(&self) -> usize {
+        self.0.usage.get().saturating_add(self.0.external.get())
+    }
+
+    // Records the memory currently allocated by the arena, and returns whether the total usage
+    // exceeds the limit.
+    pub(crate) fn record(&self, arena_usage: usize) -> bool {
+        self.0.usage.set(arena_usage);
+        let usage = self.usage();
+        self.0.peak.set(self.0.peak.get().max(usage));
+        self.0.limit.get().is_some_and(|limit| usage > limit)
+    }
+
+    // Errors if the current usage plus the given additional amount exceeds the limit.
+    pub(crate) fn check(&self, additional: usize) -> Result<(), HostError> {
+        match self.0.limit.get() {
+            Some(limit) if self.usage().saturating_add(additional) > limit => {
+                Err(HostError::OutOfMemory)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Like `check`, but for memory that is about to be allocated, which may succeed after garbage
+    // has been collected.
+    pub(crate) fn check_grow(&self, additional: usize) -> Result<(), HostError> {
+        self.check(additional)?;
+        self.0.collection.set(Collection::None);
+        Ok(())
+    }
+
+    // Asks for a full collection after `check_grow` has failed, so that the allocation may be
+    // retried.  Returns false if a collection has already been performed since the last successful
+    // allocation, in which case the allocation must fail.
+    pub(crate) fn request_collection(&self) -> bool {
+        match self.0.collection.get() {
+            Collection::None | Collection::Requested => {
+                self.0.collection.set(Collection::Requested);
+                true
+            }
+            Collection::Performed => {
+                self.0.collection.set(Collection::None);
+                false
+            }
+        }
+    }
+
+    // Returns whether a full collection has been requested, in which case the caller must perform
+    // one.
+    pub(crate) fn take_collection_request(&self) -> bool {
+        if self.0.collection.get() == Collection::Requested {
+            self.0.collection.set(Collection::Performed);
+            true
+        } else {
+            false
+        }
+    }
+
+    // Makes this the tracker that external memory is charged to until the returned guard is
+    // dropped.
+    pub(crate) fn enter(&self) -> ActiveTracker {
+        ActiveTracker(ACTIVE.with(|active| active.replace(Some(self.clone()))))
+    }
+
+    fn allocate_external(&self, bytes: usize) {
+        let external = self.0.external.get().saturating_add(bytes);
+        self.0.external.set(external);
+        self.0.peak.set(
+            self.0
+                .peak
+                .get()
+                .max(self.0.usage.get().saturating_add(external)),
+        );
+    }
+
+    fn free_external(&self, bytes: usize) {
+        self.0
+            .external
+            .set(self.0.external.get().saturating_sub(bytes));
+    }
+}
+
+// Restores the previously active tracker when dropped.
+pub(crate) struct ActiveTracker(Option<MemoryTracker>);
+
+impl Drop for ActiveTracker {
+    fn drop(&mut self) {
+        ACTIVE.with(|active| *active.borrow_mut() = self.0.take());
+    }
+}
+
+// Memory held outside of the arena by a single object, such as the storage behind a table.  It is
+// charged to the tracker that was active when the object was created, and freed along with the
+// object.
+#[derive(Collect)]
+#[collect(require_static)]
+pub(crate) struct ExternalMemory {
+    tracker: Option<MemoryTracker>,
+    bytes: usize,
+}
+
+impl ExternalMemory {
+    pub(crate) fn new(bytes: usize) -> ExternalMemory {
+        let tracker = ACTIVE.with(|active| active.borrow().clone());
+        if let Some(tracker) = &tracker {
+            tracker.allocate_external(bytes);
+        }
+        ExternalMemory { tracker, bytes }
+    }
+
+    // Errors if growing by the given number of bytes would exceed the memory limit.
+    pub(crate) fn check_grow(&self, additional: usize) -> Result<(), HostError> {
+        match &self.tracker {
+            Some(tracker) => tracker.check_grow(additional),
+            None => Ok(()),
+        }
+    }
+
+    // Updates the number of bytes held after the object has been resized.
+    pub(crate) fn resize(&mut self, bytes: usize) {
+        if let Some(tracker) = &self.tracker {
+            tracker.free_external(self.bytes);
+            tracker.allocate_external(bytes);
+        }
+        self.bytes = bytes;
+    }
+}
+
+impl Default for ExternalMemory {
+    fn default() -> ExternalMemory {
+        ExternalMemory::new(0)
+    }
+}
+
+impl Drop for ExternalMemory {
+    fn drop(&mut self) {
+        if let Some(tracker) = &self.tracker {
+            tracker.free_external(self.bytes);
+        }
+    }
+}
+
+impl fmt::Debug for ExternalMemory {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "ExternalMemory({})", self.bytes)
+    }
+}
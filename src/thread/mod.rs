@@ -1,9 +1,21 @@
 mod error;
+mod fuel;
+mod hook;
+mod interrupt;
+mod memory;
 mod thread;
 mod vm;
 
-pub use error::{BadThreadMode, BinaryOperatorError, ThreadError};
-pub use thread::{Thread, ThreadMode, ThreadSequence};
+pub use error::{
+    BadThreadMode, BinaryOperatorError, Operation, OperationError, StackOverflow, ThreadError,
+    VariableInfo,
+};
+pub use fuel::Fuel;
+pub use hook::{Hook, HookEvent, HookMask};
+pub use interrupt::InterruptHandle;
+pub use thread::{FrameInfo, Thread, ThreadMode, ThreadSequence};
 
+pub(crate) use hook::FrameHookState;
+pub(crate) use memory::{ExternalMemory, MemoryTracker};
 pub(crate) use thread::LuaFrame;
-pub(crate) use vm::run_vm;
+pub(crate) use vm::{describe_error, run_vm};
@@ -1,37 +1,58 @@
+use std::cell::{Cell, RefCell, RefMut};
 use std::collections::btree_map::Entry as BTreeEntry;
 use std::collections::BTreeMap;
 use std::fmt::{self, Debug};
 use std::hash::{Hash, Hasher};
+use std::string::String as StdString;
 
-use gc_arena::{Collect, GcCell, MutationContext};
+use gc_arena::{Collect, Gc, GcCell, MutationContext};
 use gc_sequence::Sequence;
 
 use crate::{
-    thread::run_vm, BadThreadMode, CallbackResult, CallbackReturn, Closure, Continuation, Error,
-    Function, RegisterIndex, ThreadError, TypeError, UpValue, UpValueState, Value, VarCount,
+    thread::{describe_error, run_vm, FrameHookState, MemoryTracker},
+    BadThreadMode, CallContext, Callback, CallbackResult, CallbackReturn, Closure, Continuation,
+    Error, Fuel, Function, FunctionProto, Hook, HookEvent, HookMask, HostError, InternedStringSet,
+    InterruptHandle, LineNumber, OpCode, RegisterIndex, StackOverflow, String, Table, ThreadError,
+    TypeError, UpValue, UpValueState, Value, ValueBuffer, VarCount,
 };
 
 #[derive(Clone, Copy, Collect)]
 #[collect(no_drop)]
-pub struct Thread<'gc>(pub(crate) GcCell<'gc, ThreadState<'gc>>);
+pub struct Thread<'gc>(pub(crate) Gc<'gc, ThreadInner<'gc>>);
+
+// The parts of a thread that may be inspected even while the thread is running.
+#[derive(Collect)]
+#[collect(no_drop)]
+pub(crate) struct ThreadInner<'gc> {
+    globals: Table<'gc>,
+    interned_strings: InternedStringSet<'gc>,
+    allow_yield: bool,
+    limits: RefCell<ThreadLimits>,
+    // How many coroutines deep this thread was last resumed from
+    nesting: Cell<usize>,
+    hook: GcCell<'gc, Option<Hook<'gc>>>,
+    // Instructions left until the next count hook
+    hook_count: Cell<u32>,
+    state: GcCell<'gc, ThreadState<'gc>>,
+}
 
 impl<'gc> Debug for Thread<'gc> {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.debug_tuple("Thread")
-            .field(&(&self.0 as *const _))
+            .field(&Gc::as_ptr(self.0))
             .finish()
     }
 }
 
 impl<'gc> PartialEq for Thread<'gc> {
     fn eq(&self, other: &Thread<'gc>) -> bool {
-        GcCell::ptr_eq(self.0, other.0)
+        Gc::ptr_eq(self.0, other.0)
     }
 }
 
 impl<'gc> Hash for Thread<'gc> {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        GcCell::as_ptr(self.0).hash(state)
+        Gc::as_ptr(self.0).hash(state)
     }
 }
 
@@ -51,14 +72,64 @@ pub enum ThreadMode {
 #[collect(no_drop)]
 pub struct ThreadSequence<'gc>(pub Thread<'gc>);
 
+/// Describes a single frame on the call stack of a thread.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameInfo<'gc> {
+    /// The closure running in a Lua frame, or `None` for a callback.
+    pub function: Option<Function<'gc>>,
+    /// The index of the instruction being executed by a Lua frame.
+    pub pc: Option<usize>,
+    /// The line being executed by a Lua frame, if line information is available.
+    pub current_line: Option<LineNumber>,
+    /// Whether a Lua frame was entered through a tail call, replacing its caller.
+    pub tail_call: bool,
+    /// How the frame's caller named the function it called, as a kind such as "global", "local" or
+    /// "method" and a name, if known.
+    pub name: Option<(&'static str, String<'gc>)>,
+}
+
+// Limits imposed by the host on the work a thread may do, which are shared with any coroutines the
+// thread creates.
+#[derive(Clone)]
+struct ThreadLimits {
+    fuel: Option<Fuel>,
+    memory: Option<MemoryTracker>,
+    interrupt: Option<InterruptHandle>,
+    max_call_depth: usize,
+    max_stack_size: usize,
+}
+
+impl Default for ThreadLimits {
+    fn default() -> ThreadLimits {
+        ThreadLimits {
+            fuel: None,
+            memory: None,
+            interrupt: None,
+            max_call_depth: Thread::DEFAULT_MAX_CALL_DEPTH,
+            max_stack_size: Thread::DEFAULT_MAX_STACK_SIZE,
+        }
+    }
+}
+
 #[derive(Collect)]
 #[collect(no_drop)]
 pub(crate) struct ThreadState<'gc> {
+    // The function slot and fixed size register window of every Lua frame
     values: Vec<Value<'gc>>,
+    // The varargs of every Lua frame, followed by any variable number of values that the top Lua
+    // frame is about to use as call arguments or returns
+    var_stack: Vec<Value<'gc>>,
     frames: Vec<Frame<'gc>>,
     open_upvalues: BTreeMap<usize, UpValue<'gc>>,
     result: Option<Result<Vec<Value<'gc>>, Error<'gc>>>,
-    allow_yield: bool,
+    // The error value a coroutine died with, reported by `coroutine.close`
+    dead_error: Option<Value<'gc>>,
+    // Where the innermost Lua frame was when an error last unwound the whole thread, as `chunk:line`
+    error_location: Option<StdString>,
+    // Empty buffers whose storage can be re-used for callback arguments and returns
+    spare_buffers: Vec<Vec<Value<'gc>>>,
+    // Set while a hook is being called, during which no further hooks are called
+    in_hook: bool,
 }
 
 pub(crate) struct LuaFrame<'gc, 'a> {
@@ -68,6 +139,7 @@ pub(crate) struct LuaFrame<'gc, 'a> {
 
 pub(crate) struct LuaRegisters<'gc, 'a> {
     pub pc: &'a mut usize,
+    hook: &'a mut FrameHookState,
     pub stack_frame: &'a mut [Value<'gc>],
     upper_stack: &'a mut [Value<'gc>],
     base: usize,
@@ -108,21 +180,55 @@ impl<'gc> Sequence<'gc> for ThreadSequence<'gc> {
 }
 
 impl<'gc> Thread<'gc> {
-    pub fn new(mc: MutationContext<'gc, '_>, allow_yield: bool) -> Thread<'gc> {
-        Thread(GcCell::allocate(
+    pub const DEFAULT_MAX_CALL_DEPTH: usize = 200_000;
+    pub const DEFAULT_MAX_STACK_SIZE: usize = 1_000_000;
+
+    /// Creates a new, `Stopped` thread.  The given globals and interned strings are made available
+    /// to every callback called on this thread through its `CallContext`.
+    pub fn new(
+        mc: MutationContext<'gc, '_>,
+        globals: Table<'gc>,
+        interned_strings: InternedStringSet<'gc>,
+        allow_yield: bool,
+    ) -> Thread<'gc> {
+        Thread(Gc::allocate(
             mc,
-            ThreadState {
-                values: Vec::new(),
-                frames: Vec::new(),
-                open_upvalues: BTreeMap::new(),
-                result: None,
+            ThreadInner {
+                globals,
+                interned_strings,
                 allow_yield,
+                limits: RefCell::new(ThreadLimits::default()),
+                nesting: Cell::new(0),
+                hook: GcCell::allocate(mc, None),
+                hook_count: Cell::new(0),
+                state: GcCell::allocate(
+                    mc,
+                    ThreadState {
+                        values: Vec::new(),
+                        var_stack: Vec::new(),
+                        frames: Vec::new(),
+                        open_upvalues: BTreeMap::new(),
+                        result: None,
+                        dead_error: None,
+                        error_location: None,
+                        spare_buffers: Vec::new(),
+                        in_hook: false,
+                    },
+                ),
             },
         ))
     }
 
+    pub fn globals(self) -> Table<'gc> {
+        self.0.globals
+    }
+
+    pub fn interned_strings(self) -> InternedStringSet<'gc> {
+        self.0.interned_strings
+    }
+
     pub fn mode(self) -> ThreadMode {
-        if let Ok(state) = self.0.try_read() {
+        if let Ok(state) = self.0.state.try_read() {
             get_mode(&state)
         } else {
             ThreadMode::Running
@@ -136,9 +242,11 @@ impl<'gc> Thread<'gc> {
         function: Function<'gc>,
         args: &[Value<'gc>],
     ) -> Result<(), BadThreadMode> {
-        let mut state = self.0.write(mc);
+        let mut state = self.0.state.write(mc);
         check_mode(&state, ThreadMode::Stopped)?;
-        ext_call_function(self, &mut state, mc, function, args);
+        let mut buffer = state.take_buffer();
+        buffer.extend_from_slice(args);
+        ext_call_function(self, &mut state, mc, function, buffer);
         Ok(())
     }
 
@@ -148,18 +256,248 @@ impl<'gc> Thread<'gc> {
         mc: MutationContext<'gc, '_>,
         function: Function<'gc>,
     ) -> Result<(), BadThreadMode> {
-        let mut state = self.0.write(mc);
+        let mut state = self.0.state.write(mc);
         check_mode(&state, ThreadMode::Stopped)?;
         state.frames.push(Frame::StartCoroutine(function));
         Ok(())
     }
 
+    /// Whether callbacks running on this thread may yield.
+    pub fn allow_yield(self) -> bool {
+        self.0.allow_yield
+    }
+
+    /// The fuel this thread draws from, if its work is limited.
+    pub fn fuel(self) -> Option<Fuel> {
+        self.0.limits.borrow().fuel.clone()
+    }
+
+    /// Limits the work this thread may perform to the given fuel, or removes any limit.  This may be
+    /// changed at any time, even while the thread is running.
+    pub fn set_fuel(self, fuel: Option<Fuel>) {
+        self.0.limits.borrow_mut().fuel = fuel;
+    }
+
+    /// The maximum number of frames this thread may have on its call stack at once.
+    pub fn max_call_depth(self) -> usize {
+        self.0.limits.borrow().max_call_depth
+    }
+
+    /// Sets the maximum call depth of this thread.  Calls that would exceed it raise a catchable
+    /// `StackOverflow` error instead.  Defaults to `Thread::DEFAULT_MAX_CALL_DEPTH`.
+    pub fn set_max_call_depth(self, max_call_depth: usize) {
+        self.0.limits.borrow_mut().max_call_depth = max_call_depth;
+    }
+
+    /// The maximum number of values this thread may hold on its stack at once, counting the
+    /// registers and varargs of every Lua frame.
+    pub fn max_stack_size(self) -> usize {
+        self.0.limits.borrow().max_stack_size
+    }
+
+    /// Sets the maximum stack size of this thread.  Calls that would exceed it raise a catchable
+    /// `StackOverflow` error instead.  Defaults to `Thread::DEFAULT_MAX_STACK_SIZE`.
+    pub fn set_max_stack_size(self, max_stack_size: usize) {
+        self.0.limits.borrow_mut().max_stack_size = max_stack_size;
+    }
+
+    /// The hook called on this thread, if any.
+    pub fn hook(self) -> Option<Hook<'gc>> {
+        *self.0.hook.read()
+    }
+
+    /// Sets the hook called as this thread runs Lua code, or removes it.  A hook with an empty mask
+    /// is the same as no hook.
+    ///
+    /// Callback hooks are called while the thread's state is not borrowed, so they may inspect the
+    /// hooked frame with `Thread::frame_info`.  The exception is error events, whose callback hooks
+    /// must return a sequence to inspect the stack from its steps.
+    pub fn set_hook(self, mc: MutationContext<'gc, '_>, hook: Option<Hook<'gc>>) {
+        let hook = hook.filter(|hook| !hook.mask.is_empty());
+        self.0
+            .hook_count
+            .set(hook.map(|hook| hook.mask.count).unwrap_or(0));
+        *self.0.hook.write(mc) = hook;
+    }
+
+    /// Describes the frame at the given level of this thread's call stack, where level 0 is the
+    /// innermost frame.  Returns `None` if there is no such frame, or if the thread's state is
+    /// currently borrowed, as it is during immediate callbacks called from Lua.
+    pub fn frame_info(self, level: usize) -> Option<FrameInfo<'gc>> {
+        let state = self.0.state.try_read().ok()?;
+        let info = state.frame_infos().nth(level);
+        info
+    }
+
+    /// The number of levels in this thread's call stack, numbered as for `Thread::frame_info`.
+    /// Returns `None` if the thread's state is borrowed.
+    pub fn stack_depth(self) -> Option<usize> {
+        let state = self.0.state.try_read().ok()?;
+        let depth = state.frame_infos().count();
+        Some(depth)
+    }
+
+    /// Returns the value of a register of the Lua frame at the given level, numbered as for
+    /// `Thread::frame_info`.  Returns `None` if there is no such frame or register, or if the
+    /// thread's state is borrowed.
+    pub fn frame_register(self, level: usize, register: RegisterIndex) -> Option<Value<'gc>> {
+        let state = self.0.state.try_read().ok()?;
+        match state.frames[state.frame_index(level)?] {
+            Frame::Lua {
+                base, stack_size, ..
+            } if (register.0 as usize) < stack_size => {
+                Some(state.values[base + register.0 as usize])
+            }
+            _ => None,
+        }
+    }
+
+    /// Sets a register of the Lua frame at the given level, returning false if it could not be set
+    /// for any of the reasons `Thread::frame_register` would return `None`.
+    pub fn set_frame_register(
+        self,
+        mc: MutationContext<'gc, '_>,
+        level: usize,
+        register: RegisterIndex,
+        value: Value<'gc>,
+    ) -> bool {
+        let mut state = match self.0.state.try_write(mc) {
+            Ok(state) => state,
+            Err(_) => return false,
+        };
+        let index = match state.frame_index(level) {
+            Some(index) => index,
+            None => return false,
+        };
+        match state.frames[index] {
+            Frame::Lua {
+                base, stack_size, ..
+            } if (register.0 as usize) < stack_size => {
+                state.values[base + register.0 as usize] = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Describes the frames of this thread's call stack starting at the given level, in the style of
+    /// a Lua stack traceback.  Returns `None` if the thread's state is borrowed.
+    pub fn traceback(self, level: usize) -> Option<Vec<StdString>> {
+        let state = self.0.state.try_read().ok()?;
+        Some(traceback(&state, level))
+    }
+
+    // Reads a value of this thread's stack, whose state must not be borrowed.
+    pub(crate) fn stack_value(self, index: usize) -> Value<'gc> {
+        self.0.state.read().values[index]
+    }
+
+    pub(crate) fn set_stack_value(
+        self,
+        mc: MutationContext<'gc, '_>,
+        index: usize,
+        value: Value<'gc>,
+    ) {
+        self.0.state.write(mc).values[index] = value;
+    }
+
+    // Subjects this thread to the same limits as the given thread.
+    pub(crate) fn inherit_limits(self, parent: Thread<'gc>) {
+        *self.0.limits.borrow_mut() = parent.0.limits.borrow().clone();
+    }
+
+    // The number of coroutines that are waiting, directly or indirectly, on this thread to yield or
+    // return.  Each of these is stepped recursively, so the depth must be limited.
+    pub(crate) fn nesting(self) -> usize {
+        self.0.nesting.get()
+    }
+
+    pub(crate) fn set_nesting(self, nesting: usize) {
+        self.0.nesting.set(nesting);
+    }
+
+    pub(crate) fn set_memory_tracker(self, memory: Option<MemoryTracker>) {
+        self.0.limits.borrow_mut().memory = memory;
+    }
+
+    pub(crate) fn set_interrupt_handle(self, interrupt: Option<InterruptHandle>) {
+        self.0.limits.borrow_mut().interrupt = interrupt;
+    }
+
+    // Errors if this thread has been interrupted or is over its memory limit.
+    fn check_limits(self) -> Result<(), HostError> {
+        let limits = self.0.limits.borrow();
+        if limits
+            .interrupt
+            .as_ref()
+            .is_some_and(|i| i.is_interrupted())
+        {
+            return Err(HostError::Interrupted);
+        }
+        match &limits.memory {
+            Some(memory) => memory.check(0),
+            None => Ok(()),
+        }
+    }
+
+    // Errors if allocating the given number of additional bytes would exceed the memory limit of
+    // this thread.
+    pub(crate) fn check_memory(self, additional: usize) -> Result<(), HostError> {
+        match &self.0.limits.borrow().memory {
+            Some(memory) => memory.check_grow(additional),
+            None => Ok(()),
+        }
+    }
+
+    // Asks the host to collect garbage after an allocation failed the memory limit, returning
+    // whether the allocation should be retried once it has.
+    fn request_collection(self) -> bool {
+        match &self.0.limits.borrow().memory {
+            Some(memory) => memory.request_collection(),
+            None => false,
+        }
+    }
+
+    /// If this thread is `Suspended` or `Stopped`, discard all of its frames and close any open
+    /// upvalues, leaving it `Stopped`.
+    pub fn reset(self, mc: MutationContext<'gc, '_>) -> Result<(), BadThreadMode> {
+        let mut state = self.0.state.write(mc);
+        match get_mode(&state) {
+            ThreadMode::Suspended | ThreadMode::Stopped => {
+                close_upvalues(self, &mut state, mc, 0);
+                state.values.clear();
+                state.var_stack.clear();
+                state.frames.clear();
+                state.in_hook = false;
+                Ok(())
+            }
+            found => Err(BadThreadMode {
+                expected: Some(ThreadMode::Suspended),
+                found,
+            }),
+        }
+    }
+
+    pub(crate) fn set_dead_error(self, mc: MutationContext<'gc, '_>, error: Option<Value<'gc>>) {
+        self.0.state.write(mc).dead_error = error;
+    }
+
+    pub(crate) fn take_dead_error(self, mc: MutationContext<'gc, '_>) -> Option<Value<'gc>> {
+        self.0.state.write(mc).dead_error.take()
+    }
+
+    /// Takes the location of the innermost Lua frame at the time an error last unwound this
+    /// thread's whole stack, formatted as `chunk:line` like the entries of a traceback.
+    pub(crate) fn take_error_location(self, mc: MutationContext<'gc, '_>) -> Option<StdString> {
+        self.0.state.write(mc).error_location.take()
+    }
+
     /// Take any results if they are available
     pub fn take_results(
         self,
         mc: MutationContext<'gc, '_>,
     ) -> Option<Result<Vec<Value<'gc>>, Error<'gc>>> {
-        self.0.write(mc).result.take()
+        self.0.state.write(mc).result.take()
     }
 
     /// If the thread is in `Suspended` mode, resume it.
@@ -168,33 +506,45 @@ impl<'gc> Thread<'gc> {
         mc: MutationContext<'gc, '_>,
         args: &[Value<'gc>],
     ) -> Result<(), BadThreadMode> {
-        let mut state = self.0.write(mc);
+        let mut state = self.0.state.write(mc);
         check_mode(&state, ThreadMode::Suspended)?;
         match state.frames.pop() {
             Some(Frame::StartCoroutine(function)) => {
                 state.frames.pop();
                 assert!(
                     state.values.is_empty()
+                        && state.var_stack.is_empty()
                         && state.open_upvalues.is_empty()
                         && state.frames.is_empty()
                         && state.result.is_none()
                 );
-                ext_call_function(self, &mut state, mc, function, args);
+                let mut buffer = state.take_buffer();
+                buffer.extend_from_slice(args);
+                ext_call_function(self, &mut state, mc, function, buffer);
             }
             Some(Frame::ResumeCoroutine) => match state.frames.last_mut() {
                 Some(Frame::Continuation { continuation, .. }) => {
                     let continuation = continuation.take().expect("continuation missing");
-                    let ret = continuation.call(Ok(args.to_vec()));
+                    let mut buffer = state.take_buffer();
+                    buffer.extend_from_slice(args);
+                    let ret = continuation.call(Ok(buffer));
                     state.frames.pop();
                     callback_return(self, &mut state, mc, ret);
                 }
                 Some(Frame::Lua { .. }) => {
                     return_to_lua(&mut state, args);
                 }
+                Some(Frame::Hook { .. }) => {
+                    if let Some(ret) = return_from_hook(self, &mut state, mc) {
+                        callback_return(self, &mut state, mc, ret);
+                    }
+                }
                 None => {
                     state.result = Some(Ok(args.to_vec()));
                 }
-                _ => panic!("resume coroutine frame must be above a continuation or lua frame"),
+                _ => {
+                    panic!("resume coroutine frame must be above a continuation, hook or lua frame")
+                }
             },
             _ => panic!("no suspended coroutine frame"),
         }
@@ -204,15 +554,19 @@ impl<'gc> Thread<'gc> {
     /// If the thread is in `Running` mode, either run the Lua VM for a while or step any callback
     /// that we are waiting on.
     pub fn step(self, mc: MutationContext<'gc, '_>) -> Result<(), BadThreadMode> {
-        let mut state = self.0.write(mc);
+        let mut state = self.0.state.write(mc);
         check_mode(&state, ThreadMode::Running)?;
+        if let Err(err) = self.check_limits() {
+            unwind(self, &mut state, mc, err.into());
+            return Ok(());
+        }
         match state.frames.last_mut() {
             Some(Frame::Callback(sequence)) => {
                 let mut sequence = sequence.take().expect("pending callback missing");
                 drop(state);
                 match sequence.step(mc) {
                     None => {
-                        let mut state = self.0.write(mc);
+                        let mut state = self.0.state.write(mc);
                         match state.frames.last_mut() {
                             Some(Frame::Callback(empty_sequence)) => {
                                 *empty_sequence = Some(sequence);
@@ -221,15 +575,24 @@ impl<'gc> Thread<'gc> {
                         }
                     }
                     Some(res) => {
-                        let mut state = self.0.write(mc);
+                        let mut state = self.0.state.write(mc);
                         state.frames.pop();
                         return_ext(self, &mut state, mc, res);
                     }
                 }
             }
             Some(Frame::Lua { .. }) => {
-                const VM_GRANULARITY: u32 = 256;
-                let mut instructions = VM_GRANULARITY;
+                const VM_GRANULARITY: u32 = 4096;
+                let fuel = self.fuel();
+                let budget = match &fuel {
+                    Some(fuel) => fuel.remaining().min(VM_GRANULARITY as u64) as u32,
+                    None => VM_GRANULARITY,
+                };
+                if budget == 0 {
+                    unwind(self, &mut state, mc, HostError::OutOfFuel.into());
+                    return Ok(());
+                }
+                let mut instructions = budget;
 
                 loop {
                     let lua_frame = LuaFrame {
@@ -237,22 +600,52 @@ impl<'gc> Thread<'gc> {
                         thread: self,
                     };
                     match run_vm(mc, lua_frame, instructions) {
+                        Err(Error::HostError(HostError::OutOfMemory))
+                            if self.request_collection() =>
+                        {
+                            // Growing a table or string failed before changing anything, so stop
+                            // in front of the failing instruction and run it again once the host
+                            // has collected garbage.
+                            match state.frames.last_mut() {
+                                Some(Frame::Lua { pc, .. }) => *pc -= 1,
+                                _ => panic!("lua frame changed by a failing instruction"),
+                            }
+                            break;
+                        }
                         Err(err) => {
+                            // The failing instruction is always charged, even though the number
+                            // of instructions run before it is not known.
+                            instructions -= 1;
+                            let lua_frame = LuaFrame {
+                                state: &mut state,
+                                thread: self,
+                            };
+                            let err = describe_error(lua_frame, err);
                             unwind(self, &mut state, mc, err);
                             break;
                         }
                         Ok(i) => {
-                            if let Some(Frame::Lua { .. }) = state.frames.last() {
-                                instructions = i;
-                                if instructions == 0 {
-                                    break;
-                                }
-                            } else {
-                                break;
+                            instructions = i;
+                            match state.frames.last() {
+                                Some(Frame::Lua { hook, .. })
+                                    if instructions != 0 && hook.firing.is_none() => {}
+                                _ => break,
                             }
                         }
                     }
                 }
+
+                if let Some(fuel) = fuel {
+                    fuel.consume((budget - instructions) as u64);
+                }
+
+                let firing = match state.frames.last_mut() {
+                    Some(Frame::Lua { hook, .. }) => hook.firing.take(),
+                    _ => None,
+                };
+                if let Some(event) = firing {
+                    self.call_hook(mc, state, event);
+                }
             }
             _ => panic!("no callback or lua frame"),
         }
@@ -261,7 +654,68 @@ impl<'gc> Thread<'gc> {
     }
 }
 
+impl<'gc> Thread<'gc> {
+    // Calls the hook function for the given event above the top Lua frame, which continues once the
+    // hook returns.
+    fn call_hook(
+        self,
+        mc: MutationContext<'gc, '_>,
+        mut state: RefMut<ThreadState<'gc>>,
+        event: HookEvent,
+    ) {
+        let hook = match self.hook() {
+            Some(hook) => hook,
+            None => return,
+        };
+        let mut args = state.take_buffer();
+        args.push(Value::String(String::new_static(event.name().as_bytes())));
+        if let HookEvent::Line(line) = event {
+            // Line numbers are stored 0-indexed, but Lua code expects them 1-indexed.
+            args.push(Value::Integer(line.0 as i64 + 1));
+        }
+
+        if let Err(err) = check_stack(self, &state, hook.function) {
+            state.recycle_buffer(args);
+            unwind(self, &mut state, mc, err.into());
+            return;
+        }
+        let bottom = state.values.len();
+        let var_bottom = state.var_stack.len();
+        state.frames.push(Frame::Hook {
+            bottom,
+            var_bottom,
+            error: None,
+        });
+        state.in_hook = true;
+        match hook.function {
+            Function::Closure(closure) => {
+                ext_call_closure(&mut state, closure, args);
+            }
+            Function::Callback(callback) => {
+                if let Err(err) = charge_callback(self) {
+                    state.recycle_buffer(args);
+                    unwind(self, &mut state, mc, err.into());
+                    return;
+                }
+                drop(state);
+                let ret = callback.call(call_context(self, callback), args);
+                let mut state = self.0.state.write(mc);
+                callback_return(self, &mut state, mc, ret);
+            }
+        }
+    }
+}
+
 impl<'gc, 'a> LuaFrame<'gc, 'a> {
+    // The events that the thread's hook should be called for, if any
+    pub(crate) fn hook_mask(&self) -> Option<HookMask> {
+        if self.state.in_hook {
+            None
+        } else {
+            self.thread.hook().map(|hook| hook.mask)
+        }
+    }
+
     // Returns the active closure for this Lua frame
     pub(crate) fn closure(&self) -> Closure<'gc> {
         match self.state.frames.last() {
@@ -276,10 +730,11 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
     // returns a view of the Lua frame's registers
     pub(crate) fn registers<'b>(&'b mut self) -> LuaRegisters<'gc, 'b> {
         match self.state.frames.last_mut() {
-            Some(Frame::Lua { base, pc, .. }) => {
+            Some(Frame::Lua { base, pc, hook, .. }) => {
                 let (upper_stack, stack_frame) = self.state.values.split_at_mut(*base);
                 LuaRegisters {
                     pc,
+                    hook,
                     stack_frame,
                     upper_stack,
                     base: *base,
@@ -299,32 +754,27 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
     ) -> Result<(), ThreadError> {
         match self.state.frames.last_mut() {
             Some(Frame::Lua {
-                bottom,
                 base,
-                is_variable,
+                var_bottom,
+                var_top,
+                variable,
                 ..
             }) => {
-                if *is_variable {
+                if variable.is_some() {
                     return Err(ThreadError::ExpectedVariable(false));
                 }
 
-                let varargs_start = *bottom + 1;
-                let varargs_len = *base - varargs_start;
-                let dest = *base + dest.0 as usize;
                 if let Some(count) = count.to_constant() {
+                    let varargs = &self.state.var_stack[*var_bottom..*var_top];
+                    let dest = *base + dest.0 as usize;
                     for i in 0..count as usize {
-                        self.state.values[dest + i] = if i < varargs_len {
-                            self.state.values[varargs_start + i]
-                        } else {
-                            Value::Nil
-                        };
+                        self.state.values[dest + i] = varargs.get(i).cloned().unwrap_or(Value::Nil);
                     }
                 } else {
-                    *is_variable = true;
-                    self.state.values.resize(dest + varargs_len, Value::Nil);
-                    for i in 0..varargs_len {
-                        self.state.values[dest + i] = self.state.values[varargs_start + i];
-                    }
+                    self.state
+                        .var_stack
+                        .extend_from_within(*var_bottom..*var_top);
+                    *variable = Some(dest);
                 }
             }
             _ => panic!("top frame is not lua frame"),
@@ -335,69 +785,32 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
     // Call the function at the given register with the given arguments.  On return, results will be
     // placed starting at the function register.
     pub(crate) fn call_function(
-        mut self,
+        self,
         mc: MutationContext<'gc, '_>,
         func: RegisterIndex,
         args: VarCount,
         returns: VarCount,
-    ) -> Result<(), ThreadError> {
+    ) -> Result<(), Error<'gc>> {
         match self.state.frames.last_mut() {
             Some(Frame::Lua {
                 expected_returns,
-                is_variable,
                 base,
+                var_top,
+                variable,
                 ..
             }) => {
-                if *is_variable != args.is_variable() {
-                    return Err(ThreadError::ExpectedVariable(*is_variable));
+                if variable.is_some() != args.is_variable() {
+                    return Err(ThreadError::ExpectedVariable(variable.is_some()).into());
                 }
 
                 *expected_returns = Some(returns);
                 let function_index = *base + func.0 as usize;
-                let arg_count = args
-                    .to_constant()
-                    .map(|c| c as usize)
-                    .unwrap_or(self.state.values.len() - function_index - 1);
-
-                match self.state.values[function_index] {
-                    Value::Function(Function::Closure(closure)) => {
-                        let fixed_params = closure.0.proto.fixed_params as usize;
-                        let stack_size = closure.0.proto.stack_size as usize;
-
-                        let base = if arg_count > fixed_params {
-                            self.state.values.truncate(function_index + 1 + arg_count);
-                            self.state.values[function_index + 1..].rotate_left(fixed_params);
-                            function_index + 1 + (arg_count - fixed_params)
-                        } else {
-                            function_index + 1
-                        };
-
-                        self.state.values.resize(base + stack_size, Value::Nil);
-
-                        self.state.frames.push(Frame::Lua {
-                            bottom: function_index,
-                            base,
-                            is_variable: false,
-                            pc: 0,
-                            stack_size,
-                            expected_returns: None,
-                        });
-                        Ok(())
-                    }
-                    Value::Function(Function::Callback(callback)) => {
-                        let ret = callback.call(
-                            self.state.values[function_index + 1..function_index + 1 + arg_count]
-                                .to_vec(),
-                        );
-                        self.state.values.resize(function_index, Value::Nil);
-                        callback_return(self.thread, &mut self.state, mc, ret);
-                        Ok(())
-                    }
-                    val => Err(ThreadError::BadCall(TypeError {
-                        expected: "function",
-                        found: val.type_name(),
-                    })),
-                }
+                let arg_count = match variable.take() {
+                    Some(variable) => (variable.0 - func.0 - 1) as usize,
+                    None => args.to_constant().unwrap() as usize,
+                };
+                let var_top = *var_top;
+                self.call_at(mc, function_index, arg_count, var_top, false)
             }
             _ => panic!("top frame is not lua frame"),
         }
@@ -407,73 +820,35 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
     // invalidating the function or its arguments.  Returns are placed *after* the function and its
     // aruments, and all registers past this are invalidated as normal.
     pub(crate) fn call_function_non_destructive(
-        mut self,
+        self,
         mc: MutationContext<'gc, '_>,
         func: RegisterIndex,
         arg_count: u8,
         returns: VarCount,
-    ) -> Result<(), ThreadError> {
+    ) -> Result<(), Error<'gc>> {
         match self.state.frames.last_mut() {
             Some(Frame::Lua {
                 expected_returns,
-                is_variable,
                 base,
+                var_top,
+                variable,
                 ..
             }) => {
-                if *is_variable {
-                    return Err(ThreadError::ExpectedVariable(false));
+                if variable.is_some() {
+                    return Err(ThreadError::ExpectedVariable(false).into());
                 }
 
                 let arg_count = arg_count as usize;
                 *expected_returns = Some(returns);
                 let given_function_index = *base + func.0 as usize;
                 let function_index = given_function_index + 1 + arg_count;
+                let var_top = *var_top;
+                close_upvalues(self.thread, self.state, mc, function_index);
+                self.state.values.truncate(function_index);
                 self.state
                     .values
-                    .resize(function_index + 1 + arg_count, Value::Nil);
-                for i in 0..arg_count + 1 {
-                    self.state.values[function_index + i] =
-                        self.state.values[given_function_index + i];
-                }
-
-                match self.state.values[function_index] {
-                    Value::Function(Function::Closure(closure)) => {
-                        let fixed_params = closure.0.proto.fixed_params as usize;
-                        let stack_size = closure.0.proto.stack_size as usize;
-
-                        let base = if arg_count > fixed_params {
-                            self.state.values[function_index + 1..].rotate_left(fixed_params);
-                            function_index + 1 + (arg_count - fixed_params)
-                        } else {
-                            function_index + 1
-                        };
-
-                        self.state.values.resize(base + stack_size, Value::Nil);
-
-                        self.state.frames.push(Frame::Lua {
-                            bottom: function_index,
-                            base,
-                            is_variable: false,
-                            pc: 0,
-                            stack_size,
-                            expected_returns: None,
-                        });
-                        Ok(())
-                    }
-                    Value::Function(Function::Callback(callback)) => {
-                        let ret = callback.call(
-                            self.state.values[function_index + 1..function_index + 1 + arg_count]
-                                .to_vec(),
-                        );
-                        self.state.values.resize(function_index, Value::Nil);
-                        callback_return(self.thread, &mut self.state, mc, ret);
-                        Ok(())
-                    }
-                    val => Err(ThreadError::BadCall(TypeError {
-                        expected: "function",
-                        found: val.type_name(),
-                    })),
-                }
+                    .extend_from_within(given_function_index..function_index);
+                self.call_at(mc, function_index, arg_count, var_top, false)
             }
             _ => panic!("top frame is not lua frame"),
         }
@@ -482,75 +857,39 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
     // Tail-call the function at the given register with the given arguments.  Pops the current Lua
     // frame, pushing a new frame for the given function.
     pub(crate) fn tail_call_function(
-        mut self,
+        self,
         mc: MutationContext<'gc, '_>,
         func: RegisterIndex,
         args: VarCount,
-    ) -> Result<(), ThreadError> {
+    ) -> Result<(), Error<'gc>> {
         match self.state.frames.pop() {
             Some(Frame::Lua {
                 bottom,
                 base,
-                is_variable,
+                var_bottom,
+                var_top,
+                variable,
                 ..
             }) => {
-                if is_variable != args.is_variable() {
-                    return Err(ThreadError::ExpectedVariable(is_variable));
+                if variable.is_some() != args.is_variable() {
+                    return Err(ThreadError::ExpectedVariable(variable.is_some()).into());
                 }
 
                 close_upvalues(self.thread, self.state, mc, bottom);
 
                 let function_index = base + func.0 as usize;
-                let arg_count = args
-                    .to_constant()
-                    .map(|c| c as usize)
-                    .unwrap_or(self.state.values.len() - function_index - 1);
-
-                match self.state.values[function_index] {
-                    Value::Function(Function::Closure(closure)) => {
-                        self.state.values[bottom] = self.state.values[function_index];
-                        for i in 0..arg_count {
-                            self.state.values[bottom + 1 + i] =
-                                self.state.values[function_index + 1 + i];
-                        }
+                let arg_count = match variable {
+                    Some(variable) => (variable.0 - func.0 - 1) as usize,
+                    None => args.to_constant().unwrap() as usize,
+                };
 
-                        let fixed_params = closure.0.proto.fixed_params as usize;
-                        let stack_size = closure.0.proto.stack_size as usize;
-
-                        let base = if arg_count > fixed_params {
-                            self.state.values.truncate(bottom + 1 + arg_count);
-                            self.state.values[bottom + 1..].rotate_left(fixed_params);
-                            bottom + 1 + (arg_count - fixed_params)
-                        } else {
-                            bottom + 1
-                        };
-
-                        self.state.values.resize(base + stack_size, Value::Nil);
-
-                        self.state.frames.push(Frame::Lua {
-                            bottom,
-                            base,
-                            is_variable: false,
-                            pc: 0,
-                            stack_size,
-                            expected_returns: None,
-                        });
-                        Ok(())
-                    }
-                    Value::Function(Function::Callback(callback)) => {
-                        let ret = callback.call(
-                            self.state.values[function_index + 1..function_index + 1 + arg_count]
-                                .to_vec(),
-                        );
-                        self.state.values.truncate(bottom);
-                        callback_return(self.thread, &mut self.state, mc, ret);
-                        Ok(())
-                    }
-                    val => Err(ThreadError::BadCall(TypeError {
-                        expected: "function",
-                        found: val.type_name(),
-                    })),
-                }
+                // Move the function and its arguments down to the bottom of the popped frame, and
+                // drop the popped frame's varargs from underneath any variable arguments.
+                self.state
+                    .values
+                    .copy_within(function_index..function_index + 1 + arg_count, bottom);
+                self.state.var_stack.drain(var_bottom..var_top);
+                self.call_at(mc, bottom, arg_count, var_bottom, true)
             }
             _ => panic!("top frame is not lua frame"),
         }
@@ -558,7 +897,7 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
 
     // Return to the upper frame with results starting at the given register index.
     pub(crate) fn return_upper(
-        mut self,
+        self,
         mc: MutationContext<'gc, '_>,
         start: RegisterIndex,
         count: VarCount,
@@ -567,74 +906,174 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
             Some(Frame::Lua {
                 bottom,
                 base,
-                is_variable,
+                var_bottom,
+                var_top,
+                variable,
                 ..
             }) => {
-                if is_variable != count.is_variable() {
-                    return Err(ThreadError::ExpectedVariable(is_variable));
+                if variable.is_some() != count.is_variable() {
+                    return Err(ThreadError::ExpectedVariable(variable.is_some()));
                 }
                 close_upvalues(self.thread, self.state, mc, bottom);
 
+                // Returns are the registers in `start..end`, followed by everything on the var
+                // stack above `var_top`.
                 let start = base + start.0 as usize;
-                let count = count
-                    .to_constant()
-                    .map(|c| c as usize)
-                    .unwrap_or(self.state.values.len() - start);
+                let end = match variable {
+                    Some(variable) => base + variable.0 as usize,
+                    None => start + count.to_constant().unwrap() as usize,
+                };
 
-                match self.state.frames.last_mut() {
+                let state = self.state;
+                match state.frames.last_mut() {
                     Some(Frame::Continuation { continuation, .. }) => {
                         let continuation = continuation.take().expect("continuation missing");
-                        let ret_vals = self.state.values[start..start + count].to_vec();
-                        self.state.values.truncate(bottom);
+                        let mut ret_vals = state.take_buffer();
+                        ret_vals.extend_from_slice(&state.values[start..end]);
+                        ret_vals.extend(state.var_stack.drain(var_top..));
+                        state.var_stack.truncate(var_bottom);
+                        state.values.truncate(bottom);
                         let ret = continuation.call(Ok(ret_vals));
-                        self.state.frames.pop();
-                        callback_return(self.thread, &mut self.state, mc, ret);
+                        state.frames.pop();
+                        callback_return(self.thread, state, mc, ret);
+                    }
+                    Some(Frame::Hook { .. }) => {
+                        state.var_stack.truncate(var_bottom);
+                        state.values.truncate(bottom);
+                        if let Some(ret) = return_from_hook(self.thread, state, mc) {
+                            callback_return(self.thread, state, mc, ret);
+                        }
                     }
                     Some(Frame::Lua {
                         expected_returns,
-                        is_variable,
                         base,
                         stack_size,
+                        variable,
                         ..
                     }) => {
-                        let expected_returns =
-                            expected_returns.expect("no expected returns for upper lua frame");
-                        let returning = expected_returns
-                            .to_constant()
-                            .map(|c| c as usize)
-                            .unwrap_or(count);
-
-                        for i in 0..returning.min(count) {
-                            self.state.values[bottom + i] = self.state.values[start + i]
-                        }
-
-                        for i in count..returning {
-                            self.state.values[bottom + i] = Value::Nil;
-                        }
-
-                        if expected_returns.is_variable() {
-                            self.state.values.truncate(bottom + returning);
-                            *is_variable = true;
-                        } else {
-                            self.state.values.resize(*base + *stack_size, Value::Nil);
-                            *is_variable = false;
+                        let expected_returns = expected_returns
+                            .take()
+                            .expect("no expected returns for upper lua frame");
+                        match expected_returns.to_constant() {
+                            Some(returning) => {
+                                // The returns are written over the function and the registers
+                                // after it, which the returning frame's registers may not have
+                                // covered.
+                                let returning = returning as usize;
+                                let from_registers = (end - start).min(returning);
+                                state
+                                    .values
+                                    .copy_within(start..start + from_registers, bottom);
+                                if state.values.len() < bottom + returning {
+                                    state.values.resize(bottom + returning, Value::Nil);
+                                }
+                                let mut written = from_registers;
+                                if state.var_stack.len() > var_top {
+                                    for value in state.var_stack.drain(var_top..) {
+                                        if written == returning {
+                                            break;
+                                        }
+                                        state.values[bottom + written] = value;
+                                        written += 1;
+                                    }
+                                }
+                                // Everything above the returns was the returning frame's, so it
+                                // is cleared rather than left holding dead values for the GC.
+                                let top = state
+                                    .values
+                                    .len()
+                                    .min(*base + *stack_size)
+                                    .max(bottom + returning);
+                                state.values[bottom + written..top].fill(Value::Nil);
+                                state.var_stack.truncate(var_bottom);
+                                *variable = None;
+                            }
+                            None => {
+                                state.var_stack.splice(
+                                    var_bottom..var_top,
+                                    state.values[start..end].iter().cloned(),
+                                );
+                                state.values.truncate(bottom);
+                                *variable = Some(RegisterIndex((bottom - *base) as u8));
+                            }
                         }
+                        state.values.resize(*base + *stack_size, Value::Nil);
                     }
                     None => {
-                        let ret_vals = self.state.values[start..start + count].to_vec();
-                        self.state.result = Some(Ok(ret_vals));
-                        self.state.values.clear();
+                        let mut ret_vals = state.values[start..end].to_vec();
+                        ret_vals.extend(state.var_stack.drain(var_top..));
+                        state.result = Some(Ok(ret_vals));
+                        state.values.clear();
+                        state.var_stack.clear();
                     }
-                    _ => panic!("lua frame must be above a continuation or lua frame"),
+                    _ => panic!("lua frame must be above a continuation, hook or lua frame"),
                 }
             }
             _ => panic!("top frame is not lua frame"),
         }
         Ok(())
     }
+
+    // Calls the function at the given index of the value stack.  Its arguments are the `arg_count`
+    // values following it, then any values on the var stack past `var_top`.
+    fn call_at(
+        self,
+        mc: MutationContext<'gc, '_>,
+        function_index: usize,
+        arg_count: usize,
+        var_top: usize,
+        tail_call: bool,
+    ) -> Result<(), Error<'gc>> {
+        // The callee reuses every register from the function onwards.  Compiled code never has
+        // upvalues open there, but valid bytecode may, so they are closed rather than left pointing
+        // into the callee.
+        close_upvalues(self.thread, self.state, mc, function_index);
+        match self.state.values[function_index] {
+            Value::Function(Function::Closure(closure)) => {
+                check_stack(self.thread, self.state, Function::Closure(closure))?;
+                self.state
+                    .push_lua_frame(closure, function_index, arg_count, var_top);
+                if tail_call {
+                    if let Some(Frame::Lua { hook, .. }) = self.state.frames.last_mut() {
+                        hook.tail_call = true;
+                    }
+                }
+                Ok(())
+            }
+            Value::Function(Function::Callback(callback)) => {
+                check_stack(self.thread, self.state, Function::Callback(callback))?;
+                charge_callback(self.thread)?;
+                let mut args = self.state.take_buffer();
+                args.extend_from_slice(
+                    &self.state.values[function_index + 1..function_index + 1 + arg_count],
+                );
+                args.extend(self.state.var_stack.drain(var_top..));
+                self.state.values.truncate(function_index);
+                let ret = callback.call(call_context(self.thread, callback), args);
+                callback_return(self.thread, self.state, mc, ret);
+                Ok(())
+            }
+            val => Err(ThreadError::BadCall(TypeError {
+                expected: "function",
+                found: val.type_name(),
+            })
+            .into()),
+        }
+    }
 }
 
 impl<'gc, 'a> LuaRegisters<'gc, 'a> {
+    // Checks whether a hook must be called before the current instruction executes.  If so, the VM
+    // must stop without executing it, and it will be executed once the hook returns.
+    pub fn fire_hook(&mut self, proto: &FunctionProto<'gc>, mask: HookMask) -> bool {
+        self.hook
+            .fire_next(proto, mask, &self.thread.0.hook_count, *self.pc)
+    }
+
+    pub fn check_memory(&self, additional: usize) -> Result<(), HostError> {
+        self.thread.check_memory(additional)
+    }
+
     pub fn open_upvalue(
         &mut self,
         mc: MutationContext<'gc, '_>,
@@ -661,7 +1100,7 @@ impl<'gc, 'a> LuaRegisters<'gc, 'a> {
                         self.stack_frame[ind - self.base]
                     }
                 } else {
-                    thread.0.read().values[ind]
+                    thread.0.state.read().values[ind]
                 }
             }
             UpValueState::Closed(v) => v,
@@ -684,7 +1123,7 @@ impl<'gc, 'a> LuaRegisters<'gc, 'a> {
                         self.stack_frame[*ind - self.base] = value;
                     }
                 } else {
-                    thread.0.write(mc).values[*ind] = value;
+                    thread.0.state.write(mc).values[*ind] = value;
                 }
             }
             UpValueState::Closed(v) => *v = value,
@@ -715,15 +1154,29 @@ enum Frame<'gc> {
     Lua {
         bottom: usize,
         base: usize,
-        is_variable: bool,
+        // This frame's varargs are `var_stack[var_bottom..var_top]`
+        var_bottom: usize,
+        var_top: usize,
+        // Set when the frame holds a variable number of values, which start at this register and
+        // continue onto the var stack past `var_top`
+        variable: Option<RegisterIndex>,
         pc: usize,
         stack_size: usize,
         expected_returns: Option<VarCount>,
+        hook: FrameHookState,
     },
     Continuation {
         bottom: usize,
+        var_bottom: usize,
         continuation: Option<Continuation<'gc>>,
     },
+    // Marks the call of a hook function, whose results are discarded.  For error events, holds the
+    // error to continue unwinding with once the hook returns.
+    Hook {
+        bottom: usize,
+        var_bottom: usize,
+        error: Option<Error<'gc>>,
+    },
     StartCoroutine(Function<'gc>),
     ResumeCoroutine,
     Callback(
@@ -731,6 +1184,167 @@ enum Frame<'gc> {
     ),
 }
 
+// The maximum number of spare buffers a thread keeps for re-use
+const MAX_SPARE_BUFFERS: usize = 16;
+
+impl<'gc> ThreadState<'gc> {
+    // Returns an empty buffer, re-using the storage of a previously recycled buffer if possible.
+    fn take_buffer(&mut self) -> ValueBuffer<'gc> {
+        ValueBuffer::new(self.spare_buffers.pop().unwrap_or_default())
+    }
+
+    // Pushes a new Lua frame for the closure at the given index of the value stack.  Its arguments are
+    // the `arg_count` values following it, then any values on the var stack past `var_bottom`, which
+    // becomes the bottom of the new frame's varargs.
+    fn push_lua_frame(
+        &mut self,
+        closure: Closure<'gc>,
+        bottom: usize,
+        arg_count: usize,
+        var_bottom: usize,
+    ) {
+        let fixed_params = closure.0.proto.fixed_params as usize;
+        let stack_size = closure.0.proto.stack_size as usize;
+
+        let base = bottom + 1;
+        if arg_count > fixed_params {
+            let varargs = &self.values[base + fixed_params..base + arg_count];
+            if var_bottom == self.var_stack.len() {
+                self.var_stack.extend_from_slice(varargs);
+            } else {
+                self.var_stack
+                    .splice(var_bottom..var_bottom, varargs.iter().cloned());
+            }
+            self.values.truncate(base + fixed_params);
+        } else {
+            let mut params_end = base + arg_count;
+            let missing = (fixed_params - arg_count).min(self.var_stack.len() - var_bottom);
+            if missing != 0 {
+                self.values.truncate(params_end);
+                self.values
+                    .extend(self.var_stack.drain(var_bottom..var_bottom + missing));
+                params_end += missing;
+            }
+            // Registers past the parameters are left holding whatever was there, as the compiler
+            // never reads a register before setting it, but missing parameters must be nil.
+            let nil_end = (base + fixed_params).min(self.values.len());
+            if params_end < nil_end {
+                self.values[params_end..nil_end].fill(Value::Nil);
+            }
+        }
+        self.values.resize(base + stack_size, Value::Nil);
+
+        self.frames.push(Frame::Lua {
+            bottom,
+            base,
+            var_bottom,
+            var_top: self.var_stack.len(),
+            variable: None,
+            pc: 0,
+            stack_size,
+            expected_returns: None,
+            hook: FrameHookState::default(),
+        });
+    }
+
+    // Describes the given frame, or returns `None` for frames which only mark a coroutine's state or
+    // a hook call.
+    fn frame_info(&self, frame: &Frame<'gc>) -> Option<FrameInfo<'gc>> {
+        match frame {
+            Frame::Lua {
+                bottom, pc, hook, ..
+            } => {
+                let closure = match self.values[*bottom] {
+                    Value::Function(Function::Closure(closure)) => closure,
+                    _ => panic!("thread bottom is not a closure"),
+                };
+                // The pc of a frame points past the instruction it is executing, unless it is
+                // waiting on a hook to be called before executing it.
+                let pc = if hook.is_hooking(*pc) {
+                    *pc
+                } else {
+                    pc.saturating_sub(1)
+                };
+                Some(FrameInfo {
+                    function: Some(Function::Closure(closure)),
+                    pc: Some(pc),
+                    current_line: closure.0.proto.line_number(pc),
+                    tail_call: hook.tail_call,
+                    name: None,
+                })
+            }
+            Frame::Callback(_) | Frame::Continuation { .. } => Some(FrameInfo {
+                function: None,
+                pc: None,
+                current_line: None,
+                tail_call: false,
+                name: None,
+            }),
+            Frame::Hook { .. } | Frame::StartCoroutine(_) | Frame::ResumeCoroutine => None,
+        }
+    }
+
+    // Describes every frame with `frame_info`, innermost first, along with the name each function
+    // was called by.
+    fn frame_infos(&self) -> impl Iterator<Item = FrameInfo<'gc>> + '_ {
+        self.frames
+            .iter()
+            .enumerate()
+            .rev()
+            .filter_map(move |(index, frame)| {
+                let mut info = self.frame_info(frame)?;
+                if !info.tail_call {
+                    info.name = self.function_name(index);
+                }
+                Some(info)
+            })
+    }
+
+    // Returns the index of the frame at the given level, as numbered by `frame_infos`.
+    fn frame_index(&self, level: usize) -> Option<usize> {
+        self.frames
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, frame)| self.frame_info(frame).is_some())
+            .nth(level)
+            .map(|(index, _)| index)
+    }
+
+    // Names the function running in the frame at the given index by how the frame below it called
+    // the function.
+    fn function_name(&self, index: usize) -> Option<(&'static str, String<'gc>)> {
+        let caller = &self.frames[..index].last()?;
+        match caller {
+            Frame::Hook { .. } => Some(("hook", String::new_static(b"?"))),
+            Frame::Lua { .. } => {
+                let info = self.frame_info(caller)?;
+                let proto = match info.function? {
+                    Function::Closure(closure) => closure.0.proto,
+                    Function::Callback(_) => return None,
+                };
+                let pc = info.pc?;
+                match proto.opcodes[pc] {
+                    OpCode::Call { func, .. } => proto.register_name(pc, func),
+                    OpCode::GenericForCall { .. } => {
+                        Some(("for iterator", String::new_static(b"for iterator")))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn recycle_buffer(&mut self, buffer: ValueBuffer<'gc>) {
+        if self.spare_buffers.len() < MAX_SPARE_BUFFERS {
+            let mut buffer = buffer.into_vec();
+            buffer.clear();
+            self.spare_buffers.push(buffer);
+        }
+    }
+}
+
 fn get_mode<'gc>(state: &ThreadState<'gc>) -> ThreadMode {
     if state.result.is_some() {
         ThreadMode::Results
@@ -739,15 +1353,17 @@ fn get_mode<'gc>(state: &ThreadState<'gc>) -> ThreadMode {
             None => {
                 assert!(
                     state.values.is_empty()
+                        && state.var_stack.is_empty()
                         && state.open_upvalues.is_empty()
                         && state.result.is_none(),
                 );
                 ThreadMode::Stopped
             }
             Some(frame) => match frame {
-                Frame::Callback(_) | Frame::Continuation { .. } | Frame::Lua { .. } => {
-                    ThreadMode::Running
-                }
+                Frame::Callback(_)
+                | Frame::Continuation { .. }
+                | Frame::Lua { .. }
+                | Frame::Hook { .. } => ThreadMode::Running,
                 Frame::StartCoroutine(_) | Frame::ResumeCoroutine => ThreadMode::Suspended,
             },
         }
@@ -771,53 +1387,60 @@ fn ext_call_function<'gc>(
     state: &mut ThreadState<'gc>,
     mc: MutationContext<'gc, '_>,
     function: Function<'gc>,
-    args: &[Value<'gc>],
+    args: ValueBuffer<'gc>,
 ) {
+    if let Err(err) = check_stack(thread, state, function) {
+        state.recycle_buffer(args);
+        unwind(thread, state, mc, err.into());
+        return;
+    }
     match function {
         Function::Closure(closure) => {
-            let fixed_params = closure.0.proto.fixed_params as usize;
-            let stack_size = closure.0.proto.stack_size as usize;
-
-            let var_params = if args.len() > fixed_params {
-                args.len() - fixed_params
-            } else {
-                0
-            };
-            let bottom = state.values.len();
-            let base = bottom + 1 + var_params;
-
-            state.values.resize(base + stack_size, Value::Nil);
-
-            state.values[bottom] = Value::Function(Function::Closure(closure));
-            for i in 0..fixed_params {
-                state.values[base + i] = args.get(i).cloned().unwrap_or(Value::Nil);
-            }
-            for i in 0..var_params {
-                state.values[1 + i] = args[fixed_params + i]
-            }
-
-            state.frames.push(Frame::Lua {
-                bottom,
-                base,
-                is_variable: false,
-                pc: 0,
-                stack_size,
-                expected_returns: None,
-            });
+            ext_call_closure(state, closure, args);
         }
         Function::Callback(callback) => {
-            let ret = callback.call(args.to_vec());
+            let ret = ext_call_callback(thread, state, callback, args);
             callback_return(thread, state, mc, ret);
         }
     }
 }
 
+// Pushes a new Lua frame for the given closure on top of the stack.
+fn ext_call_closure<'gc>(
+    state: &mut ThreadState<'gc>,
+    closure: Closure<'gc>,
+    args: ValueBuffer<'gc>,
+) {
+    let bottom = state.values.len();
+    state
+        .values
+        .push(Value::Function(Function::Closure(closure)));
+    state.values.extend_from_slice(&args);
+    let var_bottom = state.var_stack.len();
+    state.push_lua_frame(closure, bottom, args.len(), var_bottom);
+    state.recycle_buffer(args);
+}
+
+// Charges for and calls the given callback, without handling what it returns.
+fn ext_call_callback<'gc>(
+    thread: Thread<'gc>,
+    state: &mut ThreadState<'gc>,
+    callback: Callback<'gc>,
+    args: ValueBuffer<'gc>,
+) -> CallbackReturn<'gc> {
+    if let Err(err) = charge_callback(thread) {
+        state.recycle_buffer(args);
+        return CallbackReturn::Immediate(Err(err.into()));
+    }
+    callback.call(call_context(thread, callback), args)
+}
+
 // Return to the top Lua frame from an external call
 fn return_to_lua<'gc>(state: &mut ThreadState<'gc>, rets: &[Value<'gc>]) {
     match state.frames.last_mut() {
         Some(Frame::Lua {
             expected_returns,
-            is_variable,
+            variable,
             base,
             stack_size,
             ..
@@ -825,99 +1448,238 @@ fn return_to_lua<'gc>(state: &mut ThreadState<'gc>, rets: &[Value<'gc>]) {
             let ret_count = expected_returns
                 .take()
                 .expect("no expected returns for lua frame");
-            let return_len = ret_count
-                .to_constant()
-                .map(|c| c as usize)
-                .unwrap_or(rets.len());
-
-            let bottom = state.values.len();
-            state.values.resize(bottom + return_len, Value::Nil);
-
-            for i in 0..return_len.min(rets.len()) {
-                state.values[bottom + i] = rets[i];
-            }
-
-            *is_variable = ret_count.is_variable();
-            if !ret_count.is_variable() {
-                state.values.resize(*base + *stack_size, Value::Nil);
+            match ret_count.to_constant() {
+                Some(count) => {
+                    let count = (count as usize).min(rets.len());
+                    state.values.extend_from_slice(&rets[..count]);
+                }
+                None => {
+                    *variable = Some(RegisterIndex((state.values.len() - *base) as u8));
+                    state.var_stack.extend_from_slice(rets);
+                }
             }
+            state.values.resize(*base + *stack_size, Value::Nil);
         }
         _ => panic!("no lua frame to return to"),
     };
 }
 
-// TODO: `unwind`, `return_ext`, and `callback_return` have to be merged somehow, because otherwise
-// they are a stack overflow risk in pathalogical or malicious cases.
-
 fn unwind<'gc>(
     thread: Thread<'gc>,
     state: &mut ThreadState<'gc>,
     mc: MutationContext<'gc, '_>,
     error: Error<'gc>,
 ) {
-    while let Some(mut top_frame) = state.frames.pop() {
+    return_ext(thread, state, mc, Err(error));
+}
+
+// Pops frames until reaching a continuation, and returns the result of calling it with the given
+// error.  If there is no continuation, the error becomes the result of the thread.
+fn unwind_to_continuation<'gc>(
+    thread: Thread<'gc>,
+    state: &mut ThreadState<'gc>,
+    mc: MutationContext<'gc, '_>,
+    error: Error<'gc>,
+) -> Option<CallbackReturn<'gc>> {
+    if !error.is_catchable() {
+        state.frames.clear();
+        state.in_hook = false;
+    }
+    // Where the error was raised, found among the frames this unwind pops, so that unwinding stays
+    // proportional to the number of frames removed.
+    let mut location = None;
+    while let Some(top_frame) = state.frames.last() {
+        if location.is_none() {
+            location = state
+                .frame_info(top_frame)
+                .and_then(|info| match info.function {
+                    Some(Function::Closure(closure)) => Some((closure, info.current_line)),
+                    _ => None,
+                });
+        }
+        let mut top_frame = state.frames.pop().unwrap();
+        if let Frame::Hook { .. } = top_frame {
+            state.in_hook = false;
+        }
         if let Frame::Continuation {
             continuation,
             bottom,
+            var_bottom,
         } = &mut top_frame
         {
             close_upvalues(thread, state, mc, *bottom);
             state.values.truncate(*bottom);
+            state.var_stack.truncate(*var_bottom);
             let continuation = continuation.take().expect("missing continuation");
-            let ret = continuation.call(Err(error));
-            callback_return(thread, state, mc, ret);
-            return;
+            return Some(continuation.call(Err((error, state.take_buffer()))));
         }
     }
     close_upvalues(thread, state, mc, 0);
     state.values.clear();
+    state.var_stack.clear();
+    state.error_location = location.map(|(closure, line)| location_name(closure, line));
     state.result = Some(Err(error));
+    None
+}
+
+// Calls the hook function for an error about to unwind the stack, if the thread's hook wants error
+// events.  The error is held in the hook frame and unwinding continues once the hook returns.  If
+// the hook is called, returns what a callback hook returned, otherwise returns the error back.
+fn call_error_hook<'gc>(
+    thread: Thread<'gc>,
+    state: &mut ThreadState<'gc>,
+    mc: MutationContext<'gc, '_>,
+    error: Error<'gc>,
+) -> Result<Option<CallbackReturn<'gc>>, Error<'gc>> {
+    let hook = match thread.hook() {
+        Some(hook)
+            if hook.mask.error
+                && !state.in_hook
+                && !state.frames.is_empty()
+                && error.is_catchable() =>
+        {
+            hook
+        }
+        _ => return Err(error),
+    };
+    if check_stack(thread, state, hook.function).is_err() {
+        return Err(error);
+    }
+    let value = match &error {
+        Error::RuntimeError(error) => error.0,
+        error => Value::String(
+            thread
+                .interned_strings()
+                .new_string(mc, error.to_string().as_bytes()),
+        ),
+    };
+    let mut args = state.take_buffer();
+    args.push(Value::String(String::new_static(
+        HookEvent::Error.name().as_bytes(),
+    )));
+    args.push(value);
+
+    let bottom = state.values.len();
+    let var_bottom = state.var_stack.len();
+    state.frames.push(Frame::Hook {
+        bottom,
+        var_bottom,
+        error: Some(error),
+    });
+    state.in_hook = true;
+    match hook.function {
+        Function::Closure(closure) => {
+            ext_call_closure(state, closure, args);
+            Ok(None)
+        }
+        Function::Callback(callback) => Ok(Some(ext_call_callback(thread, state, callback, args))),
+    }
+}
+
+// Pops the hook frame on top of the stack once its hook function has returned.  If the hook was
+// called for an error, continues unwinding with it and returns the result of any continuation
+// reached.
+fn return_from_hook<'gc>(
+    thread: Thread<'gc>,
+    state: &mut ThreadState<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Option<CallbackReturn<'gc>> {
+    state.in_hook = false;
+    match state.frames.pop() {
+        Some(Frame::Hook {
+            error: Some(error), ..
+        }) => unwind_to_continuation(thread, state, mc, error),
+        Some(Frame::Hook { error: None, .. }) => None,
+        _ => panic!("no hook frame to return from"),
+    }
 }
 
+// Handles the result of a callback or continuation.  This may call further continuations and
+// callbacks, which is done in a loop rather than recursively so that long chains of them cannot
+// overflow the Rust stack.
 fn return_ext<'gc>(
     thread: Thread<'gc>,
     state: &mut ThreadState<'gc>,
     mc: MutationContext<'gc, '_>,
-    res: Result<CallbackResult<'gc>, Error<'gc>>,
+    mut res: Result<CallbackResult<'gc>, Error<'gc>>,
 ) {
-    match res {
-        Err(err) => {
-            unwind(thread, state, mc, err);
-        }
-        Ok(CallbackResult::Yield(res)) => {
-            if state.allow_yield {
-                state.frames.push(Frame::ResumeCoroutine);
-                state.result = Some(Ok(res));
-            } else {
-                unwind(thread, state, mc, ThreadError::BadYield.into());
-            }
-        }
-        Ok(CallbackResult::Return(res)) => match state.frames.last_mut() {
-            Some(Frame::Continuation { continuation, .. }) => {
-                let continuation = continuation.take().expect("continuation missing");
-                let ret = continuation.call(Ok(res));
-                state.frames.pop();
-                callback_return(thread, state, mc, ret);
+    loop {
+        let ret = match res {
+            Err(err) => match call_error_hook(thread, state, mc, err) {
+                Ok(Some(ret)) => ret,
+                Ok(None) => return,
+                Err(err) => match unwind_to_continuation(thread, state, mc, err) {
+                    Some(ret) => ret,
+                    None => return,
+                },
+            },
+            Ok(CallbackResult::Yield(res)) => {
+                if thread.allow_yield() {
+                    state.frames.push(Frame::ResumeCoroutine);
+                    state.result = Some(Ok(res.into_vec()));
+                    return;
+                }
+                CallbackReturn::Immediate(Err(ThreadError::BadYield.into()))
             }
-            Some(Frame::Lua { .. }) => {
-                return_to_lua(state, &res);
+            Ok(CallbackResult::Return(res)) => match state.frames.last_mut() {
+                Some(Frame::Continuation { continuation, .. }) => {
+                    let continuation = continuation.take().expect("continuation missing");
+                    let ret = continuation.call(Ok(res));
+                    state.frames.pop();
+                    ret
+                }
+                Some(Frame::Lua { .. }) => {
+                    return_to_lua(state, &res);
+                    state.recycle_buffer(res);
+                    return;
+                }
+                Some(Frame::Hook { .. }) => {
+                    state.recycle_buffer(res);
+                    match return_from_hook(thread, state, mc) {
+                        Some(ret) => ret,
+                        None => return,
+                    }
+                }
+                None => {
+                    state.result = Some(Ok(res.into_vec()));
+                    return;
+                }
+                _ => panic!("frame above callback must be continuation or lua frame"),
+            },
+            Ok(CallbackResult::TailCall {
+                function,
+                args,
+                continuation,
+            }) => {
+                let bottom = state.values.len();
+                let var_bottom = state.var_stack.len();
+                state.frames.push(Frame::Continuation {
+                    continuation: Some(continuation),
+                    bottom,
+                    var_bottom,
+                });
+                if let Err(err) = check_stack(thread, state, function) {
+                    state.recycle_buffer(args);
+                    res = Err(err.into());
+                    continue;
+                }
+                match function {
+                    Function::Closure(closure) => {
+                        ext_call_closure(state, closure, args);
+                        return;
+                    }
+                    Function::Callback(callback) => {
+                        ext_call_callback(thread, state, callback, args)
+                    }
+                }
             }
-            None => {
-                state.result = Some(Ok(res));
+        };
+        match ret {
+            CallbackReturn::Immediate(ret) => res = ret,
+            CallbackReturn::Sequence(seq) => {
+                state.frames.push(Frame::Callback(Some(seq)));
+                return;
             }
-            _ => panic!("frame above callback must be continuation or lua frame"),
-        },
-        Ok(CallbackResult::TailCall {
-            function,
-            args,
-            continuation,
-        }) => {
-            let bottom = state.values.len();
-            state.frames.push(Frame::Continuation {
-                continuation: Some(continuation),
-                bottom,
-            });
-            ext_call_function(thread, state, mc, function, &args);
         }
     }
 }
@@ -938,12 +1700,122 @@ fn callback_return<'gc>(
     }
 }
 
+// Errors if calling the given function would take the thread past its maximum call depth or stack
+// size.
+fn check_stack<'gc>(
+    thread: Thread<'gc>,
+    state: &ThreadState<'gc>,
+    function: Function<'gc>,
+) -> Result<(), StackOverflow> {
+    let limits = thread.0.limits.borrow();
+    let stack_size = match function {
+        Function::Closure(closure) => closure.0.proto.stack_size as usize + 1,
+        Function::Callback(_) => 0,
+    };
+    if state.frames.len() >= limits.max_call_depth
+        || state.values.len() + state.var_stack.len() + stack_size > limits.max_stack_size
+    {
+        Err(StackOverflow {
+            traceback: traceback(state, 0),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+// Names a line of the given closure.  Locations are given as `chunk:line` like PUC-Rio Lua when
+// the chunk is named.
+fn location_name<'gc>(closure: Closure<'gc>, line: Option<LineNumber>) -> StdString {
+    let line = match line {
+        Some(line) => line.to_string(),
+        None => "?".to_owned(),
+    };
+    match closure.0.proto.chunk_name {
+        Some(chunk_name) => format!(
+            "{}:{}",
+            StdString::from_utf8_lossy(chunk_name.as_bytes()),
+            line
+        ),
+        None => format!("line {}", line),
+    }
+}
+
+// Describes each frame of the given thread state from the given level, innermost first.  Like
+// PUC-Rio Lua, only the first and last few frames of very deep stacks are described.
+fn traceback<'gc>(state: &ThreadState<'gc>, level: usize) -> Vec<StdString> {
+    const LEVELS_FIRST: usize = 10;
+    const LEVELS_LAST: usize = 11;
+
+    let frames = state.frame_infos().skip(level).collect::<Vec<_>>();
+    let describe = |info: &FrameInfo<'gc>| {
+        let name = info.name.map(|(kind, name)| {
+            let kind = if kind == "global" { "function" } else { kind };
+            format!("{} '{}'", kind, StdString::from_utf8_lossy(name.as_bytes()))
+        });
+        match info.function {
+            Some(Function::Closure(closure)) => {
+                let location = |line| location_name(closure, line);
+                format!(
+                    "{}: in {}",
+                    location(info.current_line),
+                    name.unwrap_or_else(|| match closure.0.proto.line_defined {
+                        Some(line) => format!("function <{}>", location(Some(line))),
+                        None => "main chunk".to_owned(),
+                    })
+                )
+            }
+            _ => match name {
+                Some(name) => format!("[callback]: in {}", name),
+                None => "[callback]".to_owned(),
+            },
+        }
+    };
+
+    if frames.len() <= LEVELS_FIRST + LEVELS_LAST {
+        frames.iter().map(describe).collect()
+    } else {
+        let skipped = frames.len() - LEVELS_FIRST - LEVELS_LAST;
+        let mut traceback = frames[..LEVELS_FIRST]
+            .iter()
+            .map(describe)
+            .collect::<Vec<_>>();
+        traceback.push(format!("...(skipping {} levels)", skipped));
+        traceback.extend(frames[frames.len() - LEVELS_LAST..].iter().map(describe));
+        traceback
+    }
+}
+
+// Consumes the fuel of the given thread for a single callback call.
+fn charge_callback(thread: Thread) -> Result<(), HostError> {
+    match thread.fuel() {
+        Some(fuel) if !fuel.consume(fuel.callback_cost()) => Err(HostError::OutOfFuel),
+        _ => Ok(()),
+    }
+}
+
+fn call_context<'gc>(thread: Thread<'gc>, callback: Callback<'gc>) -> CallContext<'gc> {
+    CallContext {
+        thread,
+        globals: thread.globals(),
+        interned_strings: thread.interned_strings(),
+        name: callback.name(),
+    }
+}
+
 fn close_upvalues<'gc>(
     thread: Thread<'gc>,
     state: &mut ThreadState<'gc>,
     mc: MutationContext<'gc, '_>,
     bottom: usize,
 ) {
+    if state
+        .open_upvalues
+        .keys()
+        .next_back()
+        .is_none_or(|&last| last < bottom)
+    {
+        return;
+    }
     for (_, upval) in state.open_upvalues.split_off(&bottom) {
         let mut upval = upval.0.write(mc);
         if let UpValueState::Open(upvalue_thread, ind) = *upval {
@@ -1,8 +1,11 @@
-use gc_arena::{Gc, MutationContext};
+use std::string::String as StdString;
+
+use gc_arena::{Gc, GcCell, MutationContext};
 
 use crate::{
-    thread::LuaFrame, BinaryOperatorError, Closure, ClosureState, Error, Function, OpCode,
-    RegisterIndex, String, Table, TypeError, UpValueDescriptor, Value, VarCount,
+    thread::LuaFrame, BinaryOperatorError, Closure, ClosureState, ConstantIndex8, Error, Function,
+    OpCode, Operation, OperationError, RegisterIndex, String, StringError, Table, ThreadError,
+    TypeError, UpValueDescriptor, UpValueIndex, Value, VarCount, VariableInfo,
 };
 
 // Runs the VM for the given number of instructions or until the current LuaFrame may have been
@@ -16,10 +19,18 @@ pub(crate) fn run_vm<'gc>(
     assert_ne!(instructions, 0);
 
     let current_function = lua_frame.closure();
+    let opcodes = &current_function.0.proto.opcodes[..];
+    let hook_mask = lua_frame.hook_mask();
     let mut registers = lua_frame.registers();
 
     loop {
-        let op = current_function.0.proto.opcodes[*registers.pc];
+        if let Some(hook_mask) = hook_mask {
+            if registers.fire_hook(&current_function.0.proto, hook_mask) {
+                break;
+            }
+        }
+
+        let op = opcodes[*registers.pc];
         *registers.pc += 1;
 
         match op {
@@ -55,13 +66,13 @@ pub(crate) fn run_vm<'gc>(
 
             OpCode::GetTableR { dest, table, key } => {
                 registers.stack_frame[dest.0 as usize] =
-                    get_table(registers.stack_frame[table.0 as usize])?
+                    get_index_table(registers.stack_frame[table.0 as usize])?
                         .get(registers.stack_frame[key.0 as usize]);
             }
 
             OpCode::GetTableC { dest, table, key } => {
                 registers.stack_frame[dest.0 as usize] =
-                    get_table(registers.stack_frame[table.0 as usize])?
+                    get_index_table(registers.stack_frame[table.0 as usize])?
                         .get(current_function.0.proto.constants[key.0 as usize].to_value())
             }
 
@@ -98,52 +109,61 @@ pub(crate) fn run_vm<'gc>(
             }
 
             OpCode::GetUpTableR { dest, table, key } => {
-                registers.stack_frame[dest.0 as usize] = get_table(
-                    registers.get_upvalue(current_function.0.upvalues[table.0 as usize]),
+                registers.stack_frame[dest.0 as usize] = get_index_table(
+                    registers.get_upvalue(current_function.0.upvalues.read()[table.0 as usize]),
                 )?
                 .get(registers.stack_frame[key.0 as usize]);
             }
 
             OpCode::GetUpTableC { dest, table, key } => {
-                registers.stack_frame[dest.0 as usize] =
-                    get_table(registers.get_upvalue(current_function.0.upvalues[table.0 as usize]))?
-                        .get(current_function.0.proto.constants[key.0 as usize].to_value())
+                registers.stack_frame[dest.0 as usize] = get_index_table(
+                    registers.get_upvalue(current_function.0.upvalues.read()[table.0 as usize]),
+                )?
+                .get(current_function.0.proto.constants[key.0 as usize].to_value())
             }
 
             OpCode::SetUpTableRR { table, key, value } => {
-                get_table(registers.get_upvalue(current_function.0.upvalues[table.0 as usize]))?
-                    .set(
-                        mc,
-                        registers.stack_frame[key.0 as usize],
-                        registers.stack_frame[value.0 as usize],
-                    )?;
+                get_table(
+                    registers.get_upvalue(current_function.0.upvalues.read()[table.0 as usize]),
+                )?
+                .set(
+                    mc,
+                    registers.stack_frame[key.0 as usize],
+                    registers.stack_frame[value.0 as usize],
+                )?;
             }
 
             OpCode::SetUpTableRC { table, key, value } => {
-                get_table(registers.get_upvalue(current_function.0.upvalues[table.0 as usize]))?
-                    .set(
-                        mc,
-                        registers.stack_frame[key.0 as usize],
-                        current_function.0.proto.constants[value.0 as usize].to_value(),
-                    )?;
+                get_table(
+                    registers.get_upvalue(current_function.0.upvalues.read()[table.0 as usize]),
+                )?
+                .set(
+                    mc,
+                    registers.stack_frame[key.0 as usize],
+                    current_function.0.proto.constants[value.0 as usize].to_value(),
+                )?;
             }
 
             OpCode::SetUpTableCR { table, key, value } => {
-                get_table(registers.get_upvalue(current_function.0.upvalues[table.0 as usize]))?
-                    .set(
-                        mc,
-                        current_function.0.proto.constants[key.0 as usize].to_value(),
-                        registers.stack_frame[value.0 as usize],
-                    )?;
+                get_table(
+                    registers.get_upvalue(current_function.0.upvalues.read()[table.0 as usize]),
+                )?
+                .set(
+                    mc,
+                    current_function.0.proto.constants[key.0 as usize].to_value(),
+                    registers.stack_frame[value.0 as usize],
+                )?;
             }
 
             OpCode::SetUpTableCC { table, key, value } => {
-                get_table(registers.get_upvalue(current_function.0.upvalues[table.0 as usize]))?
-                    .set(
-                        mc,
-                        current_function.0.proto.constants[key.0 as usize].to_value(),
-                        current_function.0.proto.constants[value.0 as usize].to_value(),
-                    )?;
+                get_table(
+                    registers.get_upvalue(current_function.0.upvalues.read()[table.0 as usize]),
+                )?
+                .set(
+                    mc,
+                    current_function.0.proto.constants[key.0 as usize].to_value(),
+                    current_function.0.proto.constants[value.0 as usize].to_value(),
+                )?;
             }
 
             OpCode::Call {
@@ -156,6 +176,9 @@ pub(crate) fn run_vm<'gc>(
             }
 
             OpCode::TailCall { func, args } => {
+                // A bad callee must be caught before the tail call pops this frame, so that the error
+                // can be described in terms of it.
+                get_function(registers.stack_frame[func.0 as usize])?;
                 lua_frame.tail_call_function(mc, func, args)?;
                 break;
             }
@@ -212,12 +235,18 @@ pub(crate) fn run_vm<'gc>(
                             upvalues.push(registers.open_upvalue(mc, reg));
                         }
                         UpValueDescriptor::Outer(uvindex) => {
-                            upvalues.push(current_function.0.upvalues[uvindex.0 as usize]);
+                            upvalues.push(current_function.0.upvalues.read()[uvindex.0 as usize]);
                         }
                     }
                 }
 
-                let closure = Closure(Gc::allocate(mc, ClosureState { proto, upvalues }));
+                let closure = Closure(Gc::allocate(
+                    mc,
+                    ClosureState {
+                        proto,
+                        upvalues: GcCell::allocate(mc, upvalues),
+                    },
+                ));
                 registers.stack_frame[dest.0 as usize] =
                     Value::Function(Function::Closure(closure));
             }
@@ -294,14 +323,14 @@ pub(crate) fn run_vm<'gc>(
                 let table = registers.stack_frame[table.0 as usize];
                 let key = current_function.0.proto.constants[key.0 as usize].to_value();
                 registers.stack_frame[base.0 as usize + 1] = table;
-                registers.stack_frame[base.0 as usize] = get_table(table)?.get(key);
+                registers.stack_frame[base.0 as usize] = get_index_table(table)?.get(key);
             }
 
             OpCode::SelfC { base, table, key } => {
                 let table = registers.stack_frame[table.0 as usize];
                 let key = current_function.0.proto.constants[key.0 as usize].to_value();
                 registers.stack_frame[base.0 as usize + 1] = table;
-                registers.stack_frame[base.0 as usize] = get_table(table)?.get(key);
+                registers.stack_frame[base.0 as usize] = get_index_table(table)?.get(key);
             }
 
             OpCode::Concat {
@@ -309,25 +338,31 @@ pub(crate) fn run_vm<'gc>(
                 source,
                 count,
             } => {
-                registers.stack_frame[dest.0 as usize] = Value::String(
-                    String::concat(
-                        mc,
-                        &registers.stack_frame
-                            [source.0 as usize..source.0 as usize + count as usize],
-                    )
-                    .unwrap(),
-                );
+                let values =
+                    &registers.stack_frame[source.0 as usize..source.0 as usize + count as usize];
+                // Check the memory limit before building the result, so that a concatenation
+                // cannot blow far past it in one go.
+                registers.check_memory(
+                    values
+                        .iter()
+                        .map(|v| match v {
+                            Value::String(s) => s.as_bytes().len(),
+                            _ => 0,
+                        })
+                        .sum(),
+                )?;
+                registers.stack_frame[dest.0 as usize] = Value::String(String::concat(mc, values)?);
             }
 
             OpCode::GetUpValue { source, dest } => {
                 registers.stack_frame[dest.0 as usize] =
-                    registers.get_upvalue(current_function.0.upvalues[source.0 as usize]);
+                    registers.get_upvalue(current_function.0.upvalues.read()[source.0 as usize]);
             }
 
             OpCode::SetUpValue { source, dest } => {
                 registers.set_upvalue(
                     mc,
-                    current_function.0.upvalues[dest.0 as usize],
+                    current_function.0.upvalues.read()[dest.0 as usize],
                     registers.stack_frame[source.0 as usize],
                 );
             }
@@ -546,7 +581,7 @@ pub(crate) fn run_vm<'gc>(
                 let left = registers.stack_frame[left.0 as usize];
                 let right = registers.stack_frame[right.0 as usize];
                 registers.stack_frame[dest.0 as usize] =
-                    left.subtract(right).ok_or(BinaryOperatorError::Add)?;
+                    left.subtract(right).ok_or(BinaryOperatorError::Subtract)?;
             }
 
             OpCode::SubRC { dest, left, right } => {
@@ -881,6 +916,259 @@ pub(crate) fn run_vm<'gc>(
     Ok(instructions)
 }
 
+// Replaces an error returned by `run_vm` with one describing the operation that failed, the types
+// of the values involved and where those values came from.  Errors which are not about the operands
+// of the current instruction are returned unchanged.
+pub(crate) fn describe_error<'gc>(
+    mut lua_frame: LuaFrame<'gc, '_>,
+    error: Error<'gc>,
+) -> Error<'gc> {
+    let operator = match error {
+        Error::BinaryOperatorError(operator) => Some(operator),
+        Error::TypeError(_)
+        | Error::ThreadError(ThreadError::BadCall(_))
+        | Error::StringError(StringError::Concat { .. }) => None,
+        error => return error,
+    };
+
+    let closure = lua_frame.closure();
+    let proto = &closure.0.proto;
+    let registers = lua_frame.registers();
+    let pc = *registers.pc - 1;
+
+    let register = |register: RegisterIndex| {
+        let variable = proto
+            .register_name(pc, register)
+            .map(|(kind, name)| variable_info(kind, name));
+        (registers.stack_frame[register.0 as usize], variable)
+    };
+    let constant = |constant: ConstantIndex8| {
+        let value = proto.constants[constant.0 as usize].to_value();
+        let variable = match value {
+            Value::String(name) => Some(variable_info("constant", name)),
+            _ => None,
+        };
+        (value, variable)
+    };
+    let upvalue = |upvalue: UpValueIndex| {
+        let variable = proto
+            .upvalue_name(upvalue)
+            .map(|name| variable_info("upvalue", name));
+        (
+            registers.get_upvalue(closure.0.upvalues.read()[upvalue.0 as usize]),
+            variable,
+        )
+    };
+
+    let bad_operand = |operation, (value, variable): (Value<'gc>, Option<VariableInfo>)| {
+        OperationError::BadOperand {
+            operation,
+            found: value.type_name(),
+            variable,
+        }
+    };
+
+    let described = match proto.opcodes[pc] {
+        OpCode::GetTableR { table, .. }
+        | OpCode::GetTableC { table, .. }
+        | OpCode::SetTableRR { table, .. }
+        | OpCode::SetTableRC { table, .. }
+        | OpCode::SetTableCR { table, .. }
+        | OpCode::SetTableCC { table, .. }
+        | OpCode::SelfR { table, .. }
+        | OpCode::SelfC { table, .. } => Some(bad_operand(Operation::Index, register(table))),
+
+        OpCode::GetUpTableR { table, .. }
+        | OpCode::GetUpTableC { table, .. }
+        | OpCode::SetUpTableRR { table, .. }
+        | OpCode::SetUpTableRC { table, .. }
+        | OpCode::SetUpTableCR { table, .. }
+        | OpCode::SetUpTableCC { table, .. } => Some(bad_operand(Operation::Index, upvalue(table))),
+
+        OpCode::Call { func, .. } | OpCode::TailCall { func, .. } => {
+            Some(bad_operand(Operation::Call, register(func)))
+        }
+        OpCode::GenericForCall { base, .. } => Some(bad_operand(
+            Operation::Call,
+            (registers.stack_frame[base.0 as usize], None),
+        )),
+
+        OpCode::Length { source, .. } => Some(bad_operand(Operation::Length, register(source))),
+
+        OpCode::Concat { source, count, .. } => {
+            let bad = (source.0..source.0 + count)
+                .map(RegisterIndex)
+                .find(|&r| {
+                    matches!(
+                        registers.stack_frame[r.0 as usize],
+                        Value::Table(_)
+                            | Value::Function(_)
+                            | Value::Thread(_)
+                            | Value::UserData(_)
+                    )
+                })
+                .unwrap_or(source);
+            Some(bad_operand(Operation::Concatenate, register(bad)))
+        }
+
+        OpCode::NumericForPrep { base, .. } | OpCode::NumericForLoop { base, .. } => {
+            let what = ["initial value", "limit", "step"]
+                .iter()
+                .zip(&registers.stack_frame[base.0 as usize..base.0 as usize + 3])
+                .find(|(_, value)| value.to_number().is_none())
+                .map(|(&what, _)| what)
+                .unwrap_or("initial value");
+            Some(OperationError::ForLoop { what })
+        }
+
+        OpCode::Minus { source, .. } | OpCode::BitNot { source, .. } => {
+            operator_error(operator, register(source), register(source))
+        }
+
+        OpCode::AddRR { left, right, .. }
+        | OpCode::SubRR { left, right, .. }
+        | OpCode::MulRR { left, right, .. }
+        | OpCode::DivRR { left, right, .. }
+        | OpCode::IDivRR { left, right, .. }
+        | OpCode::ModRR { left, right, .. }
+        | OpCode::PowRR { left, right, .. }
+        | OpCode::BitAndRR { left, right, .. }
+        | OpCode::BitOrRR { left, right, .. }
+        | OpCode::BitXorRR { left, right, .. }
+        | OpCode::ShiftLeftRR { left, right, .. }
+        | OpCode::ShiftRightRR { left, right, .. }
+        | OpCode::LessRR { left, right, .. }
+        | OpCode::LessEqRR { left, right, .. } => {
+            operator_error(operator, register(left), register(right))
+        }
+
+        OpCode::AddRC { left, right, .. }
+        | OpCode::SubRC { left, right, .. }
+        | OpCode::MulRC { left, right, .. }
+        | OpCode::DivRC { left, right, .. }
+        | OpCode::IDivRC { left, right, .. }
+        | OpCode::ModRC { left, right, .. }
+        | OpCode::PowRC { left, right, .. }
+        | OpCode::BitAndRC { left, right, .. }
+        | OpCode::BitOrRC { left, right, .. }
+        | OpCode::BitXorRC { left, right, .. }
+        | OpCode::ShiftLeftRC { left, right, .. }
+        | OpCode::ShiftRightRC { left, right, .. }
+        | OpCode::LessRC { left, right, .. }
+        | OpCode::LessEqRC { left, right, .. } => {
+            operator_error(operator, register(left), constant(right))
+        }
+
+        OpCode::AddCR { left, right, .. }
+        | OpCode::SubCR { left, right, .. }
+        | OpCode::MulCR { left, right, .. }
+        | OpCode::DivCR { left, right, .. }
+        | OpCode::IDivCR { left, right, .. }
+        | OpCode::ModCR { left, right, .. }
+        | OpCode::PowCR { left, right, .. }
+        | OpCode::BitAndCR { left, right, .. }
+        | OpCode::BitOrCR { left, right, .. }
+        | OpCode::BitXorCR { left, right, .. }
+        | OpCode::ShiftLeftCR { left, right, .. }
+        | OpCode::ShiftRightCR { left, right, .. }
+        | OpCode::LessCR { left, right, .. }
+        | OpCode::LessEqCR { left, right, .. } => {
+            operator_error(operator, constant(left), register(right))
+        }
+
+        OpCode::AddCC { left, right, .. }
+        | OpCode::SubCC { left, right, .. }
+        | OpCode::MulCC { left, right, .. }
+        | OpCode::DivCC { left, right, .. }
+        | OpCode::IDivCC { left, right, .. }
+        | OpCode::ModCC { left, right, .. }
+        | OpCode::PowCC { left, right, .. }
+        | OpCode::BitAndCC { left, right, .. }
+        | OpCode::BitOrCC { left, right, .. }
+        | OpCode::BitXorCC { left, right, .. }
+        | OpCode::ShiftLeftCC { left, right, .. }
+        | OpCode::ShiftRightCC { left, right, .. }
+        | OpCode::LessCC { left, right, .. }
+        | OpCode::LessEqCC { left, right, .. } => {
+            operator_error(operator, constant(left), constant(right))
+        }
+
+        _ => None,
+    };
+    match described {
+        Some(described) => described.into(),
+        None => error,
+    }
+}
+
+// Describes the failure of an arithmetic, bitwise or comparison operator.  Like PUC-Rio Lua, blames
+// the left operand unless it is a valid operand for the operator.
+fn operator_error<'gc>(
+    operator: Option<BinaryOperatorError>,
+    left: (Value<'gc>, Option<VariableInfo>),
+    right: (Value<'gc>, Option<VariableInfo>),
+) -> Option<OperationError> {
+    let operation = match operator? {
+        BinaryOperatorError::LessThan | BinaryOperatorError::LessEqual => {
+            return Some(OperationError::Compare {
+                left: left.0.type_name(),
+                right: right.0.type_name(),
+            });
+        }
+        BinaryOperatorError::BitAnd
+        | BinaryOperatorError::BitOr
+        | BinaryOperatorError::BitXor
+        | BinaryOperatorError::BitNot
+        | BinaryOperatorError::ShiftLeft
+        | BinaryOperatorError::ShiftRight => Operation::Bitwise,
+        BinaryOperatorError::FloorDivide if divides_by_zero(left.0, right.0) => {
+            return Some(OperationError::DivideByZero);
+        }
+        BinaryOperatorError::Modulo if divides_by_zero(left.0, right.0) => {
+            return Some(OperationError::ModuloByZero);
+        }
+        _ => Operation::Arithmetic,
+    };
+
+    let (value, variable) = if left.0.to_number().is_none() {
+        left
+    } else if right.0.to_number().is_none() {
+        right
+    } else if left.0.to_integer().is_none() {
+        return Some(OperationError::NoIntegerRepresentation { variable: left.1 });
+    } else {
+        return Some(OperationError::NoIntegerRepresentation { variable: right.1 });
+    };
+    Some(OperationError::BadOperand {
+        operation,
+        found: value.type_name(),
+        variable,
+    })
+}
+
+// Whether an integer division or modulo failed because both operands are numbers and the divisor
+// is an integer zero.
+fn divides_by_zero<'gc>(left: Value<'gc>, right: Value<'gc>) -> bool {
+    left.to_number().is_some() && right.to_number().is_some() && right.to_integer() == Some(0)
+}
+
+fn variable_info(kind: &'static str, name: String) -> VariableInfo {
+    VariableInfo {
+        kind,
+        name: StdString::from_utf8_lossy(name.as_bytes()).into_owned(),
+    }
+}
+
+fn get_function<'gc>(value: Value<'gc>) -> Result<Function<'gc>, ThreadError> {
+    match value {
+        Value::Function(f) => Ok(f),
+        val => Err(ThreadError::BadCall(TypeError {
+            expected: "function",
+            found: val.type_name(),
+        })),
+    }
+}
+
 fn get_table<'gc>(value: Value<'gc>) -> Result<Table<'gc>, TypeError> {
     match value {
         Value::Table(t) => Ok(t),
@@ -891,6 +1179,22 @@ fn get_table<'gc>(value: Value<'gc>) -> Result<Table<'gc>, TypeError> {
     }
 }
 
+// Returns the table that should be used to look up keys when indexing the given value.  Tables are
+// indexed directly, and userdata values are indexed through their index table, if they have one.
+fn get_index_table<'gc>(value: Value<'gc>) -> Result<Table<'gc>, TypeError> {
+    match value {
+        Value::Table(t) => Ok(t),
+        Value::UserData(u) => u.index().ok_or(TypeError {
+            expected: "table",
+            found: "userdata",
+        }),
+        val => Err(TypeError {
+            expected: "table",
+            found: val.type_name(),
+        }),
+    }
+}
+
 fn add_offset(pc: usize, offset: i16) -> usize {
     if offset > 0 {
         pc.checked_add(offset as usize).unwrap()
@@ -0,0 +1,91 @@
+use std::any::Any;
+use std::cell::{Ref, RefCell, RefMut};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use gc_arena::{Collect, Gc, MutationContext, StaticCollect};
+
+use crate::Table;
+
+/// A garbage collected handle to arbitrary `'static` Rust data.
+///
+/// There are no metatables yet, so instead of a full metatable a `UserData` may carry an optional
+/// index table.  Indexing a `UserData` (including method calls with `:`) looks up the key in this
+/// table, which allows a set of methods to be shared between all values of the same kind.
+///
+/// The contained data is dropped when the `UserData` is collected.
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub struct UserData<'gc>(pub Gc<'gc, UserDataState<'gc>>);
+
+#[derive(Collect)]
+#[collect(no_drop)]
+pub struct UserDataState<'gc> {
+    data: StaticCollect<RefCell<Box<dyn Any>>>,
+    index: Option<Table<'gc>>,
+}
+
+impl<'gc> fmt::Debug for UserData<'gc> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_tuple("UserData")
+            .field(&Gc::as_ptr(self.0))
+            .finish()
+    }
+}
+
+impl<'gc> PartialEq for UserData<'gc> {
+    fn eq(&self, other: &UserData<'gc>) -> bool {
+        Gc::ptr_eq(self.0, other.0)
+    }
+}
+
+impl<'gc> Eq for UserData<'gc> {}
+
+impl<'gc> Hash for UserData<'gc> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Gc::as_ptr(self.0).hash(state)
+    }
+}
+
+impl<'gc> UserData<'gc> {
+    pub fn new<T: 'static>(
+        mc: MutationContext<'gc, '_>,
+        data: T,
+        index: Option<Table<'gc>>,
+    ) -> UserData<'gc> {
+        UserData(Gc::allocate(
+            mc,
+            UserDataState {
+                data: StaticCollect(RefCell::new(Box::new(data))),
+                index,
+            },
+        ))
+    }
+
+    /// The table used to look up keys when this value is indexed.
+    pub fn index(&self) -> Option<Table<'gc>> {
+        self.0.index
+    }
+
+    pub fn is<T: 'static>(&self) -> bool {
+        self.0.data.0.borrow().is::<T>()
+    }
+
+    /// Borrows the contained data if it is of type `T`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the data is currently mutably borrowed.
+    pub fn read<T: 'static>(&self) -> Option<Ref<'_, T>> {
+        Ref::filter_map(self.0.data.0.borrow(), |d| d.downcast_ref::<T>()).ok()
+    }
+
+    /// Mutably borrows the contained data if it is of type `T`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the data is currently borrowed.
+    pub fn write<T: 'static>(&self) -> Option<RefMut<'_, T>> {
+        RefMut::filter_map(self.0.data.0.borrow_mut(), |d| d.downcast_mut::<T>()).ok()
+    }
+}
@@ -1,10 +1,10 @@
 use std::{f64, i64, io};
 
-use gc_arena::{Collect, Gc, GcCell, MutationContext};
+use gc_arena::{Collect, Gc, MutationContext};
 
 use crate::{
     lexer::{read_float, read_hex_float},
-    Callback, Closure, String, Table, Thread,
+    Callback, Closure, String, Table, Thread, UserData,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Collect)]
@@ -25,6 +25,7 @@ pub enum Value<'gc> {
     Table(Table<'gc>),
     Function(Function<'gc>),
     Thread(Thread<'gc>),
+    UserData(UserData<'gc>),
 }
 
 impl<'gc> PartialEq for Value<'gc> {
@@ -55,6 +56,9 @@ impl<'gc> PartialEq for Value<'gc> {
 
             (Value::Thread(a), Value::Thread(b)) => a == b,
             (Value::Thread(_), _) => false,
+
+            (Value::UserData(a), Value::UserData(b)) => a == b,
+            (Value::UserData(_), _) => false,
         }
     }
 }
@@ -69,6 +73,7 @@ impl<'gc> Value<'gc> {
             Value::Table(_) => "table",
             Value::Function(_) => "function",
             Value::Thread(_) => "thread",
+            Value::UserData(_) => "userdata",
         }
     }
 
@@ -275,7 +280,8 @@ impl<'gc> Value<'gc> {
             Value::Table(t) => write!(w, "<table {:?}>", t.0.as_ptr()),
             Value::Function(Function::Closure(c)) => write!(w, "<function {:?}>", Gc::as_ptr(c.0)),
             Value::Function(Function::Callback(c)) => write!(w, "<function {:?}>", Gc::as_ptr(c.0)),
-            Value::Thread(t) => write!(w, "<thread {:?}>", GcCell::as_ptr(t.0)),
+            Value::Thread(t) => write!(w, "<thread {:?}>", Gc::as_ptr(t.0)),
+            Value::UserData(u) => write!(w, "<userdata {:?}>", Gc::as_ptr(u.0)),
         }
     }
 }
@@ -310,6 +316,12 @@ impl<'gc> From<Table<'gc>> for Value<'gc> {
     }
 }
 
+impl<'gc> From<UserData<'gc>> for Value<'gc> {
+    fn from(v: UserData<'gc>) -> Value<'gc> {
+        Value::UserData(v)
+    }
+}
+
 impl<'gc> From<Function<'gc>> for Value<'gc> {
     fn from(v: Function<'gc>) -> Value<'gc> {
         Value::Function(v)
@@ -0,0 +1,547 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+use gc_arena::Collect;
+
+use crate::{FunctionProto, OpCode, RegisterIndex, UpValueDescriptor, VarCount};
+
+/// The largest stack size a `FunctionProto` may have, registers are addressed with a single byte.
+pub const MAX_STACK_SIZE: u16 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub enum VerifierErrorKind {
+    StackSize(u16),
+    FixedParameters(u8),
+    Register(usize),
+    Constant(usize),
+    UpValue(usize),
+    Prototype(usize),
+    JumpTarget(isize),
+    FallsOffEnd,
+    VarArgs,
+    UnconsumedVariable,
+    UnexpectedVariable,
+    VariableRange,
+    VariableJumpTarget,
+    EnvironmentUpValue,
+}
+
+/// An error describing why a `FunctionProto` tree was rejected by `verify`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub struct VerifierError {
+    /// The index of the offending prototype, counting the verified prototype as 0 and numbering
+    /// nested prototypes in depth-first order.
+    pub prototype: usize,
+    /// The index of the offending instruction, if the error is in an instruction.
+    pub instruction: Option<usize>,
+    pub kind: VerifierErrorKind,
+}
+
+impl StdError for VerifierError {}
+
+impl fmt::Display for VerifierErrorKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VerifierErrorKind::StackSize(size) => write!(
+                fmt,
+                "stack size {} is larger than the maximum of {}",
+                size, MAX_STACK_SIZE
+            ),
+            VerifierErrorKind::FixedParameters(count) => {
+                write!(fmt, "{} fixed parameters do not fit in the stack", count)
+            }
+            VerifierErrorKind::Register(r) => write!(fmt, "register {} is out of bounds", r),
+            VerifierErrorKind::Constant(c) => write!(fmt, "constant {} is out of bounds", c),
+            VerifierErrorKind::UpValue(u) => write!(fmt, "upvalue {} is out of bounds", u),
+            VerifierErrorKind::Prototype(p) => write!(fmt, "prototype {} is out of bounds", p),
+            VerifierErrorKind::JumpTarget(t) => write!(fmt, "jump target {} is out of bounds", t),
+            VerifierErrorKind::FallsOffEnd => {
+                write!(fmt, "execution can continue past the last instruction")
+            }
+            VerifierErrorKind::VarArgs => write!(fmt, "varargs used in a non-variadic function"),
+            VerifierErrorKind::UnconsumedVariable => write!(
+                fmt,
+                "variable results are not immediately used by a call or return"
+            ),
+            VerifierErrorKind::UnexpectedVariable => write!(
+                fmt,
+                "variable count used without an immediately preceding variable result"
+            ),
+            VerifierErrorKind::VariableRange => write!(
+                fmt,
+                "variable results start before the registers they are used with"
+            ),
+            VerifierErrorKind::VariableJumpTarget => {
+                write!(fmt, "jump into an instruction that uses variable results")
+            }
+            VerifierErrorKind::EnvironmentUpValue => {
+                write!(fmt, "_ENV upvalue is only allowed on a top-level prototype")
+            }
+        }
+    }
+}
+
+impl fmt::Display for VerifierError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "prototype {}", self.prototype)?;
+        if let Some(instruction) = self.instruction {
+            write!(fmt, ", instruction {}", instruction)?;
+        }
+        write!(fmt, ": {}", self.kind)
+    }
+}
+
+/// Checks that a `FunctionProto` and all of its nested prototypes are safe to execute.
+///
+/// Every register, constant, upvalue and prototype index must be in range, every jump must land on
+/// an instruction, execution must never continue past the last instruction, and variable results
+/// (from a call or varargs with a variable count) must be used immediately by the following call or
+/// return.  The upvalue descriptors of nested prototypes are checked against their parent, the
+/// upvalues of the given prototype itself are checked when a top-level `Closure` is created.
+pub fn verify<'gc>(proto: &FunctionProto<'gc>) -> Result<(), VerifierError> {
+    let mut next_index = 0;
+    verify_tree(proto, &mut next_index)
+}
+
+fn verify_tree<'gc>(
+    proto: &FunctionProto<'gc>,
+    next_index: &mut usize,
+) -> Result<(), VerifierError> {
+    let index = *next_index;
+    *next_index += 1;
+
+    verify_proto(proto).map_err(|(instruction, kind)| VerifierError {
+        prototype: index,
+        instruction,
+        kind,
+    })?;
+
+    for child in &proto.prototypes {
+        let child_index = *next_index;
+        for &desc in &child.upvalues {
+            let res = match desc {
+                UpValueDescriptor::Environment => Err(VerifierErrorKind::EnvironmentUpValue),
+                UpValueDescriptor::ParentLocal(reg) => check_register(proto, reg.0 as usize),
+                UpValueDescriptor::Outer(uv) => check_index(
+                    uv.0 as usize,
+                    proto.upvalues.len(),
+                    VerifierErrorKind::UpValue,
+                ),
+            };
+            res.map_err(|kind| VerifierError {
+                prototype: child_index,
+                instruction: None,
+                kind,
+            })?;
+        }
+        verify_tree(child, next_index)?;
+    }
+
+    Ok(())
+}
+
+fn verify_proto<'gc>(proto: &FunctionProto<'gc>) -> Result<(), (Option<usize>, VerifierErrorKind)> {
+    if proto.stack_size > MAX_STACK_SIZE {
+        return Err((None, VerifierErrorKind::StackSize(proto.stack_size)));
+    }
+    if proto.fixed_params as u16 > proto.stack_size {
+        return Err((None, VerifierErrorKind::FixedParameters(proto.fixed_params)));
+    }
+    if proto.opcodes.is_empty() {
+        return Err((None, VerifierErrorKind::FallsOffEnd));
+    }
+
+    let mut jump_targets = vec![false; proto.opcodes.len()];
+    for (pc, &op) in proto.opcodes.iter().enumerate() {
+        let check = |res: Result<(), VerifierErrorKind>| res.map_err(|kind| (Some(pc), kind));
+        check(verify_operands(proto, op))?;
+
+        let (falls_through, jump) = successors(pc, op);
+        if falls_through && pc + 1 >= proto.opcodes.len() {
+            return Err((Some(pc), VerifierErrorKind::FallsOffEnd));
+        }
+        if let Some(target) = jump {
+            if target < 0 || target as usize >= proto.opcodes.len() {
+                return Err((Some(pc), VerifierErrorKind::JumpTarget(target)));
+            }
+            jump_targets[target as usize] = true;
+        }
+    }
+
+    for (pc, &op) in proto.opcodes.iter().enumerate() {
+        if let Some(var_start) = variable_result(op) {
+            let next = pc + 1;
+            match proto.opcodes.get(next).and_then(|&op| variable_use(op)) {
+                Some(base) => {
+                    if var_start < base {
+                        return Err((Some(next), VerifierErrorKind::VariableRange));
+                    }
+                    if jump_targets[next] {
+                        return Err((Some(next), VerifierErrorKind::VariableJumpTarget));
+                    }
+                }
+                None => return Err((Some(pc), VerifierErrorKind::UnconsumedVariable)),
+            }
+        }
+
+        if variable_use(op).is_some()
+            && (pc == 0 || variable_result(proto.opcodes[pc - 1]).is_none())
+        {
+            return Err((Some(pc), VerifierErrorKind::UnexpectedVariable));
+        }
+    }
+
+    Ok(())
+}
+
+// Checks every register, constant, upvalue and prototype operand of a single instruction.
+fn verify_operands<'gc>(proto: &FunctionProto<'gc>, op: OpCode) -> Result<(), VerifierErrorKind> {
+    let reg = |r: RegisterIndex| check_register(proto, r.0 as usize);
+    let regs = |r: RegisterIndex, count: usize| check_registers(proto, r.0 as usize, count);
+    let constant = |c: usize| check_index(c, proto.constants.len(), VerifierErrorKind::Constant);
+    let upvalue = |u: usize| check_index(u, proto.upvalues.len(), VerifierErrorKind::UpValue);
+
+    match op {
+        OpCode::Move { dest, source } => {
+            reg(dest)?;
+            reg(source)?;
+        }
+        OpCode::LoadConstant { dest, constant: c } => {
+            reg(dest)?;
+            constant(c.0 as usize)?;
+        }
+        OpCode::LoadBool { dest, .. } => reg(dest)?,
+        OpCode::LoadNil { dest, count } => regs(dest, count as usize)?,
+        OpCode::NewTable { dest } => reg(dest)?,
+        OpCode::GetTableR { dest, table, key } => {
+            reg(dest)?;
+            reg(table)?;
+            reg(key)?;
+        }
+        OpCode::GetTableC { dest, table, key } => {
+            reg(dest)?;
+            reg(table)?;
+            constant(key.0 as usize)?;
+        }
+        OpCode::SetTableRR { table, key, value } => {
+            reg(table)?;
+            reg(key)?;
+            reg(value)?;
+        }
+        OpCode::SetTableRC { table, key, value } => {
+            reg(table)?;
+            reg(key)?;
+            constant(value.0 as usize)?;
+        }
+        OpCode::SetTableCR { table, key, value } => {
+            reg(table)?;
+            constant(key.0 as usize)?;
+            reg(value)?;
+        }
+        OpCode::SetTableCC { table, key, value } => {
+            reg(table)?;
+            constant(key.0 as usize)?;
+            constant(value.0 as usize)?;
+        }
+        OpCode::GetUpTableR { dest, table, key } => {
+            reg(dest)?;
+            upvalue(table.0 as usize)?;
+            reg(key)?;
+        }
+        OpCode::GetUpTableC { dest, table, key } => {
+            reg(dest)?;
+            upvalue(table.0 as usize)?;
+            constant(key.0 as usize)?;
+        }
+        OpCode::SetUpTableRR { table, key, value } => {
+            upvalue(table.0 as usize)?;
+            reg(key)?;
+            reg(value)?;
+        }
+        OpCode::SetUpTableRC { table, key, value } => {
+            upvalue(table.0 as usize)?;
+            reg(key)?;
+            constant(value.0 as usize)?;
+        }
+        OpCode::SetUpTableCR { table, key, value } => {
+            upvalue(table.0 as usize)?;
+            constant(key.0 as usize)?;
+            reg(value)?;
+        }
+        OpCode::SetUpTableCC { table, key, value } => {
+            upvalue(table.0 as usize)?;
+            constant(key.0 as usize)?;
+            constant(value.0 as usize)?;
+        }
+        OpCode::Call {
+            func,
+            args,
+            returns,
+        } => {
+            reg(func)?;
+            regs(func, 1 + constant_count(args))?;
+            regs(func, constant_count(returns))?;
+        }
+        OpCode::TailCall { func, args } => {
+            reg(func)?;
+            regs(func, 1 + constant_count(args))?;
+        }
+        OpCode::Return { start, count } => {
+            regs(start, constant_count(count))?;
+        }
+        OpCode::VarArgs { dest, count } => {
+            if !proto.has_varargs {
+                return Err(VerifierErrorKind::VarArgs);
+            }
+            match count.to_constant() {
+                Some(count) => regs(dest, count as usize)?,
+                // Variable varargs are placed past the register window, `dest` only marks where
+                // they begin
+                None => check_index(
+                    dest.0 as usize,
+                    proto.stack_size as usize + 1,
+                    VerifierErrorKind::Register,
+                )?,
+            }
+        }
+        OpCode::Jump { close_upvalues, .. } => {
+            if let Some(r) = close_upvalues.to_u8() {
+                reg(RegisterIndex(r))?;
+            }
+        }
+        OpCode::Test { value, .. } => reg(value)?,
+        OpCode::TestSet { dest, value, .. } => {
+            reg(dest)?;
+            reg(value)?;
+        }
+        OpCode::Closure { dest, proto: p } => {
+            reg(dest)?;
+            check_index(
+                p.0 as usize,
+                proto.prototypes.len(),
+                VerifierErrorKind::Prototype,
+            )?;
+        }
+        OpCode::NumericForPrep { base, .. } => regs(base, 3)?,
+        OpCode::NumericForLoop { base, .. } => regs(base, 4)?,
+        OpCode::GenericForCall { base, var_count } => regs(base, 3 + var_count as usize)?,
+        OpCode::GenericForLoop { base, .. } => regs(base, 2)?,
+        OpCode::SelfR { base, table, key } => {
+            regs(base, 2)?;
+            reg(table)?;
+            reg(key)?;
+        }
+        OpCode::SelfC { base, table, key } => {
+            regs(base, 2)?;
+            reg(table)?;
+            constant(key.0 as usize)?;
+        }
+        OpCode::Concat {
+            dest,
+            source,
+            count,
+        } => {
+            reg(dest)?;
+            regs(source, count as usize)?;
+        }
+        OpCode::GetUpValue { dest, source } => {
+            reg(dest)?;
+            upvalue(source.0 as usize)?;
+        }
+        OpCode::SetUpValue { dest, source } => {
+            upvalue(dest.0 as usize)?;
+            reg(source)?;
+        }
+        OpCode::Length { dest, source }
+        | OpCode::Not { dest, source }
+        | OpCode::Minus { dest, source }
+        | OpCode::BitNot { dest, source } => {
+            reg(dest)?;
+            reg(source)?;
+        }
+        OpCode::EqRR { left, right, .. }
+        | OpCode::LessRR { left, right, .. }
+        | OpCode::LessEqRR { left, right, .. } => {
+            reg(left)?;
+            reg(right)?;
+        }
+        OpCode::EqRC { left, right, .. }
+        | OpCode::LessRC { left, right, .. }
+        | OpCode::LessEqRC { left, right, .. } => {
+            reg(left)?;
+            constant(right.0 as usize)?;
+        }
+        OpCode::EqCR { left, right, .. }
+        | OpCode::LessCR { left, right, .. }
+        | OpCode::LessEqCR { left, right, .. } => {
+            constant(left.0 as usize)?;
+            reg(right)?;
+        }
+        OpCode::EqCC { left, right, .. }
+        | OpCode::LessCC { left, right, .. }
+        | OpCode::LessEqCC { left, right, .. } => {
+            constant(left.0 as usize)?;
+            constant(right.0 as usize)?;
+        }
+        OpCode::AddRR { dest, left, right }
+        | OpCode::SubRR { dest, left, right }
+        | OpCode::MulRR { dest, left, right }
+        | OpCode::DivRR { dest, left, right }
+        | OpCode::IDivRR { dest, left, right }
+        | OpCode::ModRR { dest, left, right }
+        | OpCode::PowRR { dest, left, right }
+        | OpCode::BitAndRR { dest, left, right }
+        | OpCode::BitOrRR { dest, left, right }
+        | OpCode::BitXorRR { dest, left, right }
+        | OpCode::ShiftLeftRR { dest, left, right }
+        | OpCode::ShiftRightRR { dest, left, right } => {
+            reg(dest)?;
+            reg(left)?;
+            reg(right)?;
+        }
+        OpCode::AddRC { dest, left, right }
+        | OpCode::SubRC { dest, left, right }
+        | OpCode::MulRC { dest, left, right }
+        | OpCode::DivRC { dest, left, right }
+        | OpCode::IDivRC { dest, left, right }
+        | OpCode::ModRC { dest, left, right }
+        | OpCode::PowRC { dest, left, right }
+        | OpCode::BitAndRC { dest, left, right }
+        | OpCode::BitOrRC { dest, left, right }
+        | OpCode::BitXorRC { dest, left, right }
+        | OpCode::ShiftLeftRC { dest, left, right }
+        | OpCode::ShiftRightRC { dest, left, right } => {
+            reg(dest)?;
+            reg(left)?;
+            constant(right.0 as usize)?;
+        }
+        OpCode::AddCR { dest, left, right }
+        | OpCode::SubCR { dest, left, right }
+        | OpCode::MulCR { dest, left, right }
+        | OpCode::DivCR { dest, left, right }
+        | OpCode::IDivCR { dest, left, right }
+        | OpCode::ModCR { dest, left, right }
+        | OpCode::PowCR { dest, left, right }
+        | OpCode::BitAndCR { dest, left, right }
+        | OpCode::BitOrCR { dest, left, right }
+        | OpCode::BitXorCR { dest, left, right }
+        | OpCode::ShiftLeftCR { dest, left, right }
+        | OpCode::ShiftRightCR { dest, left, right } => {
+            reg(dest)?;
+            constant(left.0 as usize)?;
+            reg(right)?;
+        }
+        OpCode::AddCC { dest, left, right }
+        | OpCode::SubCC { dest, left, right }
+        | OpCode::MulCC { dest, left, right }
+        | OpCode::DivCC { dest, left, right }
+        | OpCode::IDivCC { dest, left, right }
+        | OpCode::ModCC { dest, left, right }
+        | OpCode::PowCC { dest, left, right }
+        | OpCode::BitAndCC { dest, left, right }
+        | OpCode::BitOrCC { dest, left, right }
+        | OpCode::BitXorCC { dest, left, right }
+        | OpCode::ShiftLeftCC { dest, left, right }
+        | OpCode::ShiftRightCC { dest, left, right } => {
+            reg(dest)?;
+            constant(left.0 as usize)?;
+            constant(right.0 as usize)?;
+        }
+    }
+    Ok(())
+}
+
+// Returns whether the instruction at `pc` may continue to the next instruction, and the target of
+// any jump or skip that it may make.  Targets are returned as `isize` so that out of range targets
+// can be reported.
+fn successors(pc: usize, op: OpCode) -> (bool, Option<isize>) {
+    let next = pc as isize + 1;
+    match op {
+        OpCode::Return { .. } | OpCode::TailCall { .. } => (false, None),
+        OpCode::Jump { offset, .. } | OpCode::NumericForPrep { jump: offset, .. } => {
+            (false, Some(next + offset as isize))
+        }
+        OpCode::NumericForLoop { jump, .. } | OpCode::GenericForLoop { jump, .. } => {
+            (true, Some(next + jump as isize))
+        }
+        OpCode::LoadBool { skip_next, .. } => {
+            if skip_next {
+                (false, Some(next + 1))
+            } else {
+                (true, None)
+            }
+        }
+        OpCode::Test { .. }
+        | OpCode::TestSet { .. }
+        | OpCode::EqRR { .. }
+        | OpCode::EqRC { .. }
+        | OpCode::EqCR { .. }
+        | OpCode::EqCC { .. }
+        | OpCode::LessRR { .. }
+        | OpCode::LessRC { .. }
+        | OpCode::LessCR { .. }
+        | OpCode::LessCC { .. }
+        | OpCode::LessEqRR { .. }
+        | OpCode::LessEqRC { .. }
+        | OpCode::LessEqCR { .. }
+        | OpCode::LessEqCC { .. } => (true, Some(next + 1)),
+        _ => (true, None),
+    }
+}
+
+// If the instruction produces a variable number of results, returns the register at which they
+// start.
+fn variable_result(op: OpCode) -> Option<usize> {
+    match op {
+        OpCode::Call { func, returns, .. } if returns.is_variable() => Some(func.0 as usize),
+        OpCode::VarArgs { dest, count } if count.is_variable() => Some(dest.0 as usize),
+        _ => None,
+    }
+}
+
+// If the instruction uses a variable number of values, returns the lowest register at which the
+// variable results may start.
+fn variable_use(op: OpCode) -> Option<usize> {
+    match op {
+        OpCode::Call { func, args, .. } | OpCode::TailCall { func, args } if args.is_variable() => {
+            Some(func.0 as usize + 1)
+        }
+        OpCode::Return { start, count } if count.is_variable() => Some(start.0 as usize),
+        _ => None,
+    }
+}
+
+fn constant_count(count: VarCount) -> usize {
+    count.to_constant().unwrap_or(0) as usize
+}
+
+fn check_register<'gc>(proto: &FunctionProto<'gc>, r: usize) -> Result<(), VerifierErrorKind> {
+    check_index(r, proto.stack_size as usize, VerifierErrorKind::Register)
+}
+
+// Checks that the `count` registers starting at `start` are all in range
+fn check_registers<'gc>(
+    proto: &FunctionProto<'gc>,
+    start: usize,
+    count: usize,
+) -> Result<(), VerifierErrorKind> {
+    if count == 0 {
+        Ok(())
+    } else {
+        check_register(proto, start + count - 1)
+    }
+}
+
+fn check_index(
+    index: usize,
+    len: usize,
+    error: fn(usize) -> VerifierErrorKind,
+) -> Result<(), VerifierErrorKind> {
+    if index < len {
+        Ok(())
+    } else {
+        Err(error(index))
+    }
+}
@@ -0,0 +1,282 @@
+mod common;
+
+use std::fs;
+use std::str;
+
+use luster::{
+    assemble, compile, compile_named, disassemble, write_chunk, AssemblerError, AssemblerErrorKind,
+    Constant, FunctionProto, Lua, OpCode, RegisterIndex, StaticError, UpValueDescriptor,
+};
+
+use common::run_with;
+
+fn chunk_bytes(proto: &FunctionProto) -> Vec<u8> {
+    let mut chunk = Vec::new();
+    write_chunk(&mut chunk, proto, false).unwrap();
+    chunk
+}
+
+#[test]
+fn round_trip() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let mut sources: Vec<Vec<u8>> = vec![
+            br#"
+                local t = {1, 2.5, -0.0, 1e300, "a \"quoted\"\n\0 string", true, false, nil}
+                local function f(a, ...)
+                    local b = a
+                    local function g()
+                        b = b + 1
+                        return b
+                    end
+                    for i = 1, 3 do
+                        g()
+                    end
+                    while b > 0 do
+                        b = b - 1
+                        if b == 3 then break end
+                    end
+                    return g(), ...
+                end
+                for k, v in next, t do
+                    print(k, v)
+                end
+                return f(t[1], t[3])
+            "#
+            .to_vec(),
+            b"".to_vec(),
+            b"goto skip; print(1) ::skip:: return".to_vec(),
+            b"return 0/0, -(0/0)".to_vec(),
+        ];
+        for entry in fs::read_dir("./tests/running").unwrap() {
+            sources.push(fs::read(entry.unwrap().path()).unwrap());
+        }
+
+        for source in &sources {
+            let proto = compile(mc, root.interned_strings, &source[..]).unwrap();
+            let text = disassemble(&proto);
+            let assembled = assemble(mc, root.interned_strings, &text).unwrap();
+            assert_eq!(chunk_bytes(&proto), chunk_bytes(&assembled));
+            assert_eq!(text, disassemble(&assembled));
+        }
+
+        let proto = compile_named(mc, root.interned_strings, "main.lua", &sources[0][..]).unwrap();
+        let text = disassemble(&proto);
+        assert!(text.contains(".chunk \"main.lua\""));
+        let assembled = assemble(mc, root.interned_strings, &text).unwrap();
+        assert_eq!(chunk_bytes(&proto), chunk_bytes(&assembled));
+    });
+}
+
+#[test]
+fn names_and_labels() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let proto = assemble(
+            mc,
+            root.interned_strings,
+            r#"
+                ; counts down from 10
+                .function
+                    .stack 2
+                    .register n r0
+                    .constant 10
+                    .constant 1
+                    .constant "done\x21"
+                    LoadConstant dest=n constant=k0
+                loop: .line 3
+                    SubRC dest=n left=n right=k1
+                    EqRC skip_if=true left=n right=k1
+                    Jump offset=loop close_upvalues=none
+                    Closure dest=r1 proto=p0
+                    Return start=r0 count=2
+                    .function
+                        .defined 6
+                        .stack 1
+                        .upvalue local n "n"
+                        .local "m" r0 1 2
+                        GetUpValue dest=r0 source=u0
+                        Return start=r0 count=1
+                    .end
+                .end
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(proto.stack_size, 2);
+        match proto.constants[2] {
+            Constant::String(s) => assert_eq!(s.as_bytes(), b"done!"),
+            c => panic!("unexpected constant {:?}", c),
+        }
+        match proto.opcodes[3] {
+            OpCode::Jump { offset, .. } => assert_eq!(offset, -3),
+            op => panic!("unexpected opcode {:?}", op),
+        }
+        assert_eq!(proto.line_number(0), None);
+        assert_eq!(proto.line_defined, None);
+        assert_eq!(proto.prototypes[0].line_defined.unwrap().to_string(), "6");
+        assert_eq!(proto.line_number(2).unwrap().to_string(), "3");
+        assert_eq!(
+            proto.prototypes[0].upvalues,
+            vec![UpValueDescriptor::ParentLocal(RegisterIndex(0))]
+        );
+        assert_eq!(proto.prototypes[0].upvalue_names[0].as_bytes(), b"n");
+        let local = proto.prototypes[0].locals[0];
+        assert_eq!(
+            (
+                local.name.as_bytes(),
+                local.register,
+                local.start_pc,
+                local.end_pc
+            ),
+            (&b"m"[..], RegisterIndex(0), 1, 2)
+        );
+    });
+}
+
+#[test]
+fn errors() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let error = |source: &str| assemble(mc, root.interned_strings, source).unwrap_err();
+        let at = |line, kind| AssemblerError { line, kind };
+
+        assert_eq!(
+            error(".function\nFrobnicate dest=r0\n.end"),
+            at(
+                2,
+                AssemblerErrorKind::UnknownOpCode("Frobnicate".to_owned())
+            )
+        );
+        assert_eq!(
+            error(".function\nMove dest=r0\n.end"),
+            at(2, AssemblerErrorKind::MissingOperand("source"))
+        );
+        assert_eq!(
+            error(".function\nMove dest=r0 source=r1 extra=r2\n.end"),
+            at(2, AssemblerErrorKind::UnexpectedOperand("extra".to_owned()))
+        );
+        assert_eq!(
+            error(".function\nMove dest=r256 source=r1\n.end"),
+            at(2, AssemblerErrorKind::BadOperand("dest=r256".to_owned()))
+        );
+        assert_eq!(
+            error(".function\nJump offset=nowhere close_upvalues=none\n.end"),
+            at(3, AssemblerErrorKind::UnknownLabel("nowhere".to_owned()))
+        );
+        assert_eq!(
+            error(".function\n.constant \"unterminated\n.end"),
+            at(
+                2,
+                AssemblerErrorKind::BadConstant("\"unterminated".to_owned())
+            )
+        );
+        assert_eq!(
+            error(".function\n.upvalue environment\n.upvalue outer u0 \"x\"\n.end"),
+            at(3, AssemblerErrorKind::BadArgument("outer u0".to_owned()))
+        );
+        assert_eq!(
+            error(".function\n.stack 1"),
+            at(2, AssemblerErrorKind::UnclosedFunction)
+        );
+        assert_eq!(
+            error(".function\n.end\n.function\n.end"),
+            at(3, AssemblerErrorKind::OutsideFunction)
+        );
+        assert_eq!(
+            error("; nothing"),
+            at(1, AssemblerErrorKind::MissingFunction)
+        );
+    });
+}
+
+fn run(source: &'static str) -> Result<Vec<String>, Box<StaticError>> {
+    run_with(
+        &mut Lua::new(),
+        source.as_bytes(),
+        |mc, interned_strings, source| {
+            Ok(assemble(mc, interned_strings, str::from_utf8(source).unwrap()).unwrap())
+        },
+    )
+    .map_err(Box::new)
+}
+
+#[test]
+fn run_opcodes() -> Result<(), Box<StaticError>> {
+    assert_eq!(
+        run(r#"
+            .function
+                .stack 2
+                LoadBool dest=r0 value=true skip_next=true
+                LoadBool dest=r0 value=false skip_next=false
+                LoadNil dest=r1 count=1
+                Return start=r0 count=2
+            .end
+        "#)?,
+        vec!["true", "nil"]
+    );
+
+    assert_eq!(
+        run(r#"
+            .function
+                .stack 3
+                .register sum r0
+                .register i r1
+                .constant 0
+                .constant 1
+                .constant 5
+                LoadConstant dest=sum constant=k0
+                LoadConstant dest=i constant=k0
+            loop:
+                AddRC dest=i left=i right=k1
+                AddRR dest=sum left=sum right=i
+                LessRC skip_if=false left=i right=k2
+                Jump offset=loop close_upvalues=none
+                Return start=sum count=1
+            .end
+        "#)?,
+        vec!["15"]
+    );
+
+    assert_eq!(
+        run(r#"
+            .function
+                .stack 3
+                .constant "a"
+                .constant 1
+                LoadConstant dest=r0 constant=k0
+                LoadConstant dest=r1 constant=k1
+                LoadConstant dest=r2 constant=k0
+                Concat dest=r0 source=r0 count=3
+                Return start=r0 count=1
+            .end
+        "#)?,
+        vec!["a1a"]
+    );
+
+    assert_eq!(
+        run(r#"
+            .function
+                .stack 2
+                .register counter r0
+                .constant 10
+                LoadConstant dest=counter constant=k0
+                Closure dest=r1 proto=p0
+                Call func=r1 args=0 returns=0
+                Return start=counter count=1
+                .function
+                    .stack 1
+                    .upvalue local counter
+                    .constant 1
+                    GetUpValue dest=r0 source=u0
+                    AddRC dest=r0 left=r0 right=k0
+                    SetUpValue dest=u0 source=r0
+                    Return start=r0 count=0
+                .end
+            .end
+        "#)?,
+        vec!["11"]
+    );
+
+    Ok(())
+}
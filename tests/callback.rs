@@ -1,7 +1,7 @@
 use gc_sequence::{self as sequence, SequenceExt, SequenceResultExt};
 use luster::{
-    compile, Callback, CallbackResult, Closure, Error, Function, Lua, StaticError, String,
-    ThreadSequence, Value,
+    compile, Callback, CallbackResult, Closure, Continuation, Error, Function, Lua, StaticError,
+    String, Thread, ThreadSequence, Value,
 };
 
 #[test]
@@ -9,10 +9,9 @@ fn callback() -> Result<(), Box<StaticError>> {
     let mut lua = Lua::new();
     lua.sequence(|root| {
         sequence::from_fn_with(root, |mc, root| {
-            let callback = Callback::new_immediate(mc, |args| {
-                let mut ret = args.to_vec();
-                ret.push(Value::Integer(42));
-                Ok(CallbackResult::Return(ret))
+            let callback = Callback::new_immediate(mc, |_, mut args| {
+                args.push(Value::Integer(42));
+                Ok(CallbackResult::Return(args))
             });
             root.globals
                 .set(mc, String::new_static(b"callback"), callback)?;
@@ -53,10 +52,9 @@ fn tail_call_trivial_callback() -> Result<(), Box<StaticError>> {
     let mut lua = Lua::new();
     lua.sequence(|root| {
         sequence::from_fn_with(root, |mc, root| {
-            let callback = Callback::new_immediate(mc, |args| {
-                let mut ret = args.to_vec();
-                ret.push(Value::Integer(3));
-                Ok(CallbackResult::Return(ret))
+            let callback = Callback::new_immediate(mc, |_, mut args| {
+                args.push(Value::Integer(3));
+                Ok(CallbackResult::Return(args))
             });
             root.globals
                 .set(mc, String::new_static(b"callback"), callback)?;
@@ -95,3 +93,116 @@ fn tail_call_trivial_callback() -> Result<(), Box<StaticError>> {
 
     Ok(())
 }
+
+#[test]
+fn call_context() -> Result<(), Box<StaticError>> {
+    let mut lua = Lua::new();
+    lua.sequence(|root| {
+        sequence::from_fn_with(root, |mc, root| {
+            let callback = Callback::new_immediate_with(mc, root, |root, ctx, args| {
+                Ok(CallbackResult::Return(args.returning([
+                    Value::Thread(ctx.thread),
+                    Value::Boolean(ctx.thread == root.main_thread),
+                    Value::Boolean(ctx.globals == root.globals),
+                    Value::Boolean(ctx.name == Some("callback")),
+                ])))
+            })
+            .named("callback");
+            root.globals
+                .set(mc, String::new_static(b"callback"), callback)?;
+            let thread = Thread::new(mc, root.globals, root.interned_strings, false);
+            root.globals
+                .set(mc, String::new_static(b"thread"), Value::Thread(thread))?;
+            Ok(thread)
+        })
+        .and_then_with(root, |mc, root, thread| {
+            Ok((
+                thread,
+                Closure::new(
+                    mc,
+                    compile(
+                        mc,
+                        root.interned_strings,
+                        &br#"
+                            local t, is_main, same_globals, named = callback()
+                            return t == thread and not is_main and same_globals and named
+                        "#[..],
+                    )?,
+                    Some(root.globals),
+                )?,
+            ))
+        })
+        .and_chain_with(root, |mc, _, (thread, closure)| {
+            Ok(ThreadSequence::call_function(
+                mc,
+                thread,
+                Function::Closure(closure),
+                &[],
+            )?)
+        })
+        .map_ok(|b| assert_eq!(b, vec![Value::Boolean(true)]))
+        .map_err(Error::to_static)
+        .boxed()
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn continuation_buffer() -> Result<(), Box<StaticError>> {
+    let mut lua = Lua::new();
+    lua.sequence(|root| {
+        sequence::from_fn_with(root, |mc, root| {
+            let callback = Callback::new_immediate(mc, |_, mut args| {
+                let function = match args.remove(0) {
+                    Value::Function(function) => function,
+                    _ => panic!("expected function"),
+                };
+                Ok(CallbackResult::TailCall {
+                    function,
+                    args,
+                    continuation: Continuation::new_immediate(|res| match res {
+                        Ok(mut res) => {
+                            res.insert(0, Value::Boolean(true));
+                            Ok(CallbackResult::Return(res))
+                        }
+                        Err((_, res)) => Ok(CallbackResult::Return(
+                            res.returning([Value::Boolean(false)]),
+                        )),
+                    }),
+                })
+            });
+            root.globals
+                .set(mc, String::new_static(b"callback"), callback)?;
+            Ok(())
+        })
+        .and_then_with(root, |mc, root, _| {
+            Ok(Closure::new(
+                mc,
+                compile(
+                    mc,
+                    root.interned_strings,
+                    &br#"
+                        local a, b, c = callback(function(x, y) return x + y, x * y end, 2, 3)
+                        local d, e = callback(error, "message")
+                        return a == true and b == 5 and c == 6 and d == false and e == nil
+                    "#[..],
+                )?,
+                Some(root.globals),
+            )?)
+        })
+        .and_chain_with(root, |mc, root, closure| {
+            Ok(ThreadSequence::call_function(
+                mc,
+                root.main_thread,
+                Function::Closure(closure),
+                &[],
+            )?)
+        })
+        .map_ok(|b| assert_eq!(b, vec![Value::Boolean(true)]))
+        .map_err(Error::to_static)
+        .boxed()
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,146 @@
+use luster::{
+    compile, compile_named, read_chunk, write_chunk, ChunkError, FunctionProto, Lua, OpCode,
+    RegisterIndex, VarCount, VerifierErrorKind, CHUNK_SIGNATURE, CHUNK_VERSION,
+};
+
+const SOURCE: &[u8] = br#"
+    local t = {1, 2.5, "three", true, nil}
+    local function f(a, ...)
+        local b = a
+        local function g()
+            b = b + 1
+            return b
+        end
+        for i = 1, 3 do
+            g()
+        end
+        return g(), ...
+    end
+    return f(t[1], t[3])
+"#;
+
+#[test]
+fn round_trip() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let proto = compile_named(mc, root.interned_strings, "main.lua", SOURCE).unwrap();
+
+        for &strip in &[false, true] {
+            let mut chunk = Vec::new();
+            write_chunk(&mut chunk, &proto, strip).unwrap();
+            assert!(chunk.starts_with(CHUNK_SIGNATURE));
+
+            let loaded = read_chunk(mc, root.interned_strings, &chunk[..]).unwrap();
+            assert_eq!(
+                format!("{:?}", proto.opcodes),
+                format!("{:?}", loaded.opcodes)
+            );
+
+            // The chunk name, definition lines, and local variable and upvalue names are debug info,
+            // and are stripped with it.
+            let f = &loaded.prototypes[0];
+            let names = f
+                .locals
+                .iter()
+                .map(|local| local.name.as_bytes())
+                .collect::<Vec<_>>();
+            let upvalue_names = f.prototypes[0]
+                .upvalue_names
+                .iter()
+                .map(|name| name.as_bytes())
+                .collect::<Vec<_>>();
+            let chunk_name = f.chunk_name.map(|name| name.as_bytes().to_vec());
+            let line_defined = f.line_defined.map(|line| line.to_string());
+            assert_eq!(loaded.line_defined, None);
+            if strip {
+                assert!(names.is_empty() && upvalue_names.is_empty());
+                assert_eq!(chunk_name, None);
+                assert_eq!(line_defined, None);
+            } else {
+                assert_eq!(chunk_name, Some(b"main.lua".to_vec()));
+                assert_eq!(line_defined.as_deref(), Some("3"));
+                assert_eq!(names, vec![&b"a"[..], b"b", b"g", b"i"]);
+                assert_eq!(upvalue_names, vec![&b"b"[..]]);
+            }
+
+            let mut rewritten = Vec::new();
+            write_chunk(&mut rewritten, &loaded, strip).unwrap();
+            assert_eq!(chunk, rewritten);
+        }
+    });
+}
+
+#[test]
+fn rejects_bad_chunks() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let proto = compile(mc, root.interned_strings, SOURCE).unwrap();
+        let mut chunk = Vec::new();
+        write_chunk(&mut chunk, &proto, false).unwrap();
+
+        match read_chunk(mc, root.interned_strings, SOURCE) {
+            Err(ChunkError::Signature) => {}
+            res => panic!("source text was not rejected: {:?}", res),
+        }
+
+        let mut wrong_version = chunk.clone();
+        wrong_version[CHUNK_SIGNATURE.len()] = CHUNK_VERSION + 1;
+        match read_chunk(mc, root.interned_strings, &wrong_version[..]) {
+            Err(ChunkError::Version(v)) => assert_eq!(v, CHUNK_VERSION + 1),
+            res => panic!("wrong version was not rejected: {:?}", res),
+        }
+
+        let mut trailing = chunk.clone();
+        trailing.push(0);
+        assert!(read_chunk(mc, root.interned_strings, &trailing[..]).is_err());
+
+        for len in CHUNK_SIGNATURE.len()..chunk.len() {
+            match read_chunk(mc, root.interned_strings, &chunk[..len]) {
+                Err(ChunkError::Truncated) => {}
+                res => panic!("truncated chunk was not rejected: {:?}", res),
+            }
+        }
+
+        // Corrupting any byte must never panic, and anything that still loads must verify
+        for i in 0..chunk.len() {
+            for &flip in &[0x01, 0x80, 0xff] {
+                let mut corrupted = chunk.clone();
+                corrupted[i] ^= flip;
+                let _ = read_chunk(mc, root.interned_strings, &corrupted[..]);
+            }
+        }
+    });
+}
+
+#[test]
+fn verifies_loaded_chunks() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let proto = FunctionProto {
+            chunk_name: None,
+            line_defined: None,
+            fixed_params: 0,
+            has_varargs: false,
+            stack_size: 1,
+            constants: Vec::new(),
+            opcodes: vec![OpCode::Return {
+                start: RegisterIndex(4),
+                count: VarCount::constant(1),
+            }],
+            opcode_lines: Vec::new(),
+            locals: Vec::new(),
+            upvalues: Vec::new(),
+            upvalue_names: Vec::new(),
+            prototypes: Vec::new(),
+        };
+
+        let mut chunk = Vec::new();
+        write_chunk(&mut chunk, &proto, true).unwrap();
+        match read_chunk(mc, root.interned_strings, &chunk[..]) {
+            Err(ChunkError::Invalid(err)) => {
+                assert_eq!(err.kind, VerifierErrorKind::Register(4))
+            }
+            res => panic!("invalid chunk was not rejected: {:?}", res),
+        }
+    });
+}
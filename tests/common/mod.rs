@@ -0,0 +1,71 @@
+// Helpers shared by the integration tests, not every test uses all of them.
+#![allow(dead_code)]
+
+use gc_arena::{MutationContext, StaticCollect};
+use gc_sequence::{self as sequence, SequenceExt, SequenceResultExt};
+use luster::{
+    compile, Closure, Error, Function, FunctionProto, HostError, InternedStringSet, Lua,
+    StaticError, ThreadSequence, Value,
+};
+
+// Loads a function prototype from test source.
+pub type Loader = for<'gc, 'a> fn(
+    MutationContext<'gc, 'a>,
+    InternedStringSet<'gc>,
+    &'static [u8],
+) -> Result<FunctionProto<'gc>, Error<'gc>>;
+
+// Compiles and runs the given source on the main thread, returning its results as displayed by
+// `Value::display`.
+pub fn run(lua: &mut Lua, source: &'static [u8]) -> Result<Vec<String>, StaticError> {
+    run_with(lua, source, |mc, interned_strings, source| {
+        compile(mc, interned_strings, source)
+    })
+}
+
+// Like `run`, but loads the prototype to run with the given loader.
+pub fn run_with(
+    lua: &mut Lua,
+    source: &'static [u8],
+    load: Loader,
+) -> Result<Vec<String>, StaticError> {
+    lua.sequence(|root| {
+        sequence::from_fn_with(
+            (root, source, StaticCollect(load)),
+            |mc, (root, source, load)| {
+                Ok(Closure::new(
+                    mc,
+                    (load.0)(mc, root.interned_strings, source)?,
+                    Some(root.globals),
+                )?)
+            },
+        )
+        .and_chain_with(root, |mc, root, closure| {
+            Ok(ThreadSequence::call_function(
+                mc,
+                root.main_thread,
+                Function::Closure(closure),
+                &[],
+            )?)
+        })
+        .map_ok(|res| {
+            res.iter()
+                .map(|v: &Value| {
+                    let mut buf = Vec::new();
+                    v.display(&mut buf).unwrap();
+                    String::from_utf8(buf).unwrap()
+                })
+                .collect()
+        })
+        .map_err(Error::to_static)
+        .boxed()
+    })
+}
+
+// Returns the host error a run failed with, if any.
+pub fn host_error<T>(res: Result<T, StaticError>) -> Option<HostError> {
+    match res {
+        Err(StaticError::HostError(error)) => Some(error),
+        _ => None,
+    }
+}
@@ -0,0 +1,61 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn write_script(name: &str, source: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("luster-compiler-{}-{}", std::process::id(), name));
+    fs::write(&path, source).unwrap();
+    path
+}
+
+fn compiler(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_compiler"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn listing_headers() {
+    let path = write_script(
+        "listing.lua",
+        "local x = 1\nlocal function f()\n\n    return x\nend\n",
+    );
+    let name = path.to_str().unwrap();
+
+    let output = compiler(&["-l", name]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(&format!("\nmain <{}>", name)));
+    // Functions are described by the line they are defined on, not their first instruction.
+    assert!(stdout.contains(&format!("\nfunction <{}:2>", name)));
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn check_only_continues_past_errors() {
+    let good = write_script("good.lua", "return 1\n");
+    let bad = write_script("bad.lua", "local = 1\n");
+    let missing = std::env::temp_dir().join("luster-compiler-missing.lua");
+    let (good, bad, missing) = (
+        good.to_str().unwrap(),
+        bad.to_str().unwrap(),
+        missing.to_str().unwrap(),
+    );
+
+    let output = compiler(&["-p", missing, bad, good]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let lines = stderr.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2, "{}", stderr);
+    assert!(lines[0].starts_with(&format!("{}: ", missing)));
+    assert!(lines[1].starts_with(&format!("{}: ", bad)));
+
+    assert!(compiler(&["-p", good]).status.success());
+
+    fs::remove_file(good).unwrap();
+    fs::remove_file(bad).unwrap();
+}
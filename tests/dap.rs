@@ -0,0 +1,345 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use serde_json::{json, Value as Json};
+
+const SCRIPT: &str = "\
+local config = {name = 'test', sizes = {1, 2}}
+local function area(w, h)
+    local result = w * h
+    return result
+end
+print(area(2, 3))
+print(config.name)
+";
+
+// Drives `luster-dap` with scripted requests, collecting the events it sends.
+struct Client {
+    child: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+    seq: i64,
+    events: Vec<Json>,
+}
+
+impl Client {
+    fn start() -> Client {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_luster-dap"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let input = child.stdin.take().unwrap();
+        let output = BufReader::new(child.stdout.take().unwrap());
+        Client {
+            child,
+            input,
+            output,
+            seq: 0,
+            events: Vec::new(),
+        }
+    }
+
+    // Sends a request and returns its response, which must be successful.
+    fn request(&mut self, command: &str, arguments: Json) -> Json {
+        let response = self.try_request(command, arguments);
+        assert_eq!(response["success"], true, "{}", response);
+        response["body"].clone()
+    }
+
+    fn try_request(&mut self, command: &str, arguments: Json) -> Json {
+        self.seq += 1;
+        let body = json!({
+            "seq": self.seq,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        })
+        .to_string();
+        write!(self.input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        self.input.flush().unwrap();
+
+        loop {
+            let message = self.read();
+            match message["type"].as_str() {
+                Some("event") => self.events.push(message),
+                Some("response") => {
+                    assert_eq!(message["request_seq"], self.seq);
+                    assert_eq!(message["command"], command);
+                    return message;
+                }
+                _ => panic!("unexpected message {}", message),
+            }
+        }
+    }
+
+    // Returns the next event, reading it if it has not arrived yet.
+    fn event(&mut self) -> Json {
+        if self.events.is_empty() {
+            let message = self.read();
+            assert_eq!(message["type"], "event", "{}", message);
+            message
+        } else {
+            self.events.remove(0)
+        }
+    }
+
+    fn read(&mut self) -> Json {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            self.output.read_line(&mut line).unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            length = line["Content-Length: ".len()..].parse().unwrap();
+        }
+        let mut body = vec![0; length];
+        self.output.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn finish(mut self) {
+        self.request("disconnect", json!({}));
+        assert!(self.child.wait().unwrap().success());
+    }
+}
+
+fn write_script(name: &str, source: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("luster-dap-{}-{}", std::process::id(), name));
+    fs::write(&path, source).unwrap();
+    path
+}
+
+fn names(variables: &Json) -> Vec<(&str, &str)> {
+    variables["variables"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| (v["name"].as_str().unwrap(), v["value"].as_str().unwrap()))
+        .collect()
+}
+
+#[test]
+fn debug_session() {
+    let path = write_script("session.lua", SCRIPT);
+    let program = path.to_str().unwrap();
+    let mut client = Client::start();
+
+    let capabilities = client.request("initialize", json!({ "adapterID": "luster" }));
+    assert_eq!(capabilities["supportsConfigurationDoneRequest"], true);
+    assert_eq!(client.event()["event"], "initialized");
+
+    client.request("launch", json!({ "program": program }));
+    let breakpoints = client.request(
+        "setBreakpoints",
+        json!({ "source": { "path": program }, "breakpoints": [{ "line": 3 }] }),
+    );
+    assert_eq!(breakpoints["breakpoints"][0]["verified"], true);
+    client.request("setExceptionBreakpoints", json!({ "filters": [] }));
+    client.request("configurationDone", json!({}));
+
+    let stopped = client.event();
+    assert_eq!(stopped["event"], "stopped");
+    assert_eq!(stopped["body"]["reason"], "breakpoint");
+
+    let threads = client.request("threads", json!({}));
+    assert_eq!(threads["threads"][0]["id"], 1);
+
+    let trace = client.request("stackTrace", json!({ "threadId": 1 }));
+    let frames = trace["stackFrames"].as_array().unwrap();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0]["name"], "local 'area'");
+    assert_eq!(frames[0]["line"], 3);
+    assert_eq!(frames[0]["source"]["path"], program);
+    assert_eq!(frames[1]["line"], 6);
+
+    let scopes = client.request("scopes", json!({ "frameId": frames[0]["id"] }));
+    let scopes = scopes["scopes"].as_array().unwrap();
+    assert_eq!(scopes[0]["name"], "Locals");
+    let locals = client.request(
+        "variables",
+        json!({ "variablesReference": scopes[0]["variablesReference"] }),
+    );
+    assert_eq!(names(&locals), vec![("w", "2"), ("h", "3")]);
+    let upvalues = client.request(
+        "variables",
+        json!({ "variablesReference": scopes[1]["variablesReference"] }),
+    );
+    assert!(names(&upvalues).is_empty());
+
+    // Tables can be expanded, from variables and evaluated expressions alike.
+    let caller = client.request("scopes", json!({ "frameId": frames[1]["id"] }));
+    let locals = client.request(
+        "variables",
+        json!({ "variablesReference": caller["scopes"][0]["variablesReference"] }),
+    );
+    let config = &locals["variables"][0];
+    assert_eq!(config["name"], "config");
+    assert_eq!(config["type"], "table");
+    let fields = client.request(
+        "variables",
+        json!({ "variablesReference": config["variablesReference"] }),
+    );
+    assert_eq!(names(&fields)[0], ("name", "\"test\""));
+
+    let result = client.request(
+        "evaluate",
+        json!({ "expression": "w * h, config", "frameId": frames[0]["id"] }),
+    );
+    assert_eq!(result["result"].as_str().unwrap(), "6, nil");
+    let result = client.request(
+        "evaluate",
+        json!({ "expression": "{w, h}", "frameId": frames[0]["id"] }),
+    );
+    let fields = client.request(
+        "variables",
+        json!({ "variablesReference": result["variablesReference"] }),
+    );
+    assert_eq!(names(&fields), vec![("[1]", "2"), ("[2]", "3")]);
+    let error = client.try_request(
+        "evaluate",
+        json!({ "expression": "w.x", "frameId": frames[0]["id"] }),
+    );
+    assert_eq!(error["success"], false);
+
+    client.request("next", json!({ "threadId": 1 }));
+    let stopped = client.event();
+    assert_eq!(stopped["body"]["reason"], "step");
+    let trace = client.request("stackTrace", json!({ "threadId": 1 }));
+    assert_eq!(trace["stackFrames"][0]["line"], 4);
+
+    client.request("continue", json!({ "threadId": 1 }));
+    let output = client.event();
+    assert_eq!(output["event"], "output");
+    assert_eq!(output["body"]["output"], "6\ntest\n");
+    let exited = client.event();
+    assert_eq!(exited["event"], "exited");
+    assert_eq!(exited["body"]["exitCode"], 0);
+    assert_eq!(client.event()["event"], "terminated");
+
+    client.finish();
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn stop_on_entry_and_errors() {
+    let path = write_script("errors.lua", "local t = nil\nprint(t.x)\n");
+    let program = path.to_str().unwrap();
+    let mut client = Client::start();
+
+    client.request("initialize", json!({}));
+    // Attaching starts the program just like launching it.
+    client.request("attach", json!({ "program": program, "stopOnEntry": true }));
+    client.request("setExceptionBreakpoints", json!({ "filters": ["error"] }));
+    client.request("configurationDone", json!({}));
+    assert_eq!(client.event()["event"], "initialized");
+
+    let stopped = client.event();
+    assert_eq!(stopped["body"]["reason"], "entry");
+    let trace = client.request("stackTrace", json!({ "threadId": 1 }));
+    assert_eq!(trace["stackFrames"][0]["line"], 1);
+
+    client.request("continue", json!({ "threadId": 1 }));
+    let stopped = client.event();
+    assert_eq!(stopped["body"]["reason"], "exception");
+    assert!(stopped["body"]["text"]
+        .as_str()
+        .unwrap()
+        .contains("local 't'"));
+
+    client.request("continue", json!({ "threadId": 1 }));
+    let output = client.event();
+    assert_eq!(output["body"]["category"], "stderr");
+    let exited = client.event();
+    assert_eq!(exited["body"]["exitCode"], 1);
+    assert_eq!(client.event()["event"], "terminated");
+
+    // Requests which need a paused script fail once it has finished.
+    let response = client.try_request("next", json!({ "threadId": 1 }));
+    assert_eq!(response["success"], false);
+
+    client.finish();
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn launch_errors() {
+    let path = write_script("syntax.lua", "local = 1\n");
+    let mut client = Client::start();
+    client.request("initialize", json!({}));
+
+    let response = client.try_request("launch", json!({ "program": path }));
+    assert_eq!(response["success"], false);
+    let response = client.try_request("launch", json!({ "program": "/nonexistent.lua" }));
+    assert_eq!(response["success"], false);
+    let response = client.try_request("pause", json!({ "threadId": 1 }));
+    assert_eq!(response["success"], false);
+    let response = client.try_request("attach", json!({ "program": path }));
+    assert_eq!(response["success"], false);
+    let response = client.try_request("attach", json!({}));
+    assert_eq!(response["success"], false);
+
+    client.finish();
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn pause_and_terminate() {
+    let path = write_script(
+        "loop.lua",
+        "local n = 0\nwhile true do\n    n = n + 1\nend\n",
+    );
+    let program = path.to_str().unwrap();
+    let mut client = Client::start();
+
+    client.request("initialize", json!({}));
+    client.request("launch", json!({ "program": program }));
+    client.request("configurationDone", json!({}));
+    assert_eq!(client.event()["event"], "initialized");
+
+    // Requests are still handled while the script is running.
+    client.request("pause", json!({ "threadId": 1 }));
+    let stopped = client.event();
+    assert_eq!(stopped["event"], "stopped");
+    assert_eq!(stopped["body"]["reason"], "pause");
+    let trace = client.request("stackTrace", json!({ "threadId": 1 }));
+    let line = trace["stackFrames"][0]["line"].as_i64().unwrap();
+    assert!((1..=3).contains(&line), "{}", line);
+
+    client.request("continue", json!({ "threadId": 1 }));
+    client.request("terminate", json!({}));
+    let exited = client.event();
+    assert_eq!(exited["event"], "exited");
+    assert_eq!(client.event()["event"], "terminated");
+
+    client.finish();
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn terminate_while_paused() {
+    let path = write_script(
+        "paused.lua",
+        "local n = 0\nwhile true do\n    n = n + 1\nend\n",
+    );
+    let program = path.to_str().unwrap();
+    let mut client = Client::start();
+
+    client.request("initialize", json!({}));
+    // Attaching starts the program just like launching it.
+    client.request("attach", json!({ "program": program, "stopOnEntry": true }));
+    client.request("configurationDone", json!({}));
+    assert_eq!(client.event()["event"], "initialized");
+    assert_eq!(client.event()["body"]["reason"], "entry");
+
+    client.request("terminate", json!({}));
+    assert_eq!(client.event()["event"], "exited");
+    assert_eq!(client.event()["event"], "terminated");
+
+    client.finish();
+    fs::remove_file(path).unwrap();
+}
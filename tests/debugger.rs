@@ -0,0 +1,200 @@
+use luster::{DebugEvent, Debugger, Lua, PauseReason, Resume, Variable};
+
+const SOURCE: &[u8] = br#"
+local scale = 10
+local function add(a, b)
+    local sum = a + b
+    return sum * scale
+end
+local x = add(1, 2)
+local co = coroutine.wrap(function(n)
+    local doubled = n * 2
+    coroutine.yield(doubled)
+end)
+co(x)
+counter = x
+return x
+"#;
+
+fn paused(event: DebugEvent) -> PauseReason {
+    match event {
+        DebugEvent::Paused(reason) => reason,
+        DebugEvent::Finished(result) => panic!("finished instead of pausing: {:?}", result),
+    }
+}
+
+fn values(variables: &[Variable]) -> Vec<(&str, &str)> {
+    variables
+        .iter()
+        .map(|v| (v.name.as_str(), v.value.as_str()))
+        .collect()
+}
+
+#[test]
+fn breakpoints() {
+    let mut lua = Lua::new();
+    let debugger = Debugger::new(&mut lua);
+    debugger.load(&mut lua, "scripts/main.lua", SOURCE).unwrap();
+    let inner = debugger.add_breakpoint(Some("main.lua"), 4);
+    debugger.add_breakpoint(Some("other.lua"), 7);
+    let coroutine = debugger.add_breakpoint(None, 10);
+
+    assert_eq!(
+        paused(debugger.resume(&mut lua, Resume::Continue)),
+        PauseReason::Breakpoint(inner)
+    );
+    let frames = debugger.frames(&mut lua);
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].to_string(), "scripts/main.lua:4 in local 'add'");
+    assert_eq!(frames[1].line, Some(7));
+    assert_eq!(
+        values(&debugger.locals(&mut lua, 0).unwrap()),
+        vec![("a", "1"), ("b", "2")]
+    );
+    assert_eq!(
+        values(&debugger.upvalues(&mut lua, 0).unwrap()),
+        vec![("scale", "10")]
+    );
+    assert_eq!(
+        values(
+            &debugger
+                .evaluate(&mut lua, 0, "(a + b) * scale, 'x'")
+                .unwrap()
+        ),
+        vec![("1", "30"), ("2", "\"x\"")]
+    );
+    assert!(debugger.evaluate(&mut lua, 0, "nil + 1").is_err());
+
+    // Tables are given handles to expand their fields while paused.
+    let table = debugger
+        .evaluate(&mut lua, 0, "{b, a, x = {y = 'z'}, ['a b'] = true}")
+        .unwrap()[0]
+        .table
+        .unwrap();
+    let fields = debugger.fields(&mut lua, table).unwrap();
+    assert_eq!(
+        values(&fields)[..3],
+        [("[1]", "2"), ("[2]", "1"), ("[\"a b\"]", "true")]
+    );
+    assert_eq!(fields[3].name, "x");
+    let nested = debugger.fields(&mut lua, fields[3].table.unwrap()).unwrap();
+    assert_eq!(values(&nested), vec![("y", "\"z\"")]);
+    assert!(debugger.locals(&mut lua, 5).is_none());
+
+    // Breakpoints in coroutines pause too.
+    assert_eq!(
+        paused(debugger.resume(&mut lua, Resume::Continue)),
+        PauseReason::Breakpoint(coroutine)
+    );
+    assert_eq!(
+        values(&debugger.locals(&mut lua, 0).unwrap()),
+        vec![("n", "30"), ("doubled", "60")]
+    );
+
+    assert!(debugger.remove_breakpoint(inner));
+    assert!(!debugger.remove_breakpoint(inner));
+    debugger.resume(&mut lua, Resume::StepOver);
+    assert_eq!(debugger.fields(&mut lua, table), None);
+    match debugger.resume(&mut lua, Resume::Continue) {
+        DebugEvent::Finished(result) => result.unwrap(),
+        event => panic!("unexpected event {:?}", event),
+    }
+    let globals = debugger.globals(&mut lua);
+    assert!(values(&globals).contains(&("counter", "30")));
+    assert!(globals
+        .iter()
+        .any(|v| v.name == "print" && v.type_name == "function"));
+}
+
+#[test]
+fn stepping() {
+    let mut lua = Lua::new();
+    let debugger = Debugger::new(&mut lua);
+    debugger.load(&mut lua, "main.lua", SOURCE).unwrap();
+
+    let line = |debugger: &Debugger, lua: &mut Lua, resume| {
+        assert_eq!(paused(debugger.resume(lua, resume)), PauseReason::Step);
+        debugger.frames(lua)[0].line.unwrap()
+    };
+    assert_eq!(line(&debugger, &mut lua, Resume::StepInto), 2);
+    assert_eq!(line(&debugger, &mut lua, Resume::StepOver), 3);
+    assert_eq!(line(&debugger, &mut lua, Resume::StepOver), 7);
+    assert_eq!(line(&debugger, &mut lua, Resume::StepInto), 4);
+    assert_eq!(line(&debugger, &mut lua, Resume::StepOut), 8);
+    assert_eq!(line(&debugger, &mut lua, Resume::StepOver), 12);
+    assert_eq!(line(&debugger, &mut lua, Resume::StepInto), 9);
+    assert_eq!(line(&debugger, &mut lua, Resume::StepOver), 10);
+    // Stepping over a yield continues in the resuming thread.
+    assert_eq!(line(&debugger, &mut lua, Resume::StepOver), 13);
+}
+
+#[test]
+fn pause_on_error() {
+    let mut lua = Lua::new();
+    let debugger = Debugger::new(&mut lua);
+    debugger.set_pause_on_error(true);
+    debugger
+        .load(
+            &mut lua,
+            "main.lua",
+            b"
+            local function fail(t)
+                return t.field.missing
+            end
+            print(pcall(error, 'caught'))
+            fail({})
+            ",
+        )
+        .unwrap();
+
+    assert_eq!(
+        paused(debugger.resume(&mut lua, Resume::Continue)),
+        PauseReason::Error("caught".to_owned())
+    );
+    match paused(debugger.resume(&mut lua, Resume::Continue)) {
+        PauseReason::Error(message) => assert!(message.contains("field 'field'")),
+        reason => panic!("unexpected pause {:?}", reason),
+    }
+    // The failing frame has not been unwound.
+    assert_eq!(debugger.frames(&mut lua)[0].line, Some(3));
+    assert_eq!(values(&debugger.locals(&mut lua, 0).unwrap())[0].0, "t");
+    match debugger.resume(&mut lua, Resume::Continue) {
+        DebugEvent::Finished(Err(err)) => assert!(err.to_string().contains("field 'field'")),
+        event => panic!("unexpected event {:?}", event),
+    }
+}
+
+#[test]
+fn pause_from_another_thread() {
+    let mut lua = Lua::new();
+    let debugger = Debugger::new(&mut lua);
+    debugger
+        .load(
+            &mut lua,
+            "loop.lua",
+            b"local n = 0\nwhile true do\n    n = n + 1\nend\n",
+        )
+        .unwrap();
+
+    let pause = debugger.pause_handle();
+    let pauser = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        pause.pause();
+    });
+    assert_eq!(
+        paused(debugger.resume(&mut lua, Resume::Continue)),
+        PauseReason::Requested
+    );
+    pauser.join().unwrap();
+    assert!(!debugger.pause_handle().is_pause_requested());
+    assert!(debugger.frames(&mut lua)[0].line.unwrap() >= 2);
+
+    // A pause withdrawn before the script runs has no effect.
+    debugger.pause_handle().pause();
+    debugger.pause_handle().cancel();
+    debugger.add_breakpoint(None, 3);
+    assert!(matches!(
+        paused(debugger.resume(&mut lua, Resume::Continue)),
+        PauseReason::Breakpoint(_)
+    ));
+}
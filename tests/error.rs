@@ -1,5 +1,12 @@
+mod common;
+
 use gc_sequence::{self as sequence, SequenceExt, SequenceResultExt};
-use luster::{compile, Closure, Error, Function, Lua, StaticError, ThreadSequence};
+use luster::{
+    compile, Closure, Error, Function, HostError, Lua, Operation, OperationError, StaticError,
+    ThreadSequence, VariableInfo,
+};
+
+use common::run;
 
 #[test]
 fn error_unwind() -> Result<(), Box<StaticError>> {
@@ -52,3 +59,134 @@ fn error_unwind() -> Result<(), Box<StaticError>> {
 
     Ok(())
 }
+
+#[test]
+fn host_errors_are_uncatchable() {
+    let mut lua = Lua::new();
+
+    match run(
+        &mut lua,
+        br#"
+            co = coroutine.create(function()
+                pcall(function() os.exit(7) end)
+                coroutine.yield("caught")
+            end)
+            local ok, v = coroutine.resume(co)
+            return "resumed", ok, v
+        "#,
+    ) {
+        Err(StaticError::HostError(HostError::Exit(7))) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+    assert_eq!(
+        run(&mut lua, b"return coroutine.status(co)").unwrap(),
+        vec!["dead"]
+    );
+
+    match run(
+        &mut lua,
+        br#"
+            local f = coroutine.wrap(function() os.exit(false) end)
+            return pcall(pcall, f)
+        "#,
+    ) {
+        Err(StaticError::HostError(HostError::Exit(1))) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+
+    // Ordinary errors are still caught.
+    assert_eq!(
+        run(
+            &mut lua,
+            br#"
+                local ok = pcall(error, "message")
+                local co = coroutine.create(function() error("message") end)
+                return ok, coroutine.resume(co)
+            "#,
+        )
+        .unwrap(),
+        vec!["false", "false", "message"]
+    );
+}
+
+#[test]
+fn operation_errors() {
+    let mut lua = Lua::new();
+
+    match run(
+        &mut lua,
+        br#"
+            local config = {}
+            return config.window.width
+        "#,
+    ) {
+        Err(StaticError::OperationError(OperationError::BadOperand {
+            operation: Operation::Index,
+            found: "nil",
+            variable:
+                Some(VariableInfo {
+                    kind: "field",
+                    name,
+                }),
+        })) if name == "window" => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+
+    match run(&mut lua, b"return 1 < {}") {
+        Err(StaticError::OperationError(OperationError::Compare {
+            left: "number",
+            right: "table",
+        })) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+
+    assert_eq!(
+        run(
+            &mut lua,
+            br#"
+                local function message(f)
+                    local ok, err = pcall(f)
+                    return err
+                end
+                local t = {}
+                local up
+                return
+                    message(function() return t.x + 1 end),
+                    message(function() local a = {}; a() end),
+                    message(function() undefined() end),
+                    message(function() t:method() end),
+                    message(function() return up.field end),
+                    message(function() return #up end),
+                    message(function() return "a" .. t end),
+                    message(function() local s = "str"; return s | 1 end),
+                    message(function() return 1.5 & 1 end),
+                    message(function() return 1 // 0 end),
+                    message(function() return 1 % 0 end),
+                    message(function() local a = 1; return a // nil end),
+                    message(function() local a, b = 1, {}; return a % b end),
+                    message(function() return nil <= nil end),
+                    message(function() for i = 1, {} do end end),
+                    message(function() return up() end)
+            "#,
+        )
+        .unwrap(),
+        vec![
+            "attempt to perform arithmetic on a nil value (field 'x')",
+            "attempt to call a table value (local 'a')",
+            "attempt to call a nil value (global 'undefined')",
+            "attempt to call a nil value (method 'method')",
+            "attempt to index a nil value (upvalue 'up')",
+            "attempt to get length of a nil value (upvalue 'up')",
+            "attempt to concatenate a table value (upvalue 't')",
+            "attempt to perform bitwise operation on a string value (local 's')",
+            "number has no integer representation",
+            "attempt to perform 'n//0'",
+            "attempt to perform 'n%0'",
+            "attempt to perform arithmetic on a nil value",
+            "attempt to perform arithmetic on a table value (local 'b')",
+            "attempt to compare two nil values",
+            "'for' limit must be a number",
+            "attempt to call a nil value (upvalue 'up')",
+        ]
+    );
+}
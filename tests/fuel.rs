@@ -0,0 +1,109 @@
+mod common;
+
+use luster::{Fuel, HostError, Lua};
+
+use common::{host_error, run};
+
+#[test]
+fn runs_within_budget() {
+    let mut lua = Lua::new();
+    let fuel = Fuel::new(10_000);
+    lua.set_fuel(Some(fuel.clone()));
+
+    assert_eq!(
+        run(
+            &mut lua,
+            b"local i = 0 while i < 100 do i = i + 1 end return i"
+        )
+        .unwrap(),
+        vec!["100"]
+    );
+    let remaining = fuel.remaining();
+    assert!(remaining < 10_000 - 200 && remaining > 10_000 - 1000);
+}
+
+#[test]
+fn infinite_loop() {
+    let mut lua = Lua::new();
+    let fuel = Fuel::new(100_000);
+    lua.set_fuel(Some(fuel.clone()));
+
+    assert_eq!(
+        host_error(run(&mut lua, b"while true do end")),
+        Some(HostError::OutOfFuel)
+    );
+    assert!(fuel.is_exhausted());
+
+    // Exhausted fuel stays exhausted until the host refuels, after which the main thread may be
+    // used again.
+    assert_eq!(
+        host_error(run(&mut lua, b"return 1")),
+        Some(HostError::OutOfFuel)
+    );
+    fuel.refuel(1000);
+    assert_eq!(run(&mut lua, b"return 1").unwrap(), vec!["1"]);
+}
+
+#[test]
+fn uncatchable() {
+    let mut lua = Lua::new();
+    lua.set_fuel(Some(Fuel::new(100_000)));
+    assert_eq!(
+        host_error(run(
+            &mut lua,
+            br#"
+            while true do
+                pcall(function() while true do end end)
+            end
+        "#
+        )),
+        Some(HostError::OutOfFuel)
+    );
+
+    let mut lua = Lua::new();
+    lua.set_fuel(Some(Fuel::new(100_000)));
+    assert_eq!(
+        host_error(run(
+            &mut lua,
+            br#"
+            while true do
+                local co = coroutine.create(function() while true do end end)
+                coroutine.resume(co)
+            end
+        "#
+        )),
+        Some(HostError::OutOfFuel)
+    );
+}
+
+#[test]
+fn callback_cost() {
+    let mut lua = Lua::new();
+    let fuel = Fuel::new(100_000);
+    fuel.set_callback_cost(1000);
+    lua.set_fuel(Some(fuel.clone()));
+
+    assert_eq!(
+        run(&mut lua, b"for i = 1, 10 do type(i) end return true").unwrap(),
+        vec!["true"]
+    );
+    assert!(fuel.remaining() <= 100_000 - 10_000);
+
+    assert_eq!(
+        host_error(run(&mut lua, b"for i = 1, 1000 do type(i) end return true")),
+        Some(HostError::OutOfFuel)
+    );
+}
+
+#[test]
+fn unlimited() {
+    let mut lua = Lua::new();
+    let fuel = Fuel::new(0);
+    lua.set_fuel(Some(fuel));
+    assert_eq!(
+        host_error(run(&mut lua, b"return 1")),
+        Some(HostError::OutOfFuel)
+    );
+    lua.set_fuel(None);
+    assert_eq!(run(&mut lua, b"return 1").unwrap(), vec!["1"]);
+}
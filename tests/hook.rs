@@ -0,0 +1,148 @@
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use luster::{Callback, CallbackResult, Error, Function, Hook, HookMask, Lua, StaticError, Value};
+
+use common::run;
+
+#[test]
+fn callback_hook() {
+    let events = Rc::new(RefCell::new(Vec::new()));
+
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let events = events.clone();
+        let hook = Callback::new_immediate(mc, move |ctx, args| {
+            let event = match args.first() {
+                Some(Value::String(event)) => String::from_utf8(event.as_bytes().to_vec()).unwrap(),
+                _ => panic!("hook called without event"),
+            };
+            // The hooked frame may be inspected while the hook runs.
+            let info = ctx.thread.frame_info(0).unwrap();
+            assert!(matches!(info.function, Some(Function::Closure(_))));
+            events
+                .borrow_mut()
+                .push((event, info.current_line.unwrap().to_string()));
+            Ok(CallbackResult::Return(args.returning(None)))
+        });
+        root.main_thread.set_hook(
+            mc,
+            Some(Hook {
+                function: Function::Callback(hook),
+                mask: HookMask {
+                    call: true,
+                    ret: true,
+                    line: true,
+                    count: 0,
+                    error: false,
+                },
+            }),
+        );
+    });
+
+    assert_eq!(
+        run(
+            &mut lua,
+            br#"
+                function f()
+                    return 1
+                end
+                return f()
+            "#,
+        )
+        .unwrap(),
+        vec!["1"]
+    );
+
+    let events = events.borrow();
+    let events = events
+        .iter()
+        .map(|(event, line)| format!("{} {}", event, line))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        events,
+        vec![
+            "call 2",
+            "line 2",
+            "line 5",
+            "tail call 3",
+            "line 3",
+            "return 3"
+        ]
+    );
+}
+
+#[test]
+fn abort_from_hook() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let hook = Callback::new_immediate(mc, |_, _| {
+            Err(Error::RuntimeError(luster::RuntimeError(Value::Integer(
+                42,
+            ))))
+        });
+        root.main_thread.set_hook(
+            mc,
+            Some(Hook {
+                function: Function::Callback(hook),
+                mask: HookMask {
+                    count: 1000,
+                    ..HookMask::default()
+                },
+            }),
+        );
+    });
+
+    assert!(matches!(
+        run(&mut lua, b"while true do end"),
+        Err(StaticError::RuntimeError(err)) if err == "42"
+    ));
+
+    lua.mutate(|mc, root| root.main_thread.set_hook(mc, None));
+    assert_eq!(run(&mut lua, b"return 1").unwrap(), vec!["1"]);
+}
+
+#[test]
+fn error_hook() {
+    let errors = Rc::new(RefCell::new(Vec::new()));
+
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let errors = errors.clone();
+        let hook = Callback::new_immediate(mc, move |_, args| {
+            let mut error = Vec::new();
+            args[1].display(&mut error).unwrap();
+            errors.borrow_mut().push(String::from_utf8(error).unwrap());
+            Ok(CallbackResult::Return(args.returning(None)))
+        });
+        root.main_thread.set_hook(
+            mc,
+            Some(Hook {
+                function: Function::Callback(hook),
+                mask: HookMask {
+                    error: true,
+                    ..HookMask::default()
+                },
+            }),
+        );
+    });
+
+    // The hook sees errors before they are caught, and they continue to unwind afterwards.
+    assert!(matches!(
+        run(
+            &mut lua,
+            br#"
+                assert(not pcall(error, "caught"))
+                local t = nil
+                return t.x
+            "#,
+        ),
+        Err(StaticError::OperationError(_))
+    ));
+    assert_eq!(
+        *errors.borrow(),
+        vec!["caught", "attempt to index a nil value (local 't')"]
+    );
+}
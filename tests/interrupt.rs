@@ -0,0 +1,83 @@
+mod common;
+
+use std::thread;
+use std::time::Duration;
+
+use luster::{HostError, InterruptHandle, Lua, ThreadMode};
+
+use common::{host_error, run};
+
+fn interrupt_later(handle: InterruptHandle) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        handle.interrupt();
+    })
+}
+
+#[test]
+fn handle_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<InterruptHandle>();
+}
+
+#[test]
+fn interrupt_loop() {
+    let mut lua = Lua::new();
+    let watchdog = interrupt_later(lua.interrupt_handle());
+    assert_eq!(
+        host_error(run(&mut lua, b"while true do end")),
+        Some(HostError::Interrupted)
+    );
+    watchdog.join().unwrap();
+
+    assert!(!lua.interrupt_handle().is_interrupted());
+    assert_eq!(
+        lua.mutate(|_, root| root.main_thread.mode()),
+        ThreadMode::Stopped
+    );
+    assert_eq!(run(&mut lua, b"return 1").unwrap(), vec!["1"]);
+}
+
+#[test]
+fn uncatchable() {
+    let mut lua = Lua::new();
+    let watchdog = interrupt_later(lua.interrupt_handle());
+    assert_eq!(
+        host_error(run(
+            &mut lua,
+            br#"
+            while true do
+                pcall(function() while true do end end)
+            end
+        "#
+        )),
+        Some(HostError::Interrupted)
+    );
+    watchdog.join().unwrap();
+
+    let watchdog = interrupt_later(lua.interrupt_handle());
+    assert_eq!(
+        host_error(run(
+            &mut lua,
+            br#"
+            local co = coroutine.wrap(function() while true do end end)
+            while true do
+                pcall(co)
+            end
+        "#
+        )),
+        Some(HostError::Interrupted)
+    );
+    watchdog.join().unwrap();
+}
+
+#[test]
+fn interrupt_before_run() {
+    let mut lua = Lua::new();
+    lua.interrupt_handle().interrupt();
+    assert_eq!(
+        host_error(run(&mut lua, b"return 1")),
+        Some(HostError::Interrupted)
+    );
+    assert_eq!(run(&mut lua, b"return 1").unwrap(), vec!["1"]);
+}
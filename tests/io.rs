@@ -1,6 +1,15 @@
-use std::io::{BufReader, Read};
+mod common;
 
-use luster::io::skip_prefix;
+use std::cell::RefCell;
+use std::io::{BufReader, Cursor, Read};
+use std::rc::Rc;
+
+use luster::{
+    io::{skip_prefix, CallbackWriter, OutputBuffer},
+    Lua, StaticError, String,
+};
+
+use common::run;
 
 #[test]
 fn test_skip_prefix() {
@@ -15,3 +24,100 @@ fn test_skip_prefix() {
     reader.read_to_end(&mut v).unwrap();
     assert_eq!(v, vec![b'\n', 0x1, 0x2, 0x3]);
 }
+
+#[test]
+fn redirect_std_streams() -> Result<(), Box<StaticError>> {
+    let stdout = OutputBuffer::new();
+    let stderr = OutputBuffer::new();
+
+    let mut lua = Lua::new();
+    let std_streams = lua.std_streams();
+    std_streams.set_stdin(Cursor::new(b"first line\n42 rest".to_vec()));
+    std_streams.set_stdout(stdout.clone());
+    std_streams.set_stderr(stderr.clone());
+
+    assert_eq!(
+        run(
+            &mut lua,
+            br#"
+            local line = io.read()
+            local n, rest = io.read("n", "a")
+            print("line:", line)
+            io.write(n + 1, rest, "\n")
+            io.stderr:write("error output")
+            return line == "first line" and n == 42 and io.read() == nil
+        "#,
+        )?,
+        vec!["true"]
+    );
+
+    assert_eq!(stdout.take(), b"line:\tfirst line\n43 rest\n");
+    assert_eq!(stdout.contents(), b"");
+    assert_eq!(stderr.contents(), b"error output");
+
+    Ok(())
+}
+
+#[test]
+fn print_to_callback() -> Result<(), Box<StaticError>> {
+    let lines = Rc::new(RefCell::new(Vec::new()));
+
+    let mut lua = Lua::new();
+    lua.std_streams().set_stdout(CallbackWriter::new({
+        let lines = lines.clone();
+        move |line: &[u8]| lines.borrow_mut().push(line.to_vec())
+    }));
+
+    assert_eq!(
+        run(
+            &mut lua,
+            br#"
+            print("one", 2, nil)
+            print()
+            return true
+        "#,
+        )?,
+        vec!["true"]
+    );
+
+    assert_eq!(
+        *lines.borrow(),
+        vec![b"one\t2\tnil\n".to_vec(), b"\n".to_vec()]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn files_closed_on_collection() -> Result<(), Box<StaticError>> {
+    let path = std::env::temp_dir().join(format!("luster-io-{}", std::process::id()));
+
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        root.globals
+            .set(
+                mc,
+                String::new_static(b"path"),
+                String::new(mc, path.to_str().unwrap().as_bytes()),
+            )
+            .unwrap();
+    });
+    assert_eq!(
+        run(
+            &mut lua,
+            br#"
+            local f = io.open(path, "w")
+            f:setvbuf("full", 1024)
+            f:write("never closed")
+            return true
+        "#,
+        )?,
+        vec!["true"]
+    );
+    // Buffered writes are flushed when the handle is dropped along with the arena.
+    drop(lua);
+
+    assert_eq!(std::fs::read(&path).unwrap(), b"never closed");
+    std::fs::remove_file(&path).unwrap();
+    Ok(())
+}
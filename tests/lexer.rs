@@ -210,3 +210,29 @@ fn ops() {
         ],
     );
 }
+
+#[test]
+fn columns() {
+    let source = "local x = 'a'\r\n  --[[ long\ncomment ]] x\tend";
+    let mut lexer = Lexer::new(source.as_bytes(), |s| s.to_vec().into_boxed_slice());
+    let mut positions = Vec::new();
+    loop {
+        lexer.skip_whitespace().unwrap();
+        let start = (lexer.line_number(), lexer.column_number());
+        if lexer.read_token().unwrap().is_none() {
+            break;
+        }
+        positions.push((start, lexer.column_number()));
+    }
+    assert_eq!(
+        positions,
+        vec![
+            ((0, 0), 5),
+            ((0, 6), 7),
+            ((0, 8), 9),
+            ((0, 10), 13),
+            ((2, 11), 12),
+            ((2, 13), 16),
+        ]
+    );
+}
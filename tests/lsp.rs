@@ -0,0 +1,295 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use serde_json::{json, Value as Json};
+
+const URI: &str = "file:///project/main.lua";
+
+const SOURCE: &str = "\
+local count = 0
+local function add(a, b)
+    count = count + 1
+    return a + b
+end
+function M.twice(x)
+    local y = add(x, x)
+    return y
+end
+for i = 1, 2 do print(i, count) end
+";
+
+// Drives `luster-lsp` with scripted requests, collecting the notifications it sends.
+struct Client {
+    child: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+    id: i64,
+    notifications: Vec<Json>,
+}
+
+impl Client {
+    fn start() -> Client {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_luster-lsp"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let input = child.stdin.take().unwrap();
+        let output = BufReader::new(child.stdout.take().unwrap());
+        let mut client = Client {
+            child,
+            input,
+            output,
+            id: 0,
+            notifications: Vec::new(),
+        };
+        let result = client.request("initialize", json!({ "capabilities": {} }));
+        assert_eq!(result["capabilities"]["definitionProvider"], true);
+        client.notify("initialized", json!({}));
+        client
+    }
+
+    // Sends a request and returns its result, which must be successful.
+    fn request(&mut self, method: &str, params: Json) -> Json {
+        let response = self.try_request(method, params);
+        assert!(response.get("error").is_none(), "{}", response);
+        response["result"].clone()
+    }
+
+    fn try_request(&mut self, method: &str, params: Json) -> Json {
+        self.id += 1;
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "id": self.id,
+            "method": method,
+            "params": params,
+        }));
+        loop {
+            let message = self.read();
+            if message.get("id").is_some() {
+                assert_eq!(message["id"], self.id);
+                return message;
+            }
+            self.notifications.push(message);
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Json) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    // Returns the diagnostics published for the next version of the document.
+    fn diagnostics(&mut self) -> Vec<Json> {
+        let notification = if self.notifications.is_empty() {
+            self.read()
+        } else {
+            self.notifications.remove(0)
+        };
+        assert_eq!(notification["method"], "textDocument/publishDiagnostics");
+        assert_eq!(notification["params"]["uri"], URI);
+        notification["params"]["diagnostics"]
+            .as_array()
+            .unwrap()
+            .clone()
+    }
+
+    fn change(&mut self, text: &str) -> Vec<Json> {
+        self.notify(
+            "textDocument/didChange",
+            json!({
+                "textDocument": { "uri": URI, "version": 2 },
+                "contentChanges": [{ "text": text }],
+            }),
+        );
+        self.diagnostics()
+    }
+
+    fn at(&mut self, method: &str, line: u64, character: u64) -> Json {
+        self.request(
+            method,
+            json!({
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character },
+            }),
+        )
+    }
+
+    fn send(&mut self, message: Json) {
+        let body = message.to_string();
+        write!(self.input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        self.input.flush().unwrap();
+    }
+
+    fn read(&mut self) -> Json {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            self.output.read_line(&mut line).unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            length = line["Content-Length: ".len()..].parse().unwrap();
+        }
+        let mut body = vec![0; length];
+        self.output.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn finish(mut self) {
+        assert_eq!(self.request("shutdown", Json::Null), Json::Null);
+        self.notify("exit", Json::Null);
+        assert!(self.child.wait().unwrap().success());
+    }
+}
+
+fn range(line: u64, start: u64, end: u64) -> Json {
+    json!({
+        "start": { "line": line, "character": start },
+        "end": { "line": line, "character": end },
+    })
+}
+
+#[test]
+fn navigation() {
+    let mut client = Client::start();
+    client.notify(
+        "textDocument/didOpen",
+        json!({
+            "textDocument": { "uri": URI, "languageId": "lua", "version": 1, "text": SOURCE },
+        }),
+    );
+    assert!(client.diagnostics().is_empty());
+
+    let symbols = client.request(
+        "textDocument/documentSymbol",
+        json!({ "textDocument": { "uri": URI } }),
+    );
+    let symbols = symbols
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|symbol| {
+            (
+                symbol["name"].as_str().unwrap(),
+                symbol["kind"].as_i64().unwrap(),
+                symbol["containerName"].as_str(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        symbols,
+        vec![
+            ("count", 13, None),
+            ("add", 12, None),
+            ("M.twice", 12, None),
+            ("y", 13, Some("M.twice")),
+        ]
+    );
+
+    // A call to a local function, and the parameters passed to it.
+    let definition = client.at("textDocument/definition", 6, 16);
+    assert_eq!(definition["uri"], URI);
+    assert_eq!(definition["range"], range(1, 15, 18));
+    assert_eq!(
+        client.at("textDocument/definition", 6, 19)["range"],
+        range(5, 17, 18)
+    );
+    // Upvalues resolve to the enclosing function's locals.
+    assert_eq!(
+        client.at("textDocument/definition", 2, 13)["range"],
+        range(0, 6, 11)
+    );
+    // Loop variables are only in scope inside the loop.
+    assert_eq!(
+        client.at("textDocument/definition", 9, 22)["range"],
+        range(9, 4, 5)
+    );
+    // Globals have no definition.
+    assert_eq!(client.at("textDocument/definition", 9, 16), Json::Null);
+    assert_eq!(client.at("textDocument/definition", 5, 9), Json::Null);
+
+    let hover = client.at("textDocument/hover", 2, 13);
+    assert_eq!(
+        hover["contents"]["value"],
+        "```lua\nlocal count = 0\n```\nlocal `count` declared on line 1, used here as an upvalue"
+    );
+    assert_eq!(hover["range"], range(2, 12, 17));
+    let hover = client.at("textDocument/hover", 3, 11);
+    assert_eq!(
+        hover["contents"]["value"],
+        "```lua\nlocal function add(a, b)\n```\nparameter `a` declared on line 2"
+    );
+    assert_eq!(client.at("textDocument/hover", 4, 1), Json::Null);
+
+    client.finish();
+}
+
+#[test]
+fn diagnostics() {
+    let mut client = Client::start();
+    client.notify(
+        "textDocument/didOpen",
+        json!({
+            "textDocument": { "uri": URI, "languageId": "lua", "version": 1, "text": "" },
+        }),
+    );
+    assert!(client.diagnostics().is_empty());
+
+    let diagnostics = client.change("local a = 1\n  local = 2\n");
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["range"], range(1, 2, 11));
+    assert_eq!(diagnostics[0]["severity"], 1);
+    assert!(diagnostics[0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("expected name"));
+
+    let diagnostics = client.change("local s = \"é\" .. 'open\nprint(s)");
+    assert_eq!(diagnostics[0]["range"], range(0, 17, 22));
+    assert!(diagnostics[0]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("short string not finished"));
+
+    let diagnostics = client.change("goto nowhere\n");
+    assert_eq!(diagnostics[0]["range"], range(0, 0, 12));
+    assert!(diagnostics[0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("goto target label not found"));
+
+    // Navigation is unavailable until the document parses again.
+    client.change("local x = 1 +");
+    assert_eq!(client.at("textDocument/definition", 0, 6), Json::Null);
+    assert!(client.change("local x = 1\nreturn x").is_empty());
+    assert_eq!(
+        client.at("textDocument/definition", 1, 7)["range"],
+        range(0, 6, 7)
+    );
+
+    client.notify(
+        "textDocument/didClose",
+        json!({ "textDocument": { "uri": URI } }),
+    );
+    assert!(client.diagnostics().is_empty());
+    let response = client.try_request(
+        "textDocument/hover",
+        json!({
+            "textDocument": { "uri": URI },
+            "position": { "line": 0, "character": 0 },
+        }),
+    );
+    assert_eq!(response["error"]["code"], -32602);
+    let response = client.try_request("textDocument/completion", json!({}));
+    assert_eq!(response["error"]["code"], -32601);
+
+    client.finish();
+}
+
+#[test]
+fn exit_without_shutdown() {
+    let mut client = Client::start();
+    client.notify("exit", Json::Null);
+    assert_eq!(client.child.wait().unwrap().code(), Some(1));
+}
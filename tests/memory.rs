@@ -0,0 +1,177 @@
+mod common;
+
+use luster::{HostError, Lua};
+
+use common::{host_error, run};
+
+const LIMIT: usize = 1 << 20;
+
+#[test]
+fn usage() {
+    let mut lua = Lua::new();
+    let initial = lua.memory_usage();
+    assert!(initial > 0);
+
+    run(
+        &mut lua,
+        b"local t = {} for i = 1, 10000 do t[i] = {} end t = nil",
+    )
+    .unwrap();
+    assert!(lua.peak_memory_usage() > initial);
+
+    lua.mutate(|_, _| {});
+    lua.reset_peak_memory_usage();
+    assert_eq!(lua.peak_memory_usage(), lua.memory_usage());
+}
+
+#[test]
+fn limit() {
+    let mut lua = Lua::new();
+    lua.set_memory_limit(Some(lua.memory_usage() + LIMIT));
+    assert_eq!(lua.memory_limit(), Some(lua.memory_usage() + LIMIT));
+
+    assert_eq!(
+        host_error(run(
+            &mut lua,
+            b"local t = {} local i = 1 while true do t[i] = {} i = i + 1 end",
+        )),
+        Some(HostError::OutOfMemory)
+    );
+    assert!(lua.peak_memory_usage() > lua.memory_limit().unwrap());
+
+    // Garbage is collected before the limit is enforced, and the `Lua` remains usable after running
+    // out of memory.
+    assert_eq!(
+        run(
+            &mut lua,
+            b"for i = 1, 100000 do local t = {} end return true"
+        )
+        .unwrap(),
+        vec!["true"]
+    );
+    assert!(lua.memory_usage() <= lua.memory_limit().unwrap());
+}
+
+#[test]
+fn uncatchable() {
+    let mut lua = Lua::new();
+    lua.set_memory_limit(Some(lua.memory_usage() + LIMIT));
+    assert_eq!(
+        host_error(run(
+            &mut lua,
+            br#"
+            pcall(function()
+                local t = {}
+                local i = 1
+                while true do
+                    t[i] = {}
+                    i = i + 1
+                end
+            end)
+            return "caught"
+        "#,
+        )),
+        Some(HostError::OutOfMemory)
+    );
+
+    assert_eq!(
+        host_error(run(
+            &mut lua,
+            br#"
+            local co = coroutine.wrap(function()
+                local t = {}
+                local i = 1
+                while true do
+                    t[i] = {}
+                    i = i + 1
+                end
+            end)
+            pcall(co)
+            return "caught"
+        "#,
+        )),
+        Some(HostError::OutOfMemory)
+    );
+}
+
+#[test]
+fn long_strings() {
+    let mut lua = Lua::new();
+    lua.set_memory_limit(Some(lua.memory_usage() + LIMIT));
+    assert_eq!(
+        host_error(run(
+            &mut lua,
+            br#"
+            local s = "x"
+            for i = 1, 64 do
+                s = s .. s
+            end
+        "#,
+        )),
+        Some(HostError::OutOfMemory)
+    );
+}
+
+#[test]
+fn table_storage() {
+    let mut lua = Lua::new();
+    lua.set_memory_limit(Some(lua.memory_usage() + LIMIT));
+    assert_eq!(
+        host_error(run(
+            &mut lua,
+            b"local t = {} for i = 1, 5000000 do t[i] = i end"
+        )),
+        Some(HostError::OutOfMemory)
+    );
+    // Growth is checked before the table is resized, so usage never goes far past the limit.
+    assert!(lua.peak_memory_usage() <= lua.memory_limit().unwrap());
+
+    assert_eq!(
+        host_error(run(
+            &mut lua,
+            b"local t = {} for i = 1, 5000000 do t[\"k\" .. i] = i end"
+        )),
+        Some(HostError::OutOfMemory)
+    );
+}
+
+#[test]
+fn string_storage() {
+    let mut lua = Lua::new();
+    lua.set_memory_limit(Some(lua.memory_usage() + LIMIT));
+
+    let strings = br#"
+        local s = "x"
+        for i = 1, 19 do
+            s = s .. s
+        end
+        local t = {}
+        for i = 1, 100 do
+            t[i] = s .. i
+        end
+    "#;
+    assert_eq!(
+        host_error(run(&mut lua, strings)),
+        Some(HostError::OutOfMemory)
+    );
+    assert!(lua.peak_memory_usage() <= lua.memory_limit().unwrap());
+
+    // Strings which are no longer reachable are collected before an allocation is refused.
+    assert_eq!(
+        run(
+            &mut lua,
+            br#"
+            local s = "x"
+            for i = 1, 18 do
+                s = s .. s
+            end
+            for i = 1, 100 do
+                local garbage = s .. i
+            end
+            return true
+        "#,
+        )
+        .unwrap(),
+        vec!["true"]
+    );
+}
@@ -0,0 +1,69 @@
+use gc_sequence::{self as sequence, SequenceExt, SequenceResultExt};
+use luster::{
+    compile, load_os_with, Clock, Closure, Error, Function, Lua, OsOptions, StaticError,
+    ThreadSequence, Value,
+};
+
+struct FixedClock;
+
+impl Clock for FixedClock {
+    fn time(&self) -> i64 {
+        1_000_000_000
+    }
+
+    fn clock(&self) -> f64 {
+        1.5
+    }
+
+    fn utc_offset(&self, _: i64) -> i64 {
+        -5 * 3600
+    }
+}
+
+#[test]
+fn safe_os_with_fixed_clock() -> Result<(), Box<StaticError>> {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        load_os_with(
+            mc,
+            root,
+            root.globals,
+            OsOptions::safe().with_clock(FixedClock),
+        )
+    });
+
+    lua.sequence(|root| {
+        sequence::from_fn_with(root, |mc, root| {
+            Ok(Closure::new(
+                mc,
+                compile(
+                    mc,
+                    root.interned_strings,
+                    &br#"
+                        return
+                            os.exit == nil and os.getenv == nil and os.remove == nil and
+                            os.rename == nil and os.tmpname == nil and
+                            os.time() == 1000000000 and os.clock() == 1.5 and
+                            os.date("!%Y-%m-%d %H:%M:%S") == "2001-09-09 01:46:40" and
+                            os.date("%Y-%m-%d %H:%M:%S %z") == "2001-09-08 20:46:40 -0500" and
+                            os.time(os.date("*t")) == 1000000000
+                    "#[..],
+                )?,
+                Some(root.globals),
+            )?)
+        })
+        .and_chain_with(root, |mc, root, closure| {
+            Ok(ThreadSequence::call_function(
+                mc,
+                root.main_thread,
+                Function::Closure(closure),
+                &[],
+            )?)
+        })
+        .map_ok(|b| assert_eq!(b, vec![Value::Boolean(true)]))
+        .map_err(Error::to_static)
+        .boxed()
+    })?;
+
+    Ok(())
+}
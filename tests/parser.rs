@@ -1,8 +1,9 @@
 use luster::parser::{
-    parse_chunk, Block, CallSuffix, Chunk, ConstructorField, Expression, FunctionCallStatement,
-    HeadExpression, PrimaryExpression, SimpleExpression, Statement, SuffixedExpression,
-    TableConstructor,
+    parse_chunk, parse_chunk_with_line, Block, CallSuffix, Chunk, ConstructorField, Expression,
+    FunctionCallStatement, HeadExpression, PrimaryExpression, SimpleExpression, Statement,
+    SuffixedExpression, TableConstructor,
 };
+use luster::LineNumber;
 
 #[test]
 fn test_function_call() {
@@ -14,66 +15,105 @@ fn test_function_call() {
         Chunk {
             block: Block {
                 statements: vec![
-                    Statement::FunctionCall(FunctionCallStatement {
-                        head: SuffixedExpression {
-                            primary: PrimaryExpression::Name(
-                                "print".as_bytes().to_vec().into_boxed_slice(),
-                            ),
-                            suffixes: vec![],
-                        },
-                        call: CallSuffix::Function(vec![
-                            Expression {
-                                head: Box::new(HeadExpression::Simple(SimpleExpression::Integer(
-                                    10,
-                                ))),
-                                tail: vec![],
+                    (
+                        LineNumber(0),
+                        Statement::FunctionCall(FunctionCallStatement {
+                            head: SuffixedExpression {
+                                primary: PrimaryExpression::Name(
+                                    "print".as_bytes().to_vec().into_boxed_slice(),
+                                ),
+                                suffixes: vec![],
+                            },
+                            call: CallSuffix::Function(vec![
+                                Expression {
+                                    head: Box::new(HeadExpression::Simple(
+                                        SimpleExpression::Integer(10,)
+                                    )),
+                                    tail: vec![],
+                                },
+                                Expression {
+                                    head: Box::new(HeadExpression::Simple(
+                                        SimpleExpression::Integer(20,)
+                                    )),
+                                    tail: vec![],
+                                },
+                            ]),
+                        })
+                    ),
+                    (
+                        LineNumber(0),
+                        Statement::FunctionCall(FunctionCallStatement {
+                            head: SuffixedExpression {
+                                primary: PrimaryExpression::Name(
+                                    "print".as_bytes().to_vec().into_boxed_slice(),
+                                ),
+                                suffixes: vec![],
                             },
-                            Expression {
-                                head: Box::new(HeadExpression::Simple(SimpleExpression::Integer(
-                                    20,
+                            call: CallSuffix::Function(vec![Expression {
+                                head: Box::new(HeadExpression::Simple(SimpleExpression::String(
+                                    "foo".as_bytes().to_vec().into_boxed_slice(),
                                 ))),
                                 tail: vec![],
+                            },]),
+                        })
+                    ),
+                    (
+                        LineNumber(0),
+                        Statement::FunctionCall(FunctionCallStatement {
+                            head: SuffixedExpression {
+                                primary: PrimaryExpression::Name(
+                                    "print".as_bytes().to_vec().into_boxed_slice(),
+                                ),
+                                suffixes: vec![],
                             },
-                        ]),
-                    }),
-                    Statement::FunctionCall(FunctionCallStatement {
-                        head: SuffixedExpression {
-                            primary: PrimaryExpression::Name(
-                                "print".as_bytes().to_vec().into_boxed_slice(),
-                            ),
-                            suffixes: vec![],
-                        },
-                        call: CallSuffix::Function(vec![Expression {
-                            head: Box::new(HeadExpression::Simple(SimpleExpression::String(
-                                "foo".as_bytes().to_vec().into_boxed_slice(),
-                            ))),
-                            tail: vec![],
-                        },]),
-                    }),
-                    Statement::FunctionCall(FunctionCallStatement {
-                        head: SuffixedExpression {
-                            primary: PrimaryExpression::Name(
-                                "print".as_bytes().to_vec().into_boxed_slice(),
-                            ),
-                            suffixes: vec![],
-                        },
-                        call: CallSuffix::Function(vec![Expression {
-                            head: Box::new(HeadExpression::Simple(
-                                SimpleExpression::TableConstructor(TableConstructor {
-                                    fields: vec![ConstructorField::Array(Expression {
-                                        head: Box::new(HeadExpression::Simple(
-                                            SimpleExpression::Float(30.0),
-                                        )),
-                                        tail: vec![],
-                                    }),],
-                                }),
-                            )),
-                            tail: vec![],
-                        },]),
-                    }),
+                            call: CallSuffix::Function(vec![Expression {
+                                head: Box::new(HeadExpression::Simple(
+                                    SimpleExpression::TableConstructor(TableConstructor {
+                                        fields: vec![ConstructorField::Array(Expression {
+                                            head: Box::new(HeadExpression::Simple(
+                                                SimpleExpression::Float(30.0),
+                                            )),
+                                            tail: vec![],
+                                        }),],
+                                    }),
+                                )),
+                                tail: vec![],
+                            },]),
+                        })
+                    ),
                 ],
                 return_statement: None,
             },
         }
     );
 }
+
+#[test]
+fn test_line_numbers() {
+    let chunk = parse_chunk(
+        "local a = 1\n\n-- comment\nprint(a,\n  2)\nreturn a".as_bytes(),
+        |s| s.to_vec().into_boxed_slice(),
+    )
+    .unwrap();
+    let lines: Vec<_> = chunk.block.statements.iter().map(|(l, _)| *l).collect();
+    assert_eq!(lines, vec![LineNumber(0), LineNumber(3)]);
+    assert_eq!(chunk.block.return_statement.unwrap().0, LineNumber(5));
+}
+
+#[test]
+fn test_error_lines() {
+    let error_line = |source: &str| {
+        parse_chunk_with_line(source.as_bytes(), |s| s.to_vec().into_boxed_slice())
+            .unwrap_err()
+            .1
+    };
+    assert_eq!(
+        error_line("local a = 1\nlocal = 2\nprint(a)"),
+        LineNumber(1)
+    );
+    assert_eq!(error_line("print(1,\n\n  2 +)\nx = 1"), LineNumber(2));
+    assert_eq!(error_line("if a then\n  f()\nelse\n"), LineNumber(2));
+    assert_eq!(error_line("f()\nend\nf()"), LineNumber(1));
+    assert_eq!(error_line("a = 1\nb = 'open\nc = 2"), LineNumber(1));
+    assert_eq!(error_line("a = 1\nf() + 1"), LineNumber(1));
+}
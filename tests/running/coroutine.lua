@@ -41,6 +41,90 @@ function test2()
         e2 == false and r2 == 'test error' and s2 == "dead"
 end
 
+function test3()
+    local gen = coroutine.wrap(function(a, b)
+        local c = coroutine.yield(a + b)
+        local d, e = coroutine.yield(c * 2)
+        return d + e
+    end)
+
+    local r1 = gen(1, 2)
+    local r2 = gen(10)
+    local r3 = gen(3, 4)
+    local ok, err = pcall(gen)
+
+    local failing = coroutine.wrap(function()
+        error("wrapped error")
+    end)
+    local ok2, err2 = pcall(failing)
+
+    local yielding = coroutine.wrap(function()
+        coroutine.yield(1)
+        error("boom")
+    end)
+    local y1 = yielding()
+    local ok3, err3 = pcall(yielding)
+
+    local err_table = {}
+    local ok4, err4 = pcall(coroutine.wrap(function() error(err_table) end))
+
+    return
+        r1 == 3 and r2 == 20 and r3 == 7 and
+        ok == false and err == "cannot resume dead coroutine" and
+        ok2 == false and err2 == "line 57: wrapped error" and
+        y1 == 1 and ok3 == false and err3 == "line 63: boom" and
+        ok4 == false and err4 == err_table
+end
+
+function test4()
+    local main, is_main = coroutine.running()
+    local inner, inner_is_main, inner_status, outer_status, yieldable
+    local co
+    co = coroutine.create(function()
+        inner, inner_is_main = coroutine.running()
+        inner_status = coroutine.status(co)
+        outer_status = coroutine.status(main)
+        yieldable = coroutine.isyieldable()
+    end)
+    coroutine.resume(co)
+
+    return
+        type(main) == "thread" and is_main == true and
+        inner == co and inner_is_main == false and
+        inner_status == "running" and outer_status == "normal" and
+        yieldable == true and coroutine.isyieldable() == false and
+        coroutine.status(main) == "running"
+end
+
+function test5()
+    local closed_upvalue
+    local co = coroutine.create(function()
+        local x = 1
+        closed_upvalue = function() return x end
+        coroutine.yield()
+        x = 2
+    end)
+    coroutine.resume(co)
+    local r1 = coroutine.close(co)
+    local s1 = coroutine.status(co)
+
+    local failed = coroutine.create(function() error("failure") end)
+    coroutine.resume(failed)
+    local r2, e2 = coroutine.close(failed)
+    local r3 = coroutine.close(failed)
+
+    local ok = pcall(coroutine.close, coroutine.running())
+    local resumed, resume_err = coroutine.resume(co)
+
+    return
+        r1 == true and s1 == "dead" and closed_upvalue() == 1 and
+        r2 == false and e2 == "failure" and r3 == true and
+        ok == false and resumed == false and resume_err == "cannot resume dead coroutine"
+end
+
 return
     test1() and
-    test2()
+    test2() and
+    test3() and
+    test4() and
+    test5()
@@ -0,0 +1,168 @@
+function test1()
+    local info = debug.getinfo(1)
+    local function_info = debug.getinfo(test1, "Su")
+    local callback_info = debug.getinfo(print)
+    return
+        info.currentline == 2 and
+        info.func == test1 and
+        info.what == "Lua" and
+        info.name == "test1" and
+        info.namewhat == "global" and
+        info.nparams == 0 and
+        info.isvararg == false and
+        function_info.linedefined == 1 and
+        function_info.lastlinedefined == 5 and
+        function_info.currentline == nil and
+        callback_info.what == "C" and
+        callback_info.currentline == -1 and
+        debug.getinfo(1000) == nil
+end
+
+function test2()
+    local t = {}
+    function t.field()
+        local info = debug.getinfo(1, "n")
+        return info
+    end
+    function t:method()
+        local info = debug.getinfo(1, "n")
+        return info
+    end
+    local function callee()
+        local info = debug.getinfo(1, "nt")
+        return info
+    end
+    local function caller()
+        return callee()
+    end
+
+    local field = t.field()
+    local method = t:method()
+    local loc = callee()
+    local tail = caller()
+
+    return
+        field.name == "field" and field.namewhat == "field" and
+        method.name == "method" and method.namewhat == "method" and
+        loc.name == "callee" and loc.namewhat == "local" and loc.istailcall == false and
+        tail.name == nil and tail.namewhat == "" and tail.istailcall == true
+end
+
+function test3()
+    local a, b = 1, "two"
+    do
+        local c = true
+    end
+    local d = 4
+
+    local name1, value1 = debug.getlocal(1, 1)
+    local name2, value2 = debug.getlocal(1, 2)
+    local name3, value3 = debug.getlocal(1, 3)
+
+    local ok = debug.setlocal(1, 1, 10) == "a"
+
+    return
+        name1 == "a" and value1 == 1 and
+        name2 == "b" and value2 == "two" and
+        name3 == "d" and value3 == 4 and
+        ok and a == 10 and
+        debug.getlocal(1, 100) == nil and
+        debug.getlocal(function(x, y) local z end, 2) == "y" and
+        debug.getlocal(function(x, y) local z end, 3) == nil
+end
+
+function test4()
+    local function inner(arg)
+        local name, value = debug.getlocal(2, 1)
+        return name, value
+    end
+    local outer = "outer"
+    local name, value = inner(1)
+    return name == "inner" and value == inner
+end
+
+function test5()
+    local x, y = 1, 2
+    local function f() return x, y end
+    local function g() return y end
+
+    local xn, xv = debug.getupvalue(f, 1)
+    local sn = debug.setupvalue(f, 1, 5)
+    local join_before = debug.upvalueid(f, 2) == debug.upvalueid(g, 1)
+    local distinct = debug.upvalueid(f, 1) ~= debug.upvalueid(f, 2)
+
+    debug.upvaluejoin(f, 1, g, 1)
+    local a, b = f()
+
+    return
+        xn == "x" and sn == "x" and xv == 1 and x == 5 and join_before and distinct and
+        a == 2 and b == 2 and
+        debug.upvalueid(f, 1) == debug.upvalueid(g, 1) and
+        debug.getupvalue(f, 3) == nil and
+        debug.getupvalue(print, 1) == nil
+end
+
+function test6()
+    local function inner()
+        local traceback = debug.traceback("message")
+        return traceback
+    end
+    local traceback = inner()
+
+    local co = coroutine.create(function()
+        coroutine.yield()
+    end)
+    coroutine.resume(co)
+
+    return
+        traceback == "message\nstack traceback:\n" ..
+            "\tline 107: in local 'inner'\n" ..
+            "\tline 110: in function 'test6'\n" ..
+            "\tline 160: in main chunk" and
+        debug.traceback(co) == "stack traceback:\n\tline 113: in function <line 112>" and
+        debug.traceback(co, nil, 1) == "stack traceback:" and
+        debug.traceback(12) == debug.traceback("12") and
+        type(debug.traceback({})) == "table"
+end
+
+function test7()
+    local t = {}
+    return
+        debug.getmetatable(t) == nil and
+        debug.setmetatable(t, nil) == t and
+        not pcall(debug.setmetatable, t, {})
+end
+
+function test8()
+    local function callee()
+        local info = debug.getinfo(1, "n")
+        return info
+    end
+    local function caller()
+        local info = callee()
+        return info
+    end
+    local t = {}
+    local function field()
+        local info = t.f()
+        return info
+    end
+    t.f = callee
+
+    local upvalue = caller()
+    local field_info = field()
+    return
+        upvalue.name == "callee" and upvalue.namewhat == "upvalue" and
+        field_info.name == "f" and field_info.namewhat == "field" and
+        debug.getupvalue(caller, 1) == "callee"
+end
+
+return
+    test1() and
+    test2() and
+    test3() and
+    test4() and
+    test5() and
+    test6() and
+    test7() and
+    test8()
@@ -0,0 +1,91 @@
+local events = {}
+local function record(event, line)
+    if line then
+        events[#events + 1] = event .. " " .. line
+    else
+        events[#events + 1] = event
+    end
+end
+
+function test1()
+    local function f(x)
+        return x + 1
+    end
+
+    events = {}
+    debug.sethook(record, "crl")
+    f(1)
+    debug.sethook()
+
+    return
+        #events == 5 and
+        events[1] == "line 17" and
+        events[2] == "call" and
+        events[3] == "line 12" and
+        events[4] == "return" and
+        events[5] == "line 18"
+end
+
+function test2()
+    local function f(x)
+        return x
+    end
+    local function g(x)
+        return f(x)
+    end
+
+    events = {}
+    debug.sethook(record, "c")
+    g(1)
+    debug.sethook()
+
+    return #events == 2 and events[1] == "call" and events[2] == "tail call"
+end
+
+function test3()
+    local count = 0
+    debug.sethook(function() count = count + 1 end, "", 1)
+    for i = 1, 100 do end
+    debug.sethook()
+
+    local count_hook = function(event) count = count + 1 end
+    debug.sethook(count_hook, "l", 10)
+    local hook, mask, n = debug.gethook()
+    debug.sethook()
+
+    return count > 100 and hook == count_hook and mask == "l" and n == 10 and debug.gethook() == nil
+end
+
+function test4()
+    -- Coroutines inherit the hook of the thread that creates them.
+    debug.sethook(record, "c")
+    local co = coroutine.create(function()
+        coroutine.yield(debug.gethook())
+        debug.sethook(record, "l")
+        coroutine.yield(debug.gethook())
+    end)
+    debug.sethook()
+
+    local _, inherited = coroutine.resume(co)
+    local _, own = coroutine.resume(co)
+
+    return inherited == record and own == record and debug.gethook() == nil
+end
+
+function test5()
+    local ok, err = pcall(function()
+        debug.sethook(function()
+            debug.sethook()
+            error("in hook")
+        end, "l")
+        local x = 1
+    end)
+    return ok == false and err == "in hook"
+end
+
+return
+    test1() and
+    test2() and
+    test3() and
+    test4() and
+    test5()
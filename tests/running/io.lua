@@ -0,0 +1,156 @@
+function test1()
+    local name = os.tmpname()
+    local f = io.open(name, "w")
+    local same = f:write("hello\n", 42, " ", 1.5, "\n") == f
+    f:close()
+
+    f = io.open(name)
+    local l1 = f:read("l")
+    local l2 = f:read("L")
+    local l3 = f:read("l")
+    f:close()
+    os.remove(name)
+
+    return
+        same and l1 == "hello" and l2 == "42 1.5\n" and l3 == nil and
+        io.type(f) == "closed file" and io.type(io.stdout) == "file" and
+        io.type(42) == nil and type(io.stdout) == "userdata"
+end
+
+function test2()
+    local name = os.tmpname()
+    local f = io.open(name, "w+")
+    f:write("  12 0x1F -3.5e1 1e junk\nabcdef")
+    f:seek("set")
+    local a, b, c = f:read("n", "n", "n")
+    local d = f:read("n")
+    f:seek("set", 25)
+    local e, g, h, i = f:read(2, 0, "a", 0)
+    local size = f:seek("end")
+    f:close()
+    os.remove(name)
+
+    return
+        a == 12 and math.type(a) == "integer" and b == 31 and c == -35.0 and
+        d == nil and e == "ab" and g == "" and h == "cdef" and i == nil and size == 31
+end
+
+function test3()
+    local name = os.tmpname()
+    local f = io.open(name, "w")
+    f:write("one\ntwo\n\nthree")
+    f:close()
+
+    local lines = {}
+    for l in io.lines(name) do
+        lines[#lines + 1] = l
+    end
+
+    f = io.open(name)
+    local counts = {}
+    for a, b in f:lines(1, "l") do
+        counts[#counts + 1] = a .. b
+    end
+    local still_open = io.type(f) == "file"
+    f:close()
+    os.remove(name)
+
+    return
+        #lines == 4 and lines[1] == "one" and lines[3] == "" and lines[4] == "three" and
+        #counts == 3 and counts[1] == "one" and counts[2] == "two" and counts[3] == "\nthree" and
+        still_open
+end
+
+function test4()
+    local name = os.tmpname()
+    local f = io.open(name, "w")
+    f:setvbuf("full", 1024)
+    f:write("buffered")
+    local before = io.open(name):read("a")
+    f:flush()
+    local after = io.open(name):read("a")
+    f:setvbuf("no")
+    f:write("!")
+    local unbuffered = io.open(name):read("a")
+    f:close()
+
+    f = io.open(name, "a+")
+    f:write("?")
+    f:seek("set")
+    local appended = f:read("a")
+    f:close()
+    os.remove(name)
+
+    return
+        before == "" and after == "buffered" and unbuffered == "buffered!" and
+        appended == "buffered!?"
+end
+
+function test5()
+    local name = os.tmpname()
+    local old_output = io.output()
+    io.output(name)
+    io.write("default ", "output\n")
+    io.close()
+    io.output(old_output)
+
+    local old_input = io.input()
+    io.input(name)
+    local l = io.read()
+    local eof = io.read()
+    io.input():close()
+    io.input(old_input)
+
+    os.remove(name)
+
+    return l == "default output" and eof == nil and io.output() == io.stdout
+end
+
+function test6()
+    local f, err, errno = io.open("/surely/does/not/exist/luster")
+    local ok1 = pcall(io.open, "x", "rw")
+    local ok2 = pcall(io.lines, "/surely/does/not/exist/luster")
+    local ok3 = pcall(io.stdout.read, io.stdout, "x")
+    local ok4 = pcall(io.stdout.write, io.stdout, {})
+    local closed, closed_err = io.stdout:close()
+
+    local name = os.tmpname()
+    local g = io.open(name, "w")
+    g:close()
+    local ok5 = pcall(g.read, g)
+    os.remove(name)
+
+    return
+        f == nil and type(err) == "string" and type(errno) == "number" and
+        not ok1 and not ok2 and not ok3 and not ok4 and not ok5 and
+        closed == nil and closed_err == "cannot close standard file"
+end
+
+function test7()
+    local function message(f, ...)
+        local _, err = pcall(f, ...)
+        return err
+    end
+
+    -- File methods count the file itself as argument #1.
+    local f = io.stdout
+    return
+        message(f.seek, f, "middle") == "bad argument #2 to 'seek' (invalid option)" and
+        message(f.seek, f, "set", {}) == "bad argument #3 to 'seek' (number expected)" and
+        message(f.setvbuf, f, "some") == "bad argument #2 to 'setvbuf' (invalid option)" and
+        message(f.setvbuf, f, "no", -1) == "bad argument #3 to 'setvbuf' (number expected)" and
+        message(f.read, f, "x") == "bad argument #2 to 'read' (invalid format)" and
+        message(f.write, f, "a", {}) ==
+            "bad argument #3 to 'write' (string expected, got table)" and
+        message(io.read, "x") == "bad argument #1 to 'read' (invalid format)" and
+        message(io.write, {}) == "bad argument #1 to 'write' (string expected, got table)"
+end
+
+return
+    test1() and
+    test2() and
+    test3() and
+    test4() and
+    test5() and
+    test6() and
+    test7()
@@ -0,0 +1,62 @@
+local function test1()
+    local f = load("return 1 + 2, ...")
+    local a, b = f(4)
+    return a == 3 and b == 4
+end
+
+local function test2()
+    local f = load("local a, b = ... return a * b")
+    local g = load(string.dump(f))
+    return g(6, 7) == 42 and load(string.dump(f, true))(2, 3) == 6
+end
+
+local function test3()
+    local function add(a, b)
+        local t = {a, b, n = "sum"}
+        for i = 1, 3 do
+            t[1] = t[1] + i
+        end
+        return t.n, t[1] + t[2]
+    end
+    local n, s = load(string.dump(add), "add", "b")(1, 2)
+    return n == "sum" and s == 9
+end
+
+local function test4()
+    local dumped = string.dump(function() return 1 end)
+    local f1, e1 = load(dumped, "chunk", "t")
+    local f2, e2 = load("return 1", "chunk", "b")
+    local f3 = load(dumped, "chunk", "bt")
+    return f1 == nil and type(e1) == "string" and
+        f2 == nil and type(e2) == "string" and
+        f3() == 1
+end
+
+local function test5()
+    local f, e = load("return +")
+    local g, e2 = load("\27Luster\99\1")
+    local h, e3 = load("\27Luster\1\1")
+    return f == nil and type(e) == "string" and
+        g == nil and type(e2) == "string" and
+        h == nil and type(e3) == "string"
+end
+
+local function test6()
+    x = 5
+    local env = {x = 10}
+    return load("return x")() == 5 and load("return x", "chunk", "t", env)() == 10
+end
+
+local function test7()
+    local ok = pcall(string.dump, print)
+    return not ok
+end
+
+return
+    test1() and
+    test2() and
+    test3() and
+    test4() and
+    test5() and
+    test6() and
+    test7()
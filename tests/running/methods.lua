@@ -23,6 +23,21 @@ function test2()
     return t:method(42) == 42
 end
 
+function test3()
+    local t = {}
+    function t:multi()
+        return 1, 2, 3
+    end
+
+    local a, b, c = t:multi()
+    local function third(_, _, c)
+        return c
+    end
+
+    return a == 1 and b == 2 and c == 3 and third(t:multi()) == 3
+end
+
 return
     test1() and
-    test2()
+    test2() and
+    test3()
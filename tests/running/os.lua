@@ -0,0 +1,61 @@
+function test1()
+    local t = os.date("!*t", 0)
+    return
+        t.year == 1970 and t.month == 1 and t.day == 1 and
+        t.hour == 0 and t.min == 0 and t.sec == 0 and
+        t.wday == 5 and t.yday == 1 and t.isdst == false
+end
+
+function test2()
+    local t = 951782400 + 3 * 3600 + 4 * 60 + 5
+    return
+        os.date("!%Y-%m-%d %H:%M:%S", t) == "2000-02-29 03:04:05" and
+        os.date("!%c", t) == "Tue Feb 29 03:04:05 2000" and
+        os.date("!%a %A %b %B %j %p %I %y %%", t) == "Tue Tuesday Feb February 060 AM 03 00 %" and
+        os.date("!%x %X %D %e", t) == "02/29/00 03:04:05 02/29/00 29"
+end
+
+function test3()
+    local t = os.time()
+    local d = os.date("*t", t)
+    return
+        os.time(d) == t and
+        os.time({year = 2000, month = 1, day = 1, hour = 0}) ==
+            os.time({year = 1999, month = 13, day = 1, hour = 0}) and
+        os.difftime(t + 10, t) == 10.0 and
+        type(os.clock()) == "number"
+end
+
+function test4()
+    local ok1 = pcall(os.date, "%Q")
+    local ok2 = pcall(os.time, {year = 2000})
+    local ok3, err3 = pcall(os.time, {year = math.maxinteger, month = 1, day = 1})
+    local ok4, err4 = pcall(os.time, {year = 2000, month = 1, day = 1, hour = math.mininteger})
+    local ok5, err5 = pcall(os.date, "!*t", math.maxinteger)
+    local ok6, err6 = pcall(os.difftime, 1, "x")
+    return
+        ok1 == false and ok2 == false and
+        ok3 == false and err3 == "field 'year' is out-of-bound" and
+        ok4 == false and err4 == "field 'hour' is out-of-bound" and
+        ok5 == false and err5 == "date result cannot be represented in this installation" and
+        ok6 == false and err6 == "bad argument #2 to 'difftime' (number expected)" and
+        os.time({year = 2000, month = 1, day = 1, hour = 2147483647}) ~= nil
+end
+
+function test5()
+    local name = os.tmpname()
+    local renamed = name .. "_renamed"
+    local r1 = os.rename(name, renamed)
+    local r2 = os.remove(renamed)
+    local r3, err = os.remove(renamed)
+    return
+        r1 == true and r2 == true and r3 == nil and type(err) == "string" and
+        os.getenv("LUSTER_SURELY_UNSET_VARIABLE") == nil
+end
+
+return
+    test1() and
+    test2() and
+    test3() and
+    test4() and
+    test5()
@@ -0,0 +1,87 @@
+local function is_sorted(t, lt)
+    lt = lt or function(a, b) return a < b end
+    for i = 2, #t do
+        if lt(t[i], t[i - 1]) then
+            return false
+        end
+    end
+    return true
+end
+
+function test1()
+    local t = {5, 3, 8, 1, 9, 2, 7, 4, 6, 0}
+    table.sort(t)
+    if not is_sorted(t) or t[1] ~= 0 or t[10] ~= 9 then
+        return false
+    end
+
+    local e = {}
+    table.sort(e)
+    local s = {1}
+    table.sort(s)
+
+    return #e == 0 and s[1] == 1
+end
+
+function test2()
+    local t = {}
+    for i = 1, 200 do
+        t[i] = (i * 7919) % 211
+    end
+    table.sort(t, function(a, b) return a > b end)
+    return is_sorted(t, function(a, b) return a > b end)
+end
+
+function test3()
+    local t = {"pear", "apple", "fig", "banana", "cherry"}
+    table.sort(t)
+    return t[1] == "apple" and t[2] == "banana" and t[3] == "cherry" and
+        t[4] == "fig" and t[5] == "pear"
+end
+
+function test4()
+    local t = {}
+    for i = 1, 100 do
+        t[i] = i % 10
+    end
+    local ok, err = pcall(table.sort, t, function(a, b) return true end)
+    return ok == false and err == "invalid order function for sorting"
+end
+
+function test5()
+    local t = {3, 1, 2}
+    local ok = pcall(table.sort, t, function(a, b) error("comparator error") end)
+    local ok2 = pcall(table.sort, {1, "a", 2})
+    return ok == false and ok2 == false
+end
+
+function test6()
+    local co = coroutine.create(function()
+        local t = {3, 1, 2}
+        table.sort(t, function(a, b)
+            coroutine.yield()
+            return a < b
+        end)
+        return t[1] == 1 and t[2] == 2 and t[3] == 3
+    end)
+
+    local yields = 0
+    while true do
+        local ok, res = coroutine.resume(co)
+        if not ok then
+            return false
+        end
+        if coroutine.status(co) == "dead" then
+            return res and yields > 0
+        end
+        yields = yields + 1
+    end
+end
+
+return
+    test1() and
+    test2() and
+    test3() and
+    test4() and
+    test5() and
+    test6()
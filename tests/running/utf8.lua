@@ -0,0 +1,89 @@
+function test1()
+    local s = utf8.char(72, 228, 8364, 128512)
+    return
+        s == "H\xC3\xA4\xE2\x82\xAC\xF0\x9F\x98\x80" and
+        utf8.char() == "" and
+        utf8.len(s) == 4 and
+        utf8.len("") == 0 and
+        utf8.len(s, 2) == 3 and
+        utf8.len(s, -4) == 1
+end
+
+function test2()
+    local s = "H\xC3\xA4\xE2\x82\xAC"
+    local a, b, c = utf8.codepoint(s, 1, -1)
+    local d = utf8.codepoint(s, 2)
+    return a == 72 and b == 228 and c == 8364 and d == 228
+end
+
+function test3()
+    local n, pos = utf8.len("ab\xFFcd")
+    local n2, pos2 = utf8.len("a\xC3")
+    local n3, pos3 = utf8.len("\xC0\x80")
+    local ok = pcall(utf8.codepoint, "\xFF")
+    local ok2 = pcall(utf8.char, 0x110000)
+    return n == nil and pos == 3 and n2 == nil and pos2 == 2 and n3 == nil and pos3 == 1 and
+        ok == false and ok2 == false
+end
+
+function test4()
+    local s = "a\xC3\xA4b\xE2\x82\xAC"
+    local ok = pcall(utf8.offset, s, 1, 3)
+    return
+        utf8.offset(s, 1) == 1 and
+        utf8.offset(s, 2) == 2 and
+        utf8.offset(s, 3) == 4 and
+        utf8.offset(s, 4) == 5 and
+        utf8.offset(s, 5) == 8 and
+        utf8.offset(s, 6) == nil and
+        utf8.offset(s, -1) == 5 and
+        utf8.offset(s, -4) == 1 and
+        utf8.offset(s, -5) == nil and
+        utf8.offset(s, 0, 3) == 2 and
+        ok == false
+end
+
+function test5()
+    local positions = {}
+    local codes = {}
+    for p, c in utf8.codes("a\xC3\xA4\xE2\x82\xAC") do
+        positions[#positions + 1] = p
+        codes[#codes + 1] = c
+    end
+
+    local ok = pcall(function()
+        for p, c in utf8.codes("a\xFF") do end
+    end)
+
+    return
+        #positions == 3 and positions[1] == 1 and positions[2] == 2 and positions[3] == 4 and
+        codes[1] == 97 and codes[2] == 228 and codes[3] == 8364 and
+        ok == false
+end
+
+function test6()
+    return utf8.charpattern == "[\0-\x7F\xC2-\xF4][\x80-\xBF]*"
+end
+
+function test7()
+    local function message(f, ...)
+        local _, err = pcall(f, ...)
+        return err
+    end
+
+    return
+        message(utf8.char, 65, -1) == "bad argument #2 to 'char' (value out of range)" and
+        message(utf8.char, "x") == "bad argument #1 to 'char' (number expected)" and
+        message(utf8.codepoint, "abc", {}) == "bad argument #2 to 'codepoint' (number expected)" and
+        message(utf8.len, "abc", 1, "x") == "bad argument #3 to 'len' (number expected)" and
+        message(utf8.offset, "abc", 1, 1.5) == "bad argument #3 to 'offset' (number expected)"
+end
+
+return
+    test1() and
+    test2() and
+    test3() and
+    test4() and
+    test5() and
+    test6() and
+    test7()
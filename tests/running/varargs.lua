@@ -20,6 +20,78 @@ local function test2()
         varargs(0, 1, 1, 2, 3, 5) == 4
 end
 
+local function test3()
+    local function pass(...)
+        return ...
+    end
+    local count
+    count = function(a, ...)
+        if a == nil then
+            return 0
+        end
+        return 1 + count(...)
+    end
+    local function three(a, b, c)
+        return c, b, a
+    end
+    local function tail(...)
+        return three(...)
+    end
+
+    local a, b, c, d = pass(1, pass(2, 3))
+    local x, y, z = tail(pass(7))
+    local p, q, r = three(1, pass(2, 3, 4))
+    return
+        a == 1 and b == 2 and c == 3 and d == nil and
+        x == nil and y == nil and z == 7 and
+        p == 3 and q == 2 and r == 1 and
+        count(pass()) == 0 and
+        count(pass(1, 2, 3, pass(4, 5, 6))) == 6 and
+        select(2, pass(pass(1, 2, 3))) == 2
+end
+
+local function test4()
+    local function outer(...)
+        local function inner(a, ...)
+            local b, c = ...
+            return a, b, c, ...
+        end
+        local a, b, c, d = inner(...)
+        return a, b, c, d
+    end
+
+    local a, b, c, d = outer(1, 2, 3)
+    return a == 1 and b == 2 and c == 3 and d == 2
+end
+
+local function test5()
+    -- Missing parameters and returns must be nil, even where earlier calls have left values in the
+    -- registers they are placed in.
+    local function params(a, b, c)
+        return a, b, c
+    end
+    local function returns(n)
+        if n == 3 then
+            return 1, 2, 3
+        else
+            return n
+        end
+    end
+
+    local a, b, c = params(4, 5, 6)
+    local d, e, f = params(7)
+    local g, h, i = returns(3)
+    local j, k, l = returns(8)
+    return
+        a == 4 and b == 5 and c == 6 and
+        d == 7 and e == nil and f == nil and
+        g == 1 and h == 2 and i == 3 and
+        j == 8 and k == nil and l == nil
+end
+
 return
     test1() and
-    test2()
+    test2() and
+    test3() and
+    test4() and
+    test5()
@@ -0,0 +1,193 @@
+mod common;
+
+use luster::{
+    compile, Callback, CallbackResult, CompilerError, Continuation, Error, Lua, StaticError,
+    String, Thread, Value,
+};
+
+use common::run;
+
+#[test]
+fn infinite_recursion() {
+    let mut lua = Lua::new();
+    let res = run(
+        &mut lua,
+        br#"
+            function f() return 1 + f() end
+            local ok, err = pcall(f)
+            return ok, err
+        "#,
+    )
+    .unwrap();
+    assert_eq!(res[0], "false");
+    assert!(res[1].starts_with("stack overflow\nstack traceback:"));
+    assert!(res[1].contains("\n\tline 2: in function 'f'"));
+    assert!(res[1].contains("levels)"));
+
+    match run(&mut lua, b"function f() return 1 + f() end return f()") {
+        Err(StaticError::StackOverflow(overflow)) => {
+            assert_eq!(overflow.traceback.len(), 22);
+            assert!(overflow.traceback[10].starts_with("...(skipping "));
+        }
+        res => panic!("expected stack overflow, got {:?}", res),
+    }
+
+    // The thread is usable again after the overflow.
+    assert_eq!(run(&mut lua, b"return 1 + 1").unwrap(), vec!["2"]);
+}
+
+#[test]
+fn configured_limits() {
+    let mut lua = Lua::new();
+    lua.mutate(|_, root| {
+        assert_eq!(
+            root.main_thread.max_call_depth(),
+            Thread::DEFAULT_MAX_CALL_DEPTH
+        );
+        root.main_thread.set_max_call_depth(50);
+    });
+    assert_eq!(
+        run(
+            &mut lua,
+            br#"
+                local depth = 0
+                function f() depth = depth + 1 f() end
+                local ok = pcall(f)
+                local co_depth = coroutine.wrap(function()
+                    depth = 0
+                    pcall(f)
+                    return depth
+                end)()
+                return ok, depth, co_depth
+            "#,
+        )
+        .unwrap(),
+        vec!["false", "48", "48"]
+    );
+
+    lua.mutate(|_, root| {
+        root.main_thread
+            .set_max_call_depth(Thread::DEFAULT_MAX_CALL_DEPTH);
+        root.main_thread.set_max_stack_size(100);
+    });
+    assert!(matches!(
+        run(
+            &mut lua,
+            br#"
+                function f(a, b, c, d, e, g, h, i, j, k)
+                    return a + f(a, b, c, d, e, g, h, i, j, k)
+                end
+                return f(1)
+            "#,
+        ),
+        Err(StaticError::StackOverflow(_))
+    ));
+}
+
+#[test]
+fn coroutine_nesting() {
+    let mut lua = Lua::new();
+    assert_eq!(
+        run(
+            &mut lua,
+            br#"
+                function nest(n)
+                    local co = coroutine.create(nest)
+                    local ok, res = coroutine.resume(co, n + 1)
+                    if ok then return res else return n end
+                end
+                return nest(0)
+            "#,
+        )
+        .unwrap(),
+        vec!["200"]
+    );
+}
+
+#[test]
+fn continuation_chain() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        // Tail calls itself with a continuation the given number of times, then returns or raises
+        // an error.
+        let nest = Callback::new_immediate(mc, |ctx, args| {
+            let (n, raise) = match (args[0], args[1]) {
+                (Value::Integer(n), Value::Boolean(raise)) => (n, raise),
+                _ => panic!("bad arguments"),
+            };
+            if n == 0 {
+                return if raise {
+                    Err(Error::RuntimeError(luster::RuntimeError(Value::Integer(
+                        -1,
+                    ))))
+                } else {
+                    Ok(CallbackResult::Return(args.returning([Value::Integer(0)])))
+                };
+            }
+            let function = match ctx.globals.get(String::new_static(b"nest")) {
+                Value::Function(function) => function,
+                _ => panic!("nest is not a function"),
+            };
+            Ok(CallbackResult::TailCall {
+                function,
+                args: args.returning([Value::Integer(n - 1), Value::Boolean(raise)]),
+                continuation: Continuation::new_immediate(|res| match res {
+                    Ok(res) => Ok(CallbackResult::Return(res)),
+                    Err((err, _)) => Err(err),
+                }),
+            })
+        });
+        root.globals
+            .set(mc, String::new_static(b"nest"), nest)
+            .unwrap();
+    });
+    assert_eq!(
+        run(
+            &mut lua,
+            br#"
+                local ok, err = pcall(nest, 100000, true)
+                return nest(100000, false), ok, err
+            "#,
+        )
+        .unwrap(),
+        vec!["0", "false", "-1"]
+    );
+}
+
+#[test]
+fn deep_expression() {
+    let mut source = b"local a = 1 return a".to_vec();
+    for _ in 0..100000 {
+        source.extend_from_slice(b" + a");
+    }
+    let mut lua = Lua::new();
+    let res = lua.mutate(|mc, root| {
+        compile(mc, root.interned_strings, &source[..])
+            .map(|_| ())
+            .map_err(Error::to_static)
+    });
+    assert!(matches!(
+        res,
+        Err(StaticError::CompilerError(CompilerError::RecursionLimit))
+    ));
+
+    // Expressions just under the limit still compile and run.
+    let mut source = b"local a = 1 return a".to_vec();
+    for _ in 0..190 {
+        source.extend_from_slice(b" + a");
+    }
+    assert_eq!(
+        run(&mut lua, Box::leak(source.into_boxed_slice())).unwrap(),
+        vec!["191"]
+    );
+
+    // Constant folded operators do not count towards the limit.
+    let mut source = b"return 1".to_vec();
+    for _ in 0..100000 {
+        source.extend_from_slice(b" + 1");
+    }
+    assert_eq!(
+        run(&mut lua, Box::leak(source.into_boxed_slice())).unwrap(),
+        vec!["100001"]
+    );
+}
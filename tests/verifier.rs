@@ -0,0 +1,237 @@
+mod common;
+
+use std::str;
+
+use gc_arena::Gc;
+use luster::{
+    assemble, compile, verify, Closure, ClosureError, FunctionProto, Lua, OpCode, Opt254,
+    RegisterIndex, UpValueDescriptor, VarCount, VerifierError, VerifierErrorKind,
+};
+
+use common::run_with;
+
+fn proto<'gc>(stack_size: u16, opcodes: Vec<OpCode>) -> FunctionProto<'gc> {
+    FunctionProto {
+        chunk_name: None,
+        line_defined: None,
+        fixed_params: 0,
+        has_varargs: true,
+        stack_size,
+        constants: Vec::new(),
+        opcodes,
+        opcode_lines: Vec::new(),
+        locals: Vec::new(),
+        upvalues: Vec::new(),
+        upvalue_names: Vec::new(),
+        prototypes: Vec::new(),
+    }
+}
+
+fn return_none() -> OpCode {
+    OpCode::Return {
+        start: RegisterIndex(0),
+        count: VarCount::constant(0),
+    }
+}
+
+fn error(prototype: usize, instruction: Option<usize>, kind: VerifierErrorKind) -> VerifierError {
+    VerifierError {
+        prototype,
+        instruction,
+        kind,
+    }
+}
+
+#[test]
+fn compiled_chunks_verify() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let proto = compile(
+            mc,
+            root.interned_strings,
+            &br#"
+                local function f(...)
+                    local a, b = ...
+                    for i = 1, 3 do
+                        a = a + i
+                    end
+                    return g(a, b, ...)
+                end
+                return f(1, 2, 3)
+            "#[..],
+        )
+        .unwrap();
+        assert_eq!(verify(&proto), Ok(()));
+    });
+}
+
+#[test]
+fn out_of_range_operands() {
+    let mut lua = Lua::new();
+    lua.mutate(|_, _| {
+        let bad_register = proto(
+            1,
+            vec![
+                OpCode::Move {
+                    dest: RegisterIndex(1),
+                    source: RegisterIndex(0),
+                },
+                return_none(),
+            ],
+        );
+        assert_eq!(
+            verify(&bad_register),
+            Err(error(0, Some(0), VerifierErrorKind::Register(1)))
+        );
+
+        let bad_jump = proto(
+            0,
+            vec![
+                OpCode::Jump {
+                    offset: 5,
+                    close_upvalues: Opt254::none(),
+                },
+                return_none(),
+            ],
+        );
+        assert_eq!(
+            verify(&bad_jump),
+            Err(error(0, Some(0), VerifierErrorKind::JumpTarget(6)))
+        );
+
+        let falls_off_end = proto(
+            1,
+            vec![OpCode::NewTable {
+                dest: RegisterIndex(0),
+            }],
+        );
+        assert_eq!(
+            verify(&falls_off_end),
+            Err(error(0, Some(0), VerifierErrorKind::FallsOffEnd))
+        );
+    });
+}
+
+#[test]
+fn variable_counts() {
+    let mut lua = Lua::new();
+    lua.mutate(|_, _| {
+        let unexpected = proto(
+            1,
+            vec![OpCode::Return {
+                start: RegisterIndex(0),
+                count: VarCount::variable(),
+            }],
+        );
+        assert_eq!(
+            verify(&unexpected),
+            Err(error(0, Some(0), VerifierErrorKind::UnexpectedVariable))
+        );
+
+        let unconsumed = proto(
+            1,
+            vec![
+                OpCode::VarArgs {
+                    dest: RegisterIndex(0),
+                    count: VarCount::variable(),
+                },
+                return_none(),
+            ],
+        );
+        assert_eq!(
+            verify(&unconsumed),
+            Err(error(0, Some(0), VerifierErrorKind::UnconsumedVariable))
+        );
+
+        let range = proto(
+            2,
+            vec![
+                OpCode::VarArgs {
+                    dest: RegisterIndex(0),
+                    count: VarCount::variable(),
+                },
+                OpCode::Return {
+                    start: RegisterIndex(1),
+                    count: VarCount::variable(),
+                },
+            ],
+        );
+        assert_eq!(
+            verify(&range),
+            Err(error(0, Some(1), VerifierErrorKind::VariableRange))
+        );
+    });
+}
+
+#[test]
+fn nested_upvalues() {
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let mut inner = proto(0, vec![return_none()]);
+        inner
+            .upvalues
+            .push(UpValueDescriptor::ParentLocal(RegisterIndex(3)));
+
+        let mut outer = proto(1, vec![return_none()]);
+        outer.prototypes.push(Gc::allocate(mc, inner));
+        assert_eq!(
+            verify(&outer),
+            Err(error(1, None, VerifierErrorKind::Register(3)))
+        );
+
+        match Closure::new(mc, outer, Some(root.globals)) {
+            Err(ClosureError::Invalid(err)) => assert_eq!(err.prototype, 1),
+            _ => panic!("invalid prototype was not rejected"),
+        }
+    });
+}
+
+#[test]
+fn upvalues_above_calls() {
+    // The closure captures the step register of the loop, which the loop never closes, and is then
+    // called from a register below it.  The compiler never does this, but it is valid, so it must
+    // not break the VM.
+    const SOURCE: &str = r#"
+        .function
+            .stack 5
+            .constant 1
+            .constant 2
+            .upvalue environment "_ENV"
+            LoadConstant dest=r0 constant=k0
+            LoadConstant dest=r1 constant=k1
+            LoadConstant dest=r2 constant=k0
+            NumericForPrep base=r0 jump=L6
+        L4:
+            Closure dest=r4 proto=p0
+            Jump offset=L6 close_upvalues=3
+        L6:
+            NumericForLoop base=r0 jump=L4
+            Move dest=r0 source=r4
+            Call func=r0 args=0 returns=1
+            Return start=r0 count=1
+            .function
+                .stack 1
+                .upvalue local r2 "step"
+                GetUpValue dest=r0 source=u0
+                Return start=r0 count=1
+            .end
+        .end
+    "#;
+
+    let mut lua = Lua::new();
+    lua.mutate(|mc, root| {
+        let proto = assemble(mc, root.interned_strings, SOURCE).unwrap();
+        assert_eq!(verify(&proto), Ok(()));
+    });
+    assert_eq!(
+        run_with(
+            &mut lua,
+            SOURCE.as_bytes(),
+            |mc, interned_strings, source| {
+                Ok(assemble(mc, interned_strings, str::from_utf8(source).unwrap()).unwrap())
+            }
+        )
+        .unwrap(),
+        vec!["1"]
+    );
+}